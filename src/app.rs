@@ -38,6 +38,33 @@ pub const DEFAULT_SERVER_PORT: u16 = 5444;
 /// Default StackQL (PostgreSQL protocol) server port as a string
 pub const DEFAULT_SERVER_PORT_STR: &str = "5444";
 
+/// Default cap on the rows buffered for a single query result
+pub const DEFAULT_MAX_ROWS: usize = 100_000;
+
+/// Default cap on the rows buffered for a single query result as a string
+pub const DEFAULT_MAX_ROWS_STR: &str = "100000";
+
+/// Default number of attempts to connect to the server before giving up
+pub const DEFAULT_CONNECT_RETRIES: u32 = 5;
+
+/// Default number of connect attempts as a string
+pub const DEFAULT_CONNECT_RETRIES_STR: &str = "5";
+
+/// Default delay in milliseconds before the first connect retry, doubled
+/// after each failed attempt
+pub const DEFAULT_CONNECT_RETRY_DELAY_MS: u64 = 500;
+
+/// Default connect retry delay as a string
+pub const DEFAULT_CONNECT_RETRY_DELAY_MS_STR: &str = "500";
+
+/// Environment variable holding the stack environment when `stack_env` is
+/// not given on the command line
+pub const DEFAULT_MANIFEST_ENV_VAR: &str = "STACKQL_DEPLOY_ENV";
+
+/// Exit code of `--detailed-exitcode` runs when changes are pending
+/// (0 means no changes and 1 an error)
+pub const CHANGES_PENDING_EXIT_CODE: i32 = 2;
+
 /// Local server addresses
 pub const LOCAL_SERVER_ADDRESSES: [&str; 3] = ["localhost", "0.0.0.0", "127.0.0.1"];
 
@@ -50,6 +77,12 @@ pub const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
 /// Default log level for the application
 pub const DEFAULT_LOG_LEVEL: &str = "info";
 
+/// Output formats for the `--output` argument
+pub const OUTPUT_FORMATS: &[&str] = &["text", "json"];
+
+/// Default output format
+pub const DEFAULT_OUTPUT_FORMAT: &str = "text";
+
 /// Supported cloud providers for the `--provider` argument in the `init` command
 pub const SUPPORTED_PROVIDERS: [&str; 3] = ["aws", "google", "azure"];
 
@@ -75,7 +108,7 @@ pub const STACKQL_BINARY_NAME: &str = "stackql";
 pub const STACKQL_RELEASE_BASE_URL: &str = "https://releases.stackql.io/stackql/latest";
 
 /// Commands exempt from binary check
-pub const EXEMPT_COMMANDS: [&str; 2] = ["init", "upgrade"];
+pub const EXEMPT_COMMANDS: [&str; 4] = ["init", "lint", "list", "upgrade"];
 
 /// The base URL for GitHub template repository
 pub const GITHUB_TEMPLATE_BASE: &str =
@@ -89,6 +122,7 @@ pub mod aws_templates {
         include_str!("../template-hub/aws/starter/stackql_manifest.yml.template");
     pub const README_TEMPLATE: &str =
         include_str!("../template-hub/aws/starter/README.md.template");
+    pub const ENV_TEMPLATE: &str = include_str!("../template-hub/aws/starter/.env.template");
 }
 
 /// Template constants for Azure
@@ -99,6 +133,7 @@ pub mod azure_templates {
         include_str!("../template-hub/azure/starter/stackql_manifest.yml.template");
     pub const README_TEMPLATE: &str =
         include_str!("../template-hub/azure/starter/README.md.template");
+    pub const ENV_TEMPLATE: &str = include_str!("../template-hub/azure/starter/.env.template");
 }
 
 /// Template constants for Google
@@ -109,4 +144,5 @@ pub mod google_templates {
         include_str!("../template-hub/google/starter/stackql_manifest.yml.template");
     pub const README_TEMPLATE: &str =
         include_str!("../template-hub/google/starter/README.md.template");
+    pub const ENV_TEMPLATE: &str = include_str!("../template-hub/google/starter/.env.template");
 }
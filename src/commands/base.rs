@@ -5,34 +5,69 @@
 //! Shared resource processing logic used by build, teardown, and test commands.
 //! This is the Rust equivalent of the Python `cmd/base.py` `StackQLBase` class.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use log::{debug, error, info, warn};
 
-use crate::core::config::{get_full_context, render_globals, render_string_value};
-use crate::core::env::load_env_vars;
+use crate::commands::build::createorupdate_unchecked;
+use crate::commands::common_args::{ExportFormat, FailureAction};
+use crate::core::config::{
+    get_full_context, multi_item_contexts, render_globals, render_providers, render_string_value,
+};
+use crate::core::env::{is_sensitive_name, load_env_vars, render_env_file_path, ProcessEnv};
+use crate::core::errors::set_abort_on_patterns;
+use crate::core::report::{self, BuildReport, ResourceOutcome, RunOutput};
+use crate::core::rollback;
+use crate::core::seed::uuid_v4;
+use crate::core::sql_dump::SqlDump;
 use crate::core::templating::{self, ParsedQuery};
 use crate::core::utils::{
-    catch_error_and_exit, check_exports_as_statecheck_proxy, check_short_circuit, export_vars,
-    flatten_returning_row, has_returning_clause, perform_retries, perform_retries_with_fields,
-    pull_providers, run_callback_poll, run_ext_script, run_stackql_command,
-    run_stackql_dml_returning, run_stackql_query, show_query,
+    apply_session_settings, catch_error_and_exit, check_exports_as_statecheck_proxy,
+    check_short_circuit, export_vars, flatten_returning_row, glob_match, has_returning_clause,
+    perform_retries, perform_retries_with_fields, pull_providers, require_min_stackql_version,
+    run_callback_poll, run_ext_script, run_stackql_command, run_stackql_dml_returning,
+    run_stackql_query, show_query, sorted_keys, strip_returning_clause, Backoff,
 };
 use crate::resource::manifest::{Manifest, Resource};
 use crate::resource::validation::validate_manifest;
 use crate::template::engine::TemplateEngine;
-use crate::utils::display::{print_unicode_box, BorderColor};
+use crate::utils::display::{json_output, print_unicode_box, BorderColor};
 use crate::utils::pgwire::PgwireLite;
 
+/// Value written to the stack outputs in place of a masked protected
+/// export; fixed, so the outputs do not reveal the length of the secret.
+const MASKED_EXPORT_VALUE: &str = "********";
+
+/// Sourceable shell file the stack exports are written to, in the current
+/// working directory.
+pub const EXPORTS_FILE_NAME: &str = ".stackql-deploy-exports";
+
+/// Row limit, row cap and query timeout of the client, as replaced by
+/// `CommandRunner::apply_anchor_limits`.
+type ClientLimits = (Option<usize>, Option<usize>, Option<Duration>);
+
 /// Core state for all command operations, equivalent to Python's StackQLBase.
+///
+/// Resources are processed strictly one at a time, in `dependency_order`
+/// (manifest order wherever `depends_on` allows it), so exports, logs and
+/// reports come out in the same order on every run.  Only `build --parallel`
+/// runs resources concurrently, each on a `fork` of the runner.
 pub struct CommandRunner {
     pub client: PgwireLite,
     pub engine: TemplateEngine,
     pub manifest: Manifest,
     pub global_context: HashMap<String, String>,
+    /// Keys of `global_context` holding protected exports, kept out of the
+    /// stack outputs unless masked or `--include-secrets` is set.
+    pub protected_names: HashSet<String>,
     pub stack_dir: String,
     pub stack_env: String,
     pub stack_name: String,
@@ -41,6 +76,49 @@ pub struct CommandRunner {
     /// Per-resource idempotency tokens (UUID v4), stable for the lifetime of
     /// a single session (invocation).  Keyed by resource name.
     pub idempotency_tokens: HashMap<String, String>,
+    /// Resources whose failure was tolerated (e.g. `--on-failure ignore`)
+    /// rather than aborting the run.
+    pub failed_resources: Vec<String>,
+    /// Per-resource outcomes of the current run.
+    pub report: BuildReport,
+    /// Results of read-only (exports) queries keyed by rendered query text.
+    /// `None` unless caching was enabled with `--cache-queries`.
+    pub query_cache: Option<HashMap<String, Vec<HashMap<String, String>>>>,
+    /// `(resource, anchor)` pairs whose anchor sets `first_row_only=true`,
+    /// recorded as each resource's queries are loaded.
+    first_row_only: HashSet<(String, String)>,
+    /// `max_rows` overrides of `(resource, anchor)` pairs, in place of the
+    /// client's `--max-rows` cap while that anchor's query runs.
+    anchor_max_rows: HashMap<(String, String), usize>,
+    /// `timeout` of `(resource, anchor)` pairs, applied to each attempt of
+    /// that anchor's query.
+    anchor_timeouts: HashMap<(String, String), Duration>,
+    /// `backoff` of `(resource, anchor)` pairs which set one, applied to the
+    /// retries of that anchor's query.
+    anchor_backoffs: HashMap<(String, String), Backoff>,
+    /// Resources with a `createorupdate` query, which then runs in place of
+    /// both `create` and `update`.
+    upsert_resources: HashSet<String>,
+    /// Destination for rendered queries, set by `--dump-sql-to-dir`; shared
+    /// with the forks building resources concurrently.
+    sql_dump: Option<Arc<Mutex<SqlDump>>>,
+    /// Write protected stack exports masked instead of leaving them out
+    /// (`--mask-protected-exports`).
+    pub mask_protected_exports: bool,
+    /// Write protected stack exports in clear (`--include-secrets`).
+    pub include_secrets: bool,
+    /// Remove SQL comments from query templates before rendering
+    /// (`--strip-comments`).
+    pub strip_comments: bool,
+    /// Print how each resource's live state differs from its properties
+    /// during a dry run (`build --diff`).
+    pub show_diff: bool,
+    /// Format of the `--output-file` outputs (`--export-format`); taken from
+    /// the file extension when not set.
+    pub export_format: Option<ExportFormat>,
+    /// Path of the sourceable exports file, `EXPORTS_FILE_NAME` unless
+    /// changed.
+    pub exports_file: String,
 }
 
 impl CommandRunner {
@@ -50,15 +128,17 @@ impl CommandRunner {
         stack_dir: &str,
         stack_env: &str,
         env_file: &str,
+        var_files: &[String],
         env_overrides: &[String],
+        process_env: ProcessEnv,
     ) -> Self {
         let engine = TemplateEngine::new();
 
-        // Load env vars
-        let env_vars = load_env_vars(env_file, env_overrides);
+        // Accept either the stack directory or a path to its manifest file
+        let stack_dir = Manifest::resolve_stack_dir_or_exit(stack_dir);
 
         // Load manifest
-        let manifest = Manifest::load_from_dir_or_exit(stack_dir);
+        let mut manifest = Manifest::load_from_dir_or_exit(&stack_dir);
 
         // Validate manifest rules
         if let Err(errors) = validate_manifest(&manifest) {
@@ -71,7 +151,23 @@ impl CommandRunner {
             ));
         }
 
+        if let Some(minimum) = &manifest.min_stackql_version {
+            require_min_stackql_version(minimum, "the manifest min_stackql_version");
+        }
+
+        // Dependencies first; teardown walks this order in reverse
+        manifest.resources = manifest.dependency_order();
+
         let stack_name = manifest.name.clone();
+        set_abort_on_patterns(manifest.abort_on.clone());
+
+        // Load env vars; the file paths may reference the stack environment
+        let env_file = render_env_file_path(&engine, env_file, stack_env, &stack_name);
+        let var_files: Vec<String> = var_files
+            .iter()
+            .map(|path| render_env_file_path(&engine, path, stack_env, &stack_name))
+            .collect();
+        let env_vars = load_env_vars(&env_file, &var_files, env_overrides, process_env);
 
         // Render globals
         let global_context = render_globals(&engine, &env_vars, &manifest, stack_env, &stack_name);
@@ -83,25 +179,317 @@ impl CommandRunner {
         let idempotency_tokens: HashMap<String, String> = manifest
             .resources
             .iter()
-            .map(|r| (r.name.clone(), uuid::Uuid::new_v4().to_string()))
+            .map(|r| (r.name.clone(), uuid_v4().to_string()))
             .collect();
 
-        // Pull providers
-        pull_providers(&manifest.providers, &mut client);
+        // Session settings apply to everything run on this connection
+        apply_session_settings(&manifest.session_settings(), &mut client);
+
+        // Pull providers (entries may reference globals)
+        let providers = render_providers(&engine, &manifest.providers, &global_context);
+        pull_providers(&providers, &mut client);
 
         Self {
             client,
             engine,
             manifest,
             global_context,
-            stack_dir: stack_dir.to_string(),
+            protected_names: HashSet::new(),
+            stack_dir,
             stack_env: stack_env.to_string(),
             stack_name,
             env_vars,
             idempotency_tokens,
+            failed_resources: Vec::new(),
+            report: BuildReport::default(),
+            query_cache: None,
+            first_row_only: HashSet::new(),
+            anchor_max_rows: HashMap::new(),
+            anchor_timeouts: HashMap::new(),
+            anchor_backoffs: HashMap::new(),
+            upsert_resources: HashSet::new(),
+            sql_dump: None,
+            mask_protected_exports: false,
+            include_secrets: false,
+            strip_comments: false,
+            show_diff: false,
+            export_format: None,
+            exports_file: EXPORTS_FILE_NAME.to_string(),
+        }
+    }
+
+    /// A runner for building resources concurrently with this one, on its
+    /// own `client`.  It starts from a copy of this runner's context with an
+    /// empty report; `join` merges it back once its resources are built.
+    pub fn fork(&self, mut client: PgwireLite) -> Self {
+        apply_session_settings(&self.manifest.session_settings(), &mut client);
+        Self {
+            client,
+            engine: TemplateEngine::new(),
+            manifest: self.manifest.clone(),
+            global_context: self.global_context.clone(),
+            protected_names: self.protected_names.clone(),
+            stack_dir: self.stack_dir.clone(),
+            stack_env: self.stack_env.clone(),
+            stack_name: self.stack_name.clone(),
+            env_vars: self.env_vars.clone(),
+            idempotency_tokens: self.idempotency_tokens.clone(),
+            failed_resources: Vec::new(),
+            report: BuildReport::default(),
+            query_cache: self.query_cache.clone(),
+            first_row_only: HashSet::new(),
+            anchor_max_rows: HashMap::new(),
+            anchor_timeouts: HashMap::new(),
+            anchor_backoffs: HashMap::new(),
+            upsert_resources: HashSet::new(),
+            sql_dump: self.sql_dump.clone(),
+            mask_protected_exports: self.mask_protected_exports,
+            include_secrets: self.include_secrets,
+            strip_comments: self.strip_comments,
+            show_diff: self.show_diff,
+            export_format: self.export_format,
+            exports_file: self.exports_file.clone(),
+        }
+    }
+
+    /// Merge a finished `fork` back: the context entries it added or changed
+    /// relative to `base_context` (the context it was forked with), its
+    /// failures, report entries and cached query results.
+    pub fn join(&mut self, fork: CommandRunner, base_context: &HashMap<String, String>) {
+        for (key, value) in fork.global_context {
+            if base_context.get(&key) != Some(&value) {
+                if fork.protected_names.contains(&key) {
+                    self.protected_names.insert(key.clone());
+                } else {
+                    self.protected_names.remove(&key);
+                }
+                self.global_context.insert(key, value);
+            }
+        }
+        self.failed_resources.extend(fork.failed_resources);
+        self.report.merge(fork.report);
+        if let (Some(cache), Some(fork_cache)) = (self.query_cache.as_mut(), fork.query_cache) {
+            cache.extend(fork_cache);
         }
     }
 
+    /// Exit with an error when a resource filter (`filter`) removed all
+    /// `defined` resources of the manifest (`--strict-manifest`).  An empty
+    /// manifest is caught by `check_manifest_defines_resources` before
+    /// connecting.
+    pub fn check_resources_to_process(&self, defined: usize, filter: Option<&str>) {
+        if !self.manifest.resources.is_empty() {
+            return;
+        }
+        catch_error_and_exit(&format!(
+            "strict manifest: {} filter removed all {} resource(s) in stack [{}]",
+            filter.unwrap_or("resource"),
+            defined,
+            self.stack_name
+        ));
+    }
+
+    /// Restrict the manifest to resources whose names match one of `patterns`
+    /// (`--resources`). Each pattern is an exact name or a glob; a pattern
+    /// that matches nothing is treated as a typo and aborts the run.
+    pub fn select_resources(&mut self, patterns: &[String]) {
+        if patterns.is_empty() {
+            return;
+        }
+        for pattern in patterns {
+            if !self
+                .manifest
+                .resources
+                .iter()
+                .any(|r| glob_match(pattern, &r.name))
+            {
+                catch_error_and_exit(&format!(
+                    "--resources entry [{}] does not match any resource in stack [{}]",
+                    pattern, self.stack_name
+                ));
+            }
+        }
+        self.manifest
+            .resources
+            .retain(|r| patterns.iter().any(|p| glob_match(p, &r.name)));
+        info!(
+            "selected {} resource(s) with --resources: {}",
+            self.manifest.resources.len(),
+            self.manifest
+                .resources
+                .iter()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    /// Restrict the manifest to the resources named by `--target` and, with
+    /// `with_dependencies` (for `build`), everything they depend on.  An
+    /// unknown name aborts the run, listing the valid names.
+    pub fn select_targets(&mut self, targets: &[String], with_dependencies: bool) {
+        if targets.is_empty() {
+            return;
+        }
+        for target in targets {
+            if self.manifest.find_resource(target).is_none() {
+                let valid: Vec<&str> = self
+                    .manifest
+                    .resources
+                    .iter()
+                    .map(|r| r.name.as_str())
+                    .collect();
+                catch_error_and_exit(&format!(
+                    "--target [{}] is not a resource in stack [{}]; valid targets are: {}",
+                    target,
+                    self.stack_name,
+                    valid.join(", ")
+                ));
+            }
+        }
+        let selected: HashSet<String> = if with_dependencies {
+            self.manifest.with_dependencies(targets)
+        } else {
+            targets.iter().cloned().collect()
+        };
+        self.manifest
+            .resources
+            .retain(|r| selected.contains(&r.name));
+        info!(
+            "targeting {} resource(s): {}",
+            self.manifest.resources.len(),
+            self.manifest
+                .resources
+                .iter()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    /// Resolve the effective failure action: an explicit `--on-failure` wins,
+    /// otherwise the manifest's `on_failure_by_env` entry for this
+    /// environment, otherwise the flag's default.
+    pub fn resolve_on_failure(&self, cli_value: FailureAction, explicit: bool) -> FailureAction {
+        if explicit {
+            return cli_value;
+        }
+        match self.manifest.on_failure_by_env.get(&self.stack_env) {
+            Some(action) => {
+                // Values are checked by manifest validation
+                let resolved = FailureAction::from_str(action).unwrap_or(cli_value);
+                info!(
+                    "using on-failure [{:?}] for environment [{}] from manifest",
+                    resolved, self.stack_env
+                );
+                resolved
+            }
+            None => cli_value,
+        }
+    }
+
+    /// Enable the per-run cache for read-only queries.
+    pub fn enable_query_cache(&mut self) {
+        self.query_cache = Some(HashMap::new());
+    }
+
+    /// Write every query rendered from now on under `dir`.
+    pub fn enable_sql_dump(&mut self, dir: &str) {
+        let order = self
+            .manifest
+            .resources
+            .iter()
+            .map(|r| r.name.clone())
+            .collect();
+        match SqlDump::new(dir, &self.stack_name, &self.stack_env, order) {
+            Ok(dump) => {
+                info!("writing rendered queries to {:?}", dump.dir());
+                self.sql_dump = Some(Arc::new(Mutex::new(dump)));
+            }
+            Err(e) => catch_error_and_exit(&format!(
+                "failed to prepare SQL dump directory {}: {}",
+                dir, e
+            )),
+        }
+    }
+
+    /// Record a rendered query in the SQL dump, if enabled.
+    fn dump_sql(&self, resource_name: &str, anchor: &str, sql: &str) {
+        if let Some(dump) = &self.sql_dump {
+            dump.lock().unwrap().write(resource_name, anchor, sql);
+        }
+    }
+
+    /// Run a read-only query, reusing an earlier result for identical query
+    /// text when the query cache is enabled.  Only non-empty, error-free
+    /// results are cached; reads are assumed stable for the run.
+    pub fn run_read_query(
+        &mut self,
+        query: &str,
+        retries: u32,
+        retry_delay: u32,
+        backoff: Backoff,
+    ) -> Vec<HashMap<String, String>> {
+        if let Some(cached) = self.query_cache.as_ref().and_then(|c| c.get(query)) {
+            debug!("using cached result for query:\n\n{}\n", query);
+            return cached.clone();
+        }
+
+        let result =
+            run_stackql_query(query, &mut self.client, true, retries, retry_delay, backoff);
+
+        if let Some(cache) = self.query_cache.as_mut() {
+            let is_error = result.first().is_some_and(|row| {
+                row.contains_key("_stackql_deploy_error") || row.contains_key("error")
+            });
+            if !result.is_empty() && !is_error {
+                cache.insert(query.to_string(), result.clone());
+            }
+        }
+
+        result
+    }
+
+    /// With `--output json`, keep the run's report where the failure path
+    /// can print it, so a run which fails part way still reports what it
+    /// did, along with the error.
+    pub fn track_report(&mut self, command: &str, dry_run: bool) {
+        if json_output() {
+            report::track_run(command, &self.stack_name, &self.stack_env, dry_run);
+            self.report.track();
+        }
+    }
+
+    /// Print the run's report as a single JSON document on stdout
+    /// (`--output json`).
+    pub fn print_json_report(&self, command: &str, dry_run: bool) {
+        let output = RunOutput {
+            command,
+            stack_name: &self.stack_name,
+            stack_env: &self.stack_env,
+            dry_run,
+            report: &self.report,
+            error: None,
+        };
+        report::end_run();
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => catch_error_and_exit(&format!("Failed to serialize the run report: {}", e)),
+        }
+    }
+
+    /// Record a resource failure that was tolerated instead of aborting.
+    pub fn record_failure(&mut self, resource_name: &str, resource_type: &str, reason: &str) {
+        warn!("[{}] failed (continuing): {}", resource_name, reason);
+        self.failed_resources.push(resource_name.to_string());
+        self.report.record(
+            resource_name,
+            resource_type,
+            ResourceOutcome::Failed,
+            Some(reason.to_string()),
+        );
+    }
+
     /// Get the full context for a resource (global + resource properties).
     pub fn get_full_context(&self, resource: &Resource) -> HashMap<String, String> {
         let token = self
@@ -113,6 +501,7 @@ impl CommandRunner {
             &self.global_context,
             resource,
             &self.stack_env,
+            &self.manifest.env_aliases,
             token,
         )
     }
@@ -153,11 +542,107 @@ impl CommandRunner {
 
     /// Get queries for a resource from its .iql file.
     pub fn get_queries(
-        &self,
+        &mut self,
         resource: &Resource,
         full_context: &HashMap<String, String>,
     ) -> HashMap<String, ParsedQuery> {
-        templating::get_queries(&self.engine, &self.stack_dir, resource, full_context)
+        let queries =
+            templating::get_queries(&self.engine, &self.stack_dir, resource, full_context);
+        if queries.contains_key("createorupdate") {
+            self.upsert_resources.insert(resource.name.clone());
+        } else {
+            self.upsert_resources.remove(&resource.name);
+        }
+        for (anchor, query) in &queries {
+            let key = (resource.name.clone(), anchor.clone());
+            match query.options.max_rows {
+                Some(max_rows) => self.anchor_max_rows.insert(key.clone(), max_rows as usize),
+                None => self.anchor_max_rows.remove(&key),
+            };
+            match query.options.timeout {
+                Some(timeout) => self
+                    .anchor_timeouts
+                    .insert(key.clone(), Duration::from_secs(timeout as u64)),
+                None => self.anchor_timeouts.remove(&key),
+            };
+            match query.options.backoff {
+                Backoff::Constant => self.anchor_backoffs.remove(&key),
+                backoff => self.anchor_backoffs.insert(key.clone(), backoff),
+            };
+            if query.options.first_row_only {
+                self.first_row_only.insert(key);
+            } else {
+                self.first_row_only.remove(&key);
+            }
+        }
+        queries
+    }
+
+    /// Limit the client to the first result row when `anchor` of `resource`
+    /// sets `first_row_only`, and apply its `max_rows` cap, `timeout` and
+    /// `backoff` if it sets them.  Returns the previous limits, to be restored with
+    /// `restore_anchor_limits` once the query has run.
+    fn apply_anchor_limits(&mut self, resource: &Resource, anchor: &str) -> ClientLimits {
+        let key = (resource.name.clone(), anchor.to_string());
+        let limit = self.first_row_only.contains(&key).then_some(1);
+        let previous_limit = self.client.set_row_limit(limit);
+        let previous_max_rows = self.client.max_rows();
+        if let Some(max_rows) = self.anchor_max_rows.get(&key) {
+            self.client.set_max_rows(Some(*max_rows));
+        }
+        let timeout = self.anchor_timeouts.get(&key).copied();
+        let previous_timeout = self.client.set_query_timeout(timeout);
+        (previous_limit, previous_max_rows, previous_timeout)
+    }
+
+    /// The `backoff` option of `anchor` of `resource`, constant unless the
+    /// anchor sets one.
+    pub fn anchor_backoff(&self, resource: &Resource, anchor: &str) -> Backoff {
+        self.anchor_backoffs
+            .get(&(resource.name.clone(), anchor.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Restore the limits replaced by `apply_anchor_limits`.
+    fn restore_anchor_limits(&mut self, previous: ClientLimits) {
+        self.client.set_row_limit(previous.0);
+        self.client.set_max_rows(previous.1);
+        self.client.set_query_timeout(previous.2);
+    }
+
+    /// The anchor whose limits apply to `create_resource` or
+    /// `update_resource` (`operation`): `createorupdate` when the resource
+    /// has one, as it then runs in place of both.
+    fn write_anchor<'a>(&self, resource: &Resource, operation: &'a str) -> &'a str {
+        if self.upsert_resources.contains(&resource.name) {
+            "createorupdate"
+        } else {
+            operation
+        }
+    }
+
+    /// Log the effective options of every anchor for a resource, so dry runs
+    /// show how anchor options such as `retries` were actually parsed.
+    pub fn report_query_options(
+        &self,
+        resource: &Resource,
+        queries: &HashMap<String, ParsedQuery>,
+    ) {
+        if queries.is_empty() {
+            return;
+        }
+        let mut anchors: Vec<&String> = queries.keys().collect();
+        anchors.sort();
+        let lines: Vec<String> = anchors
+            .iter()
+            .map(|anchor| format!("  {}: {}", anchor, queries[*anchor].options))
+            .collect();
+        info!(
+            "dry run anchor options for [{}]:\n{}",
+            resource.name,
+            lines.join("\n")
+        );
     }
 
     /// Render inline SQL template.
@@ -167,7 +652,10 @@ impl CommandRunner {
         sql: &str,
         full_context: &HashMap<String, String>,
     ) -> String {
-        templating::render_inline_template(&self.engine, resource_name, sql, full_context)
+        let rendered =
+            templating::render_inline_template(&self.engine, resource_name, sql, full_context);
+        self.dump_sql(resource_name, "sql", &rendered);
+        rendered
     }
 
     /// Render a single query template JIT with the current context.
@@ -178,7 +666,20 @@ impl CommandRunner {
         template: &str,
         full_context: &HashMap<String, String>,
     ) -> String {
-        templating::render_query(&self.engine, resource_name, anchor, template, full_context)
+        let template = self.query_template(template);
+        let rendered =
+            templating::render_query(&self.engine, resource_name, anchor, &template, full_context);
+        self.dump_sql(resource_name, anchor, &rendered);
+        rendered
+    }
+
+    /// The template to render, without its comments under `--strip-comments`.
+    fn query_template<'a>(&self, template: &'a str) -> Cow<'a, str> {
+        if self.strip_comments {
+            Cow::Owned(templating::strip_sql_comments(template))
+        } else {
+            Cow::Borrowed(template)
+        }
     }
 
     /// Try to render a query template, returning None if variables are missing.
@@ -190,7 +691,18 @@ impl CommandRunner {
         template: &str,
         full_context: &HashMap<String, String>,
     ) -> Option<String> {
-        templating::try_render_query(&self.engine, resource_name, anchor, template, full_context)
+        let template = self.query_template(template);
+        let rendered = templating::try_render_query(
+            &self.engine,
+            resource_name,
+            anchor,
+            &template,
+            full_context,
+        );
+        if let Some(ref sql) = rendered {
+            self.dump_sql(resource_name, anchor, sql);
+        }
+        rendered
     }
 
     /// Check if a resource exists using the exists query.
@@ -227,14 +739,18 @@ impl CommandRunner {
         info!("running {} check for [{}]...", check_type, resource.name);
         show_query(show_queries, exists_query);
 
+        let backoff = self.anchor_backoff(resource, "exists");
+        let previous_limits = self.apply_anchor_limits(resource, "exists");
         let (exists, fields) = perform_retries_with_fields(
             &resource.name,
             exists_query,
             retries,
             retry_delay,
+            backoff,
             &mut self.client,
             delete_test,
         );
+        self.restore_anchor_limits(previous_limits);
 
         if delete_test {
             if exists {
@@ -281,14 +797,18 @@ impl CommandRunner {
         info!("running state check for [{}]...", resource.name);
         show_query(show_queries, statecheck_query);
 
+        let backoff = self.anchor_backoff(resource, "statecheck");
+        let previous_limits = self.apply_anchor_limits(resource, "statecheck");
         let is_correct = perform_retries(
             &resource.name,
             statecheck_query,
             retries,
             retry_delay,
+            backoff,
             &mut self.client,
             false,
         );
+        self.restore_anchor_limits(previous_limits);
 
         if is_correct {
             info!("[{}] is in the desired state", resource.name);
@@ -299,6 +819,32 @@ impl CommandRunner {
         is_correct
     }
 
+    /// Run the read-only `anchor` query of a resource and return its first
+    /// row, for `build --dry-run --diff`, which reads the live state of a
+    /// resource without changing it.  `None` when the query returns no rows
+    /// or fails.
+    pub fn fetch_first_row(
+        &mut self,
+        resource: &Resource,
+        anchor: &str,
+        query: &str,
+        show_queries: bool,
+    ) -> Option<HashMap<String, String>> {
+        info!(
+            "fetching current state of [{}] with its {} query...",
+            resource.name, anchor
+        );
+        show_query(show_queries, query);
+
+        let previous_limits = self.apply_anchor_limits(resource, anchor);
+        let rows = self.run_read_query(query, 1, 0, Backoff::Constant);
+        self.restore_anchor_limits(previous_limits);
+
+        rows.into_iter()
+            .next()
+            .filter(|row| !row.contains_key("_stackql_deploy_error") && !row.contains_key("error"))
+    }
+
     /// Use exports query as a proxy for state check.
     pub fn check_state_using_exports_proxy(
         &mut self,
@@ -323,7 +869,17 @@ impl CommandRunner {
         );
         show_query(show_queries, exports_query);
 
-        let result = run_stackql_query(exports_query, &mut self.client, true, retries, retry_delay);
+        let backoff = self.anchor_backoff(resource, "exports");
+        let previous_limits = self.apply_anchor_limits(resource, "exports");
+        let result = run_stackql_query(
+            exports_query,
+            &mut self.client,
+            true,
+            retries,
+            retry_delay,
+            backoff,
+        );
+        self.restore_anchor_limits(previous_limits);
 
         let is_correct = check_exports_as_statecheck_proxy(&result);
 
@@ -376,13 +932,17 @@ impl CommandRunner {
         info!("creating [{}]...", resource.name);
         show_query(show_queries, create_query);
 
-        if has_returning_clause(create_query) {
+        let anchor = self.write_anchor(resource, "create");
+        let backoff = self.anchor_backoff(resource, anchor);
+        let previous_limits = self.apply_anchor_limits(resource, anchor);
+        let created = if has_returning_clause(create_query) {
             let (msg, returning_row) = run_stackql_dml_returning(
                 create_query,
                 &mut self.client,
                 ignore_errors,
                 retries,
                 retry_delay,
+                backoff,
             );
             if msg.is_empty() && returning_row.is_none() {
                 debug!("Create response: no response");
@@ -397,6 +957,7 @@ impl CommandRunner {
                 ignore_errors,
                 retries,
                 retry_delay,
+                backoff,
             );
             if msg.is_empty() {
                 debug!("Create response: no response");
@@ -404,7 +965,9 @@ impl CommandRunner {
                 debug!("Create response: {}", msg);
             }
             (true, None)
-        }
+        };
+        self.restore_anchor_limits(previous_limits);
+        created
     }
 
     /// Update a resource.
@@ -443,13 +1006,17 @@ impl CommandRunner {
                 info!("updating [{}]...", resource.name);
                 show_query(show_queries, query);
 
-                if has_returning_clause(query) {
+                let anchor = self.write_anchor(resource, "update");
+                let backoff = self.anchor_backoff(resource, anchor);
+                let previous_limits = self.apply_anchor_limits(resource, anchor);
+                let updated = if has_returning_clause(query) {
                     let (msg, returning_row) = run_stackql_dml_returning(
                         query,
                         &mut self.client,
                         ignore_errors,
                         retries,
                         retry_delay,
+                        backoff,
                     );
                     if msg.is_empty() && returning_row.is_none() {
                         debug!("Update response: no response");
@@ -464,6 +1031,7 @@ impl CommandRunner {
                         ignore_errors,
                         retries,
                         retry_delay,
+                        backoff,
                     );
                     if msg.is_empty() {
                         debug!("Update response: no response");
@@ -471,7 +1039,9 @@ impl CommandRunner {
                         debug!("Update response: {}", msg);
                     }
                     (true, None)
-                }
+                };
+                self.restore_anchor_limits(previous_limits);
+                updated
             }
             None => {
                 info!(
@@ -536,11 +1106,12 @@ impl CommandRunner {
             info!("deleting [{}]...", res_name);
             show_query(sq, query);
             if has_returning_clause(query) {
-                let (msg, row) = run_stackql_dml_returning(query, client, ignore, 0, 0);
+                let (msg, row) =
+                    run_stackql_dml_returning(query, client, ignore, 0, 0, Backoff::Constant);
                 debug!("Delete response: {}", msg);
                 row
             } else {
-                let msg = run_stackql_command(query, client, ignore, 0, 0);
+                let msg = run_stackql_command(query, client, ignore, 0, 0, Backoff::Constant);
                 debug!("Delete response: {}", msg);
                 None
             }
@@ -555,7 +1126,7 @@ impl CommandRunner {
          -> Result<i64, String> {
             info!("running post-delete check for [{}]...", res_name);
             show_query(sq, query);
-            let result = run_stackql_query(query, client, true, 0, 5);
+            let result = run_stackql_query(query, client, true, 0, 5, Backoff::Constant);
             if result.is_empty() {
                 return Ok(0); // no rows → resource gone
             }
@@ -580,6 +1151,7 @@ impl CommandRunner {
 
         // --- no-retry path: single delete + single check ---
         if delete_retries == 0 {
+            let previous_limits = self.apply_anchor_limits(resource, "delete");
             let row = execute_delete(
                 &mut self.client,
                 delete_query,
@@ -587,6 +1159,7 @@ impl CommandRunner {
                 show_queries,
                 ignore_errors,
             );
+            self.restore_anchor_limits(previous_limits);
             if returning_row.is_none() {
                 returning_row = row;
             }
@@ -620,6 +1193,7 @@ impl CommandRunner {
 
         for attempt in 0..delete_retries {
             // Step 1: execute DELETE
+            let previous_limits = self.apply_anchor_limits(resource, "delete");
             let row = execute_delete(
                 &mut self.client,
                 delete_query,
@@ -627,6 +1201,7 @@ impl CommandRunner {
                 show_queries,
                 ignore_errors,
             );
+            self.restore_anchor_limits(previous_limits);
             if returning_row.is_none() {
                 returning_row = row;
             }
@@ -747,6 +1322,7 @@ impl CommandRunner {
         callback_query: &str,
         retries: u32,
         retry_delay: u32,
+        backoff: Backoff,
         short_circuit_field: Option<&str>,
         short_circuit_value: Option<&str>,
         operation: &str,
@@ -781,6 +1357,7 @@ impl CommandRunner {
             callback_query,
             retries,
             retry_delay,
+            backoff,
             &mut self.client,
         );
 
@@ -860,6 +1437,7 @@ impl CommandRunner {
             true,
             pq.options.retries,
             pq.options.retry_delay,
+            pq.options.backoff,
         );
 
         if results.is_empty() {
@@ -884,6 +1462,7 @@ impl CommandRunner {
     /// Run a command-type query.
     pub fn run_command(
         &mut self,
+        resource: &Resource,
         command_query: &str,
         retries: u32,
         retry_delay: u32,
@@ -897,8 +1476,17 @@ impl CommandRunner {
 
         info!("running command...");
         show_query(show_queries, command_query);
-        let result =
-            run_stackql_command(command_query, &mut self.client, false, retries, retry_delay);
+        let backoff = self.anchor_backoff(resource, "command");
+        let previous_limits = self.apply_anchor_limits(resource, "command");
+        let result = run_stackql_command(
+            command_query,
+            &mut self.client,
+            false,
+            retries,
+            retry_delay,
+            backoff,
+        );
+        self.restore_anchor_limits(previous_limits);
         if result.is_empty() {
             debug!("Command response: no response");
         } else {
@@ -924,30 +1512,17 @@ impl CommandRunner {
             return;
         }
 
-        let all_dicts = expected_exports.iter().all(|e| e.is_mapping());
-        let protected_exports = &resource.protected;
+        let protected_exports = &resource.protected_export_names();
 
         if dry_run {
-            let mut export_data = HashMap::new();
-            if all_dicts {
-                for item in expected_exports {
-                    if let Some(map) = item.as_mapping() {
-                        for (_, val) in map {
-                            if let Some(v) = val.as_str() {
-                                export_data.insert(v.to_string(), "<evaluated>".to_string());
-                            }
-                        }
-                    }
-                }
-            } else {
-                for item in expected_exports {
-                    if let Some(s) = item.as_str() {
-                        export_data.insert(s.to_string(), "<evaluated>".to_string());
-                    }
-                }
-            }
+            let export_data: HashMap<String, String> = resource
+                .export_names()
+                .into_iter()
+                .map(|name| (name, "<evaluated>".to_string()))
+                .collect();
             export_vars(
                 &mut self.global_context,
+                &mut self.protected_names,
                 &resource.name,
                 &export_data,
                 protected_exports,
@@ -962,8 +1537,10 @@ impl CommandRunner {
         info!("exporting variables for [{}]...", resource.name);
         show_query(show_queries, exports_query);
 
-        let exports =
-            run_stackql_query(exports_query, &mut self.client, true, retries, retry_delay);
+        let backoff = self.anchor_backoff(resource, "exports");
+        let previous_limits = self.apply_anchor_limits(resource, "exports");
+        let exports = self.run_read_query(exports_query, retries, retry_delay, backoff);
+        self.restore_anchor_limits(previous_limits);
 
         debug!("Exports result: {:?}", exports);
 
@@ -986,6 +1563,7 @@ impl CommandRunner {
                 }
                 export_vars(
                     &mut self.global_context,
+                    &mut self.protected_names,
                     &resource.name,
                     &fallback,
                     protected_exports,
@@ -1023,13 +1601,7 @@ impl CommandRunner {
             ));
         }
 
-        self.process_export_data(
-            resource,
-            &exports,
-            expected_exports,
-            all_dicts,
-            protected_exports,
-        );
+        self.process_export_data(resource, &exports, protected_exports);
     }
 
     /// Process exports from an already-obtained result (e.g., from exports proxy).
@@ -1043,8 +1615,7 @@ impl CommandRunner {
             return;
         }
 
-        let all_dicts = expected_exports.iter().all(|e| e.is_mapping());
-        let protected_exports = &resource.protected;
+        let protected_exports = &resource.protected_export_names();
 
         if exports_result.len() > 1 {
             catch_error_and_exit(&format!(
@@ -1053,13 +1624,7 @@ impl CommandRunner {
             ));
         }
 
-        self.process_export_data(
-            resource,
-            exports_result,
-            expected_exports,
-            all_dicts,
-            protected_exports,
-        );
+        self.process_export_data(resource, exports_result, protected_exports);
     }
 
     /// Internal helper to extract export data from query results.
@@ -1067,8 +1632,6 @@ impl CommandRunner {
         &mut self,
         resource: &Resource,
         exports: &[HashMap<String, String>],
-        expected_exports: &[serde_yaml::Value],
-        all_dicts: bool,
         protected_exports: &[String],
     ) {
         let export_row = if exports.is_empty() {
@@ -1077,31 +1640,19 @@ impl CommandRunner {
             exports[0].clone()
         };
 
-        let mut export_data = HashMap::new();
-
-        for item in expected_exports {
-            if all_dicts {
-                if let Some(map) = item.as_mapping() {
-                    for (key_val, val_val) in map {
-                        let key = key_val.as_str().unwrap_or("");
-                        let val = val_val.as_str().unwrap_or("");
-                        // key in expected_exports maps to key in export_row
-                        // val becomes the key in export_data
-                        let exported_value = export_row.get(key).cloned().unwrap_or_default();
-                        export_data.insert(val.to_string(), exported_value);
-                    }
-                }
-            } else {
-                let item_name = item.as_str().unwrap_or("");
-                if !item_name.is_empty() {
-                    let exported_value = export_row.get(item_name).cloned().unwrap_or_default();
-                    export_data.insert(item_name.to_string(), exported_value);
-                }
-            }
-        }
+        // The source column in the export row becomes the exported name
+        let export_data: HashMap<String, String> = resource
+            .export_mappings()
+            .into_iter()
+            .map(|(source, target)| {
+                let exported_value = export_row.get(&source).cloned().unwrap_or_default();
+                (target, exported_value)
+            })
+            .collect();
 
         export_vars(
             &mut self.global_context,
+            &mut self.protected_names,
             &resource.name,
             &export_data,
             protected_exports,
@@ -1147,42 +1698,324 @@ impl CommandRunner {
                 Some(export_names.as_slice())
             };
 
-            if let Some(ret_vars) = run_ext_script(&script, export_names_opt) {
+            // The resource's `env` variables are also passed to the script
+            let script_env: HashMap<String, String> = resource
+                .env
+                .keys()
+                .filter_map(|k| full_context.get(k).map(|v| (k.clone(), v.clone())))
+                .collect();
+
+            if let Some(ret_vars) = run_ext_script(
+                &script,
+                resource.shell.as_deref(),
+                resource.script_timeout.map(Duration::from_secs),
+                export_names_opt,
+                &script_env,
+            ) {
                 if !resource.exports.is_empty() {
-                    info!("Exported variables from script: {:?}", ret_vars);
+                    // Values are logged by export_vars, masking protected
+                    // exports and any with secret-looking names
+                    info!(
+                        "Exported variables from script: {:?}",
+                        sorted_keys(&ret_vars)
+                    );
+                    let mut masked = resource.protected_export_names();
+                    masked.extend(ret_vars.keys().filter(|k| is_sensitive_name(k)).cloned());
                     export_vars(
                         &mut self.global_context,
+                        &mut self.protected_names,
                         &resource.name,
                         &ret_vars,
-                        &resource.protected,
+                        &masked,
                     );
                 }
             }
         }
     }
 
-    /// Process stack-level exports to a JSON output file.
-    pub fn process_stack_exports(
-        &self,
+    /// Process a `multi` resource with `for_each`: its exists, statecheck,
+    /// create and update queries run once per item of the `for_each`
+    /// property, rendered with the item bound as `item` (see
+    /// [`multi_item_contexts`]), and its exports query runs once afterwards.
+    /// A dry run logs the rendered queries of every item.  As for any
+    /// `multi` resource, query errors are ignored.
+    ///
+    /// Returns the outcome of the resource as a whole.
+    pub fn process_multi_resource(
+        &mut self,
+        resource: &Resource,
+        for_each: &str,
         dry_run: bool,
-        output_file: Option<&str>,
-        elapsed_time: &str,
-    ) {
-        let manifest_exports = &self.manifest.exports;
+        show_queries: bool,
+        full_context: &HashMap<String, String>,
+    ) -> ResourceOutcome {
+        let iterations = multi_item_contexts(&resource.name, for_each, full_context)
+            .unwrap_or_else(|e| catch_error_and_exit(&e));
+        info!(
+            "[{}] runs once for each of the {} item(s) in [{}]",
+            resource.name,
+            iterations.len(),
+            for_each
+        );
 
-        if manifest_exports.is_empty() {
-            return;
-        }
+        let (mut created, mut updated, mut applied) = (0, 0, 0);
+        for (index, context) in iterations.into_iter().enumerate() {
+            let mut context = context;
+            info!("[{}] item {}", resource.name, index);
+            let queries = self.get_queries(resource, &context);
+            let anchor = |name: &str| {
+                queries
+                    .get(name)
+                    .map(|q| (q.template.clone(), q.options.retries, q.options.retry_delay))
+            };
 
-        if dry_run {
-            let total_vars = manifest_exports.len() + 3;
-            info!(
-                "dry run: would export {} variables (including automatic stack_name, stack_env, and elapsed_time)",
-                total_vars
+            // With nothing to check against, createorupdate always runs
+            if createorupdate_unchecked(&queries) {
+                let (template, retries, retry_delay) = anchor("createorupdate").unwrap();
+                let query =
+                    self.render_query(&resource.name, "createorupdate", &template, &context);
+                self.create_resource(
+                    resource,
+                    &query,
+                    retries,
+                    retry_delay,
+                    dry_run,
+                    show_queries,
+                    true,
+                );
+                applied += 1;
+                continue;
+            }
+
+            let (exists, in_desired_state) =
+                self.check_multi_item(resource, &queries, &mut context, dry_run, show_queries);
+
+            if !exists {
+                let Some((create_anchor, (template, retries, retry_delay))) =
+                    ["create", "createorupdate"]
+                        .into_iter()
+                        .find_map(|name| anchor(name).map(|a| (name, a)))
+                else {
+                    catch_error_and_exit(
+                        "iql file must include either 'create' or 'createorupdate' anchor.",
+                    );
+                };
+                let query = self.render_query(&resource.name, create_anchor, &template, &context);
+                let (was_created, _) = self.create_resource(
+                    resource,
+                    &query,
+                    retries,
+                    retry_delay,
+                    dry_run,
+                    show_queries,
+                    true,
+                );
+                if was_created {
+                    self.record_item_for_rollback(resource, index, &queries, &context);
+                }
+                created += 1;
+                continue;
+            }
+
+            if in_desired_state {
+                continue;
+            }
+            let update_query = ["update", "createorupdate"]
+                .into_iter()
+                .find_map(|name| anchor(name).map(|a| (name, a)))
+                .map(|(name, (template, retries, retry_delay))| {
+                    let query = self.render_query(&resource.name, name, &template, &context);
+                    (query, retries, retry_delay)
+                });
+            let (retries, retry_delay) = update_query.as_ref().map_or((1, 0), |q| (q.1, q.2));
+            let (was_updated, _) = self.update_resource(
+                resource,
+                update_query.as_ref().map(|q| q.0.as_str()),
+                retries,
+                retry_delay,
+                dry_run,
+                show_queries,
+                true,
             );
+            if was_updated || (dry_run && update_query.is_some()) {
+                updated += 1;
+            }
+        }
+
+        self.process_multi_exports(resource, full_context, dry_run, show_queries);
+
+        if created > 0 {
+            ResourceOutcome::Created
+        } else if updated > 0 {
+            ResourceOutcome::Updated
+        } else if applied > 0 {
+            ResourceOutcome::Applied
+        } else {
+            ResourceOutcome::Unchanged
+        }
+    }
+
+    /// Whether an item of a `multi` resource exists, and whether it is in the
+    /// desired state, checked with its `exists` and `statecheck` queries
+    /// rendered in the item's `context`.  Fields captured by `exists` are
+    /// added to the context.  Without an `exists` query the statecheck
+    /// decides both; without a statecheck an existing item is never in the
+    /// desired state, so it is always updated.
+    fn check_multi_item(
+        &mut self,
+        resource: &Resource,
+        queries: &HashMap<String, ParsedQuery>,
+        context: &mut HashMap<String, String>,
+        dry_run: bool,
+        show_queries: bool,
+    ) -> (bool, bool) {
+        let exists = match queries.get("exists") {
+            Some(q) => {
+                let query = self.render_query(&resource.name, "exists", &q.template, context);
+                let (exists, fields) = self.check_if_resource_exists(
+                    resource,
+                    &query,
+                    1,
+                    0,
+                    dry_run,
+                    show_queries,
+                    false,
+                );
+                for (k, v) in fields.unwrap_or_default() {
+                    context.insert(format!("{}.{}", resource.name, k), v);
+                }
+                Some(exists)
+            }
+            None => None,
+        };
+        if exists == Some(false) {
+            return (false, false);
+        }
+        let in_desired_state = match queries.get("statecheck") {
+            Some(q) => {
+                let query = self.render_query(&resource.name, "statecheck", &q.template, context);
+                self.check_if_resource_is_correct_state(
+                    resource,
+                    &query,
+                    1,
+                    0,
+                    dry_run,
+                    show_queries,
+                )
+            }
+            None => false,
+        };
+        (exists.unwrap_or(in_desired_state), in_desired_state)
+    }
+
+    /// Record an item of a `multi` resource created by this run for
+    /// rollback, as `<resource>[<index>]`, with its delete query rendered
+    /// in the item's context.
+    fn record_item_for_rollback(
+        &self,
+        resource: &Resource,
+        index: usize,
+        queries: &HashMap<String, ParsedQuery>,
+        context: &HashMap<String, String>,
+    ) {
+        if !rollback::is_armed() {
             return;
         }
+        let delete_query = queries
+            .get("delete")
+            .and_then(|dq| self.try_render_query(&resource.name, "delete", &dq.template, context))
+            .map(|rendered| {
+                if has_returning_clause(&rendered) {
+                    strip_returning_clause(&rendered)
+                } else {
+                    rendered
+                }
+            });
+        if delete_query.is_none() {
+            warn!(
+                "[{}] item {} cannot be rolled back: its delete query could not be rendered",
+                resource.name, index
+            );
+        }
+        rollback::record_created(
+            &format!("{}[{}]", resource.name, index),
+            delete_query.as_deref(),
+        );
+    }
 
+    /// Run the exports query of a `multi` resource with `for_each`, once for
+    /// the resource: exports are resource level, so rendered without an item.
+    pub fn process_multi_exports(
+        &mut self,
+        resource: &Resource,
+        full_context: &HashMap<String, String>,
+        dry_run: bool,
+        show_queries: bool,
+    ) {
+        let queries = self.get_queries(resource, full_context);
+        if let Some(q) = queries.get("exports") {
+            match self.try_render_query(&resource.name, "exports", &q.template, full_context) {
+                Some(exports_query) => self.process_exports(
+                    resource,
+                    full_context,
+                    &exports_query,
+                    q.options.retries,
+                    q.options.retry_delay,
+                    dry_run,
+                    show_queries,
+                    false,
+                ),
+                None => warn!(
+                    "exports query of [{}] cannot reference the for_each item, skipping exports",
+                    resource.name
+                ),
+            }
+        }
+    }
+
+    /// Test a `multi` resource with `for_each`: check each item with its
+    /// `exists` and `statecheck` queries, as build does, returning the
+    /// positions of the items which are missing or not in the desired state.
+    pub fn test_multi_items(
+        &mut self,
+        resource: &Resource,
+        for_each: &str,
+        full_context: &HashMap<String, String>,
+        dry_run: bool,
+        show_queries: bool,
+    ) -> Vec<usize> {
+        let iterations = multi_item_contexts(&resource.name, for_each, full_context)
+            .unwrap_or_else(|e| catch_error_and_exit(&e));
+        let mut failed = Vec::new();
+        for (index, mut context) in iterations.into_iter().enumerate() {
+            info!("[{}] item {}", resource.name, index);
+            let queries = self.get_queries(resource, &context);
+            if !queries.contains_key("exists") && !queries.contains_key("statecheck") {
+                catch_error_and_exit(&format!(
+                    "iql file of [{}] must include either 'exists' or 'statecheck' anchor to test its for_each items.",
+                    resource.name
+                ));
+            }
+            let (exists, in_desired_state) =
+                self.check_multi_item(resource, &queries, &mut context, dry_run, show_queries);
+            // Without a statecheck, an item passes by existing
+            let passed = if queries.contains_key("statecheck") {
+                in_desired_state
+            } else {
+                exists
+            };
+            if !passed {
+                failed.push(index);
+            }
+        }
+        failed
+    }
+
+    /// The stack outputs: the manifest `exports` from the context, named
+    /// with the export prefix and suffix, with the stack metadata and
+    /// `elapsed_time`.  Exits if an export is not in the context.
+    fn stack_export_data(&self, elapsed_time: &str) -> serde_json::Map<String, serde_json::Value> {
+        let manifest_exports = &self.manifest.exports;
         let mut export_data = serde_json::Map::new();
         let mut missing_vars = Vec::new();
 
@@ -1196,19 +2029,41 @@ impl CommandRunner {
             serde_json::Value::String(self.stack_env.clone()),
         );
 
+        // Namespace export names, e.g. per preview deployment
+        let render_affix = |affix: &Option<String>| {
+            affix.as_deref().map_or(String::new(), |a| {
+                render_string_value(&self.engine, a, &self.global_context)
+            })
+        };
+        let export_prefix = render_affix(&self.manifest.export_prefix);
+        let export_suffix = render_affix(&self.manifest.export_suffix);
+
+        // Values protected by a resource only reach the outputs in clear
+        // with --include-secrets
+        let protected = &self.protected_names;
+        let mut withheld = Vec::new();
+
         for var_name in manifest_exports {
             if var_name == "stack_name" || var_name == "stack_env" {
                 continue;
             }
 
             if let Some(value) = self.global_context.get(var_name) {
+                let export_name = format!("{}{}{}", export_prefix, var_name, export_suffix);
+                if protected.contains(var_name) && !self.include_secrets {
+                    withheld.push(var_name.as_str());
+                    if self.mask_protected_exports {
+                        export_data.insert(export_name, MASKED_EXPORT_VALUE.into());
+                    }
+                    continue;
+                }
                 if value.starts_with('[') || value.starts_with('{') {
                     if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(value) {
-                        export_data.insert(var_name.clone(), parsed);
+                        export_data.insert(export_name, parsed);
                         continue;
                     }
                 }
-                export_data.insert(var_name.clone(), serde_json::Value::String(value.clone()));
+                export_data.insert(export_name, serde_json::Value::String(value.clone()));
             } else {
                 missing_vars.push(var_name.clone());
             }
@@ -1221,11 +2076,63 @@ impl CommandRunner {
             ));
         }
 
+        if self.include_secrets {
+            let included: Vec<&str> = manifest_exports
+                .iter()
+                .filter(|name| protected.contains(*name))
+                .map(String::as_str)
+                .collect();
+            if !included.is_empty() {
+                warn!(
+                    "stack exports [{}] are protected and written in clear to the outputs (--include-secrets)",
+                    included.join(", ")
+                );
+            }
+        }
+
+        if !withheld.is_empty() {
+            warn!(
+                "stack exports [{}] are protected and {} the outputs",
+                withheld.join(", "),
+                if self.mask_protected_exports {
+                    "are masked in"
+                } else {
+                    "are left out of (use --mask-protected-exports to include them masked, or --include-secrets in clear)"
+                }
+            );
+        }
+
         // Add elapsed_time
         export_data.insert(
             "elapsed_time".to_string(),
             serde_json::Value::String(elapsed_time.to_string()),
         );
+        export_data
+    }
+
+    /// Process stack-level exports to a JSON output file.
+    pub fn process_stack_exports(
+        &self,
+        dry_run: bool,
+        output_file: Option<&str>,
+        elapsed_time: &str,
+    ) {
+        let manifest_exports = &self.manifest.exports;
+
+        if manifest_exports.is_empty() {
+            return;
+        }
+
+        if dry_run {
+            let total_vars = manifest_exports.len() + 3;
+            info!(
+                "dry run: would export {} variables (including automatic stack_name, stack_env, and elapsed_time)",
+                total_vars
+            );
+            return;
+        }
+
+        let export_data = self.stack_export_data(elapsed_time);
 
         // Display stack exports table
         print_unicode_box("stack exports", BorderColor::Cyan);
@@ -1249,45 +2156,48 @@ impl CommandRunner {
             max_name_len = max_name_len.max(fq_name.len());
             rows.push((fq_name, val_str));
         }
-        let max_val_len = rows
-            .iter()
-            .map(|(_, v)| v.len())
-            .max()
-            .unwrap_or(5)
-            .clamp(5, 80); // cap value display width
-
-        let sep = format!(
-            "+-{}-+-{}-+",
-            "-".repeat(max_name_len),
-            "-".repeat(max_val_len)
-        );
-        println!("{}", sep);
-        println!(
-            "| {:<width_n$} | {:<width_v$} |",
-            "variable",
-            "value",
-            width_n = max_name_len,
-            width_v = max_val_len
-        );
-        println!("{}", sep);
-        for (name, val) in &rows {
-            let display_val = if val.len() > max_val_len {
-                format!("{}...", &val[..max_val_len - 3])
-            } else {
-                val.clone()
-            };
+        // The table is console chatter; `--output json` keeps stdout for the report
+        if !json_output() {
+            let max_val_len = rows
+                .iter()
+                .map(|(_, v)| v.len())
+                .max()
+                .unwrap_or(5)
+                .clamp(5, 80); // cap value display width
+
+            let sep = format!(
+                "+-{}-+-{}-+",
+                "-".repeat(max_name_len),
+                "-".repeat(max_val_len)
+            );
+            println!("{}", sep);
             println!(
                 "| {:<width_n$} | {:<width_v$} |",
-                name,
-                display_val,
+                "variable",
+                "value",
                 width_n = max_name_len,
                 width_v = max_val_len
             );
+            println!("{}", sep);
+            for (name, val) in &rows {
+                let display_val = if val.len() > max_val_len {
+                    format!("{}...", &val[..max_val_len - 3])
+                } else {
+                    val.clone()
+                };
+                println!(
+                    "| {:<width_n$} | {:<width_v$} |",
+                    name,
+                    display_val,
+                    width_n = max_name_len,
+                    width_v = max_val_len
+                );
+            }
+            println!("{}", sep);
         }
-        println!("{}", sep);
 
         // Write sourceable exports file
-        let exports_file = ".stackql-deploy-exports";
+        let exports_file = &self.exports_file;
         let mut export_lines = Vec::new();
         for (name, val) in &rows {
             // Escape single quotes in values
@@ -1297,25 +2207,27 @@ impl CommandRunner {
         match fs::write(exports_file, export_lines.join("\n") + "\n") {
             Ok(_) => {
                 info!("{} variables written to {}", rows.len(), exports_file);
-                println!();
-                println!("To load these variables into your shell:");
-                if cfg!(target_os = "windows") {
-                    println!(
-                        "  PowerShell:  Get-Content {} | ForEach-Object {{ Invoke-Expression $_ }}",
-                        exports_file
-                    );
-                    println!("  Git Bash:    source {}", exports_file);
-                } else {
-                    println!("  source {}", exports_file);
+                if !json_output() {
+                    println!();
+                    println!("To load these variables into your shell:");
+                    if cfg!(target_os = "windows") {
+                        println!(
+                            "  PowerShell:  Get-Content {} | ForEach-Object {{ Invoke-Expression $_ }}",
+                            exports_file
+                        );
+                        println!("  Git Bash:    source {}", exports_file);
+                    } else {
+                        println!("  source {}", exports_file);
+                    }
+                    println!();
                 }
-                println!();
             }
             Err(e) => {
                 error!("Failed to write exports file {}: {}", exports_file, e);
             }
         }
 
-        // Write JSON file if --output-file was specified
+        // Write JSON (or YAML) file if --output-file was specified
         if let Some(output_file) = output_file {
             if let Some(parent) = Path::new(output_file).parent() {
                 if !parent.as_os_str().is_empty() && !parent.exists() {
@@ -1328,8 +2240,17 @@ impl CommandRunner {
                 }
             }
 
-            let json = serde_json::Value::Object(export_data);
-            match fs::write(output_file, serde_json::to_string_pretty(&json).unwrap()) {
+            let outputs = serde_json::Value::Object(export_data);
+            let format = self
+                .export_format
+                .unwrap_or_else(|| ExportFormat::from_path(output_file));
+            let content = match format {
+                ExportFormat::Json => serde_json::to_string_pretty(&outputs).unwrap(),
+                ExportFormat::Yaml => serde_yaml::to_string(&outputs).unwrap_or_else(|e| {
+                    catch_error_and_exit(&format!("Failed to serialize exports as YAML: {}", e))
+                }),
+            };
+            match fs::write(output_file, content) {
                 Ok(_) => info!("Exports also written to {}", output_file),
                 Err(e) => catch_error_and_exit(&format!(
                     "Failed to write exports file {}: {}",
@@ -1340,11 +2261,45 @@ impl CommandRunner {
     }
 }
 
+/// Exit with an error when the manifest of `stack_dir` defines no resources
+/// (`--strict-manifest`), before a server is started or connected to.
+pub fn check_manifest_defines_resources(stack_dir: &str) {
+    let stack_dir = Manifest::resolve_stack_dir_or_exit(stack_dir);
+    let manifest = Manifest::load_from_dir_or_exit(&stack_dir);
+    if manifest.resources.is_empty() {
+        catch_error_and_exit(&format!(
+            "strict manifest: stack [{}] defines no resources",
+            manifest.name
+        ));
+    }
+}
+
 /// Evaluate a simple condition expression.
-/// Supports: 'value1' == 'value2', 'value1' != 'value2', true, false
+/// Supports: 'value1' == 'value2', 'value1' != 'value2', `>`, `<`, `>=`,
+/// `<=` (numeric when both sides are numbers), `in` / `not in` lists, true,
+/// false, and clauses of these combined with `and` / `or` (`and` binding
+/// tighter, as in Python).
 fn evaluate_simple_condition(condition: &str) -> Option<bool> {
     let trimmed = condition.trim();
 
+    // Every clause is evaluated, so an unparseable one always yields None
+    let or_clauses = split_outside_quotes(trimmed, " or ");
+    if or_clauses.len() > 1 {
+        let results = or_clauses
+            .into_iter()
+            .map(evaluate_simple_condition)
+            .collect::<Option<Vec<bool>>>()?;
+        return Some(results.into_iter().any(|r| r));
+    }
+    let and_clauses = split_outside_quotes(trimmed, " and ");
+    if and_clauses.len() > 1 {
+        let results = and_clauses
+            .into_iter()
+            .map(evaluate_simple_condition)
+            .collect::<Option<Vec<bool>>>()?;
+        return Some(results.into_iter().all(|r| r));
+    }
+
     // Direct boolean values
     if trimmed == "true" || trimmed == "True" {
         return Some(true);
@@ -1367,38 +2322,400 @@ fn evaluate_simple_condition(condition: &str) -> Option<bool> {
         return Some(l != r);
     }
 
-    // `in` check: 'a' in ['a', 'b']
-    if trimmed.contains(" in ") {
-        let parts: Vec<&str> = trimmed.splitn(2, " in ").collect();
+    // Ordering checks: 3 > 2, '1.5' <= '2.0'; two-character operators first
+    for (op, accept) in [
+        (">=", &[Ordering::Greater, Ordering::Equal][..]),
+        ("<=", &[Ordering::Less, Ordering::Equal][..]),
+        (">", &[Ordering::Greater][..]),
+        ("<", &[Ordering::Less][..]),
+    ] {
+        if let [left, right] = split_outside_quotes(trimmed, op)[..] {
+            return compare_values(left, right).map(|ordering| accept.contains(&ordering));
+        }
+    }
+
+    // `not in` is checked first, as it also contains ` in `
+    if trimmed.contains(" not in ") {
+        let parts: Vec<&str> = trimmed.splitn(2, " not in ").collect();
         if parts.len() == 2 {
             let needle = parts[0].trim().trim_matches('\'').trim_matches('"');
             let haystack = parts[1].trim();
-            // Simple list check
             if haystack.starts_with('[') && haystack.ends_with(']') {
                 let items: Vec<&str> = haystack[1..haystack.len() - 1]
                     .split(',')
                     .map(|s| s.trim().trim_matches('\'').trim_matches('"'))
                     .collect();
-                return Some(items.contains(&needle));
+                return Some(!items.contains(&needle));
             }
         }
     }
 
-    // `not in` check
-    if trimmed.contains(" not in ") {
-        let parts: Vec<&str> = trimmed.splitn(2, " not in ").collect();
+    // `in` check: 'a' in ['a', 'b']
+    if trimmed.contains(" in ") {
+        let parts: Vec<&str> = trimmed.splitn(2, " in ").collect();
         if parts.len() == 2 {
             let needle = parts[0].trim().trim_matches('\'').trim_matches('"');
             let haystack = parts[1].trim();
+            // Simple list check
             if haystack.starts_with('[') && haystack.ends_with(']') {
                 let items: Vec<&str> = haystack[1..haystack.len() - 1]
                     .split(',')
                     .map(|s| s.trim().trim_matches('\'').trim_matches('"'))
                     .collect();
-                return Some(!items.contains(&needle));
+                return Some(items.contains(&needle));
             }
         }
     }
 
     None
 }
+
+/// Compare two condition operands, numerically when both parse as numbers
+/// and as strings otherwise.  `None` if either side is empty or the numbers
+/// are not comparable (NaN).
+fn compare_values(left: &str, right: &str) -> Option<Ordering> {
+    let l = left.trim().trim_matches('\'').trim_matches('"');
+    let r = right.trim().trim_matches('\'').trim_matches('"');
+    if l.is_empty() || r.is_empty() {
+        return None;
+    }
+    match (l.parse::<f64>(), r.parse::<f64>()) {
+        (Ok(l), Ok(r)) => l.partial_cmp(&r),
+        _ => Some(l.cmp(r)),
+    }
+}
+
+/// Split `s` on `separator`, ignoring occurrences inside quoted strings.
+fn split_outside_quotes<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if s[i..].starts_with(separator) => {
+                parts.push(&s[start..i]);
+                start = i + separator.len();
+                // Skip the rest of the separator
+                for _ in 1..separator.chars().count() {
+                    chars.next();
+                }
+            }
+            None => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing::{resource, TestStack};
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    #[test]
+    fn test_condition_and() {
+        assert_eq!(
+            evaluate_simple_condition("'prod' == 'prod' and 'us-east-1' == 'us-east-1'"),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate_simple_condition("'prod' == 'prod' and 'eu-west-1' == 'us-east-1'"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_condition_or() {
+        assert_eq!(
+            evaluate_simple_condition("'dev' == 'prod' or 'dev' in ['dev', 'sit']"),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate_simple_condition("'dev' == 'prod' or 'dev' not in ['dev', 'sit']"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_condition_and_binds_tighter_than_or() {
+        // true or (false and false)
+        assert_eq!(
+            evaluate_simple_condition("'a' == 'a' or 'a' == 'b' and 'a' == 'c'"),
+            Some(true)
+        );
+        // (false and true) or false
+        assert_eq!(
+            evaluate_simple_condition("'a' == 'b' and 'a' == 'a' or 'a' == 'c'"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_condition_operators_inside_quotes() {
+        assert_eq!(
+            evaluate_simple_condition("'salt and pepper' == 'salt and pepper'"),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate_simple_condition("'this or that' != 'this'"),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_condition_numeric_comparisons() {
+        assert_eq!(evaluate_simple_condition("3 > 2"), Some(true));
+        assert_eq!(evaluate_simple_condition("10 > 9"), Some(true));
+        assert_eq!(evaluate_simple_condition("'2' >= '2'"), Some(true));
+        assert_eq!(evaluate_simple_condition("1 < 1"), Some(false));
+        assert_eq!(evaluate_simple_condition("1.5 <= 1.25"), Some(false));
+        assert_eq!(evaluate_simple_condition("0.5 < 1"), Some(true));
+        assert_eq!(
+            evaluate_simple_condition("3 > 2 and 'prod' == 'prod'"),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_condition_comparison_falls_back_to_strings() {
+        // Non-numeric operands compare as strings, so '10a' sorts before '9a'
+        assert_eq!(evaluate_simple_condition("'b' > 'a'"), Some(true));
+        assert_eq!(evaluate_simple_condition("'abc' <= 'abd'"), Some(true));
+        assert_eq!(evaluate_simple_condition("'10a' > '9a'"), Some(false));
+        assert_eq!(evaluate_simple_condition("'a>b' == 'a>b'"), Some(true));
+        assert_eq!(evaluate_simple_condition(" > 2"), None);
+    }
+
+    #[test]
+    fn test_condition_unparseable_clause() {
+        assert_eq!(evaluate_simple_condition("'a' == 'a' and bogus"), None);
+        assert_eq!(evaluate_simple_condition("true or bogus"), None);
+    }
+
+    const DB_MANIFEST: &str = r#"
+version: 1
+name: app
+providers:
+  - aws
+resources:
+  - name: db
+    exports:
+      - host: db_host
+      - password: db_password
+    protected:
+      - password
+exports:
+  - db_host
+  - db_password
+"#;
+
+    /// The stack outputs of the db stack of `manifest` once `db` has
+    /// exported its host and password, with `configure` applied to the
+    /// runner.
+    fn db_outputs(manifest: &str, configure: impl Fn(&mut CommandRunner)) -> serde_json::Value {
+        let stack = TestStack::new(manifest, &[]);
+        let server = MockServer::start(|_| Reply::ok());
+        let mut runner = stack.runner(&server);
+        configure(&mut runner);
+        let db = resource(&runner, "db");
+        let row = HashMap::from([
+            ("host".to_string(), "db.internal".to_string()),
+            ("password".to_string(), "s3cr3t".to_string()),
+        ]);
+        runner.process_exports_from_result(&db, &[row]);
+        serde_json::Value::Object(runner.stack_export_data("1s"))
+    }
+
+    #[test]
+    fn test_stack_exports_of_renamed_protected_export() {
+        // Protected by its source column, exported as db_password
+        let outputs = db_outputs(DB_MANIFEST, |_| {});
+        assert_eq!(outputs["db_host"], "db.internal");
+        assert!(outputs.get("db_password").is_none());
+
+        let outputs = db_outputs(DB_MANIFEST, |runner| runner.mask_protected_exports = true);
+        assert_eq!(outputs["db_password"], MASKED_EXPORT_VALUE);
+
+        let outputs = db_outputs(DB_MANIFEST, |runner| runner.include_secrets = true);
+        assert_eq!(outputs["db_password"], "s3cr3t");
+        assert_eq!(outputs["db_host"], "db.internal");
+    }
+
+    #[test]
+    fn test_stack_exports_with_prefix_and_suffix() {
+        let manifest = format!(
+            "{}export_prefix: \"{{{{ stack_env }}}}_\"\nexport_suffix: _v1\n",
+            DB_MANIFEST
+        );
+
+        let outputs = db_outputs(&manifest, |runner| runner.mask_protected_exports = true);
+        assert_eq!(outputs["dev_db_host_v1"], "db.internal");
+        assert_eq!(outputs["dev_db_password_v1"], MASKED_EXPORT_VALUE);
+        assert!(outputs.get("db_host").is_none());
+        // Stack metadata keeps its names
+        assert_eq!(outputs["stack_name"], "app");
+        assert_eq!(outputs["stack_env"], "dev");
+    }
+
+    #[test]
+    fn test_stack_exports_file_format() {
+        let stack = TestStack::new(DB_MANIFEST, &[]);
+        let server = MockServer::start(|_| Reply::ok());
+        let mut runner = stack.runner(&server);
+        let db = resource(&runner, "db");
+        let row = HashMap::from([
+            ("host".to_string(), "db.internal".to_string()),
+            ("password".to_string(), "s3cr3t".to_string()),
+        ]);
+        runner.process_exports_from_result(&db, &[row]);
+        let written = |runner: &CommandRunner, file: &str| {
+            let path = format!("{}/{}", stack.path(), file);
+            runner.process_stack_exports(false, Some(&path), "1s");
+            std::fs::read_to_string(path).unwrap()
+        };
+        let exports_file = format!("{}/{}", stack.path(), EXPORTS_FILE_NAME);
+        assert_eq!(runner.exports_file, exports_file);
+
+        // By the file extension unless --export-format is given
+        let is_json = |text: &str| serde_json::from_str::<serde_json::Value>(text).is_ok();
+        let yaml = written(&runner, "out.yml");
+        assert!(!is_json(&yaml), "{}", yaml);
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(yaml["db_host"], "db.internal");
+        assert!(is_json(&written(&runner, "out.json")));
+        let exports = std::fs::read_to_string(&exports_file).unwrap();
+        assert!(
+            exports.contains("export STACKQL_DEPLOY__app__dev__db_host='db.internal'"),
+            "{}",
+            exports
+        );
+
+        runner.export_format = Some(ExportFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(&written(&runner, "out.yaml")).unwrap();
+        assert_eq!(json["db_host"], "db.internal");
+    }
+
+    #[test]
+    fn test_join_merges_changes_of_each_fork() {
+        let stack = TestStack::new(DB_MANIFEST, &[]);
+        let server = MockServer::start(|_| Reply::ok());
+        let mut runner = stack.runner(&server);
+        runner
+            .global_context
+            .insert("db_host".to_string(), "old.internal".to_string());
+        let base_context = runner.global_context.clone();
+
+        let mut changed = runner.fork(server.client());
+        changed
+            .global_context
+            .insert("db_host".to_string(), "db.internal".to_string());
+        let mut added = runner.fork(server.client());
+        added
+            .global_context
+            .insert("db_password".to_string(), "s3cr3t".to_string());
+        added.protected_names.insert("db_password".to_string());
+
+        runner.join(changed, &base_context);
+        runner.join(added, &base_context);
+
+        // A fork which left a key as it was does not undo another's change
+        assert_eq!(runner.global_context["db_host"], "db.internal");
+        assert_eq!(runner.global_context["db_password"], "s3cr3t");
+        assert!(runner.protected_names.contains("db_password"));
+        assert!(!runner.protected_names.contains("db_host"));
+    }
+
+    /// How many of the queries `server` received were `query`.
+    fn times_run(server: &MockServer, query: &str) -> usize {
+        server.queries().iter().filter(|q| *q == query).count()
+    }
+
+    #[test]
+    fn test_read_query_cache() {
+        let stack = TestStack::new(DB_MANIFEST, &[]);
+        let server = MockServer::start(|query| {
+            if query.contains("FROM aws.rds.instances") {
+                Reply::rows(&["host"], &[&["db.internal"]])
+            } else if query.contains("FROM aws.rds.errors") {
+                Reply::rows(&["error"], &[&["not found"]])
+            } else if query.contains("FROM aws.rds.failures") {
+                Reply::rows(&["_stackql_deploy_error"], &[&["boom"]])
+            } else {
+                Reply::rows(&["host"], &[])
+            }
+        });
+        let mut runner = stack.runner(&server);
+        runner.enable_query_cache();
+        let mut read = |query: &str| runner.run_read_query(query, 0, 0, Backoff::Constant);
+
+        // An identical rendered query runs once
+        let found = "SELECT host FROM aws.rds.instances";
+        assert_eq!(read(found), read(found));
+        assert_eq!(read(found)[0]["host"], "db.internal");
+        assert_eq!(times_run(&server, found), 1);
+
+        // Empty and error results are not cached
+        for query in [
+            "SELECT host FROM aws.rds.missing",
+            "SELECT host FROM aws.rds.errors",
+            "SELECT host FROM aws.rds.failures",
+        ] {
+            assert_eq!(read(query).is_empty(), query.ends_with("missing"));
+            read(query);
+            assert_eq!(times_run(&server, query), 2, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_read_query_without_cache() {
+        let stack = TestStack::new(DB_MANIFEST, &[]);
+        let server = MockServer::start(|_| Reply::rows(&["host"], &[&["db.internal"]]));
+        let mut runner = stack.runner(&server);
+
+        let query = "SELECT host FROM aws.rds.instances";
+        for _ in 0..3 {
+            runner.run_read_query(query, 0, 0, Backoff::Constant);
+        }
+        assert_eq!(times_run(&server, query), 3);
+    }
+
+    #[test]
+    fn test_resolve_on_failure() {
+        let manifest = r#"
+version: 1
+name: app
+providers:
+  - aws
+on_failure_by_env:
+  dev: ignore
+  prd: rollback
+resources:
+  - name: db
+"#;
+        let stack = TestStack::new(manifest, &[]);
+        let server = MockServer::start(|_| Reply::ok());
+        let mut runner = stack.runner(&server);
+
+        // An explicit --on-failure wins over the manifest
+        assert_eq!(
+            runner.resolve_on_failure(FailureAction::Error, true),
+            FailureAction::Error
+        );
+        // Otherwise the manifest's entry for the environment
+        assert_eq!(
+            runner.resolve_on_failure(FailureAction::Error, false),
+            FailureAction::Ignore
+        );
+        // Otherwise the flag's default
+        runner.stack_env = "staging".to_string();
+        assert_eq!(
+            runner.resolve_on_failure(FailureAction::Error, false),
+            FailureAction::Error
+        );
+    }
+}
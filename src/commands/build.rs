@@ -6,21 +6,41 @@
 //! resources defined in a stack manifest.
 //! This is the Rust equivalent of Python's `cmd/build.py` `StackQLProvisioner`.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::thread;
 use std::time::Instant;
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use colored::*;
 use log::{debug, info, warn};
 
-use crate::commands::base::CommandRunner;
+use crate::commands::base::{check_manifest_defines_resources, CommandRunner};
 use crate::commands::common_args::{
-    dry_run, env_file, env_var, log_level, on_failure, show_queries, stack_dir, stack_env,
-    FailureAction,
+    cache_queries, check_binary_version, detailed_exitcode, dry_run, dump_sql_to_dir, env_all,
+    env_all_unsafe, env_file, env_overrides, env_var, export_format, fail_if_binary_older_than,
+    include_secrets, log_level, manifest_env_var, mask_protected_exports, on_failure,
+    on_failure_value, process_env_value, registry, registry_options, resources, resources_value,
+    show_queries, stack_dir, stack_env, stack_env_value, state_file, strict_manifest,
+    strip_comments, target, targets_value, var_file, var_files_value, var_from_command,
+    ExportFormat, FailureAction,
 };
+use crate::commands::test::run_test;
+use crate::core::checkpoint::Checkpoint;
 use crate::core::config::get_resource_type;
-use crate::core::utils::{catch_error_and_exit, export_vars};
-use crate::utils::connection::create_client;
-use crate::utils::display::{print_unicode_box, BorderColor};
+use crate::core::env::is_sensitive_name;
+use crate::core::report::ResourceOutcome;
+use crate::core::rollback;
+use crate::core::state::StateFile;
+use crate::core::templating::ParsedQuery;
+use crate::core::utils::{
+    catch_error_and_exit, export_vars, has_returning_clause, sorted_keys, strip_returning_clause,
+    Backoff, RetryBudget,
+};
+use crate::resource::manifest::{ReplaceOnChange, Resource};
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::display::{json_output, print_unicode_box, BorderColor};
+use crate::utils::pgwire::PgwireLite;
 use crate::utils::server::{check_and_start_server, stop_local_server};
 
 /// Defines the `build` command for the CLI application.
@@ -29,43 +49,153 @@ pub fn command() -> Command {
         .about("Create or update resources")
         .arg(stack_dir())
         .arg(stack_env())
+        .arg(manifest_env_var())
         .arg(log_level())
+        .arg(fail_if_binary_older_than())
+        .arg(registry())
         .arg(env_file())
         .arg(env_var())
+        .arg(var_file())
+        .arg(var_from_command())
+        .arg(env_all())
+        .arg(env_all_unsafe())
         .arg(dry_run())
+        .arg(detailed_exitcode().requires("dry-run"))
         .arg(show_queries())
         .arg(on_failure())
+        .arg(resources())
+        .arg(target())
+        .arg(strict_manifest())
+        .arg(dump_sql_to_dir())
+        .arg(strip_comments())
+        .arg(cache_queries())
+        .arg(mask_protected_exports())
+        .arg(include_secrets())
+        .arg(export_format())
         .arg(
             Arg::new("output-file")
                 .long("output-file")
-                .help("File path to write deployment outputs as JSON")
+                .help("File path to write deployment outputs as JSON (or YAML for .yml/.yaml)")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("only-providers")
+                .long("only-providers")
+                .help("Pull the providers listed in the manifest and exit without deploying")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Continue an interrupted build, skipping resources completed by the previous run")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("After the build, re-run every resource's statecheck and fail if any resource is not in the desired state")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("parallel")
+                .long("parallel")
+                .value_name("N")
+                .help("Build up to N resources at a time, each on its own connection, in waves that respect depends_on")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("export-only-on-success")
+                .long("export-only-on-success")
+                .help("Only write stack outputs if every resource succeeded; otherwise leave previous outputs untouched")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("diff")
+                .long("diff")
+                .help("With --dry-run, fetch each resource's current state and print how it differs from its properties")
+                .requires("dry-run")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(state_file())
 }
 
 /// Executes the `build` command.
-pub fn execute(matches: &ArgMatches) {
+///
+/// Returns whether a `--dry-run` with `--detailed-exitcode` found changes to
+/// make.
+pub fn execute(matches: &ArgMatches, server: &ServerConfig, options: &RunOptions) -> bool {
     let stack_dir_val = matches.get_one::<String>("stack_dir").unwrap();
-    let stack_env_val = matches.get_one::<String>("stack_env").unwrap();
+    let stack_env_val = &stack_env_value(matches);
     let env_file_val = matches.get_one::<String>("env-file").unwrap();
-    let env_vars: Vec<String> = matches
-        .get_many::<String>("env")
-        .map(|v| v.cloned().collect())
-        .unwrap_or_default();
+    let env_vars = env_overrides(matches);
     let is_dry_run = matches.get_flag("dry-run");
     let is_show_queries = matches.get_flag("show-queries");
-    let on_failure_val = matches.get_one::<FailureAction>("on-failure").unwrap();
+    let (on_failure_cli, on_failure_explicit) = on_failure_value(matches);
     let output_file = matches.get_one::<String>("output-file");
+    let export_only_on_success = matches.get_flag("export-only-on-success");
+    let state_file = matches.get_one::<String>("state-file");
+    let parallel = *matches.get_one::<u32>("parallel").unwrap() as usize;
+    // Concurrent builds draw seeded uuids in whatever order they run
+    if parallel > 1 && matches.get_one::<u64>("seed").is_some() {
+        catch_error_and_exit(
+            "--seed cannot be combined with --parallel above 1: concurrent builds would not render reproducible uuids",
+        );
+    }
+
+    let strict_manifest = matches.get_flag("strict-manifest");
+    if strict_manifest {
+        check_manifest_defines_resources(stack_dir_val);
+    }
 
-    check_and_start_server();
-    let client = create_client();
+    check_binary_version(matches);
+    let options = &registry_options(matches, options);
+    check_and_start_server(server, options);
+    let client = create_client(server, options);
     let mut runner = CommandRunner::new(
         client,
         stack_dir_val,
         stack_env_val,
         env_file_val,
+        &var_files_value(matches),
         &env_vars,
+        process_env_value(matches),
     );
+    runner.track_report("build", is_dry_run);
+    let on_failure_val = runner.resolve_on_failure(on_failure_cli, on_failure_explicit);
+    if matches.get_flag("only-providers") {
+        // CommandRunner::new has already pulled (and version-pinned) providers
+        print_unicode_box(
+            &format!("providers installed for stack: [{}]", runner.stack_name),
+            BorderColor::Green,
+        );
+        stop_local_server(server);
+        return false;
+    }
+    let defined = runner.manifest.resources.len();
+    let resource_patterns = resources_value(matches);
+    runner.select_resources(&resource_patterns);
+    let targets = targets_value(matches);
+    runner.select_targets(&targets, true);
+    if strict_manifest {
+        let filter = if !targets.is_empty() {
+            Some("--target")
+        } else {
+            (!resource_patterns.is_empty()).then_some("--resources")
+        };
+        runner.check_resources_to_process(defined, filter);
+    }
+    if let Some(dir) = matches.get_one::<String>("dump-sql-to-dir") {
+        runner.enable_sql_dump(dir);
+    }
+    runner.strip_comments = matches.get_flag("strip-comments");
+    runner.show_diff = matches.get_flag("diff");
+    if matches.get_flag("cache-queries") {
+        runner.enable_query_cache();
+    }
+    runner.mask_protected_exports = matches.get_flag("mask-protected-exports");
+    runner.include_secrets = matches.get_flag("include-secrets");
+    runner.export_format = matches.get_one::<ExportFormat>("export-format").copied();
 
     let stack_name_display = if runner.stack_name.is_empty() {
         runner.stack_dir.clone()
@@ -81,21 +211,363 @@ pub fn execute(matches: &ArgMatches) {
         BorderColor::Yellow,
     );
 
+    // Resources created from here on are deleted again if the build fails
+    if on_failure_val == FailureAction::Rollback && !is_dry_run {
+        rollback::arm(server, options, runner.manifest.session_settings());
+    }
+
     run_build(
         &mut runner,
         is_dry_run,
         is_show_queries,
         &format!("{:?}", on_failure_val),
         output_file.map(|s| s.as_str()),
+        state_file.map(|s| s.as_str()),
+        !resource_patterns.is_empty() || !targets.is_empty(),
+        export_only_on_success,
+        matches.get_flag("resume"),
+        parallel,
+        &|| create_client(server, options),
     );
+    rollback::disarm();
+
+    if matches.get_flag("verify") && !is_dry_run {
+        verify_build(&mut runner, is_show_queries);
+    }
 
     if is_dry_run {
         print_unicode_box("dry-run build complete", BorderColor::Green);
     } else {
         print_unicode_box("build complete", BorderColor::Green);
     }
+    if json_output() {
+        runner.print_json_report("build", is_dry_run);
+    }
+
+    stop_local_server(server);
+
+    is_dry_run
+        && matches.get_flag("detailed-exitcode")
+        && runner.report.created + runner.report.updated + runner.report.replaced > 0
+}
+
+/// Re-check every resource once the whole stack is deployed, using the
+/// `test` flow, and fail listing the resources that did not converge (e.g.
+/// due to eventual-consistency lag or ordering issues).
+fn verify_build(runner: &mut CommandRunner, show_queries: bool) {
+    print_unicode_box("Verifying stack state", BorderColor::Yellow);
+
+    // The verification pass keeps its own report; the build report is kept
+    let build_report = std::mem::take(&mut runner.report);
+    run_test(runner, false, show_queries, "Error", None, &[]);
+    let verify_report = std::mem::replace(&mut runner.report, build_report);
+
+    let failed: Vec<&str> = verify_report
+        .resources
+        .iter()
+        .filter(|r| r.outcome == ResourceOutcome::Failed)
+        .map(|r| r.name.as_str())
+        .collect();
+    if !failed.is_empty() {
+        catch_error_and_exit(&format!(
+            "verification failed: {} resource(s) not in the desired state after the build: {}",
+            failed.len(),
+            failed.join(", ")
+        ));
+    }
+    info!("verification passed: all resources are in the desired state");
+}
+
+/// Whether a resource which is not in the desired state must be replaced
+/// rather than updated.  With a list of properties, only a difference between
+/// a listed property and the same-named field returned by the `exists` query
+/// (captured as `<resource>.<property>`) calls for replacement.
+pub(crate) fn needs_replacement(
+    resource: &Resource,
+    full_context: &HashMap<String, String>,
+) -> bool {
+    let properties = match &resource.replace_on_change {
+        None | Some(ReplaceOnChange::Always(false)) => return false,
+        Some(ReplaceOnChange::Always(true)) => return true,
+        Some(ReplaceOnChange::Properties(properties)) => properties,
+    };
+    let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+    properties.iter().any(|prop| {
+        let desired = full_context.get(prop);
+        let live = full_context.get(&format!("{}.{}", resource.name, prop));
+        match (desired, live) {
+            (Some(desired), Some(live)) if unquote(desired) != unquote(live) => {
+                info!(
+                    "[{}] property [{}] changed ({} -> {}), replacing",
+                    resource.name, prop, live, desired
+                );
+                true
+            }
+            (Some(_), None) => {
+                debug!(
+                    "[{}] exists query returned no [{}] field to compare for replace_on_change",
+                    resource.name, prop
+                );
+                false
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether the `createorupdate` query of a resource runs without checking
+/// its state first.  The single idempotent query takes the place of both
+/// `create` and `update`; with an `exists` or `statecheck` query to check
+/// against it only runs when the resource is missing or not in the desired
+/// state, as `create` and `update` would.
+pub(crate) fn createorupdate_unchecked(resource_queries: &HashMap<String, ParsedQuery>) -> bool {
+    resource_queries.contains_key("createorupdate")
+        && !resource_queries.contains_key("exists")
+        && !resource_queries.contains_key("statecheck")
+}
+
+/// How the live value of a property compares with its desired value.
+#[derive(Debug, PartialEq)]
+enum PropertyDiff {
+    Unchanged,
+    Changed {
+        live: String,
+        desired: String,
+    },
+    /// The fetched state has no field named after the property
+    NotFetched,
+}
+
+/// Whether a live value equals a desired one, ignoring surrounding quotes
+/// and JSON formatting.
+fn same_value(live: &str, desired: &str) -> bool {
+    let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+    if unquote(live) == unquote(desired) {
+        return true;
+    }
+    match (
+        serde_json::from_str::<serde_json::Value>(live),
+        serde_json::from_str::<serde_json::Value>(desired),
+    ) {
+        (Ok(live), Ok(desired)) => live == desired,
+        _ => false,
+    }
+}
+
+/// Compare each property of a resource, as rendered in `full_context`, with
+/// the same-named field (matched case-insensitively) of its live state.
+fn property_diffs(
+    resource: &Resource,
+    full_context: &HashMap<String, String>,
+    live: &HashMap<String, String>,
+) -> Vec<(String, PropertyDiff)> {
+    resource
+        .props
+        .iter()
+        .filter_map(|prop| {
+            let desired = full_context.get(&prop.name)?;
+            let live_value = live.get(&prop.name).or_else(|| {
+                live.iter()
+                    .find(|(field, _)| field.eq_ignore_ascii_case(&prop.name))
+                    .map(|(_, value)| value)
+            });
+            let diff = match live_value {
+                None => PropertyDiff::NotFetched,
+                Some(live) if same_value(live, desired) => PropertyDiff::Unchanged,
+                Some(live) => PropertyDiff::Changed {
+                    live: live.clone(),
+                    desired: desired.clone(),
+                },
+            };
+            Some((prop.name.clone(), diff))
+        })
+        .collect()
+}
+
+/// `build --dry-run --diff`: fetch the live state of a resource with its
+/// read-only `exists` and `statecheck` queries and log how each property
+/// differs from it.  Returns whether the changes call for the resource to
+/// be replaced (`replace_on_change`).
+fn print_resource_diff(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    resource_queries: &HashMap<String, ParsedQuery>,
+    exists_query: Option<&str>,
+    full_context: &HashMap<String, String>,
+    show_queries: bool,
+) -> bool {
+    let Some(exists_query) = exists_query else {
+        info!(
+            "no exists query to fetch the current state of [{}], skipping diff",
+            resource.name
+        );
+        return false;
+    };
+    let row = runner.fetch_first_row(resource, "exists", exists_query, show_queries);
+    let exists = row.as_ref().is_some_and(|row| match row.get("count") {
+        Some(count) => count.parse::<i64>().is_ok_and(|n| n > 0),
+        None => row.values().any(|v| v != "null" && !v.is_empty()),
+    });
+    let mut live = match row {
+        Some(row) if exists => row,
+        _ => {
+            info!(
+                "{}",
+                format!("+ [{}] does not exist and would be created", resource.name).green()
+            );
+            return false;
+        }
+    };
+    live.remove("count");
+
+    // The statecheck query can use the fields captured by exists as this.*
+    if let Some(statecheck) = resource_queries.get("statecheck") {
+        let mut context = full_context.clone();
+        for (k, v) in &live {
+            context.insert(format!("{}.{}", resource.name, k), v.clone());
+        }
+        let rendered =
+            runner.try_render_query(&resource.name, "statecheck", &statecheck.template, &context);
+        if let Some(row) = rendered
+            .and_then(|query| runner.fetch_first_row(resource, "statecheck", &query, show_queries))
+        {
+            live.extend(row.into_iter().filter(|(k, _)| k != "count"));
+        }
+    }
+
+    let diffs = property_diffs(resource, full_context, &live);
+    let changed: Vec<String> = diffs
+        .iter()
+        .filter_map(|(name, diff)| match diff {
+            PropertyDiff::Changed { live, desired } => Some(format!(
+                "  {} {}: {} -> {}",
+                "~".yellow(),
+                name,
+                live.red(),
+                desired.green()
+            )),
+            _ => None,
+        })
+        .collect();
+    let not_fetched: Vec<&str> = diffs
+        .iter()
+        .filter(|(_, diff)| *diff == PropertyDiff::NotFetched)
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    info!("diff for [{}]:", resource.name);
+    if changed.is_empty() {
+        info!("  {}", "no changes to the fetched properties".green());
+    }
+    for line in &changed {
+        info!("{}", line);
+    }
+    if !not_fetched.is_empty() {
+        info!(
+            "  {} propert{} not returned by the exists or statecheck query: {}",
+            not_fetched.len(),
+            if not_fetched.len() == 1 { "y" } else { "ies" },
+            not_fetched.join(", ")
+        );
+    }
+
+    if changed.is_empty() {
+        return false;
+    }
+    let mut context = full_context.clone();
+    for (k, v) in &live {
+        context.insert(format!("{}.{}", resource.name, k), v.clone());
+    }
+    needs_replacement(resource, &context)
+}
+
+/// Delete an existing resource ahead of recreating it, exiting if the delete
+/// cannot be confirmed.  A resource other resources depend on is not
+/// replaced: deleting it would fail, or orphan them, while they still exist.
+#[allow(clippy::too_many_arguments)]
+fn replace_existing(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    resource_queries: &HashMap<String, ParsedQuery>,
+    exists_query: Option<&str>,
+    full_context: &HashMap<String, String>,
+    dry_run: bool,
+    show_queries: bool,
+    ignore_errors: bool,
+) {
+    let (Some(dq), Some(exists_query)) = (resource_queries.get("delete"), exists_query) else {
+        catch_error_and_exit(&format!(
+            "replace_on_change is set for [{}], which needs both 'exists' and 'delete' anchors.",
+            resource.name
+        ));
+    };
+
+    let dependents: Vec<&str> = runner
+        .manifest
+        .resources
+        .iter()
+        .filter(|r| r.depends_on.contains(&resource.name))
+        .map(|r| r.name.as_str())
+        .collect();
+    if !dependents.is_empty() {
+        catch_error_and_exit(&format!(
+            "[{}] must be replaced but {} depend{} on it; tear down the dependents first, or remove replace_on_change.",
+            resource.name,
+            dependents.join(", "),
+            if dependents.len() == 1 { "s" } else { "" }
+        ));
+    }
+    info!("replacing [{}]: deleting before recreating", resource.name);
+
+    let rendered = runner.render_query(&resource.name, "delete", &dq.template, full_context);
+    let delete_query = if has_returning_clause(&rendered) {
+        strip_returning_clause(&rendered)
+    } else {
+        rendered
+    };
+    let (_, confirmed) = runner.delete_and_confirm(
+        resource,
+        &delete_query,
+        exists_query,
+        dq.options.retries,
+        dq.options.retry_delay,
+        dry_run,
+        show_queries,
+        ignore_errors,
+    );
+    if !confirmed {
+        catch_error_and_exit(&format!(
+            "replace of {} failed: delete could not be confirmed.",
+            resource.name
+        ));
+    }
+}
 
-    stop_local_server();
+/// Push a resource created by this run onto the rollback stack with its
+/// delete query, rendered with as much of its context as is known so far.
+/// Called as soon as the resource is created, and again as its post-deploy
+/// checks capture `this.*` fields and exports, refining the query.  Returns
+/// whether the delete query could be rendered.
+fn record_for_rollback(
+    runner: &CommandRunner,
+    resource: &Resource,
+    resource_queries: &HashMap<String, ParsedQuery>,
+    full_context: &HashMap<String, String>,
+) -> bool {
+    let mut context = full_context.clone();
+    context.extend(runner.get_full_context(resource));
+    let delete_query = resource_queries
+        .get("delete")
+        .and_then(|dq| runner.try_render_query(&resource.name, "delete", &dq.template, &context))
+        .map(|rendered| {
+            if has_returning_clause(&rendered) {
+                strip_returning_clause(&rendered)
+            } else {
+                rendered
+            }
+        });
+    rollback::record_created(&resource.name, delete_query.as_deref());
+    delete_query.is_some()
 }
 
 /// Render the statecheck query template with the given context.
@@ -137,12 +609,25 @@ macro_rules! render_exports {
 }
 
 /// Main build workflow matching Python's StackQLProvisioner.run().
+///
+/// With `parallel` above 1, the resources of each dependency wave are built
+/// up to `parallel` at a time; a wave's exports are visible to the next.
+/// `filtered` is set when `--resources` or `--target` restricted the build to
+/// some of the stack's resources, and `connect` opens the connection of each
+/// concurrent build.
+#[allow(clippy::too_many_arguments)]
 fn run_build(
     runner: &mut CommandRunner,
     dry_run: bool,
     show_queries: bool,
-    _on_failure: &str,
+    on_failure: &str,
     output_file: Option<&str>,
+    state_file: Option<&str>,
+    filtered: bool,
+    export_only_on_success: bool,
+    resume: bool,
+    parallel: usize,
+    connect: &dyn Fn() -> PgwireLite,
 ) {
     let start_time = Instant::now();
 
@@ -155,167 +640,517 @@ fn run_build(
 
     let resources = runner.manifest.resources.clone();
 
-    for resource in &resources {
-        print_unicode_box(
-            &format!("Processing resource: [{}]", resource.name),
-            BorderColor::Blue,
+    // Progress is only checkpointed for real deployments
+    let mut checkpoint = if resume {
+        let checkpoint = Checkpoint::load(&runner.stack_dir, &runner.stack_name, &runner.stack_env);
+        info!(
+            "resuming build, {} resource(s) completed by the previous run: [{}]",
+            checkpoint.completed().len(),
+            checkpoint.completed().join(", ")
         );
+        checkpoint
+    } else {
+        let checkpoint = Checkpoint::new(&runner.stack_dir, &runner.stack_name, &runner.stack_env);
+        if !dry_run {
+            checkpoint.clear();
+        }
+        checkpoint
+    };
 
-        let res_type = get_resource_type(resource).to_string();
-        info!(
-            "processing resource [{}], type: {}",
-            resource.name, res_type
+    if parallel <= 1 {
+        for resource in &resources {
+            if build_resource(
+                runner,
+                resource,
+                &checkpoint,
+                dry_run,
+                show_queries,
+                on_failure,
+            ) {
+                record_completed(runner, &mut checkpoint, resource);
+            }
+        }
+    } else {
+        for (i, wave) in runner.manifest.dependency_waves().iter().enumerate() {
+            info!(
+                "wave {}: [{}]",
+                i + 1,
+                wave.iter()
+                    .map(|r| r.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for batch in wave.chunks(parallel) {
+                build_concurrently(
+                    runner,
+                    batch,
+                    &mut checkpoint,
+                    connect,
+                    dry_run,
+                    show_queries,
+                    on_failure,
+                );
+            }
+        }
+    }
+
+    let elapsed = start_time.elapsed();
+    let elapsed_str = format!("{:.2?}", elapsed);
+    runner.report.finish(elapsed);
+    info!("deployment completed in {}", elapsed_str);
+    info!("{}", runner.report);
+
+    if !dry_run && runner.failed_resources.is_empty() {
+        if let Some(path) = state_file {
+            write_state_file(runner, checkpoint.completed(), path, filtered);
+        }
+        checkpoint.clear();
+    }
+
+    if export_only_on_success && !runner.failed_resources.is_empty() {
+        warn!(
+            "{} resource(s) failed ({}), stack outputs not written; previous outputs left untouched",
+            runner.failed_resources.len(),
+            runner.failed_resources.join(", ")
         );
+        return;
+    }
 
-        let full_context = runner.get_full_context(resource);
+    runner.process_stack_exports(dry_run, output_file, &elapsed_str);
+}
 
-        // Evaluate condition
-        if !runner.evaluate_condition(resource, &full_context) {
-            continue;
+/// Record the `deployed` resources, in the order they completed, with the
+/// variables each exported. Protected exports are only written with
+/// `--include-secrets`.  A `filtered` build merges its resources into an
+/// existing state file rather than replacing the resources it did not build.
+fn write_state_file(runner: &CommandRunner, deployed: &[String], path: &str, filtered: bool) {
+    let mut state = StateFile::new(&runner.stack_name, &runner.stack_env);
+    for resource in deployed
+        .iter()
+        .filter_map(|name| runner.manifest.resources.iter().find(|r| &r.name == name))
+    {
+        let exports = resource
+            .export_names()
+            .into_iter()
+            .filter(|name| runner.include_secrets || !resource.protected.contains(name))
+            .filter_map(|name| {
+                let value = runner
+                    .global_context
+                    .get(&format!("{}.{}", resource.name, name))?;
+                Some((name, value.clone()))
+            })
+            .collect();
+        state.record(&resource.name, exports);
+    }
+    if filtered && Path::new(path).exists() {
+        let mut existing = StateFile::load(path).unwrap_or_else(|e| catch_error_and_exit(&e));
+        if existing.stack_name != state.stack_name || existing.stack_env != state.stack_env {
+            catch_error_and_exit(&format!(
+                "state file [{}] records stack [{}] in [{}], not [{}] in [{}]",
+                path, existing.stack_name, existing.stack_env, state.stack_name, state.stack_env
+            ));
         }
+        existing.merge(state);
+        state = existing;
+    }
+    match state.save(path) {
+        Ok(()) => info!(
+            "recorded {} resource(s) in state file [{}]",
+            state.resources.len(),
+            path
+        ),
+        Err(e) => catch_error_and_exit(&e),
+    }
+}
 
-        // Handle script type
-        if res_type == "script" {
-            runner.process_script_resource(resource, dry_run, &full_context);
-            continue;
+/// Mark `resource` completed in the checkpoint, with the variables exported
+/// by a `script` or `command` resource, which a resumed build skips rather
+/// than re-running.
+fn record_completed(runner: &CommandRunner, checkpoint: &mut Checkpoint, resource: &Resource) {
+    let exports = match get_resource_type(resource) {
+        "script" | "command" => resource
+            .export_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = runner
+                    .global_context
+                    .get(&format!("{}.{}", resource.name, name))?;
+                Some((name, value.clone()))
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+    checkpoint.record(&resource.name, exports);
+}
+
+/// Build the resources of `batch` at the same time, each on a fork of
+/// `runner` with its own connection opened by `connect`, then merge the forks
+/// back in manifest order.
+fn build_concurrently(
+    runner: &mut CommandRunner,
+    batch: &[Resource],
+    checkpoint: &mut Checkpoint,
+    connect: &dyn Fn() -> PgwireLite,
+    dry_run: bool,
+    show_queries: bool,
+    on_failure: &str,
+) {
+    if let [resource] = batch {
+        if build_resource(
+            runner,
+            resource,
+            checkpoint,
+            dry_run,
+            show_queries,
+            on_failure,
+        ) {
+            record_completed(runner, checkpoint, resource);
         }
+        return;
+    }
 
-        // Get resource queries (templates only, not yet rendered)
-        let (resource_queries, inline_query) = if let Some(sql_val) = resource
-            .sql
-            .as_ref()
-            .filter(|_| res_type == "command" || res_type == "query")
-        {
-            let iq = runner.render_inline_template(&resource.name, sql_val, &full_context);
-            (HashMap::new(), Some(iq))
-        } else {
-            (runner.get_queries(resource, &full_context), None)
-        };
+    let base_context = runner.global_context.clone();
+    let forks: Vec<CommandRunner> = batch.iter().map(|_| runner.fork(connect())).collect();
+    let shared_checkpoint: &Checkpoint = checkpoint;
+    let results: Vec<(CommandRunner, bool)> = thread::scope(|scope| {
+        let handles: Vec<_> = forks
+            .into_iter()
+            .zip(batch)
+            .map(|(mut fork, resource)| {
+                scope.spawn(move || {
+                    let deployed = build_resource(
+                        &mut fork,
+                        resource,
+                        shared_checkpoint,
+                        dry_run,
+                        show_queries,
+                        on_failure,
+                    );
+                    (fork, deployed)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| catch_error_and_exit("a parallel resource build panicked"))
+            })
+            .collect()
+    });
+
+    for ((fork, deployed), resource) in results.into_iter().zip(batch) {
+        runner.join(fork, &base_context);
+        if deployed {
+            record_completed(runner, checkpoint, resource);
+        }
+    }
+}
 
-        // Detect anchor presence and extract retry options (no rendering yet).
-        // All query rendering is deferred to the point of use (JIT) because
-        // exists may capture this.* fields needed by downstream queries.
-        let has_createorupdate = resource_queries.contains_key("createorupdate");
-        let create_retries;
-        let create_retry_delay;
-        let update_retries;
-        let update_retry_delay;
-
-        if res_type == "resource" || res_type == "multi" {
-            if has_createorupdate {
-                let cou = resource_queries.get("createorupdate").unwrap();
-                create_retries = cou.options.retries;
-                create_retry_delay = cou.options.retry_delay;
-                update_retries = cou.options.retries;
-                update_retry_delay = cou.options.retry_delay;
-            } else {
-                if let Some(cq) = resource_queries.get("create") {
-                    create_retries = cq.options.retries;
-                    create_retry_delay = cq.options.retry_delay;
-                } else {
-                    catch_error_and_exit(
-                        "iql file must include either 'create' or 'createorupdate' anchor.",
+/// Build a single resource.  Returns whether it was deployed, to be recorded
+/// in the checkpoint (never in a dry run).
+fn build_resource(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    checkpoint: &Checkpoint,
+    dry_run: bool,
+    show_queries: bool,
+    on_failure: &str,
+) -> bool {
+    print_unicode_box(
+        &format!("Processing resource: {}", resource.display_name()),
+        BorderColor::Blue,
+    );
+    runner.report.start_resource();
+
+    let res_type = get_resource_type(resource).to_string();
+    info!(
+        "processing resource {}, type: {}",
+        resource.display_name(),
+        res_type
+    );
+
+    let full_context = runner.get_full_context(resource);
+
+    // Evaluate condition
+    if !runner.evaluate_condition(resource, &full_context) {
+        runner
+            .report
+            .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
+        return false;
+    }
+
+    // Shared wall-clock budget for every retrying phase of this resource
+    let budget = RetryBudget::new(&resource.name, resource.retry_budget);
+
+    // On resume, scripts and commands that already ran are not repeated;
+    // other types are re-validated (and re-export) through the normal flow
+    if checkpoint.is_completed(&resource.name) {
+        if res_type == "script" || res_type == "command" {
+            info!(
+                "skipping [{}], completed by the previous run",
+                resource.name
+            );
+            match checkpoint.exports(&resource.name) {
+                Some(exports) => {
+                    let exports: HashMap<String, String> = exports.clone().into_iter().collect();
+                    let mut masked = resource.protected_export_names();
+                    masked.extend(exports.keys().filter(|k| is_sensitive_name(k)).cloned());
+                    export_vars(
+                        &mut runner.global_context,
+                        &mut runner.protected_names,
+                        &resource.name,
+                        &exports,
+                        &masked,
                     );
                 }
-                if let Some(uq) = resource_queries.get("update") {
-                    update_retries = uq.options.retries;
-                    update_retry_delay = uq.options.retry_delay;
-                } else {
-                    update_retries = 1;
-                    update_retry_delay = 0;
-                }
+                None if !resource.exports.is_empty() => warn!(
+                    "exports of [{}] were not checkpointed and are not available when resuming past it",
+                    resource.name
+                ),
+                None => {}
             }
-        } else {
-            create_retries = 1;
-            create_retry_delay = 0;
-            update_retries = 1;
-            update_retry_delay = 0;
+            runner.report.record(
+                &resource.name,
+                &res_type,
+                ResourceOutcome::Skipped,
+                Some("completed by the previous run".to_string()),
+            );
+            return false;
         }
+        info!(
+            "re-validating [{}], completed by the previous run",
+            resource.name
+        );
+    }
 
-        // Render exists eagerly (it never depends on this.* fields)
-        let exists_query = resource_queries.get("exists").map(|q| {
-            let rendered =
-                runner.render_query(&resource.name, "exists", &q.template, &full_context);
-            (rendered, q.options.clone())
-        });
+    // Handle script type
+    if res_type == "script" {
+        runner.process_script_resource(resource, dry_run, &full_context);
+        runner
+            .report
+            .record(&resource.name, &res_type, ResourceOutcome::Executed, None);
+        return !dry_run;
+    }
+
+    // Handle multi type expanded over its for_each items
+    if let Some(for_each) = resource.for_each.as_deref() {
+        let outcome =
+            runner.process_multi_resource(resource, for_each, dry_run, show_queries, &full_context);
+        runner
+            .report
+            .record(&resource.name, &res_type, outcome, None);
+        return !dry_run;
+    }
 
-        let mut full_context = full_context;
-        let exports_opts = resource_queries.get("exports");
-        let exports_retries = exports_opts.map_or(1, |q| q.options.retries);
-        let exports_retry_delay = exports_opts.map_or(0, |q| q.options.retry_delay);
-
-        // All other queries (create, update, statecheck, exports) are rendered
-        // JIT at the point of use, after exists has had a chance to capture
-        // this.* fields into full_context.
-        let mut exports_query_str: Option<String> = None;
-
-        // Handle query type: render exports eagerly (query types don't
-        // have exists/statecheck so there's no this.* deferral needed).
-        if res_type == "query" {
-            if let Some(ref iq) = inline_query {
-                exports_query_str = Some(iq.clone());
+    // Get resource queries (templates only, not yet rendered)
+    let (resource_queries, inline_query) = if let Some(sql_val) = resource
+        .sql
+        .as_ref()
+        .filter(|_| res_type == "command" || res_type == "query")
+    {
+        let iq = runner.render_inline_template(&resource.name, sql_val, &full_context);
+        (HashMap::new(), Some(iq))
+    } else {
+        (runner.get_queries(resource, &full_context), None)
+    };
+    if dry_run {
+        runner.report_query_options(resource, &resource_queries);
+    }
+
+    // Detect anchor presence and extract retry options (no rendering yet).
+    // All query rendering is deferred to the point of use (JIT) because
+    // exists may capture this.* fields needed by downstream queries.
+    let has_createorupdate = resource_queries.contains_key("createorupdate");
+    let create_retries;
+    let create_retry_delay;
+    let update_retries;
+    let update_retry_delay;
+
+    if res_type == "resource" || res_type == "multi" {
+        if has_createorupdate {
+            let cou = resource_queries.get("createorupdate").unwrap();
+            create_retries = cou.options.retries;
+            create_retry_delay = cou.options.retry_delay;
+            update_retries = cou.options.retries;
+            update_retry_delay = cou.options.retry_delay;
+        } else {
+            if let Some(cq) = resource_queries.get("create") {
+                create_retries = cq.options.retries;
+                create_retry_delay = cq.options.retry_delay;
             } else {
-                exports_query_str =
-                    render_exports!(runner, resource_queries, resource, &full_context);
-                if exports_query_str.is_none() {
-                    catch_error_and_exit(
-                        "Inline sql must be supplied or an iql file must be present with an 'exports' anchor for query type resources.",
-                    );
-                }
+                catch_error_and_exit(
+                    "iql file must include either 'create' or 'createorupdate' anchor.",
+                );
+            }
+            if let Some(uq) = resource_queries.get("update") {
+                update_retries = uq.options.retries;
+                update_retry_delay = uq.options.retry_delay;
+            } else {
+                update_retries = 1;
+                update_retry_delay = 0;
             }
         }
+    } else {
+        create_retries = 1;
+        create_retry_delay = 0;
+        update_retries = 1;
+        update_retry_delay = 0;
+    }
+    let create_backoff = runner.anchor_backoff(
+        resource,
+        if has_createorupdate {
+            "createorupdate"
+        } else {
+            "create"
+        },
+    );
+    let update_backoff = runner.anchor_backoff(
+        resource,
+        if has_createorupdate {
+            "createorupdate"
+        } else {
+            "update"
+        },
+    );
 
-        let mut exports_result_from_proxy: Option<Vec<HashMap<String, String>>> = None;
-
-        if res_type == "resource" || res_type == "multi" {
-            let ignore_errors = res_type == "multi";
-            let mut resource_exists = false;
-            let mut is_correct_state = false;
-
-            /// Inject fields captured by the exists query into the context as
-            /// `this.<field>` variables (scoped to the resource name), so that
-            /// statecheck / exports / delete templates can reference the
-            /// discovered identifiers.
-            fn apply_exists_fields(
-                fields: Option<HashMap<String, String>>,
-                resource_name: &str,
-                full_context: &mut HashMap<String, String>,
-            ) {
-                if let Some(ref f) = fields {
-                    for (k, v) in f {
-                        full_context.insert(format!("{}.{}", resource_name, k), v.clone());
-                    }
+    // Render exists eagerly (it never depends on this.* fields)
+    let exists_query = resource_queries.get("exists").map(|q| {
+        let rendered = runner.render_query(&resource.name, "exists", &q.template, &full_context);
+        (rendered, q.options.clone())
+    });
+
+    let mut full_context = full_context;
+    let exports_opts = resource_queries.get("exports");
+    let exports_retries = exports_opts.map_or(1, |q| q.options.retries);
+    let exports_retry_delay = exports_opts.map_or(0, |q| q.options.retry_delay);
+    let exports_backoff = runner.anchor_backoff(resource, "exports");
+
+    // All other queries (create, update, statecheck, exports) are rendered
+    // JIT at the point of use, after exists has had a chance to capture
+    // this.* fields into full_context.
+    let mut exports_query_str: Option<String> = None;
+
+    // Handle query type: render exports eagerly (query types don't
+    // have exists/statecheck so there's no this.* deferral needed).
+    if res_type == "query" {
+        if let Some(ref iq) = inline_query {
+            exports_query_str = Some(iq.clone());
+        } else {
+            exports_query_str = render_exports!(runner, resource_queries, resource, &full_context);
+            if exports_query_str.is_none() {
+                catch_error_and_exit(
+                    "Inline sql must be supplied or an iql file must be present with an 'exports' anchor for query type resources.",
+                );
+            }
+        }
+    }
+
+    let mut exports_result_from_proxy: Option<Vec<HashMap<String, String>>> = None;
+    let mut rollback_recorded = false;
+    let mut outcome = if res_type == "command" {
+        ResourceOutcome::Executed
+    } else {
+        ResourceOutcome::Unchanged
+    };
+
+    if res_type == "resource" || res_type == "multi" {
+        let mut would_replace = false;
+        if dry_run && runner.show_diff {
+            would_replace = print_resource_diff(
+                runner,
+                resource,
+                &resource_queries,
+                exists_query.as_ref().map(|q| q.0.as_str()),
+                &full_context,
+                show_queries,
+            );
+        }
+        let ignore_errors = res_type == "multi";
+        let mut resource_exists = false;
+        let mut is_correct_state = false;
+
+        /// Inject fields captured by the exists query into the context as
+        /// `this.<field>` variables (scoped to the resource name), so that
+        /// statecheck / exports / delete templates can reference the
+        /// discovered identifiers.
+        fn apply_exists_fields(
+            fields: Option<HashMap<String, String>>,
+            resource_name: &str,
+            full_context: &mut HashMap<String, String>,
+        ) {
+            if let Some(ref f) = fields {
+                for (k, v) in f {
+                    full_context.insert(format!("{}.{}", resource_name, k), v.clone());
                 }
             }
+        }
 
-            // State checking logic
-            if has_createorupdate {
-                // Skip all existence and state checks for createorupdate
-            } else if resource_queries.contains_key("statecheck") {
-                // Flow 1: Traditional flow when statecheck exists
-                if let Some(ref eq) = exists_query {
-                    // Pre-create: fast fail (1 attempt, no delay)
-                    let (exists, fields) = runner.check_if_resource_exists(
+        // State checking logic
+        if createorupdate_unchecked(&resource_queries) {
+            // Nothing to check against: the createorupdate query always runs
+        } else if resource_queries.contains_key("statecheck") {
+            // Flow 1: Traditional flow when statecheck exists
+            if let Some(ref eq) = exists_query {
+                // Pre-create: fast fail (1 attempt, no delay)
+                let (exists, fields) = runner.check_if_resource_exists(
+                    resource,
+                    &eq.0,
+                    1,
+                    0,
+                    dry_run,
+                    show_queries,
+                    false,
+                );
+                resource_exists = exists;
+
+                // If the exists query captured fields, inject them and
+                // re-render downstream queries.
+                if fields.is_some() {
+                    apply_exists_fields(fields, &resource.name, &mut full_context);
+                }
+            } else {
+                // Use statecheck as exists check (render with current ctx).
+                // If the statecheck template has unresolved variables (e.g.
+                // this.* fields not yet captured), the resource cannot exist
+                // yet - treat as not-found.
+                if let Some(sq) =
+                    render_statecheck!(runner, resource_queries, resource, &full_context)
+                {
+                    let sq_opts = resource_queries.get("statecheck").unwrap();
+                    is_correct_state = runner.check_if_resource_is_correct_state(
                         resource,
-                        &eq.0,
-                        1,
-                        0,
+                        &sq.0,
+                        budget.cap(
+                            sq_opts.options.retries,
+                            sq_opts.options.retry_delay,
+                            sq_opts.options.backoff,
+                        ),
+                        sq_opts.options.retry_delay,
                         dry_run,
                         show_queries,
-                        false,
                     );
-                    resource_exists = exists;
-
-                    // If the exists query captured fields, inject them and
-                    // re-render downstream queries.
-                    if fields.is_some() {
-                        apply_exists_fields(fields, &resource.name, &mut full_context);
-                    }
+                    resource_exists = is_correct_state;
                 } else {
-                    // Use statecheck as exists check (render with current ctx).
-                    // If the statecheck template has unresolved variables (e.g.
-                    // this.* fields not yet captured), the resource cannot exist
-                    // yet - treat as not-found.
+                    info!(
+                        "[{}] statecheck has unresolved variables, treating as not found",
+                        resource.name
+                    );
+                    resource_exists = false;
+                }
+            }
+
+            // Pre-deployment state check for existing resources
+            if resource_exists && !is_correct_state {
+                if resource.skip_validation.unwrap_or(false) {
+                    info!(
+                        "skipping validation for [{}] as skip_validation is set to true.",
+                        resource.name
+                    );
+                    is_correct_state = true;
+                } else {
+                    // Re-render statecheck with (possibly enriched) context
                     if let Some(sq) =
                         render_statecheck!(runner, resource_queries, resource, &full_context)
                     {
@@ -323,605 +1158,581 @@ fn run_build(
                         is_correct_state = runner.check_if_resource_is_correct_state(
                             resource,
                             &sq.0,
-                            sq_opts.options.retries,
+                            budget.cap(
+                                sq_opts.options.retries,
+                                sq_opts.options.retry_delay,
+                                sq_opts.options.backoff,
+                            ),
                             sq_opts.options.retry_delay,
                             dry_run,
                             show_queries,
                         );
-                        resource_exists = is_correct_state;
                     } else {
-                        info!(
-                            "[{}] statecheck has unresolved variables, treating as not found",
+                        warn!(
+                            "[{}] statecheck has unresolved variables during pre-deploy validation",
                             resource.name
                         );
-                        resource_exists = false;
                     }
                 }
+            }
 
-                // Pre-deployment state check for existing resources
-                if resource_exists && !is_correct_state {
-                    if resource.skip_validation.unwrap_or(false) {
-                        info!(
-                            "skipping validation for [{}] as skip_validation is set to true.",
-                            resource.name
-                        );
-                        is_correct_state = true;
-                    } else {
-                        // Re-render statecheck with (possibly enriched) context
-                        if let Some(sq) =
-                            render_statecheck!(runner, resource_queries, resource, &full_context)
-                        {
-                            let sq_opts = resource_queries.get("statecheck").unwrap();
-                            is_correct_state = runner.check_if_resource_is_correct_state(
-                                resource,
-                                &sq.0,
-                                sq_opts.options.retries,
-                                sq_opts.options.retry_delay,
-                                dry_run,
-                                show_queries,
-                            );
-                        } else {
-                            warn!(
-                                "[{}] statecheck has unresolved variables during pre-deploy validation",
-                                resource.name
-                            );
-                        }
-                    }
-                }
+            // Re-render exports with enriched context (only if exists
+            // captured fields; otherwise defer until post-create).
+            if resource_exists {
+                exports_query_str =
+                    render_exports!(runner, resource_queries, resource, &full_context);
+            }
+        } else if exports_query_str.is_some() {
+            // Flow 2: Optimized flow using exports as proxy
+            info!(
+                "trying exports query first (fast-fail) for optimal validation for [{}]",
+                resource.name
+            );
+            let (state, proxy_result) = runner.check_state_using_exports_proxy(
+                resource,
+                exports_query_str.as_ref().unwrap(),
+                1,
+                0,
+                dry_run,
+                show_queries,
+            );
+            is_correct_state = state;
+            resource_exists = is_correct_state;
 
-                // Re-render exports with enriched context (only if exists
-                // captured fields; otherwise defer until post-create).
-                if resource_exists {
-                    exports_query_str =
-                        render_exports!(runner, resource_queries, resource, &full_context);
-                }
-            } else if exports_query_str.is_some() {
-                // Flow 2: Optimized flow using exports as proxy
+            if is_correct_state {
                 info!(
-                    "trying exports query first (fast-fail) for optimal validation for [{}]",
+                    "[{}] validated successfully with fast exports query",
                     resource.name
                 );
-                let (state, proxy_result) = runner.check_state_using_exports_proxy(
-                    resource,
-                    exports_query_str.as_ref().unwrap(),
-                    1,
-                    0,
-                    dry_run,
-                    show_queries,
+                exports_result_from_proxy = proxy_result;
+            } else {
+                info!(
+                    "fast exports validation failed, falling back to exists check for [{}]",
+                    resource.name
                 );
-                is_correct_state = state;
-                resource_exists = is_correct_state;
+                exports_result_from_proxy = None;
 
-                if is_correct_state {
-                    info!(
-                        "[{}] validated successfully with fast exports query",
-                        resource.name
+                if let Some(ref eq) = exists_query {
+                    // Pre-create: fast fail (1 attempt, no delay)
+                    let (exists, fields) = runner.check_if_resource_exists(
+                        resource,
+                        &eq.0,
+                        1,
+                        0,
+                        dry_run,
+                        show_queries,
+                        false,
                     );
-                    exports_result_from_proxy = proxy_result;
+                    resource_exists = exists;
+
+                    if fields.is_some() {
+                        apply_exists_fields(fields, &resource.name, &mut full_context);
+                    }
+                    // Always try to render exports after fallback exists
+                    // (needed for count-based exists where exports doesn't
+                    // depend on this.* fields).
+                    exports_query_str =
+                        render_exports!(runner, resource_queries, resource, &full_context);
                 } else {
+                    resource_exists = false;
+                }
+            }
+        } else if let Some(ref eq) = exists_query {
+            // Flow 3: exists query only (no statecheck rendered yet)
+            // Pre-create: fast fail (1 attempt, no delay)
+            let (exists, fields) = runner.check_if_resource_exists(
+                resource,
+                &eq.0,
+                1,
+                0,
+                dry_run,
+                show_queries,
+                false,
+            );
+            resource_exists = exists;
+            let has_fields = fields.is_some();
+
+            if has_fields {
+                apply_exists_fields(fields, &resource.name, &mut full_context);
+            }
+            // Always try to render exports after exists
+            exports_query_str = render_exports!(runner, resource_queries, resource, &full_context);
+
+            // Determine correctness based on what's available:
+            if exists {
+                if let Some(ref eq_str) = exports_query_str {
+                    // Use exports as statecheck proxy
                     info!(
-                        "fast exports validation failed, falling back to exists check for [{}]",
+                        "using exports query as statecheck proxy for [{}]",
                         resource.name
                     );
-                    exports_result_from_proxy = None;
+                    let (state, proxy) = runner.check_state_using_exports_proxy(
+                        resource,
+                        eq_str,
+                        budget.cap(exports_retries, exports_retry_delay, exports_backoff),
+                        exports_retry_delay,
+                        dry_run,
+                        show_queries,
+                    );
+                    is_correct_state = state;
+                    if proxy.is_some() {
+                        exports_result_from_proxy = proxy;
+                    }
+                } else {
+                    // No statecheck and no exports: exists IS the statecheck
+                    is_correct_state = true;
+                }
+            }
+        } else {
+            catch_error_and_exit(
+                "iql file must include either 'exists', 'statecheck', or 'exports' anchor.",
+            );
+        }
 
-                    if let Some(ref eq) = exists_query {
-                        // Pre-create: fast fail (1 attempt, no delay)
-                        let (exists, fields) = runner.check_if_resource_exists(
-                            resource,
-                            &eq.0,
-                            1,
-                            0,
-                            dry_run,
-                            show_queries,
-                            false,
-                        );
-                        resource_exists = exists;
+        // Resources which cannot be updated in place are deleted here
+        // and recreated by the create path below
+        let mut replaced = false;
+        if resource_exists && !is_correct_state && needs_replacement(resource, &full_context) {
+            replace_existing(
+                runner,
+                resource,
+                &resource_queries,
+                exists_query.as_ref().map(|q| q.0.as_str()),
+                &full_context,
+                dry_run,
+                show_queries,
+                ignore_errors,
+            );
+            resource_exists = false;
+            replaced = true;
+        }
 
-                        if fields.is_some() {
-                            apply_exists_fields(fields, &resource.name, &mut full_context);
-                        }
-                        // Always try to render exports after fallback exists
-                        // (needed for count-based exists where exports doesn't
-                        // depend on this.* fields).
-                        exports_query_str =
-                            render_exports!(runner, resource_queries, resource, &full_context);
-                    } else {
-                        resource_exists = false;
-                    }
+        // Create or update
+        let mut is_created_or_updated = false;
+        if replaced || would_replace {
+            outcome = ResourceOutcome::Replaced;
+        } else if createorupdate_unchecked(&resource_queries) {
+            outcome = ResourceOutcome::Applied;
+        } else if !resource_exists {
+            outcome = ResourceOutcome::Created;
+        } else if !is_correct_state {
+            outcome = ResourceOutcome::Updated;
+        }
+
+        if !resource_exists {
+            // JIT render create/createorupdate query.
+            // In dry-run mode, use try_render_query so that unresolved
+            // variables (from exports not yet available) produce a
+            // deferral instead of a hard error.
+            let create_query = if has_createorupdate {
+                let cou = resource_queries.get("createorupdate").unwrap();
+                if dry_run {
+                    runner.try_render_query(
+                        &resource.name,
+                        "createorupdate",
+                        &cou.template,
+                        &full_context,
+                    )
+                } else {
+                    Some(runner.render_query(
+                        &resource.name,
+                        "createorupdate",
+                        &cou.template,
+                        &full_context,
+                    ))
                 }
-            } else if let Some(ref eq) = exists_query {
-                // Flow 3: exists query only (no statecheck rendered yet)
-                // Pre-create: fast fail (1 attempt, no delay)
-                let (exists, fields) = runner.check_if_resource_exists(
+            } else {
+                let cq = resource_queries.get("create").unwrap();
+                if dry_run {
+                    runner.try_render_query(&resource.name, "create", &cq.template, &full_context)
+                } else {
+                    Some(runner.render_query(&resource.name, "create", &cq.template, &full_context))
+                }
+            };
+
+            if create_query.is_none() {
+                info!(
+                    "dry run create for [{}]: query has unresolved variables \
+                     (upstream exports not yet available), skipping render",
+                    resource.name
+                );
+            }
+
+            let (created, returning_row) = if let Some(ref cq) = create_query {
+                runner.create_resource(
                     resource,
-                    &eq.0,
-                    1,
-                    0,
+                    cq,
+                    budget.cap(create_retries, create_retry_delay, create_backoff),
+                    create_retry_delay,
                     dry_run,
                     show_queries,
-                    false,
-                );
-                resource_exists = exists;
-                let has_fields = fields.is_some();
+                    ignore_errors,
+                )
+            } else {
+                (false, None)
+            };
+            is_created_or_updated = created;
+
+            // Only a resource which a check showed to be missing was created
+            // by this run and may be deleted if the build is rolled back;
+            // recorded before the post-deploy checks, which may fail the build
+            rollback_recorded =
+                created && !dry_run && outcome == ResourceOutcome::Created && rollback::is_armed();
+            if rollback_recorded {
+                record_for_rollback(runner, resource, &resource_queries, &full_context);
+            }
 
-                if has_fields {
-                    apply_exists_fields(fields, &resource.name, &mut full_context);
+            // Capture RETURNING * result.
+            if let Some(ref row) = returning_row {
+                debug!("RETURNING payload for [{}]: {:?}", resource.name, row);
+                runner.store_callback_data(&resource.name, row);
+
+                // Apply return_vals mappings from manifest.
+                let mappings = resource.get_return_val_mappings("create");
+                if !mappings.is_empty() {
+                    let mut fields = HashMap::new();
+                    for (src, tgt) in &mappings {
+                        if let Some(val) = row.get(src.as_str()) {
+                            if !val.is_empty() && val != "null" {
+                                info!(
+                                    "RETURNING [{}] for [{}] captured as [this.{}] = [{}]",
+                                    src, resource.name, tgt, val
+                                );
+                                fields.insert(tgt.clone(), val.clone());
+                            } else {
+                                catch_error_and_exit(&format!(
+                                    "return_vals for [{}]: field [{}] in RETURNING result \
+                                     is null or empty.",
+                                    resource.name, src
+                                ));
+                            }
+                        } else {
+                            catch_error_and_exit(&format!(
+                                "return_vals for [{}]: expected field [{}] not found in \
+                                 RETURNING result. Ensure the create query includes \
+                                 'RETURNING *' or 'RETURNING {}'.",
+                                resource.name, src, src
+                            ));
+                        }
+                    }
+                    apply_exists_fields(Some(fields), &resource.name, &mut full_context);
+                    // Re-render exports/statecheck with the captured values
+                    exports_query_str =
+                        render_exports!(runner, resource_queries, resource, &full_context);
                 }
-                // Always try to render exports after exists
-                exports_query_str =
-                    render_exports!(runner, resource_queries, resource, &full_context);
+            } else if !resource.get_return_val_mappings("create").is_empty() {
+                warn!(
+                    "return_vals specified for [{}] create but no RETURNING data received. \
+                     Will fall back to post-create exists query.",
+                    resource.name
+                );
+            }
 
-                // Determine correctness based on what's available:
-                if exists {
-                    if let Some(ref eq_str) = exports_query_str {
-                        // Use exports as statecheck proxy
-                        info!(
-                            "using exports query as statecheck proxy for [{}]",
-                            resource.name
-                        );
-                        let (state, proxy) = runner.check_state_using_exports_proxy(
+            // Run callback:create block if present.
+            if is_created_or_updated {
+                let cb_anchor = if resource_queries.contains_key("callback:create") {
+                    Some("callback:create")
+                } else if resource_queries.contains_key("callback") {
+                    Some("callback")
+                } else {
+                    None
+                };
+                if let Some(anchor) = cb_anchor {
+                    // Pre-extract before the mutable borrow of runner.
+                    if let Some(q) = resource_queries.get(anchor) {
+                        let cb_template = q.template.clone();
+                        let cb_retries = q.options.retries;
+                        let cb_delay = q.options.retry_delay;
+                        let cb_backoff = q.options.backoff;
+                        let cb_sc_field = q.options.short_circuit_field.clone();
+                        let cb_sc_value = q.options.short_circuit_value.clone();
+                        let cb_ctx = runner.get_full_context(resource);
+                        let rendered_cb =
+                            runner.render_query(&resource.name, anchor, &cb_template, &cb_ctx);
+                        runner.run_callback(
                             resource,
-                            eq_str,
-                            exports_retries,
-                            exports_retry_delay,
+                            &rendered_cb,
+                            budget.cap(cb_retries, cb_delay, cb_backoff),
+                            cb_delay,
+                            cb_backoff,
+                            cb_sc_field.as_deref(),
+                            cb_sc_value.as_deref(),
+                            "create",
                             dry_run,
                             show_queries,
                         );
-                        is_correct_state = state;
-                        if proxy.is_some() {
-                            exports_result_from_proxy = proxy;
-                        }
-                    } else {
-                        // No statecheck and no exports: exists IS the statecheck
-                        is_correct_state = true;
                     }
                 }
-            } else {
-                catch_error_and_exit(
-                    "iql file must include either 'exists', 'statecheck', or 'exports' anchor.",
-                );
             }
+        }
 
-            // Create or update
-            let mut is_created_or_updated = false;
-
-            if !resource_exists {
-                // JIT render create/createorupdate query.
-                // In dry-run mode, use try_render_query so that unresolved
-                // variables (from exports not yet available) produce a
-                // deferral instead of a hard error.
-                let create_query = if has_createorupdate {
-                    let cou = resource_queries.get("createorupdate").unwrap();
-                    if dry_run {
-                        runner.try_render_query(
-                            &resource.name,
-                            "createorupdate",
-                            &cou.template,
-                            &full_context,
-                        )
-                    } else {
-                        Some(runner.render_query(
-                            &resource.name,
-                            "createorupdate",
-                            &cou.template,
-                            &full_context,
-                        ))
-                    }
+        if resource_exists && !is_correct_state {
+            // JIT render update/createorupdate query.
+            // In dry-run mode, use try_render_query for tolerance.
+            let update_query: Option<String> = if has_createorupdate {
+                let cou = resource_queries.get("createorupdate").unwrap();
+                if dry_run {
+                    runner.try_render_query(
+                        &resource.name,
+                        "createorupdate",
+                        &cou.template,
+                        &full_context,
+                    )
                 } else {
-                    let cq = resource_queries.get("create").unwrap();
+                    Some(runner.render_query(
+                        &resource.name,
+                        "createorupdate",
+                        &cou.template,
+                        &full_context,
+                    ))
+                }
+            } else {
+                resource_queries.get("update").and_then(|uq| {
                     if dry_run {
                         runner.try_render_query(
                             &resource.name,
-                            "create",
-                            &cq.template,
+                            "update",
+                            &uq.template,
                             &full_context,
                         )
                     } else {
                         Some(runner.render_query(
                             &resource.name,
-                            "create",
-                            &cq.template,
+                            "update",
+                            &uq.template,
                             &full_context,
                         ))
                     }
-                };
+                })
+            };
 
-                if create_query.is_none() {
-                    info!(
-                        "dry run create for [{}]: query has unresolved variables \
-                         (upstream exports not yet available), skipping render",
-                        resource.name
-                    );
-                }
+            if update_query.is_none() && dry_run {
+                info!(
+                    "dry run update for [{}]: query has unresolved variables \
+                     (upstream exports not yet available), skipping render",
+                    resource.name
+                );
+            }
 
-                let (created, returning_row) = if let Some(ref cq) = create_query {
-                    runner.create_resource(
-                        resource,
-                        cq,
-                        create_retries,
-                        create_retry_delay,
-                        dry_run,
-                        show_queries,
-                        ignore_errors,
-                    )
-                } else {
-                    (false, None)
-                };
-                is_created_or_updated = created;
-
-                // Capture RETURNING * result.
-                if let Some(ref row) = returning_row {
-                    debug!("RETURNING payload for [{}]: {:?}", resource.name, row);
-                    runner.store_callback_data(&resource.name, row);
-
-                    // Apply return_vals mappings from manifest.
-                    let mappings = resource.get_return_val_mappings("create");
-                    if !mappings.is_empty() {
-                        let mut fields = HashMap::new();
-                        for (src, tgt) in &mappings {
-                            if let Some(val) = row.get(src.as_str()) {
-                                if !val.is_empty() && val != "null" {
-                                    info!(
-                                        "RETURNING [{}] for [{}] captured as [this.{}] = [{}]",
-                                        src, resource.name, tgt, val
-                                    );
-                                    fields.insert(tgt.clone(), val.clone());
-                                } else {
-                                    catch_error_and_exit(&format!(
-                                        "return_vals for [{}]: field [{}] in RETURNING result \
-                                         is null or empty.",
-                                        resource.name, src
-                                    ));
-                                }
+            let (updated, returning_row) = runner.update_resource(
+                resource,
+                update_query.as_deref(),
+                budget.cap(update_retries, update_retry_delay, update_backoff),
+                update_retry_delay,
+                dry_run,
+                show_queries,
+                ignore_errors,
+            );
+            is_created_or_updated = updated;
+
+            // Capture RETURNING * result.
+            if let Some(ref row) = returning_row {
+                debug!(
+                    "RETURNING payload for [{}] (update): {:?}",
+                    resource.name, row
+                );
+                runner.store_callback_data(&resource.name, row);
+
+                // Apply return_vals mappings from manifest.
+                let mappings = resource.get_return_val_mappings("update");
+                if !mappings.is_empty() {
+                    let mut fields = HashMap::new();
+                    for (src, tgt) in &mappings {
+                        if let Some(val) = row.get(src.as_str()) {
+                            if !val.is_empty() && val != "null" {
+                                info!(
+                                    "RETURNING [{}] for [{}] captured as [this.{}] = [{}]",
+                                    src, resource.name, tgt, val
+                                );
+                                fields.insert(tgt.clone(), val.clone());
                             } else {
                                 catch_error_and_exit(&format!(
-                                    "return_vals for [{}]: expected field [{}] not found in \
-                                     RETURNING result. Ensure the create query includes \
-                                     'RETURNING *' or 'RETURNING {}'.",
-                                    resource.name, src, src
+                                    "return_vals for [{}]: field [{}] in RETURNING result \
+                                     is null or empty.",
+                                    resource.name, src
                                 ));
                             }
+                        } else {
+                            catch_error_and_exit(&format!(
+                                "return_vals for [{}]: expected field [{}] not found in \
+                                 RETURNING result. Ensure the update query includes \
+                                 'RETURNING *' or 'RETURNING {}'.",
+                                resource.name, src, src
+                            ));
                         }
-                        apply_exists_fields(Some(fields), &resource.name, &mut full_context);
-                        // Re-render exports/statecheck with the captured values
-                        exports_query_str =
-                            render_exports!(runner, resource_queries, resource, &full_context);
                     }
-                } else if !resource.get_return_val_mappings("create").is_empty() {
-                    warn!(
-                        "return_vals specified for [{}] create but no RETURNING data received. \
-                         Will fall back to post-create exists query.",
-                        resource.name
-                    );
+                    apply_exists_fields(Some(fields), &resource.name, &mut full_context);
+                    exports_query_str =
+                        render_exports!(runner, resource_queries, resource, &full_context);
                 }
+            } else if !resource.get_return_val_mappings("update").is_empty()
+                && is_created_or_updated
+            {
+                warn!(
+                    "return_vals specified for [{}] update but no RETURNING data received. \
+                     Will fall back to post-update exists query.",
+                    resource.name
+                );
+            }
 
-                // Run callback:create block if present.
-                if is_created_or_updated {
-                    let cb_anchor = if resource_queries.contains_key("callback:create") {
-                        Some("callback:create")
-                    } else if resource_queries.contains_key("callback") {
-                        Some("callback")
-                    } else {
-                        None
-                    };
-                    if let Some(anchor) = cb_anchor {
-                        // Pre-extract before the mutable borrow of runner.
-                        if let Some(q) = resource_queries.get(anchor) {
-                            let cb_template = q.template.clone();
-                            let cb_retries = q.options.retries;
-                            let cb_delay = q.options.retry_delay;
-                            let cb_sc_field = q.options.short_circuit_field.clone();
-                            let cb_sc_value = q.options.short_circuit_value.clone();
-                            let cb_ctx = runner.get_full_context(resource);
-                            let rendered_cb =
-                                runner.render_query(&resource.name, anchor, &cb_template, &cb_ctx);
-                            runner.run_callback(
-                                resource,
-                                &rendered_cb,
-                                cb_retries,
-                                cb_delay,
-                                cb_sc_field.as_deref(),
-                                cb_sc_value.as_deref(),
-                                "create",
-                                dry_run,
-                                show_queries,
-                            );
-                        }
+            // Run callback:update block if present.
+            if is_created_or_updated {
+                let cb_anchor = if resource_queries.contains_key("callback:update") {
+                    Some("callback:update")
+                } else if resource_queries.contains_key("callback") {
+                    Some("callback")
+                } else {
+                    None
+                };
+                if let Some(anchor) = cb_anchor {
+                    if let Some(q) = resource_queries.get(anchor) {
+                        let cb_template = q.template.clone();
+                        let cb_retries = q.options.retries;
+                        let cb_delay = q.options.retry_delay;
+                        let cb_backoff = q.options.backoff;
+                        let cb_sc_field = q.options.short_circuit_field.clone();
+                        let cb_sc_value = q.options.short_circuit_value.clone();
+                        let cb_ctx = runner.get_full_context(resource);
+                        let rendered_cb =
+                            runner.render_query(&resource.name, anchor, &cb_template, &cb_ctx);
+                        runner.run_callback(
+                            resource,
+                            &rendered_cb,
+                            budget.cap(cb_retries, cb_delay, cb_backoff),
+                            cb_delay,
+                            cb_backoff,
+                            cb_sc_field.as_deref(),
+                            cb_sc_value.as_deref(),
+                            "update",
+                            dry_run,
+                            show_queries,
+                        );
                     }
                 }
             }
+        }
 
-            if resource_exists && !is_correct_state {
-                // JIT render update/createorupdate query.
-                // In dry-run mode, use try_render_query for tolerance.
-                let update_query: Option<String> = if has_createorupdate {
-                    let cou = resource_queries.get("createorupdate").unwrap();
-                    if dry_run {
-                        runner.try_render_query(
-                            &resource.name,
-                            "createorupdate",
-                            &cou.template,
-                            &full_context,
-                        )
+        // Post-deploy state check
+        if is_created_or_updated {
+            let op = if !resource_exists { "create" } else { "update" };
+
+            // After create/update, re-run the exists query to capture
+            // this.* fields (e.g. identifier) needed by statecheck and
+            // exports queries.  This always runs even when return_vals
+            // captured some fields, because the exists query discovers
+            // the resource identifier and waits for the resource to
+            // become available (async/eventual consistency).
+            if let Some(ref eq) = exists_query {
+                // Use statecheck retry settings for the post-create
+                // exists check when available (async providers need
+                // time for the resource to become discoverable).
+                let (post_retries, post_delay) =
+                    if let Some(sc_opts) = resource_queries.get("statecheck") {
+                        (sc_opts.options.retries, sc_opts.options.retry_delay)
                     } else {
-                        Some(runner.render_query(
-                            &resource.name,
-                            "createorupdate",
-                            &cou.template,
-                            &full_context,
-                        ))
-                    }
-                } else {
-                    resource_queries.get("update").and_then(|uq| {
-                        if dry_run {
-                            runner.try_render_query(
-                                &resource.name,
-                                "update",
-                                &uq.template,
-                                &full_context,
-                            )
-                        } else {
-                            Some(runner.render_query(
-                                &resource.name,
-                                "update",
-                                &uq.template,
-                                &full_context,
-                            ))
-                        }
-                    })
-                };
-
-                if update_query.is_none() && dry_run {
-                    info!(
-                        "dry run update for [{}]: query has unresolved variables \
-                         (upstream exports not yet available), skipping render",
-                        resource.name
-                    );
-                }
+                        let eq_opts = resource_queries.get("exists").unwrap();
+                        (eq_opts.options.retries, eq_opts.options.retry_delay)
+                    };
+                // The check runs the exists query, with its backoff
+                let post_backoff = runner.anchor_backoff(resource, "exists");
 
-                let (updated, returning_row) = runner.update_resource(
+                let (post_exists, fields) = runner.check_if_resource_exists(
                     resource,
-                    update_query.as_deref(),
-                    update_retries,
-                    update_retry_delay,
+                    &eq.0,
+                    budget.cap(post_retries, post_delay, post_backoff),
+                    post_delay,
                     dry_run,
                     show_queries,
-                    ignore_errors,
+                    false,
                 );
-                is_created_or_updated = updated;
-
-                // Capture RETURNING * result.
-                if let Some(ref row) = returning_row {
-                    debug!(
-                        "RETURNING payload for [{}] (update): {:?}",
-                        resource.name, row
-                    );
-                    runner.store_callback_data(&resource.name, row);
-
-                    // Apply return_vals mappings from manifest.
-                    let mappings = resource.get_return_val_mappings("update");
-                    if !mappings.is_empty() {
-                        let mut fields = HashMap::new();
-                        for (src, tgt) in &mappings {
-                            if let Some(val) = row.get(src.as_str()) {
-                                if !val.is_empty() && val != "null" {
-                                    info!(
-                                        "RETURNING [{}] for [{}] captured as [this.{}] = [{}]",
-                                        src, resource.name, tgt, val
-                                    );
-                                    fields.insert(tgt.clone(), val.clone());
-                                } else {
-                                    catch_error_and_exit(&format!(
-                                        "return_vals for [{}]: field [{}] in RETURNING result \
-                                         is null or empty.",
-                                        resource.name, src
-                                    ));
-                                }
-                            } else {
-                                catch_error_and_exit(&format!(
-                                    "return_vals for [{}]: expected field [{}] not found in \
-                                     RETURNING result. Ensure the update query includes \
-                                     'RETURNING *' or 'RETURNING {}'.",
-                                    resource.name, src, src
-                                ));
-                            }
-                        }
-                        apply_exists_fields(Some(fields), &resource.name, &mut full_context);
-                        exports_query_str =
-                            render_exports!(runner, resource_queries, resource, &full_context);
-                    }
-                } else if !resource.get_return_val_mappings("update").is_empty()
-                    && is_created_or_updated
-                {
-                    warn!(
-                        "return_vals specified for [{}] update but no RETURNING data received. \
-                         Will fall back to post-update exists query.",
-                        resource.name
-                    );
-                }
-
-                // Run callback:update block if present.
-                if is_created_or_updated {
-                    let cb_anchor = if resource_queries.contains_key("callback:update") {
-                        Some("callback:update")
-                    } else if resource_queries.contains_key("callback") {
-                        Some("callback")
-                    } else {
-                        None
-                    };
-                    if let Some(anchor) = cb_anchor {
-                        if let Some(q) = resource_queries.get(anchor) {
-                            let cb_template = q.template.clone();
-                            let cb_retries = q.options.retries;
-                            let cb_delay = q.options.retry_delay;
-                            let cb_sc_field = q.options.short_circuit_field.clone();
-                            let cb_sc_value = q.options.short_circuit_value.clone();
-                            let cb_ctx = runner.get_full_context(resource);
-                            let rendered_cb =
-                                runner.render_query(&resource.name, anchor, &cb_template, &cb_ctx);
-                            runner.run_callback(
-                                resource,
-                                &rendered_cb,
-                                cb_retries,
-                                cb_delay,
-                                cb_sc_field.as_deref(),
-                                cb_sc_value.as_deref(),
-                                "update",
-                                dry_run,
-                                show_queries,
-                            );
-                        }
-                    }
-                }
-            }
 
-            // Post-deploy state check
-            if is_created_or_updated {
-                let op = if !resource_exists { "create" } else { "update" };
-
-                // After create/update, re-run the exists query to capture
-                // this.* fields (e.g. identifier) needed by statecheck and
-                // exports queries.  This always runs even when return_vals
-                // captured some fields, because the exists query discovers
-                // the resource identifier and waits for the resource to
-                // become available (async/eventual consistency).
-                if let Some(ref eq) = exists_query {
-                    // Use statecheck retry settings for the post-create
-                    // exists check when available (async providers need
-                    // time for the resource to become discoverable).
-                    let (post_retries, post_delay) =
-                        if let Some(sc_opts) = resource_queries.get("statecheck") {
-                            (sc_opts.options.retries, sc_opts.options.retry_delay)
-                        } else {
-                            let eq_opts = resource_queries.get("exists").unwrap();
-                            (eq_opts.options.retries, eq_opts.options.retry_delay)
-                        };
-
-                    let (post_exists, fields) = runner.check_if_resource_exists(
+                // If exists retries are exhausted and resource still
+                // not found, run troubleshoot and exit immediately -
+                // don't attempt statecheck/exports.
+                if !post_exists && !dry_run {
+                    runner.run_troubleshoot(
                         resource,
-                        &eq.0,
-                        post_retries,
-                        post_delay,
-                        dry_run,
+                        &resource_queries,
+                        op,
+                        &full_context,
                         show_queries,
-                        false,
                     );
+                    catch_error_and_exit(&format!(
+                        "[{}] not found after {} post-deploy check, {} operation may have failed.",
+                        resource.name, op, op
+                    ));
+                }
 
-                    // If exists retries are exhausted and resource still
-                    // not found, run troubleshoot and exit immediately -
-                    // don't attempt statecheck/exports.
-                    if !post_exists && !dry_run {
-                        runner.run_troubleshoot(
-                            resource,
-                            &resource_queries,
-                            op,
-                            &full_context,
-                            show_queries,
-                        );
-                        catch_error_and_exit(&format!(
-                            "[{}] not found after {} post-deploy check, {} operation may have failed.",
-                            resource.name, op, op
-                        ));
-                    }
-
-                    apply_exists_fields(fields, &resource.name, &mut full_context);
+                apply_exists_fields(fields, &resource.name, &mut full_context);
+                if rollback_recorded {
+                    record_for_rollback(runner, resource, &resource_queries, &full_context);
+                }
 
-                    // Always try to render exports after post-create exists
-                    exports_query_str =
-                        render_exports!(runner, resource_queries, resource, &full_context);
+                // Always try to render exports after post-create exists
+                exports_query_str =
+                    render_exports!(runner, resource_queries, resource, &full_context);
 
-                    // If exists confirms the resource is present and there is
-                    // no statecheck or exports query, the exists query IS
-                    // the statecheck: a successful re-run confirms the
-                    // resource was created/updated successfully.
-                    if post_exists
-                        && !resource_queries.contains_key("statecheck")
-                        && exports_query_str.is_none()
-                    {
-                        is_correct_state = true;
-                    }
+                // If exists confirms the resource is present and there is
+                // no statecheck or exports query, the exists query IS
+                // the statecheck: a successful re-run confirms the
+                // resource was created/updated successfully.
+                if post_exists
+                    && !resource_queries.contains_key("statecheck")
+                    && exports_query_str.is_none()
+                {
+                    is_correct_state = true;
                 }
+            }
 
-                // If exports wasn't rendered yet (e.g. no exists query to
-                // trigger it), try now — the context may already contain all
-                // the variables the exports template needs.
-                if exports_query_str.is_none() {
-                    exports_query_str =
-                        render_exports!(runner, resource_queries, resource, &full_context);
-                }
+            // If exports wasn't rendered yet (e.g. no exists query to
+            // trigger it), try now — the context may already contain all
+            // the variables the exports template needs.
+            if exports_query_str.is_none() {
+                exports_query_str =
+                    render_exports!(runner, resource_queries, resource, &full_context);
+            }
 
-                debug!(
-                    "post-deploy for [{}]: is_correct_state={}, has_statecheck={}, exports_query_str={}",
-                    resource.name,
-                    is_correct_state,
-                    resource_queries.contains_key("statecheck"),
-                    if exports_query_str.is_some() { "Some" } else { "None" }
-                );
+            debug!(
+                "post-deploy for [{}]: is_correct_state={}, has_statecheck={}, exports_query_str={}",
+                resource.name,
+                is_correct_state,
+                resource_queries.contains_key("statecheck"),
+                if exports_query_str.is_some() { "Some" } else { "None" }
+            );
 
-                if let Some(sq) =
-                    render_statecheck!(runner, resource_queries, resource, &full_context)
-                {
-                    let sq_opts = resource_queries.get("statecheck").unwrap();
-                    is_correct_state = runner.check_if_resource_is_correct_state(
-                        resource,
-                        &sq.0,
+            if let Some(sq) = render_statecheck!(runner, resource_queries, resource, &full_context)
+            {
+                let sq_opts = resource_queries.get("statecheck").unwrap();
+                is_correct_state = runner.check_if_resource_is_correct_state(
+                    resource,
+                    &sq.0,
+                    budget.cap(
                         sq_opts.options.retries,
                         sq_opts.options.retry_delay,
-                        dry_run,
-                        show_queries,
-                    );
-                } else if resource_queries.contains_key("statecheck") {
-                    // Statecheck anchor exists but could not be rendered (unresolved
-                    // this.* variables). Fall through to exports-as-proxy if available,
-                    // otherwise treat as correct (the resource was just created and
-                    // the post-create exists query did not return identifier fields).
-                    if let Some(ref eq_str) = exports_query_str {
-                        info!(
-                            "statecheck deferred for [{}], using exports query as post-deploy statecheck",
-                            resource.name
-                        );
-                        let post_retries = exports_retries;
-                        let post_delay = exports_retry_delay;
-
-                        let (state, proxy) = runner.check_state_using_exports_proxy(
-                            resource,
-                            eq_str,
-                            post_retries,
-                            post_delay,
-                            dry_run,
-                            show_queries,
-                        );
-                        is_correct_state = state;
-                        if proxy.is_some() {
-                            exports_result_from_proxy = proxy;
-                        }
-                    } else {
-                        info!(
-                            "statecheck deferred for [{}] and no exports available, \
-                             accepting post-deploy state based on successful create/update",
-                            resource.name
-                        );
-                        is_correct_state = true;
-                    }
-                } else if has_createorupdate {
-                    info!(
-                        "createorupdate for [{}] is authoritative, skipping exports-as-statecheck proxy",
-                        resource.name
-                    );
-                    is_correct_state = true;
-                } else if let Some(ref eq_str) = exports_query_str {
+                        sq_opts.options.backoff,
+                    ),
+                    sq_opts.options.retry_delay,
+                    dry_run,
+                    show_queries,
+                );
+            } else if resource_queries.contains_key("statecheck") {
+                // Statecheck anchor exists but could not be rendered (unresolved
+                // this.* variables). Fall through to exports-as-proxy if available,
+                // otherwise treat as correct (the resource was just created and
+                // the post-create exists query did not return identifier fields).
+                if let Some(ref eq_str) = exports_query_str {
                     info!(
-                        "using exports query as post-deploy statecheck for [{}]",
+                        "statecheck deferred for [{}], using exports query as post-deploy statecheck",
                         resource.name
                     );
                     let post_retries = exports_retries;
                     let post_delay = exports_retry_delay;
+                    let post_backoff = exports_backoff;
 
                     let (state, proxy) = runner.check_state_using_exports_proxy(
                         resource,
                         eq_str,
-                        post_retries,
+                        budget.cap(post_retries, post_delay, post_backoff),
                         post_delay,
                         dry_run,
                         show_queries,
@@ -930,142 +1741,867 @@ fn run_build(
                     if proxy.is_some() {
                         exports_result_from_proxy = proxy;
                     }
+                } else {
+                    info!(
+                        "statecheck deferred for [{}] and no exports available, \
+                         accepting post-deploy state based on successful create/update",
+                        resource.name
+                    );
+                    is_correct_state = true;
                 }
-            }
+            } else if has_createorupdate {
+                info!(
+                    "createorupdate for [{}] is authoritative, skipping exports-as-statecheck proxy",
+                    resource.name
+                );
+                is_correct_state = true;
+            } else if let Some(ref eq_str) = exports_query_str {
+                info!(
+                    "using exports query as post-deploy statecheck for [{}]",
+                    resource.name
+                );
+                let post_retries = exports_retries;
+                let post_delay = exports_retry_delay;
+                let post_backoff = exports_backoff;
 
-            if !is_correct_state && !dry_run {
-                let op = if !resource_exists { "create" } else { "update" };
-                runner.run_troubleshoot(
+                let (state, proxy) = runner.check_state_using_exports_proxy(
                     resource,
-                    &resource_queries,
-                    op,
-                    &full_context,
+                    eq_str,
+                    budget.cap(post_retries, post_delay, post_backoff),
+                    post_delay,
+                    dry_run,
                     show_queries,
                 );
-                catch_error_and_exit(&format!(
-                    "deployment failed for {} after post-deploy checks.",
-                    resource.name
-                ));
+                is_correct_state = state;
+                if proxy.is_some() {
+                    exports_result_from_proxy = proxy;
+                }
             }
         }
 
-        // Handle command type
-        if res_type == "command" {
-            let (command_query, command_retries, command_retry_delay) = if let Some(ref iq) =
-                inline_query
-            {
-                (iq.clone(), 1u32, 0u32)
-            } else if let Some(cq) = resource_queries.get("command") {
-                let rendered =
-                    runner.render_query(&resource.name, "command", &cq.template, &full_context);
-                (rendered, cq.options.retries, cq.options.retry_delay)
-            } else {
-                catch_error_and_exit(
-                        "'sql' should be defined in the resource or the 'command' anchor needs to be supplied in the corresponding iql file for command type resources.",
-                    );
-            };
+        if !is_correct_state && !dry_run {
+            let op = if !resource_exists { "create" } else { "update" };
+            runner.run_troubleshoot(resource, &resource_queries, op, &full_context, show_queries);
+            if on_failure == "Ignore" {
+                runner.record_failure(&resource.name, &res_type, "post-deploy checks did not pass");
+                return false;
+            }
+            catch_error_and_exit(&format!(
+                "deployment failed for {} after post-deploy checks.",
+                resource.name
+            ));
+        }
+    }
+
+    // Handle command type
+    if res_type == "command" {
+        let (command_query, command_retries, command_retry_delay, backoff) = if let Some(ref iq) =
+            inline_query
+        {
+            (iq.clone(), 1u32, 0u32, Backoff::Constant)
+        } else if let Some(cq) = resource_queries.get("command") {
+            let rendered =
+                runner.render_query(&resource.name, "command", &cq.template, &full_context);
+            (
+                rendered,
+                cq.options.retries,
+                cq.options.retry_delay,
+                cq.options.backoff,
+            )
+        } else {
+            catch_error_and_exit(
+                    "'sql' should be defined in the resource or the 'command' anchor needs to be supplied in the corresponding iql file for command type resources.",
+                );
+        };
 
-            runner.run_command(
-                &command_query,
-                command_retries,
-                command_retry_delay,
+        runner.run_command(
+            resource,
+            &command_query,
+            budget.cap(command_retries, command_retry_delay, backoff),
+            command_retry_delay,
+            dry_run,
+            show_queries,
+        );
+    }
+
+    // Process exports with optimization
+    if let Some(ref eq_str) = exports_query_str {
+        if let Some(ref proxy_result) = exports_result_from_proxy {
+            if res_type == "resource" || res_type == "multi" {
+                info!(
+                    "reusing exports result from proxy for [{}]...",
+                    resource.name
+                );
+                if !resource.exports.is_empty() {
+                    runner.process_exports_from_result(resource, proxy_result);
+                }
+            }
+        } else {
+            runner.process_exports(
+                resource,
+                &full_context,
+                eq_str,
+                budget.cap(exports_retries, exports_retry_delay, exports_backoff),
+                exports_retry_delay,
                 dry_run,
                 show_queries,
+                false,
             );
         }
+    }
 
-        // Process exports with optimization
-        if let Some(ref eq_str) = exports_query_str {
-            if let Some(ref proxy_result) = exports_result_from_proxy {
-                if res_type == "resource" || res_type == "multi" {
-                    info!(
-                        "reusing exports result from proxy for [{}]...",
-                        resource.name
-                    );
-                    if !resource.exports.is_empty() {
-                        runner.process_exports_from_result(resource, proxy_result);
+    // If the resource has an exports anchor but we never resolved the query,
+    // that's a fatal error - variables that can't be resolved at this point
+    // indicate a missing dependency or misconfigured template.
+    if exports_query_str.is_none()
+        && resource_queries.contains_key("exports")
+        && !resource.exports.is_empty()
+    {
+        if dry_run {
+            // In dry-run mode, exports may not render because this.*
+            // fields are unavailable (no actual API calls).  Inject
+            // placeholder values so downstream resources can still
+            // render their templates.
+            let mut placeholder_data = HashMap::new();
+            for item in &resource.exports {
+                if let Some(map) = item.as_mapping() {
+                    for (_, val) in map {
+                        if let Some(v) = val.as_str() {
+                            placeholder_data.insert(v.to_string(), "<evaluated>".to_string());
+                        }
                     }
+                } else if let Some(s) = item.as_str() {
+                    placeholder_data.insert(s.to_string(), "<evaluated>".to_string());
                 }
-            } else {
-                runner.process_exports(
-                    resource,
-                    &full_context,
-                    eq_str,
-                    exports_retries,
-                    exports_retry_delay,
-                    dry_run,
-                    show_queries,
-                    false,
-                );
             }
+            info!(
+                "dry run: injecting placeholder exports for [{}]: {:?}",
+                resource.name,
+                sorted_keys(&placeholder_data)
+            );
+            export_vars(
+                &mut runner.global_context,
+                &mut runner.protected_names,
+                &resource.name,
+                &placeholder_data,
+                &resource.protected_export_names(),
+            );
+        } else {
+            runner.run_troubleshoot(
+                resource,
+                &resource_queries,
+                "create",
+                &full_context,
+                show_queries,
+            );
+            catch_error_and_exit(&format!(
+                "exports query for [{}] could not be rendered - unresolved template variables. \
+                 Check that all referenced variables are defined in the manifest or exported by prior resources.",
+                resource.name
+            ));
         }
+    }
 
-        // If the resource has an exports anchor but we never resolved the query,
-        // that's a fatal error - variables that can't be resolved at this point
-        // indicate a missing dependency or misconfigured template.
-        if exports_query_str.is_none()
-            && resource_queries.contains_key("exports")
-            && !resource.exports.is_empty()
-        {
-            if dry_run {
-                // In dry-run mode, exports may not render because this.*
-                // fields are unavailable (no actual API calls).  Inject
-                // placeholder values so downstream resources can still
-                // render their templates.
-                let mut placeholder_data = HashMap::new();
-                for item in &resource.exports {
-                    if let Some(map) = item.as_mapping() {
-                        for (_, val) in map {
-                            if let Some(v) = val.as_str() {
-                                placeholder_data.insert(v.to_string(), "<evaluated>".to_string());
-                            }
-                        }
-                    } else if let Some(s) = item.as_str() {
-                        placeholder_data.insert(s.to_string(), "<evaluated>".to_string());
+    if rollback_recorded && !record_for_rollback(runner, resource, &resource_queries, &full_context)
+    {
+        warn!(
+            "[{}] cannot be rolled back (no renderable delete query), it will be left in place if the build fails",
+            resource.name
+        );
+    }
+
+    if !dry_run {
+        if res_type == "resource" {
+            info!("successfully deployed {}", resource.name);
+        } else if res_type == "query" {
+            info!(
+                "successfully exported variables for query in {}",
+                resource.name
+            );
+        }
+    }
+    runner
+        .report
+        .record(&resource.name, &res_type, outcome, None);
+    !dry_run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use crate::commands::testing::{resource, TestStack};
+    use crate::core::templating::QueryOptions;
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    const VPC_MANIFEST: &str = r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+    props:
+      - name: cidr_block
+        value: "10.0.0.0/16"
+"#;
+
+    /// Build `name` of the stack once on a fresh runner, with rollback
+    /// armed, returning what was recorded for rollback and the report
+    /// outcome.
+    fn build_with_rollback(
+        stack: &TestStack,
+        server: &MockServer,
+        name: &str,
+    ) -> (Vec<(String, Option<String>)>, ResourceOutcome) {
+        let mut runner = stack.runner(server);
+        let target = resource(&runner, name);
+        let checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+        rollback::arm(&ServerConfig::default(), &RunOptions::default(), Vec::new());
+        build_resource(&mut runner, &target, &checkpoint, false, false, "Error");
+        let recorded = rollback::recorded();
+        rollback::disarm();
+        (recorded, runner.report.resources[0].outcome)
+    }
+
+    #[test]
+    fn test_rollback_skips_unchecked_createorupdate_resource() {
+        let _guard = rollback::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let stack = TestStack::new(
+            VPC_MANIFEST,
+            &[(
+                "vpc.iql",
+                "/*+ createorupdate */\n\
+                 INSERT INTO aws.ec2.vpcs SELECT '{{ cidr_block }}';\n\n\
+                 /*+ delete */\n\
+                 DELETE FROM aws.ec2.vpcs WHERE cidr_block = '{{ cidr_block }}';\n",
+            )],
+        );
+        // The vpc already exists: the upsert succeeds without creating it
+        let server = MockServer::start(|_| Reply::ok());
+
+        let (recorded, _) = build_with_rollback(&stack, &server, "vpc");
+        assert!(recorded.is_empty(), "recorded {:?}", recorded);
+        assert!(server
+            .queries()
+            .iter()
+            .any(|q| q.starts_with("INSERT INTO aws.ec2.vpcs")));
+    }
+
+    #[test]
+    fn test_createorupdate_only_resource_applied_on_every_build() {
+        let stack = TestStack::new(
+            VPC_MANIFEST,
+            &[(
+                "vpc.iql",
+                "/*+ createorupdate */\n\
+                 INSERT INTO aws.ec2.vpcs SELECT '{{ cidr_block }}';\n",
+            )],
+        );
+        let server = MockServer::start(|_| Reply::ok());
+
+        // The first build creates the vpc, the second runs against it
+        for run in 1..=2 {
+            let mut runner = stack.runner(&server);
+            let vpc = resource(&runner, "vpc");
+            let checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+            build_resource(&mut runner, &vpc, &checkpoint, false, false, "Error");
+
+            assert_eq!(runner.report.resources[0].outcome, ResourceOutcome::Applied);
+            assert_eq!((runner.report.created, runner.report.applied), (0, 1));
+            let inserts = server
+                .queries()
+                .iter()
+                .filter(|q| *q == "INSERT INTO aws.ec2.vpcs SELECT '10.0.0.0/16';")
+                .count();
+            assert_eq!(inserts, run);
+        }
+    }
+
+    #[test]
+    fn test_filtered_build_merges_into_state_file() {
+        let stack = TestStack::new(
+            r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+    exports:
+      - vpc_id
+  - name: subnet
+    exports:
+      - subnet_id
+"#,
+            &[],
+        );
+        let server = MockServer::start(|_| Reply::ok());
+        let path = Path::new(stack.path()).join("state.json");
+        let path = path.to_str().unwrap();
+        let exports = |name: &str, id: &str| BTreeMap::from([(name.to_string(), id.to_string())]);
+
+        let mut previous = StateFile::new("net", "dev");
+        previous.record("vpc", exports("vpc_id", "vpc-1"));
+        previous.record("subnet", exports("subnet_id", "subnet-1"));
+        previous.save(path).unwrap();
+
+        let mut runner = stack.runner(&server);
+        runner.select_resources(&["subnet".to_string()]);
+        runner
+            .global_context
+            .insert("subnet.subnet_id".to_string(), "subnet-2".to_string());
+        let deployed = ["subnet".to_string()];
+
+        write_state_file(&runner, &deployed, path, true);
+        let state = StateFile::load(path).unwrap();
+        let names: Vec<&str> = state.resources.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["vpc", "subnet"]);
+        assert_eq!(
+            state.resource("vpc").unwrap().exports,
+            exports("vpc_id", "vpc-1")
+        );
+        assert_eq!(
+            state.resource("subnet").unwrap().exports,
+            exports("subnet_id", "subnet-2")
+        );
+
+        // A build of the whole stack replaces the file
+        write_state_file(&runner, &deployed, path, false);
+        assert_eq!(StateFile::load(path).unwrap().resources.len(), 1);
+    }
+
+    #[test]
+    fn test_resume_restores_exports_of_skipped_script() {
+        let stack = TestStack::new(
+            r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: setup
+    type: script
+    run: |
+      echo '{"token": "t-1"}'
+    exports:
+      - token
+"#,
+            &[],
+        );
+        let server = MockServer::start(|_| Reply::ok());
+
+        let mut runner = stack.runner(&server);
+        let setup = resource(&runner, "setup");
+        let mut checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+        assert!(build_resource(
+            &mut runner,
+            &setup,
+            &checkpoint,
+            false,
+            false,
+            "Error"
+        ));
+        record_completed(&runner, &mut checkpoint, &setup);
+
+        // The resumed build skips the script but still has its exports
+        let checkpoint = Checkpoint::load(stack.path(), "net", "dev");
+        let mut resumed = stack.runner(&server);
+        assert!(!build_resource(
+            &mut resumed,
+            &setup,
+            &checkpoint,
+            false,
+            false,
+            "Error"
+        ));
+        assert_eq!(resumed.report.skipped, 1);
+        assert_eq!(resumed.global_context["token"], "t-1");
+        assert_eq!(resumed.global_context["setup.token"], "t-1");
+        checkpoint.clear();
+    }
+
+    #[test]
+    fn test_created_resource_recorded_before_post_deploy_checks() {
+        let _guard = rollback::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let stack = TestStack::new(
+            VPC_MANIFEST,
+            &[(
+                "vpc.iql",
+                "/*+ exists */\n\
+                 SELECT COUNT(*) as count FROM aws.ec2.vpcs WHERE cidr_block = '{{ cidr_block }}';\n\n\
+                 /*+ create */\n\
+                 INSERT INTO aws.ec2.vpcs SELECT '{{ cidr_block }}';\n\n\
+                 /*+ delete */\n\
+                 DELETE FROM aws.ec2.vpcs WHERE cidr_block = '{{ cidr_block }}';\n",
+            )],
+        );
+        let created = Arc::new(AtomicBool::new(false));
+        let recorded_when_checked = Arc::new(AtomicBool::new(false));
+        let server = {
+            let created = Arc::clone(&created);
+            let recorded_when_checked = Arc::clone(&recorded_when_checked);
+            MockServer::start(move |query| {
+                if query.starts_with("INSERT") {
+                    created.store(true, Ordering::SeqCst);
+                    Reply::ok()
+                } else if query.starts_with("SELECT COUNT(*)") {
+                    if created.load(Ordering::SeqCst) {
+                        // The post-deploy check, which could fail the build
+                        let recorded = rollback::recorded().iter().any(|(name, _)| name == "vpc");
+                        recorded_when_checked.store(recorded, Ordering::SeqCst);
+                        Reply::count(1)
+                    } else {
+                        Reply::count(0)
                     }
+                } else {
+                    Reply::ok()
                 }
-                info!(
-                    "dry run: injecting placeholder exports for [{}]: {:?}",
-                    resource.name,
-                    placeholder_data.keys().collect::<Vec<_>>()
-                );
-                export_vars(
-                    &mut runner.global_context,
-                    &resource.name,
-                    &placeholder_data,
-                    &resource.protected,
-                );
+            })
+        };
+
+        let (recorded, outcome) = build_with_rollback(&stack, &server, "vpc");
+        assert_eq!(outcome, ResourceOutcome::Created);
+        assert!(recorded_when_checked.load(Ordering::SeqCst));
+        assert_eq!(
+            recorded,
+            [(
+                "vpc".to_string(),
+                Some("DELETE FROM aws.ec2.vpcs WHERE cidr_block = '10.0.0.0/16';".to_string())
+            )]
+        );
+    }
+
+    const REPLACED_VPC_MANIFEST: &str = r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+    replace_on_change:
+      - cidr_block
+    props:
+      - name: cidr_block
+        value: "10.0.0.0/16"
+"#;
+
+    const REPLACED_VPC_QUERIES: &str = "/*+ exists */\n\
+         SELECT cidr_block FROM aws.ec2.vpcs WHERE name = 'main';\n\n\
+         /*+ statecheck */\n\
+         SELECT COUNT(*) as count FROM aws.ec2.vpcs WHERE cidr_block = '{{ cidr_block }}';\n\n\
+         /*+ create */\n\
+         INSERT INTO aws.ec2.vpcs SELECT '{{ cidr_block }}';\n\n\
+         /*+ update */\n\
+         UPDATE aws.ec2.vpcs SET cidr_block = '{{ cidr_block }}';\n\n\
+         /*+ delete */\n\
+         DELETE FROM aws.ec2.vpcs WHERE name = 'main';\n";
+
+    /// A server holding a vpc named `main` with the 10.1.0.0/16 block,
+    /// which DELETE removes and INSERT recreates with the desired block.
+    fn replaced_vpc_server() -> MockServer {
+        let live = Arc::new(Mutex::new(Some("10.1.0.0/16")));
+        MockServer::start(move |query| {
+            let mut live = live.lock().unwrap();
+            if query.starts_with("DELETE") {
+                *live = None;
+                Reply::ok()
+            } else if query.starts_with("INSERT") {
+                *live = Some("10.0.0.0/16");
+                Reply::ok()
+            } else if query.starts_with("SELECT cidr_block") {
+                match *live {
+                    Some(cidr) => Reply::rows(&["cidr_block"], &[&[cidr]]),
+                    None => Reply::rows(&["cidr_block"], &[]),
+                }
+            } else if query.starts_with("SELECT COUNT(*)") {
+                Reply::count(usize::from(
+                    live.is_some_and(|cidr| query.contains(&format!("'{}'", cidr))),
+                ))
             } else {
-                runner.run_troubleshoot(
-                    resource,
-                    &resource_queries,
-                    "create",
-                    &full_context,
-                    show_queries,
-                );
-                catch_error_and_exit(&format!(
-                    "exports query for [{}] could not be rendered - unresolved template variables. \
-                     Check that all referenced variables are defined in the manifest or exported by prior resources.",
-                    resource.name
-                ));
+                Reply::ok()
             }
-        }
+        })
+    }
 
-        if !dry_run {
-            if res_type == "resource" {
-                info!("successfully deployed {}", resource.name);
-            } else if res_type == "query" {
-                info!(
-                    "successfully exported variables for query in {}",
-                    resource.name
-                );
+    #[test]
+    fn test_replace_on_change_deletes_and_recreates() {
+        let stack = TestStack::new(REPLACED_VPC_MANIFEST, &[("vpc.iql", REPLACED_VPC_QUERIES)]);
+        let server = replaced_vpc_server();
+        let mut runner = stack.runner(&server);
+        let vpc = resource(&runner, "vpc");
+        let checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+
+        build_resource(&mut runner, &vpc, &checkpoint, false, false, "Error");
+
+        assert_eq!(
+            runner.report.resources[0].outcome,
+            ResourceOutcome::Replaced
+        );
+        assert_eq!(runner.report.replaced, 1);
+        let queries = server.queries();
+        let position = |prefix: &str| queries.iter().position(|q| q.starts_with(prefix));
+        assert!(position("DELETE").unwrap() < position("INSERT").unwrap());
+        assert_eq!(position("UPDATE"), None);
+    }
+
+    #[test]
+    fn test_dry_run_diff_reports_replacement() {
+        let stack = TestStack::new(REPLACED_VPC_MANIFEST, &[("vpc.iql", REPLACED_VPC_QUERIES)]);
+        let server = replaced_vpc_server();
+        let mut runner = stack.runner(&server);
+        runner.show_diff = true;
+        let vpc = resource(&runner, "vpc");
+        let checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+
+        build_resource(&mut runner, &vpc, &checkpoint, true, false, "Error");
+
+        assert_eq!(
+            runner.report.resources[0].outcome,
+            ResourceOutcome::Replaced
+        );
+        // Only the read-only queries ran
+        assert!(!server
+            .queries()
+            .iter()
+            .any(|q| ["DELETE", "INSERT", "UPDATE"]
+                .iter()
+                .any(|dml| q.starts_with(dml))));
+    }
+
+    #[test]
+    fn test_needs_replacement() {
+        let resource = |replace_on_change: &str| -> Resource {
+            serde_yaml::from_str(&format!(
+                "name: vpc\nreplace_on_change: {}\nprops:\n- name: cidr_block\n  value: 10.0.0.0/16\n",
+                replace_on_change
+            ))
+            .unwrap()
+        };
+        let context = |live: Option<&str>| -> HashMap<String, String> {
+            let mut context =
+                HashMap::from([("cidr_block".to_string(), "10.0.0.0/16".to_string())]);
+            if let Some(live) = live {
+                context.insert("vpc.cidr_block".to_string(), live.to_string());
             }
-        }
+            context
+        };
+
+        assert!(resource("true").replace_on_change.is_some());
+        assert!(needs_replacement(&resource("true"), &context(None)));
+        assert!(!needs_replacement(
+            &resource("false"),
+            &context(Some("10.1.0.0/16"))
+        ));
+
+        let on_cidr = resource("[cidr_block]");
+        assert!(needs_replacement(&on_cidr, &context(Some("10.1.0.0/16"))));
+        assert!(!needs_replacement(
+            &on_cidr,
+            &context(Some("\"10.0.0.0/16\""))
+        ));
+        // Nothing to compare against: updated in place
+        assert!(!needs_replacement(&on_cidr, &context(None)));
+
+        let on_tenancy = resource("[instance_tenancy]");
+        assert!(!needs_replacement(
+            &on_tenancy,
+            &context(Some("10.1.0.0/16"))
+        ));
     }
 
-    let elapsed = start_time.elapsed();
-    let elapsed_str = format!("{:.2?}", elapsed);
-    info!("deployment completed in {}", elapsed_str);
+    fn queries(anchors: &[&str]) -> HashMap<String, ParsedQuery> {
+        anchors
+            .iter()
+            .map(|anchor| {
+                let query = ParsedQuery {
+                    template: format!("/* {} */", anchor),
+                    options: QueryOptions::default(),
+                };
+                (anchor.to_string(), query)
+            })
+            .collect()
+    }
 
-    runner.process_stack_exports(dry_run, output_file, &elapsed_str);
+    #[test]
+    fn test_createorupdate_only_runs_unchecked() {
+        assert!(createorupdate_unchecked(&queries(&["createorupdate"])));
+        assert!(createorupdate_unchecked(&queries(&[
+            "createorupdate",
+            "exports",
+            "delete"
+        ])));
+    }
+
+    #[test]
+    fn test_createorupdate_checks_state_when_it_can() {
+        assert!(!createorupdate_unchecked(&queries(&[
+            "createorupdate",
+            "exists"
+        ])));
+        assert!(!createorupdate_unchecked(&queries(&[
+            "createorupdate",
+            "exists",
+            "statecheck"
+        ])));
+        assert!(!createorupdate_unchecked(&queries(&[
+            "createorupdate",
+            "statecheck"
+        ])));
+        // create and update are always checked
+        assert!(!createorupdate_unchecked(&queries(&["create", "update"])));
+    }
+
+    #[test]
+    fn test_property_diffs() {
+        let resource: Resource = serde_yaml::from_str(
+            "name: vpc\n\
+             props:\n\
+             - name: cidr_block\n  value: 10.0.0.0/16\n\
+             - name: instance_tenancy\n  value: default\n\
+             - name: tags\n  value: [{Key: env, Value: dev}]\n\
+             - name: region\n  value: us-east-1\n",
+        )
+        .unwrap();
+        let full_context: HashMap<String, String> = [
+            ("cidr_block", "10.0.0.0/16"),
+            ("instance_tenancy", "default"),
+            ("tags", r#"[{"Key":"env","Value":"dev"}]"#),
+            ("region", "us-east-1"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let live: HashMap<String, String> = [
+            ("CidrBlock", "10.1.0.0/16"),
+            ("instance_tenancy", "\"default\""),
+            ("tags", r#"[{"Key": "env", "Value": "dev"}]"#),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let diffs = property_diffs(&resource, &full_context, &live);
+        assert_eq!(
+            diffs,
+            [
+                ("cidr_block".to_string(), PropertyDiff::NotFetched),
+                ("instance_tenancy".to_string(), PropertyDiff::Unchanged),
+                ("tags".to_string(), PropertyDiff::Unchanged),
+                ("region".to_string(), PropertyDiff::NotFetched),
+            ]
+        );
+
+        let live: HashMap<String, String> =
+            HashMap::from([("CIDR_BLOCK".to_string(), "10.1.0.0/16".to_string())]);
+        let diffs = property_diffs(&resource, &full_context, &live);
+        assert_eq!(
+            diffs[0].1,
+            PropertyDiff::Changed {
+                live: "10.1.0.0/16".to_string(),
+                desired: "10.0.0.0/16".to_string(),
+            }
+        );
+    }
+
+    const BUCKETS_MANIFEST: &str = r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: buckets
+    type: multi
+    for_each: names
+    props:
+      - name: names
+        value: [logs, assets]
+"#;
+
+    const BUCKETS_QUERIES: &str = "/*+ exists */\n\
+         SELECT COUNT(*) as count FROM aws.s3.buckets WHERE name = '{{ item }}';\n\n\
+         /*+ statecheck */\n\
+         SELECT COUNT(*) as count FROM aws.s3.buckets WHERE name = '{{ item }}' AND versioned;\n\n\
+         /*+ createorupdate */\n\
+         INSERT INTO aws.s3.buckets SELECT '{{ item }}';\n\n\
+         /*+ delete */\n\
+         DELETE FROM aws.s3.buckets WHERE name = '{{ item }}';\n";
+
+    /// A server holding only the `logs` bucket.
+    fn logs_bucket_server() -> MockServer {
+        MockServer::start(|query| {
+            if query.starts_with("SELECT COUNT(*)") {
+                Reply::count(usize::from(query.contains("'logs'")))
+            } else {
+                Reply::ok()
+            }
+        })
+    }
+
+    #[test]
+    fn test_for_each_items_created_and_recorded_for_rollback() {
+        let _guard = rollback::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let stack = TestStack::new(BUCKETS_MANIFEST, &[("buckets.iql", BUCKETS_QUERIES)]);
+        let server = logs_bucket_server();
+
+        let (recorded, outcome) = build_with_rollback(&stack, &server, "buckets");
+
+        // Only the missing item is created, and only it is rolled back
+        assert_eq!(outcome, ResourceOutcome::Created);
+        let inserts: Vec<String> = server
+            .queries()
+            .into_iter()
+            .filter(|q| q.starts_with("INSERT"))
+            .collect();
+        assert_eq!(inserts, ["INSERT INTO aws.s3.buckets SELECT 'assets';"]);
+        assert_eq!(
+            recorded,
+            [(
+                "buckets[1]".to_string(),
+                Some("DELETE FROM aws.s3.buckets WHERE name = 'assets';".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_for_each_unchecked_createorupdate_is_applied() {
+        let _guard = rollback::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let stack = TestStack::new(
+            BUCKETS_MANIFEST,
+            &[(
+                "buckets.iql",
+                "/*+ createorupdate */\n\
+                 INSERT INTO aws.s3.buckets SELECT '{{ item }}';\n",
+            )],
+        );
+        let server = logs_bucket_server();
+
+        let (recorded, outcome) = build_with_rollback(&stack, &server, "buckets");
+
+        assert_eq!(outcome, ResourceOutcome::Applied);
+        assert!(recorded.is_empty());
+        let inserts = server
+            .queries()
+            .iter()
+            .filter(|q| q.starts_with("INSERT"))
+            .count();
+        assert_eq!(inserts, 2);
+    }
+
+    #[test]
+    fn test_build_concurrently_joins_in_manifest_order() {
+        let names = ["vpc", "subnet", "sg"];
+        let queries: Vec<(String, String)> = names
+            .iter()
+            .map(|name| {
+                (
+                    format!("{}.iql", name),
+                    format!(
+                        "/*+ exists */\n\
+                         SELECT COUNT(*) as count FROM aws.ec2.{name}s;\n\n\
+                         /*+ create */\n\
+                         INSERT INTO aws.ec2.{name}s SELECT 1;\n\n\
+                         /*+ exports */\n\
+                         SELECT '{name}-1' as {name}_id FROM aws.ec2.{name}s;\n"
+                    ),
+                )
+            })
+            .collect();
+        let files: Vec<(&str, &str)> = queries
+            .iter()
+            .map(|(file, contents)| (file.as_str(), contents.as_str()))
+            .collect();
+        let stack = TestStack::new(
+            r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+    exports:
+      - vpc_id
+  - name: subnet
+    exports:
+      - subnet_id
+  - name: sg
+    exports:
+      - sg_id
+"#,
+            &files,
+        );
+        // The vpc is created slowest, so its build finishes last
+        let created = Arc::new(Mutex::new(Vec::<String>::new()));
+        let finished = Arc::clone(&created);
+        let server = MockServer::start(move |query| {
+            let Some(name) = names
+                .iter()
+                .find(|n| query.contains(&format!("aws.ec2.{}s", n)))
+            else {
+                return Reply::ok();
+            };
+            if query.starts_with("INSERT") {
+                if *name == "vpc" {
+                    thread::sleep(std::time::Duration::from_millis(500));
+                }
+                created.lock().unwrap().push(name.to_string());
+                return Reply::ok();
+            }
+            let exists = created.lock().unwrap().iter().any(|c| c == name);
+            if query.starts_with("SELECT COUNT(*)") {
+                Reply::count(usize::from(exists))
+            } else if exists {
+                let column = format!("{}_id", name);
+                Reply::rows(&[&column], &[&[&format!("{}-1", name)]])
+            } else {
+                Reply::rows(&["id"], &[])
+            }
+        });
+        let mut runner = stack.runner(&server);
+        let dump_dir = format!("{}/sql", stack.path());
+        runner.enable_sql_dump(&dump_dir);
+        let batch = runner.manifest.resources.clone();
+        let mut checkpoint = Checkpoint::new(stack.path(), "net", "dev");
+
+        build_concurrently(
+            &mut runner,
+            &batch,
+            &mut checkpoint,
+            &|| server.client(),
+            false,
+            false,
+            "Error",
+        );
+
+        assert_eq!(finished.lock().unwrap().last().unwrap(), "vpc");
+        // Merged in manifest order, not in the order the builds finished
+        let reported: Vec<&str> = runner
+            .report
+            .resources
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(reported, names);
+        assert_eq!(checkpoint.completed(), names);
+        assert_eq!(runner.report.created, 3);
+        for name in names {
+            assert_eq!(
+                runner.global_context.get(&format!("{}_id", name)),
+                Some(&format!("{}-1", name))
+            );
+            // Queries rendered by each fork reach the shared SQL dump
+            let create = std::fs::read_to_string(format!("{}/{}/create.sql", dump_dir, name));
+            assert!(create.unwrap().contains("INSERT INTO"), "{}", name);
+        }
+    }
 }
@@ -5,9 +5,15 @@
 //! This module defines common command-line arguments that can be reused across
 //! different commands in the application.
 
-use clap::{value_parser, Arg, ArgAction};
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches};
 use std::str::FromStr;
 
+use crate::app::DEFAULT_MANIFEST_ENV_VAR;
+use crate::core::env::{vars_from_commands, ProcessEnv};
+use crate::core::utils::{catch_error_and_exit, is_valid_version, require_min_stackql_version};
+use crate::utils::connection::RunOptions;
+
 /// Possible actions to take on failure
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FailureAction {
@@ -29,6 +35,41 @@ impl FromStr for FailureAction {
     }
 }
 
+/// File formats for the stack outputs written by `--output-file`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Yaml,
+}
+
+impl ExportFormat {
+    /// The format implied by the extension of `path`: YAML for `.yml` and
+    /// `.yaml`, JSON otherwise.
+    pub fn from_path(path: &str) -> Self {
+        let is_yaml = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("yml") || ext.eq_ignore_ascii_case("yaml"));
+        if is_yaml {
+            ExportFormat::Yaml
+        } else {
+            ExportFormat::Json
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "yaml" | "yml" => Ok(ExportFormat::Yaml),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
 // Positional arguments
 /// Common positional argument for the stack directory
 pub fn stack_dir() -> Arg {
@@ -40,8 +81,46 @@ pub fn stack_dir() -> Arg {
 /// Common positional argument for the stack environment
 pub fn stack_env() -> Arg {
     Arg::new("stack_env")
-        .required(true)
-        .help("Environment to deploy to (e.g., `prod`, `dev`, `test`)")
+        .required(false)
+        .help("Environment to deploy to (e.g., `prod`, `dev`, `test`); read from --manifest-env-var when omitted")
+}
+
+/// Common argument naming the environment variable that supplies
+/// `stack_env` when it is omitted
+pub fn manifest_env_var() -> Arg {
+    Arg::new("manifest-env-var")
+        .long("manifest-env-var")
+        .value_name("NAME")
+        .help("Environment variable to read the stack environment from when stack_env is omitted")
+        .default_value(DEFAULT_MANIFEST_ENV_VAR)
+        .action(ArgAction::Set)
+}
+
+/// Returns the stack environment: the `stack_env` positional, or else the
+/// value of the `--manifest-env-var` environment variable.  Exits when
+/// neither is set.
+pub fn stack_env_value(matches: &ArgMatches) -> String {
+    resolve_stack_env(matches, |name| std::env::var(name).ok())
+        .unwrap_or_else(|e| catch_error_and_exit(&e))
+}
+
+/// The stack environment as [`stack_env_value`] resolves it, reading
+/// environment variables with `env_var`.
+fn resolve_stack_env(
+    matches: &ArgMatches,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    if let Some(env) = matches.get_one::<String>("stack_env") {
+        return Ok(env.clone());
+    }
+    let var_name = matches.get_one::<String>("manifest-env-var").unwrap();
+    match env_var(var_name) {
+        Some(env) if !env.trim().is_empty() => Ok(env.trim().to_string()),
+        _ => Err(format!(
+            "No stack environment given: pass stack_env or set the {} environment variable",
+            var_name
+        )),
+    }
 }
 
 // Optional arguments
@@ -61,7 +140,7 @@ pub fn log_level() -> Arg {
 pub fn env_file() -> Arg {
     Arg::new("env-file")
         .long("env-file")
-        .help("Environment variables file")
+        .help("Environment variables file; may reference {{ stack_env }} and {{ stack_name }}")
         .default_value(".env")
 }
 
@@ -74,6 +153,76 @@ pub fn env_var() -> Arg {
         .action(ArgAction::Append)
 }
 
+/// Common argument for loading variables from YAML or JSON files
+pub fn var_file() -> Arg {
+    Arg::new("var-file")
+        .long("var-file")
+        .value_name("FILE")
+        .help("Load variables from a YAML or JSON file (repeatable, later files win, -e overrides); may reference {{ stack_env }} and {{ stack_name }}")
+        .action(ArgAction::Append)
+}
+
+/// Returns the `--var-file` paths in the order given.
+pub fn var_files_value(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("var-file")
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Common argument for setting a variable from the output of a shell command
+pub fn var_from_command() -> Arg {
+    Arg::new("var-from-command")
+        .long("var-from-command")
+        .value_name("NAME=COMMAND")
+        .help("Set a variable to the trimmed stdout of a shell command (format: NAME=COMMAND)")
+        .action(ArgAction::Append)
+}
+
+/// Common argument for loading the process environment into the context
+pub fn env_all() -> Arg {
+    Arg::new("env-all")
+        .long("env-all")
+        .help("Load all process environment variables (lowest precedence), skipping sensitive-looking names")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for loading the process environment without filtering
+pub fn env_all_unsafe() -> Arg {
+    Arg::new("env-all-unsafe")
+        .long("env-all-unsafe")
+        .help("With --env-all, also load sensitive-looking names (e.g. *_TOKEN, *_SECRET)")
+        .requires("env-all")
+        .action(ArgAction::SetTrue)
+}
+
+/// Returns which process environment variables to load.
+pub fn process_env_value(matches: &ArgMatches) -> ProcessEnv {
+    if !matches.get_flag("env-all") {
+        ProcessEnv::Ignore
+    } else if matches.get_flag("env-all-unsafe") {
+        ProcessEnv::Unsafe
+    } else {
+        ProcessEnv::Safe
+    }
+}
+
+/// Returns the `--env` overrides followed by the variables produced by
+/// `--var-from-command`, which are run here so a failing command stops the
+/// run before anything is deployed.
+pub fn env_overrides(matches: &ArgMatches) -> Vec<String> {
+    let mut overrides: Vec<String> = matches
+        .get_many::<String>("env")
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default();
+    let commands: Vec<String> = matches
+        .get_many::<String>("var-from-command")
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default();
+    overrides.extend(vars_from_commands(&commands));
+    overrides
+}
+
 /// Common argument for performing a dry run
 pub fn dry_run() -> Arg {
     Arg::new("dry-run")
@@ -90,6 +239,161 @@ pub fn show_queries() -> Arg {
         .action(ArgAction::SetTrue)
 }
 
+/// Common argument for selecting a subset of resources by name or glob
+pub fn resources() -> Arg {
+    Arg::new("resources")
+        .long("resources")
+        .help("Only process these resources (comma separated names; `*` and `?` globs allowed)")
+        .value_delimiter(',')
+        .action(ArgAction::Append)
+}
+
+/// Returns the `--resources` patterns, if any were given.
+pub fn resources_value(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("resources")
+        .map(|v| v.map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Common argument for operating on named resources only
+pub fn target() -> Arg {
+    Arg::new("target")
+        .long("target")
+        .value_name("RESOURCE")
+        .help("Only operate on this resource (repeatable or comma separated)")
+        .value_delimiter(',')
+        .action(ArgAction::Append)
+        .conflicts_with("resources")
+}
+
+/// Returns the `--target` resource names, if any were given.
+pub fn targets_value(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("target")
+        .map(|v| v.map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Common argument for failing when there are no resources to process
+pub fn strict_manifest() -> Arg {
+    Arg::new("strict-manifest")
+        .long("strict-manifest")
+        .help("Fail if the manifest (after any filters) has no resources to process")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for caching identical read-only query results within a run
+pub fn cache_queries() -> Arg {
+    Arg::new("cache-queries")
+        .long("cache-queries")
+        .help("Reuse results of identical read-only (exports) queries within a run")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for writing protected stack exports masked rather than
+/// leaving them out
+pub fn mask_protected_exports() -> Arg {
+    Arg::new("mask-protected-exports")
+        .long("mask-protected-exports")
+        .help("Write protected stack exports to the outputs masked, instead of leaving them out")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for writing protected stack exports in clear
+pub fn include_secrets() -> Arg {
+    Arg::new("include-secrets")
+        .long("include-secrets")
+        .help("Write protected stack exports to the outputs in clear, instead of leaving them out")
+        .conflicts_with("mask-protected-exports")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for removing SQL comments from queries before they run
+pub fn strip_comments() -> Arg {
+    Arg::new("strip-comments")
+        .long("strip-comments")
+        .help("Remove -- and /* */ comments from resource queries before rendering them")
+        .action(ArgAction::SetTrue)
+}
+
+/// Common argument for the state file recording deployed resources
+pub fn state_file() -> Arg {
+    Arg::new("state-file")
+        .long("state-file")
+        .value_name("FILE")
+        .help("JSON file recording deployed resources and their exports (written by build, read by teardown)")
+        .action(ArgAction::Set)
+}
+
+/// Common argument for the format of the `--output-file` stack outputs
+pub fn export_format() -> Arg {
+    Arg::new("export-format")
+        .long("export-format")
+        .value_name("FORMAT")
+        .help("Format of the --output-file outputs, json or yaml; taken from the file extension when omitted")
+        .value_parser(value_parser!(ExportFormat))
+}
+
+/// Common argument for the minimum stackql binary version a run needs
+pub fn fail_if_binary_older_than() -> Arg {
+    Arg::new("fail-if-binary-older-than")
+        .long("fail-if-binary-older-than")
+        .value_name("VERSION")
+        .help("Abort if the installed stackql binary is older than this version (e.g. v0.5.612)")
+        .value_parser(|s: &str| {
+            if is_valid_version(s) {
+                Ok(s.to_string())
+            } else {
+                Err(format!("'{}' is not a version such as v0.5.612", s))
+            }
+        })
+}
+
+/// Abort when `--fail-if-binary-older-than` is set and the installed binary
+/// is older, before the server is started.
+pub fn check_binary_version(matches: &ArgMatches) {
+    if let Some(minimum) = matches.get_one::<String>("fail-if-binary-older-than") {
+        require_min_stackql_version(minimum, "--fail-if-binary-older-than");
+    }
+}
+
+/// Common argument for the provider registry of the server started by a
+/// deploy command
+pub fn registry() -> Arg {
+    Arg::new("registry")
+        .long("registry")
+        .value_name("URL")
+        .help("Pull providers from this registry (URL or stackql registry JSON) instead of the public registry")
+}
+
+/// The run options of a deploy command, with its `--registry` applied.
+pub fn registry_options(matches: &ArgMatches, options: &RunOptions) -> RunOptions {
+    let mut options = options.clone();
+    if let Some(registry) = matches.get_one::<String>("registry") {
+        options.registry = Some(registry.clone());
+    }
+    options
+}
+
+/// Common argument for writing every rendered query to a directory
+pub fn dump_sql_to_dir() -> Arg {
+    Arg::new("dump-sql-to-dir")
+        .long("dump-sql-to-dir")
+        .value_name("DIR")
+        .help("Write each rendered query to DIR/<resource>/<anchor>.sql, with an index in manifest order")
+        .num_args(1)
+}
+
+/// Common argument for signalling pending changes in the exit code
+pub fn detailed_exitcode() -> Arg {
+    Arg::new("detailed-exitcode")
+        .long("detailed-exitcode")
+        .alias("dry-run-exit-code")
+        .help("Exit with 0 when there are no changes, 2 when changes are pending, 1 on error")
+        .action(ArgAction::SetTrue)
+}
+
 /// Common argument for specifying the action on failure
 pub fn on_failure() -> Arg {
     Arg::new("on-failure")
@@ -98,3 +402,102 @@ pub fn on_failure() -> Arg {
         .value_parser(value_parser!(FailureAction))
         .default_value("error")
 }
+
+/// Returns the `--on-failure` value and whether it was passed explicitly
+/// (as opposed to taken from the default).
+pub fn on_failure_value(matches: &ArgMatches) -> (FailureAction, bool) {
+    let action = *matches.get_one::<FailureAction>("on-failure").unwrap();
+    let explicit = matches.value_source("on-failure") == Some(ValueSource::CommandLine);
+    (action, explicit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Command;
+
+    /// Matches of a command taking the stack environment arguments.
+    fn env_matches(args: &[&str]) -> ArgMatches {
+        Command::new("build")
+            .arg(stack_dir())
+            .arg(stack_env())
+            .arg(manifest_env_var())
+            .try_get_matches_from(std::iter::once("build").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stack_env_from_positional() {
+        let matches = env_matches(&["stack", "prd"]);
+        let env = resolve_stack_env(&matches, |_| Some("dev".to_string()));
+        assert_eq!(env, Ok("prd".to_string()));
+    }
+
+    #[test]
+    fn test_stack_env_from_manifest_env_var() {
+        let lookup = |name: &str| match name {
+            "STACKQL_DEPLOY_ENV" => Some(" dev \n".to_string()),
+            "DEPLOY_ENV" => Some("staging".to_string()),
+            _ => None,
+        };
+
+        let matches = env_matches(&["stack"]);
+        assert_eq!(resolve_stack_env(&matches, lookup), Ok("dev".to_string()));
+
+        let matches = env_matches(&["stack", "--manifest-env-var", "DEPLOY_ENV"]);
+        assert_eq!(
+            resolve_stack_env(&matches, lookup),
+            Ok("staging".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stack_env_missing() {
+        let matches = env_matches(&["stack", "--manifest-env-var", "DEPLOY_ENV"]);
+        let err = resolve_stack_env(&matches, |_| Some("  ".to_string())).unwrap_err();
+        assert!(
+            err.contains("set the DEPLOY_ENV environment variable"),
+            "{}",
+            err
+        );
+
+        let err = resolve_stack_env(&matches, |_| None).unwrap_err();
+        assert!(err.contains("DEPLOY_ENV"), "{}", err);
+    }
+
+    #[test]
+    fn test_export_format_from_path() {
+        assert_eq!(ExportFormat::from_path("outputs.json"), ExportFormat::Json);
+        assert_eq!(
+            ExportFormat::from_path("out/outputs.yml"),
+            ExportFormat::Yaml
+        );
+        assert_eq!(ExportFormat::from_path("OUTPUTS.YAML"), ExportFormat::Yaml);
+        // Anything else, including no extension, is written as JSON
+        assert_eq!(ExportFormat::from_path("outputs"), ExportFormat::Json);
+        assert_eq!(
+            ExportFormat::from_path("yaml/outputs.txt"),
+            ExportFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_export_format_from_str() {
+        assert_eq!("json".parse(), Ok(ExportFormat::Json));
+        assert_eq!("YAML".parse(), Ok(ExportFormat::Yaml));
+        assert_eq!("yml".parse(), Ok(ExportFormat::Yaml));
+        assert_eq!(
+            "toml".parse::<ExportFormat>(),
+            Err("Unknown export format: toml".to_string())
+        );
+
+        let matches = Command::new("build")
+            .arg(export_format())
+            .try_get_matches_from(["build", "--export-format", "yaml"])
+            .unwrap();
+        assert_eq!(
+            matches.get_one::<ExportFormat>("export-format"),
+            Some(&ExportFormat::Yaml)
+        );
+    }
+}
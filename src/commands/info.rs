@@ -8,6 +8,7 @@
 //! ## Features
 //! - Displays version information for the StackQL Deploy CLI.
 //! - Retrieves and displays StackQL binary version, SHA, platform, and binary path.
+//! - Shows the configured server host and port and whether a server is reachable there.
 //! - Lists all running local StackQL servers by PID and port.
 //! - Displays installed providers and their versions.
 //! - Lists contributors if available via the `CONTRIBUTORS` environment variable.
@@ -24,9 +25,10 @@ use clap::Command;
 use colored::*;
 use log::error;
 
+use crate::utils::connection::ServerConfig;
 use crate::utils::display::print_unicode_box;
 use crate::utils::platform::get_platform;
-use crate::utils::server::find_all_running_servers;
+use crate::utils::server::{find_all_running_servers, is_server_reachable};
 use crate::utils::stackql::{get_installed_providers, get_stackql_path, get_version};
 
 /// Defines the `info` command for the CLI application.
@@ -35,7 +37,7 @@ pub fn command() -> Command {
 }
 
 /// Executes the `info` command.
-pub fn execute() {
+pub fn execute(server: &ServerConfig) {
     print_unicode_box(
         "Getting program information...",
         crate::utils::display::BorderColor::Green,
@@ -75,6 +77,18 @@ pub fn execute() {
     println!("  Platform: {:?}", platform);
     println!("  Binary Path: {}", binary_path);
 
+    // Display the configured server and whether it is up
+    let host = server.host.as_str();
+    let port = server.port;
+    println!("\n{}", "Configured StackQL Server".green().bold());
+    println!("  Host: {}", host);
+    println!("  Port: {}", port);
+    if is_server_reachable(host, port) {
+        println!("  Status: {}", "reachable".green());
+    } else {
+        println!("  Status: {}", "not reachable".red());
+    }
+
     // Display running servers
     println!("\n{}", "Local StackQL Servers".green().bold());
     if running_servers.is_empty() {
@@ -6,10 +6,11 @@
 //! It supports built-in templates for major providers (AWS, Azure, Google) as well as custom templates via URL or file path.
 //!
 //! ## Features
-//! - Initializes project directory structure.
+//! - Initializes project directory structure, with a sample `.env` for the provider's variables.
 //! - Supports both embedded templates and custom templates.
 //! - Fetches templates from URLs or uses built-in ones.
 //! - Validates supported providers and applies default providers when necessary.
+//! - Refuses to write into an existing directory unless `--force` is passed.
 //!
 //! ## Example Usage
 //! ```bash
@@ -21,6 +22,7 @@ use std::collections::HashSet;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
+use std::process;
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use reqwest::blocking::Client;
@@ -76,7 +78,6 @@ pub fn command() -> Command {
         )
         .arg(
             Arg::new("provider")
-                .short('p')
                 .long("provider")
                 .help("Specify a provider (aws, azure, google)")
                 .action(ArgAction::Set)
@@ -98,6 +99,12 @@ pub fn command() -> Command {
                 .default_value("dev")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Initialize the project even if the directory already exists, overwriting generated files")
+                .action(ArgAction::SetTrue),
+        )
 }
 
 /// Executes the `init` command to initialize a new project structure.
@@ -127,13 +134,16 @@ pub fn execute(matches: &ArgMatches) {
         TemplateSource::Embedded(provider)
     };
 
+    let force = matches.get_flag("force");
+
     // Create project structure
-    match create_project_structure(&stack_name, &template_source, &env) {
+    match create_project_structure(&stack_name, &template_source, &env, force) {
         Ok(_) => {
             print_success!("Project '{}' initialized successfully.", stack_name);
         }
         Err(e) => {
             print_error!("Error initializing project: {}", e);
+            process::exit(1);
         }
     }
 }
@@ -204,6 +214,7 @@ fn build_template_url(template_path: &str, resource_name: &str, file_type: &str)
             "resource" => format!("{}/resources/{}.iql.template", base_url, resource_name),
             "manifest" => format!("{}/stackql_manifest.yml.template", base_url),
             "readme" => format!("{}/README.md.template", base_url),
+            "env" => format!("{}/.env.template", base_url),
             _ => base_url,
         }
     } else {
@@ -214,6 +225,7 @@ fn build_template_url(template_path: &str, resource_name: &str, file_type: &str)
             "resource" => format!("{}/resources/{}.iql.template", base_url, resource_name),
             "manifest" => format!("{}/stackql_manifest.yml.template", base_url),
             "readme" => format!("{}/README.md.template", base_url),
+            "env" => format!("{}/.env.template", base_url),
             _ => base_url,
         }
     }
@@ -232,12 +244,15 @@ fn get_template_content(
                 ("aws", "resource") => Ok(aws_templates::RESOURCE_TEMPLATE.to_string()),
                 ("aws", "manifest") => Ok(aws_templates::MANIFEST_TEMPLATE.to_string()),
                 ("aws", "readme") => Ok(aws_templates::README_TEMPLATE.to_string()),
+                ("aws", "env") => Ok(aws_templates::ENV_TEMPLATE.to_string()),
                 ("azure", "resource") => Ok(azure_templates::RESOURCE_TEMPLATE.to_string()),
                 ("azure", "manifest") => Ok(azure_templates::MANIFEST_TEMPLATE.to_string()),
                 ("azure", "readme") => Ok(azure_templates::README_TEMPLATE.to_string()),
+                ("azure", "env") => Ok(azure_templates::ENV_TEMPLATE.to_string()),
                 ("google", "resource") => Ok(google_templates::RESOURCE_TEMPLATE.to_string()),
                 ("google", "manifest") => Ok(google_templates::MANIFEST_TEMPLATE.to_string()),
                 ("google", "readme") => Ok(google_templates::README_TEMPLATE.to_string()),
+                ("google", "env") => Ok(google_templates::ENV_TEMPLATE.to_string()),
                 _ => Err(format!(
                     "Unsupported provider or template type: {}, {}",
                     provider, template_type
@@ -260,6 +275,7 @@ fn create_project_structure(
     stack_name: &str,
     template_source: &TemplateSource,
     env: &str,
+    force: bool,
 ) -> Result<(), String> {
     let cwd =
         std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
@@ -267,7 +283,16 @@ fn create_project_structure(
 
     // Check if directory already exists
     if base_path.exists() {
-        return Err(format!("Directory '{}' already exists", stack_name));
+        if !force {
+            return Err(format!(
+                "Directory '{}' already exists, use --force to initialize it anyway",
+                stack_name
+            ));
+        }
+        print_info!(
+            "Directory '{}' already exists, overwriting generated files (--force)",
+            stack_name
+        );
     }
 
     // Determine sample resource name based on provider
@@ -282,6 +307,11 @@ fn create_project_structure(
     let manifest_template = get_template_content(template_source, "manifest", "")?;
     let readme_template = get_template_content(template_source, "readme", "")?;
     let resource_template = get_template_content(template_source, "resource", sample_res_name)?;
+    // Custom templates may not ship a sample .env
+    let env_template = match template_source {
+        TemplateSource::Embedded(_) => Some(get_template_content(template_source, "env", "")?),
+        TemplateSource::Custom(_) => get_template_content(template_source, "env", "").ok(),
+    };
 
     // Now create directories
     let resource_dir = base_path.join("resources");
@@ -292,6 +322,9 @@ fn create_project_structure(
     create_manifest_file(&base_path, &manifest_template, &context)?;
     create_readme_file(&base_path, &readme_template, &context)?;
     create_resource_file(&resource_dir, sample_res_name, &resource_template, &context)?;
+    if let Some(env_template) = env_template {
+        create_env_file(&base_path, &env_template, &context)?;
+    }
 
     Ok(())
 }
@@ -357,6 +390,22 @@ fn create_readme_file(
     Ok(())
 }
 
+/// Creates a sample `.env` file in the specified directory using the provided template and context.
+fn create_env_file(base_path: &Path, template_str: &str, context: &Context) -> Result<(), String> {
+    // Render template with Tera
+    let env_content = render_template(template_str, context)
+        .map_err(|e| format!("Template rendering error: {}", e))?;
+
+    let env_path = base_path.join(".env");
+    let mut file =
+        fs::File::create(env_path).map_err(|e| format!("Failed to create .env file: {}", e))?;
+
+    file.write_all(env_content.as_bytes())
+        .map_err(|e| format!("Failed to write to .env file: {}", e))?;
+
+    Ok(())
+}
+
 /// Renders a template string using Tera with the provided context.
 fn render_template(template_str: &str, context: &Context) -> Result<String, String> {
     // Create a one-off Tera instance for rendering a single template
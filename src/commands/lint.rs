@@ -0,0 +1,313 @@
+// commands/lint.rs
+
+//! # Lint Command Module
+//!
+//! This module handles the `lint` command, which checks a stack for
+//! configuration drift between its manifest and its query files without
+//! connecting to a StackQL server.
+//!
+//! ## Features
+//! - Warns about resource properties which none of the resource's queries
+//!   (or other properties) reference.
+//! - Warns about variables referenced by queries which are not defined as a
+//!   property, global, export or built-in variable.
+//! - Exits non-zero when there are warnings with `--strict`.
+//!
+//! ## Example Usage
+//! ```bash
+//! ./stackql-deploy lint /path/to/stack
+//! ./stackql-deploy lint /path/to/stack --strict
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::process;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use colored::*;
+use log::warn;
+use regex::Regex;
+
+use crate::commands::common_args::stack_dir;
+use crate::core::config::get_resource_type;
+use crate::core::templating::{get_queries, query_file_path, referenced_variables};
+use crate::resource::manifest::{Manifest, ReplaceOnChange, Resource};
+use crate::template::engine::TemplateEngine;
+
+/// Variables in the context of every query without being declared.
+const BUILTIN_VARIABLES: &[&str] = &[
+    "stack_name",
+    "stack_env",
+    "resource_name",
+    "idempotency_token",
+    "this",
+    "callback",
+];
+
+/// Variables bound per item for a resource with `for_each`.
+const FOR_EACH_VARIABLES: &[&str] = &["item", "item_index"];
+
+/// Template functions, which are referenced like variables.
+const TEMPLATE_FUNCTIONS: &[&str] = &[
+    "uuid",
+    "env",
+    "range",
+    "now",
+    "get_env",
+    "get_random",
+    "throw",
+];
+
+/// Defines the `lint` command for the CLI application.
+pub fn command() -> Command {
+    Command::new("lint")
+        .about("Check a stack for unused properties and undefined query variables")
+        .arg(stack_dir())
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Exit with an error if there are any lint warnings")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Executes the `lint` command.
+pub fn execute(matches: &ArgMatches) {
+    let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
+    let stack_dir = Manifest::resolve_stack_dir_or_exit(stack_dir);
+    let manifest = Manifest::load_from_dir_or_exit(&stack_dir);
+    let engine = TemplateEngine::new();
+
+    let stack_variables = stack_variables(&manifest);
+    let mut warnings = Vec::new();
+    for resource in &manifest.resources {
+        if get_resource_type(resource) == "script" {
+            // Scripts receive their properties as environment variables
+            continue;
+        }
+        let templates = match query_templates(&engine, &stack_dir, resource) {
+            Ok(templates) => templates,
+            Err(e) => {
+                warnings.push(e);
+                continue;
+            }
+        };
+        warnings.extend(lint_resource(resource, &templates, &stack_variables));
+    }
+
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+    if warnings.is_empty() {
+        println!(
+            "{}",
+            format!("no lint warnings for [{}]", manifest.name).green()
+        );
+    } else {
+        println!(
+            "{}",
+            format!("{} lint warning(s) for [{}]", warnings.len(), manifest.name).yellow()
+        );
+        if matches.get_flag("strict") {
+            process::exit(1);
+        }
+    }
+}
+
+/// Names any resource's queries can reference: globals, the unscoped
+/// exports of every resource and resource names (for `<resource>.<field>`).
+fn stack_variables(manifest: &Manifest) -> HashSet<String> {
+    let mut names: HashSet<String> = manifest.globals.iter().map(|g| g.name.clone()).collect();
+    for resource in &manifest.resources {
+        names.insert(resource.name.clone());
+        names.extend(resource.export_names());
+    }
+    names
+}
+
+/// The query templates of a resource by anchor: its inline `sql`, or the
+/// queries of its .iql file.
+fn query_templates(
+    engine: &TemplateEngine,
+    stack_dir: &str,
+    resource: &Resource,
+) -> Result<BTreeMap<String, String>, String> {
+    if let Some(ref sql) = resource.sql {
+        return Ok(BTreeMap::from([("sql".to_string(), sql.clone())]));
+    }
+    let path = query_file_path(stack_dir, resource);
+    if !path.exists() {
+        return Err(format!(
+            "[{}] query file {} not found",
+            resource.name,
+            path.display()
+        ));
+    }
+    Ok(get_queries(engine, stack_dir, resource, &HashMap::new())
+        .into_iter()
+        .map(|(anchor, query)| (anchor, query.template))
+        .collect())
+}
+
+/// Warnings for one resource: properties nothing references, and variables
+/// its queries reference which are not defined for it.
+fn lint_resource(
+    resource: &Resource,
+    templates: &BTreeMap<String, String>,
+    stack_variables: &HashSet<String>,
+) -> Vec<String> {
+    let set_re = Regex::new(r"\{%-?\s*set(?:_global)?\s+(\w+)").unwrap();
+    let prop_names: HashSet<&str> = resource.props.iter().map(|p| p.name.as_str()).collect();
+    // Sources of mapped exports are set for the resource's own teardown
+    let export_sources: HashSet<String> = resource
+        .export_mappings()
+        .into_iter()
+        .map(|(source, _)| source)
+        .collect();
+    let is_defined = |var: &str| {
+        stack_variables.contains(var)
+            || prop_names.contains(var)
+            || export_sources.contains(var)
+            || resource.env.contains_key(var)
+            || BUILTIN_VARIABLES.contains(&var)
+            || TEMPLATE_FUNCTIONS.contains(&var)
+            || (resource.for_each.is_some() && FOR_EACH_VARIABLES.contains(&var))
+    };
+
+    let mut referenced: HashSet<String> = HashSet::new();
+    // Undefined variables and the anchors referencing them
+    let mut undefined: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (anchor, template) in templates {
+        let locals: HashSet<&str> = set_re
+            .captures_iter(template)
+            .filter_map(|c| c.get(1).map(|m| m.as_str()))
+            .collect();
+        for var in referenced_variables(template) {
+            if !is_defined(&var) && !locals.contains(var.as_str()) {
+                undefined.entry(var.clone()).or_default().push(anchor);
+            }
+            referenced.insert(var);
+        }
+    }
+
+    let mut warnings: Vec<String> = undefined
+        .into_iter()
+        .map(|(var, anchors)| {
+            format!(
+                "[{}] variable '{}' is not defined as a property, global or export (referenced by {})",
+                resource.name,
+                var,
+                anchors.join(", ")
+            )
+        })
+        .collect();
+
+    // Text outside the queries which can also use a property
+    let mut other_uses: Vec<String> = resource.env.values().cloned().collect();
+    other_uses.extend(resource.r#if.clone());
+    other_uses.extend(resource.for_each.clone());
+    if let Some(ReplaceOnChange::Properties(ref props)) = resource.replace_on_change {
+        other_uses.extend(props.iter().cloned());
+    }
+    for prop in &resource.props {
+        if prop.name.is_empty() || referenced.contains(&prop.name) {
+            continue;
+        }
+        let word = Regex::new(&format!(r"\b{}\b", regex::escape(&prop.name))).unwrap();
+        let used_elsewhere = other_uses.iter().any(|text| word.is_match(text))
+            || resource.props.iter().any(|other| {
+                other.name != prop.name
+                    && (other.merge.iter().flatten().any(|m| *m == prop.name)
+                        || serde_yaml::to_string(&(&other.value, &other.values))
+                            .is_ok_and(|text| word.is_match(&text)))
+            });
+        if !used_elsewhere {
+            warnings.push(format!(
+                "[{}] property '{}' is not referenced by any query",
+                resource.name, prop.name
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(yaml: &str) -> Resource {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn templates(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(anchor, template)| (anchor.to_string(), template.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_reports_unused_property() {
+        let resource = resource(
+            "name: vpc\n\
+             props:\n\
+             - name: cidr_block\n  value: 10.0.0.0/16\n\
+             - name: stale_tag\n  value: old\n\
+             - name: tags\n  value: [\"{{ tag_prefix }}\"]\n\
+             - name: tag_prefix\n  value: app\n",
+        );
+        let queries = templates(&[
+            (
+                "create",
+                "INSERT INTO vpcs SELECT '{{ cidr_block }}', '{{ tags }}'",
+            ),
+            ("exists", "SELECT COUNT(*) as count FROM vpcs"),
+        ]);
+        let warnings = lint_resource(&resource, &queries, &HashSet::new());
+        assert_eq!(
+            warnings,
+            ["[vpc] property 'stale_tag' is not referenced by any query"]
+        );
+    }
+
+    #[test]
+    fn test_lint_reports_undefined_variables() {
+        let resource = resource("name: subnet\nprops:\n- name: cidr\n  value: 10.0.1.0/24\n");
+        let queries = templates(&[
+            (
+                "create",
+                "{% set zone = region ~ 'a' %}\n\
+                 INSERT INTO subnets SELECT '{{ cidr }}', '{{ vpc.vpc_id }}', '{{ zone }}',\n\
+                 '{{ this.identifier }}', '{{ stack_env }}', '{{ uuid() }}', '{{ regoin }}'",
+            ),
+            (
+                "exists",
+                "SELECT COUNT(*) as count FROM subnets WHERE id = '{{ regoin }}'",
+            ),
+        ]);
+        let stack_variables: HashSet<String> =
+            ["vpc", "region"].iter().map(|s| s.to_string()).collect();
+        let warnings = lint_resource(&resource, &queries, &stack_variables);
+        assert_eq!(
+            warnings,
+            [
+                "[subnet] variable 'regoin' is not defined as a property, global or export \
+              (referenced by create, exists)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_variables_include_globals_exports_and_resources() {
+        let manifest: Manifest = serde_yaml::from_str(
+            "name: stack\n\
+             providers: [aws]\n\
+             globals:\n- name: region\n  value: us-east-1\n\
+             resources:\n- name: vpc\n  exports: [vpc_id, {cidr_block: vpc_cidr}]\n",
+        )
+        .unwrap();
+        let names = stack_variables(&manifest);
+        for name in ["region", "vpc", "vpc_id"] {
+            assert!(names.contains(name), "missing {}", name);
+        }
+    }
+}
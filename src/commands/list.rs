@@ -0,0 +1,435 @@
+// commands/list.rs
+
+//! # List Command Module
+//!
+//! This module handles the `list` command, which loads a stack manifest and
+//! prints an overview of its resources without connecting to a StackQL server.
+//!
+//! ## Features
+//! - Lists each resource with its `type`, `if` condition and exported variables.
+//! - Supports machine-readable output with `--json`.
+//! - Explains how a resource's exports resolve with `--describe-exports`.
+//! - Compares the resolved globals and properties of two environments with
+//!   `--print-context-diff-between-envs`.
+//!
+//! ## Example Usage
+//! ```bash
+//! ./stackql-deploy list /path/to/stack
+//! ./stackql-deploy list /path/to/stack --json
+//! ./stackql-deploy list /path/to/stack --describe-exports vpc
+//! ./stackql-deploy list /path/to/stack --print-context-diff-between-envs staging prd
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use colored::*;
+use serde_json::json;
+
+use crate::commands::common_args::{
+    env_all, env_all_unsafe, env_file, env_overrides, env_var, process_env_value, stack_dir,
+    var_file, var_files_value, var_from_command,
+};
+use crate::core::config::{get_full_context, get_resource_type, render_globals};
+use crate::core::env::{load_env_vars, render_env_file_path};
+use crate::core::utils::catch_error_and_exit;
+use crate::resource::manifest::{Manifest, Resource};
+use crate::template::engine::TemplateEngine;
+
+/// Defines the `list` command for the CLI application.
+pub fn command() -> Command {
+    Command::new("list")
+        .about("List the resources defined in a stack manifest")
+        .arg(stack_dir())
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Output the resource list as JSON")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("describe-exports")
+                .long("describe-exports")
+                .value_name("RESOURCE")
+                .help("Show how each export of a resource maps from its source column")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("print-context-diff-between-envs")
+                .long("print-context-diff-between-envs")
+                .value_names(["ENV_A", "ENV_B"])
+                .help("Render the globals and properties for two environments and print the differences")
+                .num_args(2)
+                .conflicts_with("describe-exports"),
+        )
+        .arg(env_file())
+        .arg(env_var())
+        .arg(var_file())
+        .arg(var_from_command())
+        .arg(env_all())
+        .arg(env_all_unsafe())
+}
+
+/// Executes the `list` command.
+pub fn execute(matches: &ArgMatches) {
+    let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
+    let stack_dir = Manifest::resolve_stack_dir_or_exit(stack_dir);
+    let manifest = Manifest::load_from_dir_or_exit(&stack_dir);
+
+    if let Some(name) = matches.get_one::<String>("describe-exports") {
+        describe_exports(&manifest, name, matches.get_flag("json"));
+        return;
+    }
+
+    if let Some(envs) = matches.get_many::<String>("print-context-diff-between-envs") {
+        let envs: Vec<&String> = envs.collect();
+        // The env file path may differ per environment
+        let engine = TemplateEngine::new();
+        let env_file = matches.get_one::<String>("env-file").unwrap();
+        let overrides = env_overrides(matches);
+        let env_vars_for = |env: &str| {
+            let path = render_env_file_path(&engine, env_file, env, &manifest.name);
+            let var_files: Vec<String> = var_files_value(matches)
+                .iter()
+                .map(|path| render_env_file_path(&engine, path, env, &manifest.name))
+                .collect();
+            load_env_vars(&path, &var_files, &overrides, process_env_value(matches))
+        };
+        print_context_diff(
+            &manifest,
+            (&env_vars_for(envs[0]), &env_vars_for(envs[1])),
+            envs[0],
+            envs[1],
+            matches.get_flag("json"),
+        );
+        return;
+    }
+
+    if matches.get_flag("json") {
+        let resources: Vec<serde_json::Value> = manifest
+            .resources
+            .iter()
+            .map(|resource| {
+                json!({
+                    "name": resource.name,
+                    "type": get_resource_type(resource),
+                    "if": resource.r#if,
+                    "depends_on": resource.depends_on,
+                    "exports": resource.export_names(),
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&resources).unwrap_or_default()
+        );
+        return;
+    }
+
+    let headers = ["NAME", "TYPE", "IF", "DEPENDS ON", "EXPORTS"];
+    let rows: Vec<[String; 5]> = manifest
+        .resources
+        .iter()
+        .map(|resource| {
+            [
+                resource.name.clone(),
+                get_resource_type(resource).to_string(),
+                resource.r#if.clone().unwrap_or_else(|| "-".to_string()),
+                match resource.depends_on.join(", ") {
+                    deps if deps.is_empty() => "-".to_string(),
+                    deps => deps,
+                },
+                match resource.export_names() {
+                    names if names.is_empty() => "-".to_string(),
+                    names => names.join(", "),
+                },
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    println!(
+        "{} ({} resources)\n",
+        manifest.name.green().bold(),
+        rows.len()
+    );
+    let header_line = headers
+        .iter()
+        .zip(widths.iter())
+        .map(|(h, w)| format!("{:<w$}", h, w = w))
+        .collect::<Vec<_>>()
+        .join("  ");
+    println!("{}", header_line.trim_end().bold());
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Prints how each declared export of `name` resolves, using the same
+/// mapping the exports phase applies to query results.
+fn describe_exports(manifest: &Manifest, name: &str, as_json: bool) {
+    let Some(resource) = manifest.find_resource(name) else {
+        catch_error_and_exit(&format!(
+            "resource [{}] not found in stack [{}]",
+            name, manifest.name
+        ));
+    };
+
+    let (exports, ignored) = export_descriptions(resource);
+
+    if as_json {
+        let exports: Vec<serde_json::Value> = exports
+            .iter()
+            .map(|export| {
+                json!({
+                    "source": export.source,
+                    "target": export.target,
+                    "scoped_target": format!("{}.{}", resource.name, export.target),
+                    "protected": export.protected,
+                })
+            })
+            .collect();
+        let output = json!({
+            "resource": resource.name,
+            "exports": exports,
+            "ignored_entries": ignored,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).unwrap_or_default()
+        );
+        return;
+    }
+
+    println!(
+        "{}",
+        format!("exports for [{}]", resource.name).green().bold()
+    );
+    if exports.is_empty() {
+        println!("  None");
+    }
+    for export in &exports {
+        let protected = if export.protected { " (protected)" } else { "" };
+        println!(
+            "  {} -> {} / {}.{}{}",
+            export.source, export.target, resource.name, export.target, protected
+        );
+    }
+    if ignored > 0 {
+        println!(
+            "  {} map entr{} ignored: exports that mix names and {{ source: target }} maps only export the names",
+            ignored,
+            if ignored == 1 { "y" } else { "ies" }
+        );
+    }
+}
+
+/// How one export of a resource maps from its source column.
+#[derive(Debug, PartialEq)]
+struct ExportDescription {
+    source: String,
+    target: String,
+    protected: bool,
+}
+
+/// The exports of `resource` as the exports phase maps them, with the
+/// number of map entries ignored because they are mixed with plain names.
+fn export_descriptions(resource: &Resource) -> (Vec<ExportDescription>, usize) {
+    let protected = resource.protected_export_names();
+    let exports = resource
+        .export_mappings()
+        .into_iter()
+        .map(|(source, target)| ExportDescription {
+            protected: protected.contains(&target),
+            source,
+            target,
+        })
+        .collect();
+
+    // Map entries only count when every entry is a map
+    let map_entries = resource.exports.iter().filter(|e| e.is_mapping()).count();
+    let ignored = if map_entries < resource.exports.len() {
+        map_entries
+    } else {
+        0
+    };
+    (exports, ignored)
+}
+
+/// Resolves the globals and the properties (and resource `env` values) of
+/// every resource for `stack_env`, keyed as `globals.<name>` and
+/// `<resource>.<name>`.
+fn resolved_context(
+    engine: &TemplateEngine,
+    manifest: &Manifest,
+    env_vars: &HashMap<String, String>,
+    stack_env: &str,
+) -> BTreeMap<String, String> {
+    let global_context = render_globals(engine, env_vars, manifest, stack_env, &manifest.name);
+    let mut resolved: BTreeMap<String, String> = manifest
+        .globals
+        .iter()
+        .filter_map(|g| {
+            global_context
+                .get(&g.name)
+                .map(|v| (format!("globals.{}", g.name), v.clone()))
+        })
+        .collect();
+
+    for resource in &manifest.resources {
+        let full_context = get_full_context(
+            engine,
+            &global_context,
+            resource,
+            stack_env,
+            &manifest.env_aliases,
+            None,
+        );
+        let names = resource
+            .props
+            .iter()
+            .map(|p| &p.name)
+            .chain(resource.env.keys());
+        for name in names {
+            if let Some(value) = full_context.get(name) {
+                resolved.insert(format!("{}.{}", resource.name, name), value.clone());
+            }
+        }
+    }
+    resolved
+}
+
+/// Prints the differences between the resolved context of two environments,
+/// each resolved with its own env vars.
+fn print_context_diff(
+    manifest: &Manifest,
+    env_vars: (&HashMap<String, String>, &HashMap<String, String>),
+    env_a: &str,
+    env_b: &str,
+    as_json: bool,
+) {
+    let engine = TemplateEngine::new();
+    let context_a = resolved_context(&engine, manifest, env_vars.0, env_a);
+    let context_b = resolved_context(&engine, manifest, env_vars.1, env_b);
+
+    let mut keys: Vec<&String> = context_a.keys().chain(context_b.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let differences: Vec<(&String, Option<&String>, Option<&String>)> = keys
+        .into_iter()
+        .map(|key| (key, context_a.get(key), context_b.get(key)))
+        .filter(|(_, a, b)| a != b)
+        .collect();
+
+    if as_json {
+        let output: Vec<serde_json::Value> = differences
+            .iter()
+            .map(|(key, a, b)| {
+                json!({
+                    "key": key,
+                    env_a: a,
+                    env_b: b,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).unwrap_or_default()
+        );
+        return;
+    }
+
+    println!(
+        "{}",
+        format!("context diff: {} -> {}", env_a, env_b)
+            .green()
+            .bold()
+    );
+    if differences.is_empty() {
+        println!("  no differences");
+        return;
+    }
+    for (key, a, b) in &differences {
+        match (a, b) {
+            (Some(a), Some(b)) => println!("{} {}: {} -> {}", "~".yellow(), key, a, b),
+            (Some(a), None) => println!("{} {}: {} (only in {})", "-".red(), key, a, env_a),
+            (None, Some(b)) => println!("{} {}: {} (only in {})", "+".green(), key, b, env_b),
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(name: &str, to: &str, protected: bool) -> ExportDescription {
+        ExportDescription {
+            source: name.to_string(),
+            target: to.to_string(),
+            protected,
+        }
+    }
+
+    #[test]
+    fn test_export_descriptions() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: db
+    exports: [db_host, db_password]
+    protected: [db_password]
+  - name: mapped
+    exports:
+      - host: admin_host
+      - password: admin_password
+      - token: api_token
+    protected: [password, api_token]
+  - name: mixed
+    exports:
+      - vpc_id
+      - cidr: vpc_cidr
+      - tags: vpc_tags
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+
+        let (exports, ignored) = export_descriptions(&manifest.resources[0]);
+        assert_eq!(
+            exports,
+            [
+                describe("db_host", "db_host", false),
+                describe("db_password", "db_password", true),
+            ]
+        );
+        assert_eq!(ignored, 0);
+
+        // Protected by the source column, or by the renamed export
+        let (exports, ignored) = export_descriptions(&manifest.resources[1]);
+        assert_eq!(
+            exports,
+            [
+                describe("host", "admin_host", false),
+                describe("password", "admin_password", true),
+                describe("token", "api_token", true),
+            ]
+        );
+        assert_eq!(ignored, 0);
+
+        let (exports, ignored) = export_descriptions(&manifest.resources[2]);
+        assert_eq!(exports, [describe("vpc_id", "vpc_id", false)]);
+        assert_eq!(ignored, 2);
+    }
+}
@@ -3,10 +3,14 @@ pub mod build;
 pub mod common_args;
 pub mod info;
 pub mod init;
+pub mod lint;
+pub mod list;
 pub mod plan;
 pub mod shell;
 pub mod start_server;
 pub mod stop_server;
 pub mod teardown;
 pub mod test;
+#[cfg(test)]
+pub mod testing;
 pub mod upgrade;
@@ -4,75 +4,612 @@
 //!
 //! This module provides the `plan` command for the StackQL Deploy application.
 //! The `plan` command compares the current state of infrastructure (live, not from a state file)
-//! against the desired state defined by configuration files. It outputs the necessary queries
-//! that would need to be run to achieve the desired state.
+//! against the desired state defined by configuration files, and reports what a `build` would do
+//! to each resource.  Only read-only queries (`exists`, `statecheck`, `exports`) are run; the
+//! `create`, `update` and `delete` queries are never executed.
 //!
 //! ## Features
 //! - Compare live infrastructure state against desired state.
-//! - Generate queries required to achieve the desired state.
-//! - Provide dry-run capability for previewing changes before applying.
+//! - Classify each resource (each item of a `for_each` resource) as to create, to update,
+//!   to replace or unchanged.
+//! - Print a summary of the planned actions without mutating anything.
 //!
 //! ## Example Usage
 //! ```bash
 //! ./stackql-deploy plan path/to/stack dev
 //! ```
 
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
 use clap::{ArgMatches, Command};
+use log::info;
 
+use crate::commands::base::CommandRunner;
+use crate::commands::build::needs_replacement;
 use crate::commands::common_args::{
-    dry_run, env_file, env_var, log_level, on_failure, show_queries, stack_dir, stack_env,
-    FailureAction,
+    check_binary_version, detailed_exitcode, env_all, env_all_unsafe, env_file, env_overrides,
+    env_var, fail_if_binary_older_than, log_level, manifest_env_var, process_env_value, resources,
+    resources_value, show_queries, stack_dir, stack_env, stack_env_value, var_file,
+    var_files_value, var_from_command,
 };
-use crate::utils::display::print_unicode_box;
+use crate::core::config::{get_resource_type, multi_item_contexts};
+use crate::core::report::ResourceOutcome;
+use crate::core::templating::ParsedQuery;
+use crate::core::utils::catch_error_and_exit;
+use crate::resource::manifest::Resource;
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::display::{json_output, print_unicode_box, BorderColor};
+use crate::utils::server::{check_and_start_server, stop_local_server};
 
 /// Configures the `plan` command for the CLI application.
 pub fn command() -> Command {
     Command::new("plan")
-        .about("Plan infrastructure changes (coming soon)")
+        .about("Show the changes a build would make, without making them")
         .arg(stack_dir())
         .arg(stack_env())
+        .arg(manifest_env_var())
         .arg(log_level())
+        .arg(fail_if_binary_older_than())
         .arg(env_file())
         .arg(env_var())
-        .arg(dry_run())
+        .arg(var_file())
+        .arg(var_from_command())
+        .arg(env_all())
+        .arg(env_all_unsafe())
         .arg(show_queries())
-        .arg(on_failure())
+        .arg(resources())
+        .arg(detailed_exitcode())
+}
+
+/// What a `build` would do to a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlannedAction {
+    Create,
+    Update,
+    /// The resource is deleted and created again (`replace_on_change`)
+    Replace,
+    NoChange,
+    /// `script` and `command` resources are run on every build
+    Run,
+    /// The resource's `if` condition is false
+    Skip,
+}
+
+impl PlannedAction {
+    /// The outcome a `build` would report, as recorded in the plan report.
+    fn outcome(self) -> ResourceOutcome {
+        match self {
+            PlannedAction::Create => ResourceOutcome::Created,
+            PlannedAction::Update => ResourceOutcome::Updated,
+            PlannedAction::Replace => ResourceOutcome::Replaced,
+            PlannedAction::NoChange => ResourceOutcome::Unchanged,
+            PlannedAction::Run => ResourceOutcome::Executed,
+            PlannedAction::Skip => ResourceOutcome::Skipped,
+        }
+    }
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            PlannedAction::Create => "+",
+            PlannedAction::Update => "~",
+            PlannedAction::Replace => "-/+",
+            PlannedAction::NoChange => "=",
+            PlannedAction::Run => ">",
+            PlannedAction::Skip => "-",
+        };
+        write!(f, "{}", symbol)
+    }
 }
 
 /// Executes the `plan` command.
-pub fn execute(matches: &ArgMatches) {
-    let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
-    let stack_env = matches.get_one::<String>("stack_env").unwrap();
-
-    // Extract the common arguments
-    let log_level = matches.get_one::<String>("log-level").unwrap();
-    let env_file = matches.get_one::<String>("env-file").unwrap();
-    let env_vars = matches.get_many::<String>("env");
-    let dry_run = matches.get_flag("dry-run");
-    let show_queries = matches.get_flag("show-queries");
-    let on_failure = matches.get_one::<FailureAction>("on-failure").unwrap();
+///
+/// Returns whether `--detailed-exitcode` is set and resources are to be
+/// created, updated or replaced.
+pub fn execute(matches: &ArgMatches, server: &ServerConfig, options: &RunOptions) -> bool {
+    let stack_dir_val = matches.get_one::<String>("stack_dir").unwrap();
+    let stack_env_val = &stack_env_value(matches);
+    let env_file_val = matches.get_one::<String>("env-file").unwrap();
+    let env_vars = env_overrides(matches);
+    let is_show_queries = matches.get_flag("show-queries");
+
+    check_binary_version(matches);
+    check_and_start_server(server, options);
+    let client = create_client(server, options);
+    let mut runner = CommandRunner::new(
+        client,
+        stack_dir_val,
+        stack_env_val,
+        env_file_val,
+        &var_files_value(matches),
+        &env_vars,
+        process_env_value(matches),
+    );
+    runner.track_report("plan", true);
+    runner.select_resources(&resources_value(matches));
+
+    let stack_name_display = if runner.stack_name.is_empty() {
+        runner.stack_dir.clone()
+    } else {
+        runner.stack_name.clone()
+    };
 
     print_unicode_box(
         &format!(
             "Planning changes for stack: [{}] in environment: [{}]",
-            stack_dir, stack_env
+            stack_name_display, stack_env_val
         ),
-        crate::utils::display::BorderColor::Yellow,
+        BorderColor::Yellow,
     );
 
-    println!("Log Level: {}", log_level);
-    println!("Environment File: {}", env_file);
+    let plan = run_plan(&mut runner, is_show_queries);
+    if json_output() {
+        runner.print_json_report("plan", true);
+    } else {
+        print_plan(&plan);
+    }
+
+    print_unicode_box("plan complete", BorderColor::Green);
+
+    stop_local_server(server);
+
+    matches.get_flag("detailed-exitcode")
+        && plan.iter().any(|(_, action)| {
+            matches!(
+                action,
+                PlannedAction::Create | PlannedAction::Update | PlannedAction::Replace
+            )
+        })
+}
+
+/// Classify every resource in manifest order.  Exports of existing
+/// resources are collected as they are checked, so downstream resources
+/// render against live values; a resource whose queries depend on values
+/// that do not exist yet is planned for creation.  Each planned action is
+/// also recorded in `runner.report` as the outcome a build would report.
+fn run_plan(runner: &mut CommandRunner, show_queries: bool) -> Vec<(String, PlannedAction)> {
+    let start_time = Instant::now();
+    let resources = runner.manifest.resources.clone();
+    let mut plan = Vec::new();
+
+    for resource in &resources {
+        runner.report.start_resource();
+        let res_type = get_resource_type(resource).to_string();
+        let full_context = runner.get_full_context(resource);
+
+        let action = if !runner.evaluate_condition(resource, &full_context) {
+            PlannedAction::Skip
+        } else if res_type == "script" || res_type == "command" {
+            PlannedAction::Run
+        } else if res_type == "query" {
+            plan_query(runner, resource, full_context, show_queries);
+            continue;
+        } else if let Some(for_each) = resource.for_each.as_deref() {
+            let items = plan_items(runner, resource, for_each, full_context, show_queries);
+            let action = [
+                PlannedAction::Create,
+                PlannedAction::Replace,
+                PlannedAction::Update,
+            ]
+            .into_iter()
+            .find(|action| items.contains(action))
+            .unwrap_or(PlannedAction::NoChange);
+            info!("[{}] planned action: {:?}", resource.name, action);
+            runner
+                .report
+                .record(&resource.name, &res_type, action.outcome(), None);
+            plan.extend(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| (format!("{}[{}]", resource.name, index), item)),
+            );
+            continue;
+        } else {
+            plan_resource(runner, resource, full_context, show_queries)
+        };
+        info!("[{}] planned action: {:?}", resource.name, action);
+        runner
+            .report
+            .record(&resource.name, &res_type, action.outcome(), None);
+        plan.push((resource.name.clone(), action));
+    }
+
+    runner.report.finish(start_time.elapsed());
+    plan
+}
+
+/// Decide whether a `resource` (or `multi`) needs to be created, updated or
+/// replaced.
+fn plan_resource(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    full_context: HashMap<String, String>,
+    show_queries: bool,
+) -> PlannedAction {
+    let queries = runner.get_queries(resource, &full_context);
+    plan_checked(runner, resource, &queries, full_context, show_queries)
+}
 
-    if let Some(vars) = env_vars {
-        println!("Environment Variables:");
-        for var in vars {
-            println!("  - {}", var);
+/// Decide, item by item, whether each item of a `multi` resource expanded
+/// over its `for_each` property needs to be created, updated or replaced,
+/// checking it with its `exists` and `statecheck` queries as build does.
+/// Exports are resource level, so run once all items are in the desired
+/// state.
+fn plan_items(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    for_each: &str,
+    full_context: HashMap<String, String>,
+    show_queries: bool,
+) -> Vec<PlannedAction> {
+    let iterations = multi_item_contexts(&resource.name, for_each, &full_context)
+        .unwrap_or_else(|e| catch_error_and_exit(&e));
+    let items: Vec<PlannedAction> = iterations
+        .into_iter()
+        .map(|context| {
+            let mut queries = runner.get_queries(resource, &context);
+            queries.remove("exports");
+            plan_checked(runner, resource, &queries, context, show_queries)
+        })
+        .collect();
+
+    if items.iter().all(|item| *item == PlannedAction::NoChange) {
+        let exports = runner
+            .get_queries(resource, &full_context)
+            .get("exports")
+            .and_then(|q| {
+                runner.try_render_query(&resource.name, "exports", &q.template, &full_context)
+            });
+        if let Some(eq) = exports {
+            runner.process_exports(
+                resource,
+                &full_context,
+                &eq,
+                1,
+                0,
+                false,
+                show_queries,
+                true,
+            );
+        }
+    }
+    items
+}
+
+/// Decide whether a resource checked with its `queries`, rendered in
+/// `full_context`, needs to be created, updated or replaced.
+fn plan_checked(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    queries: &HashMap<String, ParsedQuery>,
+    mut full_context: HashMap<String, String>,
+    show_queries: bool,
+) -> PlannedAction {
+    // Existence: exists query, falling back to the statecheck, then exports
+    let exists_anchor = ["exists", "statecheck", "exports"]
+        .into_iter()
+        .find(|anchor| queries.contains_key(*anchor))
+        .unwrap_or("exists");
+    let exists = match queries.get(exists_anchor) {
+        Some(q) => {
+            let Some(rendered) =
+                runner.try_render_query(&resource.name, exists_anchor, &q.template, &full_context)
+            else {
+                info!(
+                    "[{}] {} query depends on values not yet available",
+                    resource.name, exists_anchor
+                );
+                return PlannedAction::Create;
+            };
+            let (exists, fields) = runner.check_if_resource_exists(
+                resource,
+                &rendered,
+                1,
+                0,
+                false,
+                show_queries,
+                false,
+            );
+            for (k, v) in fields.unwrap_or_default() {
+                full_context.insert(format!("{}.{}", resource.name, k), v);
+            }
+            exists
         }
+        None => false,
+    };
+    if !exists {
+        return PlannedAction::Create;
     }
 
-    println!("Dry Run: {}", dry_run);
-    println!("Show Queries: {}", show_queries);
-    println!("On Failure: {:?}", on_failure);
+    // Desired state: statecheck, falling back to the exports query as proxy
+    let render = |anchor: &str| {
+        queries.get(anchor).and_then(|q| {
+            runner.try_render_query(&resource.name, anchor, &q.template, &full_context)
+        })
+    };
+    let statecheck = render("statecheck");
+    let exports = render("exports");
+
+    let is_correct_state = if resource.skip_validation.unwrap_or(false) {
+        true
+    } else if let Some(ref sq) = statecheck {
+        runner.check_if_resource_is_correct_state(resource, sq, 1, 0, false, show_queries)
+    } else if let Some(ref eq) = exports {
+        let (state, proxy) =
+            runner.check_state_using_exports_proxy(resource, eq, 1, 0, false, show_queries);
+        if let Some(ref result) = proxy {
+            runner.process_exports_from_result(resource, result);
+        }
+        return if state {
+            PlannedAction::NoChange
+        } else {
+            update_or_replace(resource, &full_context)
+        };
+    } else {
+        // No way to check the state; a build would run the update
+        false
+    };
 
-    println!("📐 plan complete (dry run: {})", dry_run);
+    if !is_correct_state {
+        return update_or_replace(resource, &full_context);
+    }
+    if let Some(ref eq) = exports {
+        runner.process_exports(resource, &full_context, eq, 1, 0, false, show_queries, true);
+    }
+    PlannedAction::NoChange
+}
+
+/// How a build brings an existing resource which is not in the desired state
+/// into it: replacing it when `replace_on_change` calls for that, comparing
+/// against the fields captured by its `exists` query.
+fn update_or_replace(resource: &Resource, full_context: &HashMap<String, String>) -> PlannedAction {
+    if needs_replacement(resource, full_context) {
+        PlannedAction::Replace
+    } else {
+        PlannedAction::Update
+    }
+}
+
+/// Run the exports of a `query` resource so downstream resources can use them.
+fn plan_query(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    full_context: HashMap<String, String>,
+    show_queries: bool,
+) {
+    let exports_query = match resource.sql.as_ref() {
+        Some(sql) => Some(runner.render_inline_template(&resource.name, sql, &full_context)),
+        None => runner
+            .get_queries(resource, &full_context)
+            .get("exports")
+            .and_then(|q| {
+                runner.try_render_query(&resource.name, "exports", &q.template, &full_context)
+            }),
+    };
+    if let Some(eq) = exports_query {
+        runner.process_exports(
+            resource,
+            &full_context,
+            &eq,
+            1,
+            0,
+            false,
+            show_queries,
+            true,
+        );
+    }
+}
+
+/// Print a Terraform-like summary of the planned actions.
+fn print_plan(plan: &[(String, PlannedAction)]) {
+    let count = |action: PlannedAction| plan.iter().filter(|(_, a)| *a == action).count();
+
+    println!();
+    for (name, action) in plan {
+        println!("  {} {}", action, name);
+    }
+    println!();
+    println!(
+        "Plan: {} to create, {} to update, {} to replace, {} unchanged, {} to run, {} skipped.",
+        count(PlannedAction::Create),
+        count(PlannedAction::Update),
+        count(PlannedAction::Replace),
+        count(PlannedAction::NoChange),
+        count(PlannedAction::Run),
+        count(PlannedAction::Skip)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing::TestStack;
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    /// A vpc stack whose `replace_on_change` setting is `replace_on_change`.
+    fn vpc_stack(replace_on_change: &str) -> TestStack {
+        let manifest = format!(
+            r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+    replace_on_change: {}
+    props:
+      - name: cidr_block
+        value: "10.0.0.0/16"
+"#,
+            replace_on_change
+        );
+        TestStack::new(
+            &manifest,
+            &[(
+                "vpc.iql",
+                "/*+ exists */\n\
+                 SELECT cidr_block FROM aws.ec2.vpcs WHERE name = 'main';\n\n\
+                 /*+ statecheck */\n\
+                 SELECT COUNT(*) as count FROM aws.ec2.vpcs WHERE cidr_block = '{{ cidr_block }}';\n",
+            )],
+        )
+    }
+
+    /// A server holding the vpc with a different block than desired.
+    fn drifted_vpc_server() -> MockServer {
+        MockServer::start(|query| {
+            if query.starts_with("SELECT cidr_block") {
+                Reply::rows(&["cidr_block"], &[&["10.1.0.0/16"]])
+            } else if query.starts_with("SELECT COUNT(*)") {
+                Reply::count(0)
+            } else {
+                Reply::ok()
+            }
+        })
+    }
+
+    #[test]
+    fn test_plan_replaces_on_changed_property() {
+        let server = drifted_vpc_server();
+
+        let stack = vpc_stack("[cidr_block]");
+        let mut runner = stack.runner(&server);
+        let plan = run_plan(&mut runner, false);
+        assert_eq!(plan, [("vpc".to_string(), PlannedAction::Replace)]);
+        assert_eq!(runner.report.replaced, 1);
+
+        let stack = vpc_stack("[instance_tenancy]");
+        let mut runner = stack.runner(&server);
+        let plan = run_plan(&mut runner, false);
+        assert_eq!(plan, [("vpc".to_string(), PlannedAction::Update)]);
+    }
+
+    /// Resources in every state a plan tells apart.
+    const NETWORK_MANIFEST: &str = r#"
+version: 1
+name: net
+providers:
+  - aws
+resources:
+  - name: vpc
+  - name: subnet
+  - name: sg
+  - name: nat
+    if: "'{{ stack_env }}' == 'prd'"
+  - name: tag_all
+    type: command
+    sql: UPDATE aws.ec2.tags SET env = 'dev'
+"#;
+
+    /// Queries of a resource kept in `table`.
+    fn checked_queries(table: &str) -> String {
+        format!(
+            "/*+ exists */\n\
+             SELECT COUNT(*) as count FROM {table};\n\n\
+             /*+ statecheck */\n\
+             SELECT COUNT(*) as count FROM {table} WHERE tagged;\n\n\
+             /*+ create */\n\
+             INSERT INTO {table} SELECT 1;\n\n\
+             /*+ update */\n\
+             UPDATE {table} SET tagged = true;\n"
+        )
+    }
+
+    #[test]
+    fn test_plan_classifies_each_resource() {
+        let queries: Vec<(String, String)> = ["vpc", "subnet", "sg", "nat"]
+            .iter()
+            .map(|name| {
+                (
+                    format!("{}.iql", name),
+                    checked_queries(&format!("aws.ec2.{}s", name)),
+                )
+            })
+            .collect();
+        let files: Vec<(&str, &str)> = queries
+            .iter()
+            .map(|(file, contents)| (file.as_str(), contents.as_str()))
+            .collect();
+        let stack = TestStack::new(NETWORK_MANIFEST, &files);
+        // The vpc is as desired, the subnet is missing and the sg has drifted
+        let server = MockServer::start(|query| {
+            let in_state = query.contains("aws.ec2.vpcs")
+                || (query.contains("aws.ec2.sgs") && !query.contains("tagged"));
+            Reply::count(usize::from(in_state))
+        });
+        let mut runner = stack.runner(&server);
+
+        let plan = run_plan(&mut runner, false);
+
+        assert_eq!(
+            plan,
+            [
+                ("vpc".to_string(), PlannedAction::NoChange),
+                ("subnet".to_string(), PlannedAction::Create),
+                ("sg".to_string(), PlannedAction::Update),
+                ("nat".to_string(), PlannedAction::Skip),
+                ("tag_all".to_string(), PlannedAction::Run),
+            ]
+        );
+        let report = &runner.report;
+        assert_eq!(
+            (report.unchanged, report.created, report.updated),
+            (1, 1, 1)
+        );
+        assert_eq!((report.skipped, report.executed), (1, 1));
+        // Only read-only queries are run
+        assert!(!server
+            .queries()
+            .iter()
+            .any(|q| ["INSERT", "UPDATE", "DELETE"]
+                .iter()
+                .any(|dml| q.starts_with(dml))));
+    }
+
+    #[test]
+    fn test_plan_for_each_items() {
+        let stack = TestStack::new(
+            r#"
+version: 1
+name: store
+providers:
+  - aws
+resources:
+  - name: buckets
+    type: multi
+    for_each: names
+    props:
+      - name: names
+        value: [logs, assets]
+"#,
+            &[(
+                "buckets.iql",
+                "/*+ exists */\n\
+                 SELECT COUNT(*) as count FROM aws.s3.buckets WHERE name = '{{ item }}';\n\n\
+                 /*+ statecheck */\n\
+                 SELECT COUNT(*) as count FROM aws.s3.buckets WHERE name = '{{ item }}' AND versioned;\n\n\
+                 /*+ create */\n\
+                 INSERT INTO aws.s3.buckets SELECT '{{ item }}';\n",
+            )],
+        );
+        // Only the logs bucket exists, as desired
+        let server = MockServer::start(|query| Reply::count(usize::from(query.contains("'logs'"))));
+        let mut runner = stack.runner(&server);
+
+        let plan = run_plan(&mut runner, false);
+
+        assert_eq!(
+            plan,
+            [
+                ("buckets[0]".to_string(), PlannedAction::NoChange),
+                ("buckets[1]".to_string(), PlannedAction::Create),
+            ]
+        );
+        assert_eq!(runner.report.resources.len(), 1);
+        assert_eq!(runner.report.resources[0].outcome, ResourceOutcome::Created);
+    }
 }
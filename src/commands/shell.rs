@@ -24,9 +24,8 @@ use colored::*;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use crate::globals::{server_host, server_port};
-use crate::utils::connection::create_client;
-use crate::utils::display::print_unicode_box;
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::display::{print_unicode_box, render_table};
 use crate::utils::query::{execute_query, QueryResult};
 use crate::utils::server::check_and_start_server;
 
@@ -36,19 +35,19 @@ pub fn command() -> Command {
 }
 
 /// Executes the `shell` command, launching an interactive query interface.
-pub fn execute(_matches: &ArgMatches) {
+pub fn execute(_matches: &ArgMatches, server: &ServerConfig, options: &RunOptions) {
     print_unicode_box(
         "Launching interactive shell...",
         crate::utils::display::BorderColor::Cyan,
     );
 
-    let host = server_host();
-    let port = server_port();
+    let host = server.host.as_str();
+    let port = server.port;
 
-    check_and_start_server();
+    check_and_start_server(server, options);
 
-    // Connect to the server using the global host and port
-    let mut stackql_client_conn = create_client();
+    // Connect to the configured server
+    let mut stackql_client_conn = create_client(server, options);
 
     println!("Type 'exit' to quit the shell");
     println!("---");
@@ -91,7 +90,10 @@ pub fn execute(_matches: &ArgMatches) {
                                 rows,
                                 notices,
                             } => {
-                                print_table(columns, rows);
+                                println!("{}", render_table(&columns, &rows));
+                                if !rows.is_empty() {
+                                    println!("{} rows returned", rows.len());
+                                }
 
                                 // Display notices if any
                                 if !notices.is_empty() {
@@ -147,67 +149,3 @@ fn normalize_query(input: &str) -> String {
         .collect::<Vec<_>>()
         .join(" ")
 }
-
-/// Prints the query result in a tabular format.
-fn print_table(
-    columns: Vec<crate::utils::query::QueryResultColumn>,
-    rows: Vec<crate::utils::query::QueryResultRow>,
-) {
-    let mut column_widths: Vec<usize> = columns.iter().map(|col| col.name.len()).collect();
-
-    for row in &rows {
-        for (i, value) in row.values.iter().enumerate() {
-            if i < column_widths.len() && value.len() > column_widths[i] {
-                column_widths[i] = value.len();
-            }
-        }
-    }
-
-    // Print header border
-    print!("+");
-    for width in &column_widths {
-        print!("{}+", "-".repeat(width + 2));
-    }
-    println!();
-
-    // Print column headers
-    print!("|");
-    for (i, col) in columns.iter().enumerate() {
-        print!(
-            " {}{} |",
-            col.name,
-            " ".repeat(column_widths[i] - col.name.len())
-        );
-    }
-    println!();
-
-    // Print border after header
-    print!("+");
-    for width in &column_widths {
-        print!("{}+", "-".repeat(width + 2));
-    }
-    println!();
-
-    // Print each row with a border after it
-    let row_count = rows.len();
-    for row in rows {
-        print!("|");
-        for (i, value) in row.values.iter().enumerate() {
-            if i < column_widths.len() {
-                print!(" {}{} |", value, " ".repeat(column_widths[i] - value.len()));
-            }
-        }
-        println!();
-
-        // Print border after each row
-        print!("+");
-        for width in &column_widths {
-            print!("{}+", "-".repeat(width + 2));
-        }
-        println!();
-    }
-
-    if row_count > 0 {
-        println!("{} rows returned", row_count);
-    }
-}
@@ -23,7 +23,7 @@ use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
 
 use crate::app::LOCAL_SERVER_ADDRESSES;
-use crate::globals::{server_host, server_port};
+use crate::utils::connection::ServerConfig;
 use crate::utils::display::print_unicode_box;
 use crate::utils::server::{is_server_running, start_server, StartServerOptions};
 
@@ -63,15 +63,14 @@ pub fn command() -> Command {
 }
 
 /// Executes the `start-server` command.
-pub fn execute(matches: &ArgMatches) {
+pub fn execute(matches: &ArgMatches, server: &ServerConfig) {
     print_unicode_box(
         "Starting stackql server...",
         crate::utils::display::BorderColor::Yellow,
     );
 
-    // Use global vars for host and port
-    let port = server_port();
-    let host = server_host().to_string();
+    let port = server.port;
+    let host = server.host.clone();
 
     // Validate host - must be localhost or 0.0.0.0
     if !LOCAL_SERVER_ADDRESSES.contains(&host.as_str()) {
@@ -110,6 +109,8 @@ pub fn execute(matches: &ArgMatches) {
         mtls_config,
         custom_auth_config,
         log_level,
+        // The server outlives this command, so its output cannot be relayed
+        http_log: false,
     };
 
     // Start the server
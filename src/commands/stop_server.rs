@@ -21,7 +21,7 @@ use std::process;
 use clap::{ArgMatches, Command};
 use colored::*;
 
-use crate::globals::server_port;
+use crate::utils::connection::ServerConfig;
 use crate::utils::display::print_unicode_box;
 use crate::utils::server::stop_server;
 
@@ -31,8 +31,8 @@ pub fn command() -> Command {
 }
 
 /// Executes the `stop-server` command.
-pub fn execute(_matches: &ArgMatches) {
-    let port = server_port();
+pub fn execute(_matches: &ArgMatches, server: &ServerConfig) {
+    let port = server.port;
 
     print_unicode_box(
         "Stopping stackql server...",
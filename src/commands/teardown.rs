@@ -5,20 +5,29 @@
 //! Implements the `teardown` command. Destroys provisioned resources in reverse order.
 //! This is the Rust equivalent of Python's `cmd/teardown.py` `StackQLDeProvisioner`.
 
+use std::collections::HashMap;
 use std::time::Instant;
 
 use clap::{ArgMatches, Command};
 use log::{debug, info, warn};
 
-use crate::commands::base::CommandRunner;
+use crate::commands::base::{check_manifest_defines_resources, CommandRunner};
 use crate::commands::common_args::{
-    dry_run, env_file, env_var, log_level, on_failure, show_queries, stack_dir, stack_env,
-    FailureAction,
+    check_binary_version, dry_run, dump_sql_to_dir, env_all, env_all_unsafe, env_file,
+    env_overrides, env_var, fail_if_binary_older_than, log_level, manifest_env_var, on_failure,
+    on_failure_value, process_env_value, registry, registry_options, resources, resources_value,
+    show_queries, stack_dir, stack_env, stack_env_value, state_file, strict_manifest,
+    strip_comments, target, targets_value, var_file, var_files_value, var_from_command,
 };
-use crate::core::config::get_resource_type;
-use crate::core::utils::{has_returning_clause, strip_returning_clause};
-use crate::utils::connection::create_client;
-use crate::utils::display::{print_unicode_box, BorderColor};
+use crate::core::config::{get_resource_type, multi_item_contexts};
+use crate::core::report::ResourceOutcome;
+use crate::core::state::StateFile;
+use crate::core::utils::{
+    catch_error_and_exit, export_vars, has_returning_clause, strip_returning_clause,
+};
+use crate::resource::manifest::Resource;
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::display::{json_output, print_unicode_box, BorderColor};
 use crate::utils::server::{check_and_start_server, stop_local_server};
 
 /// Configures the `teardown` command for the CLI application.
@@ -27,36 +36,79 @@ pub fn command() -> Command {
         .about("Teardown a provisioned stack")
         .arg(stack_dir())
         .arg(stack_env())
+        .arg(manifest_env_var())
         .arg(log_level())
+        .arg(fail_if_binary_older_than())
+        .arg(registry())
         .arg(env_file())
         .arg(env_var())
+        .arg(var_file())
+        .arg(var_from_command())
+        .arg(env_all())
+        .arg(env_all_unsafe())
         .arg(dry_run())
         .arg(show_queries())
         .arg(on_failure())
+        .arg(resources())
+        .arg(target())
+        .arg(strict_manifest())
+        .arg(dump_sql_to_dir())
+        .arg(strip_comments())
+        .arg(state_file())
 }
 
 /// Executes the `teardown` command.
-pub fn execute(matches: &ArgMatches) {
+pub fn execute(matches: &ArgMatches, server: &ServerConfig, options: &RunOptions) {
     let stack_dir_val = matches.get_one::<String>("stack_dir").unwrap();
-    let stack_env_val = matches.get_one::<String>("stack_env").unwrap();
+    let stack_env_val = &stack_env_value(matches);
     let env_file_val = matches.get_one::<String>("env-file").unwrap();
-    let env_vars: Vec<String> = matches
-        .get_many::<String>("env")
-        .map(|v| v.cloned().collect())
-        .unwrap_or_default();
+    let env_vars = env_overrides(matches);
     let is_dry_run = matches.get_flag("dry-run");
     let is_show_queries = matches.get_flag("show-queries");
-    let on_failure_val = matches.get_one::<FailureAction>("on-failure").unwrap();
+    let (on_failure_cli, on_failure_explicit) = on_failure_value(matches);
+
+    let strict_manifest = matches.get_flag("strict-manifest");
+    if strict_manifest {
+        check_manifest_defines_resources(stack_dir_val);
+    }
 
-    check_and_start_server();
-    let client = create_client();
+    check_binary_version(matches);
+    let options = &registry_options(matches, options);
+    check_and_start_server(server, options);
+    let client = create_client(server, options);
     let mut runner = CommandRunner::new(
         client,
         stack_dir_val,
         stack_env_val,
         env_file_val,
+        &var_files_value(matches),
         &env_vars,
+        process_env_value(matches),
     );
+    runner.track_report("teardown", is_dry_run);
+    let on_failure_val = runner.resolve_on_failure(on_failure_cli, on_failure_explicit);
+    let defined = runner.manifest.resources.len();
+    let resource_patterns = resources_value(matches);
+    runner.select_resources(&resource_patterns);
+    let targets = targets_value(matches);
+    runner.select_targets(&targets, false);
+    if strict_manifest {
+        let filter = if !targets.is_empty() {
+            Some("--target")
+        } else {
+            (!resource_patterns.is_empty()).then_some("--resources")
+        };
+        runner.check_resources_to_process(defined, filter);
+    }
+    if let Some(dir) = matches.get_one::<String>("dump-sql-to-dir") {
+        runner.enable_sql_dump(dir);
+    }
+    runner.strip_comments = matches.get_flag("strip-comments");
+    let state = matches.get_one::<String>("state-file").map(|path| {
+        let state = StateFile::load(path).unwrap_or_else(|e| catch_error_and_exit(&e));
+        select_from_state(&mut runner, &state);
+        state
+    });
 
     let stack_name_display = if runner.stack_name.is_empty() {
         runner.stack_dir.clone()
@@ -77,6 +129,7 @@ pub fn execute(matches: &ArgMatches) {
         is_dry_run,
         is_show_queries,
         &format!("{:?}", on_failure_val),
+        state.as_ref(),
     );
 
     if is_dry_run {
@@ -84,12 +137,50 @@ pub fn execute(matches: &ArgMatches) {
     } else {
         print_unicode_box("teardown complete", BorderColor::Green);
     }
+    if json_output() {
+        runner.print_json_report("teardown", is_dry_run);
+    }
+
+    stop_local_server(server);
+}
 
-    stop_local_server();
+/// Limit teardown to the resources recorded in `state`, in the order they
+/// were deployed.
+fn select_from_state(runner: &mut CommandRunner, state: &StateFile) {
+    if state.stack_name != runner.stack_name || state.stack_env != runner.stack_env {
+        warn!(
+            "state file was written for [{}] in [{}] environment, tearing down [{}] in [{}]",
+            state.stack_name, state.stack_env, runner.stack_name, runner.stack_env
+        );
+    }
+    let (mut recorded, absent): (Vec<_>, Vec<_>) = runner
+        .manifest
+        .resources
+        .drain(..)
+        .partition(|r| state.position(&r.name).is_some());
+    if !absent.is_empty() {
+        info!(
+            "skipping {} resource(s) absent from the state file: [{}]",
+            absent.len(),
+            absent
+                .iter()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    recorded.sort_by_key(|r| state.position(&r.name));
+    runner.manifest.resources = recorded;
 }
 
-/// Collect exports for all resources before teardown.
-fn collect_exports(runner: &mut CommandRunner, show_queries: bool, dry_run: bool) {
+/// Collect exports for all resources before teardown. Exports recorded in
+/// `state` are used as they are, without querying the provider.
+fn collect_exports(
+    runner: &mut CommandRunner,
+    show_queries: bool,
+    dry_run: bool,
+    state: Option<&StateFile>,
+) {
     info!(
         "collecting exports for [{}] in [{}] environment",
         runner.stack_name, runner.stack_env
@@ -107,6 +198,26 @@ fn collect_exports(runner: &mut CommandRunner, show_queries: bool, dry_run: bool
             continue;
         }
 
+        if let Some(recorded) = state.and_then(|s| s.resource(&resource.name)) {
+            if resource
+                .export_names()
+                .iter()
+                .all(|name| recorded.exports.contains_key(name))
+            {
+                debug!("using exports of [{}] from the state file", resource.name);
+                let export_data: HashMap<String, String> =
+                    recorded.exports.clone().into_iter().collect();
+                export_vars(
+                    &mut runner.global_context,
+                    &mut runner.protected_names,
+                    &resource.name,
+                    &export_data,
+                    &resource.protected_export_names(),
+                );
+                continue;
+            }
+        }
+
         let (exports_query, exports_retries, exports_retry_delay) = if let Some(sql_val) =
             resource.sql.as_ref().filter(|_| res_type == "query")
         {
@@ -180,7 +291,13 @@ fn collect_exports(runner: &mut CommandRunner, show_queries: bool, dry_run: bool
 }
 
 /// Main teardown workflow matching Python's StackQLDeProvisioner.run().
-fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _on_failure: &str) {
+fn run_teardown(
+    runner: &mut CommandRunner,
+    dry_run: bool,
+    show_queries: bool,
+    on_failure: &str,
+    state: Option<&StateFile>,
+) {
     let start_time = Instant::now();
 
     info!(
@@ -191,7 +308,7 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
     );
 
     // Collect all exports first
-    collect_exports(runner, show_queries, dry_run);
+    collect_exports(runner, show_queries, dry_run, state);
 
     // Process resources in reverse order
     let resources: Vec<_> = runner
@@ -204,14 +321,18 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
 
     for resource in &resources {
         print_unicode_box(
-            &format!("Processing resource: [{}]", resource.name),
+            &format!("Processing resource: {}", resource.display_name()),
             BorderColor::Red,
         );
+        runner.report.start_resource();
 
         let res_type = get_resource_type(resource).to_string();
 
         if res_type != "resource" && res_type != "multi" {
             debug!("skipping resource [{}] (type: {})", resource.name, res_type);
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         }
 
@@ -224,6 +345,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
 
         // Evaluate condition
         if !runner.evaluate_condition(resource, &full_context) {
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         }
 
@@ -241,8 +365,26 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
             }
         }
 
+        if let Some(for_each) = resource.for_each.as_deref() {
+            let outcome = teardown_multi_items(
+                runner,
+                resource,
+                for_each,
+                &full_context,
+                dry_run,
+                show_queries,
+            );
+            runner
+                .report
+                .record(&resource.name, &res_type, outcome, None);
+            continue;
+        }
+
         // Get resource queries (templates only)
         let resource_queries = runner.get_queries(resource, &full_context);
+        if dry_run {
+            runner.report_query_options(resource, &resource_queries);
+        }
 
         // Get exists query (fallback to statecheck) - render JIT
         let (exists_query_str, exists_retries, exists_retry_delay) = if let Some(eq) =
@@ -257,6 +399,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                     "[{}] exists query has unresolved variables, assuming resource does not exist, skipping...",
                     resource.name
                 );
+                runner
+                    .report
+                    .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
                 continue;
             }
         } else if let Some(sq) = resource_queries.get("statecheck") {
@@ -273,6 +418,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                     "[{}] statecheck has unresolved variables, skipping...",
                     resource.name
                 );
+                runner
+                    .report
+                    .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
                 continue;
             }
         } else {
@@ -280,6 +428,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                 "No exists or statecheck query for [{}], skipping...",
                 resource.name
             );
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         };
 
@@ -291,6 +442,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                 "delete query not defined for [{}], skipping...",
                 resource.name
             );
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         }
 
@@ -335,6 +489,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                         "[{}] delete query has unresolved variables, assuming resource does not exist, skipping...",
                         resource.name
                     );
+                    runner
+                        .report
+                        .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
                     continue;
                 }
             };
@@ -426,6 +583,7 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                     let cb_template = q.template.clone();
                     let cb_retries = q.options.retries;
                     let cb_delay = q.options.retry_delay;
+                    let cb_backoff = q.options.backoff;
                     let cb_sc_field = q.options.short_circuit_field.clone();
                     let cb_sc_value = q.options.short_circuit_value.clone();
                     let cb_ctx = runner.get_full_context(resource);
@@ -436,6 +594,7 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                         &rendered_cb,
                         cb_retries,
                         cb_delay,
+                        cb_backoff,
                         cb_sc_field.as_deref(),
                         cb_sc_value.as_deref(),
                         "delete",
@@ -447,6 +606,9 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
 
             if delete_confirmed {
                 info!("successfully deleted {}", resource.name);
+                runner
+                    .report
+                    .record(&resource.name, &res_type, ResourceOutcome::Deleted, None);
             } else {
                 runner.run_troubleshoot(
                     resource,
@@ -455,17 +617,88 @@ fn run_teardown(runner: &mut CommandRunner, dry_run: bool, show_queries: bool, _
                     &full_context,
                     show_queries,
                 );
-                info!("[{}] delete could not be confirmed", resource.name);
+                if on_failure != "Ignore" {
+                    catch_error_and_exit(&format!(
+                        "delete could not be confirmed for {}.",
+                        resource.name
+                    ));
+                }
+                runner.record_failure(&resource.name, &res_type, "delete could not be confirmed");
             }
         } else {
             info!(
                 "resource [{}] does not exist, skipping delete",
                 resource.name
             );
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         }
     }
 
     let elapsed = start_time.elapsed();
+    runner.report.finish(elapsed);
     info!("teardown completed in {:.2?}", elapsed);
+    info!("{}", runner.report);
+}
+
+/// Delete each item of a `multi` resource with `for_each`, rendering its
+/// exists (or statecheck) and delete queries once per item.  As for any
+/// `multi` resource, query errors are ignored.
+fn teardown_multi_items(
+    runner: &mut CommandRunner,
+    resource: &Resource,
+    for_each: &str,
+    full_context: &HashMap<String, String>,
+    dry_run: bool,
+    show_queries: bool,
+) -> ResourceOutcome {
+    let iterations = multi_item_contexts(&resource.name, for_each, full_context)
+        .unwrap_or_else(|e| catch_error_and_exit(&e));
+
+    let mut deleted = 0;
+    for (index, context) in iterations.iter().enumerate() {
+        info!("[{}] item {}", resource.name, index);
+        let queries = runner.get_queries(resource, context);
+        let Some(dq) = queries.get("delete") else {
+            info!(
+                "delete query not defined for [{}], skipping...",
+                resource.name
+            );
+            return ResourceOutcome::Skipped;
+        };
+        let exists_query = ["exists", "statecheck"].into_iter().find_map(|anchor| {
+            queries
+                .get(anchor)
+                .and_then(|q| runner.try_render_query(&resource.name, anchor, &q.template, context))
+        });
+        let delete_query = runner.try_render_query(&resource.name, "delete", &dq.template, context);
+        let (Some(exists_query), Some(delete_query)) = (exists_query, delete_query) else {
+            info!(
+                "[{}] item {} queries have unresolved variables, skipping...",
+                resource.name, index
+            );
+            continue;
+        };
+        let (_, confirmed) = runner.delete_and_confirm(
+            resource,
+            &delete_query,
+            &exists_query,
+            dq.options.retries,
+            dq.options.retry_delay,
+            dry_run,
+            show_queries,
+            true,
+        );
+        if confirmed {
+            deleted += 1;
+        }
+    }
+
+    if deleted > 0 {
+        ResourceOutcome::Deleted
+    } else {
+        ResourceOutcome::Skipped
+    }
 }
@@ -9,18 +9,23 @@
 use std::collections::HashMap;
 use std::time::Instant;
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use log::info;
 
-use crate::commands::base::CommandRunner;
+use crate::commands::base::{check_manifest_defines_resources, CommandRunner};
 use crate::commands::common_args::{
-    dry_run, env_file, env_var, log_level, on_failure, show_queries, stack_dir, stack_env,
-    FailureAction,
+    cache_queries, check_binary_version, dry_run, dump_sql_to_dir, env_all, env_all_unsafe,
+    env_file, env_overrides, env_var, export_format, fail_if_binary_older_than, include_secrets,
+    log_level, manifest_env_var, mask_protected_exports, on_failure, on_failure_value,
+    process_env_value, registry, registry_options, resources, resources_value, show_queries,
+    stack_dir, stack_env, stack_env_value, strict_manifest, strip_comments, target, targets_value,
+    var_file, var_files_value, var_from_command, ExportFormat,
 };
 use crate::core::config::get_resource_type;
+use crate::core::report::ResourceOutcome;
 use crate::core::utils::catch_error_and_exit;
-use crate::utils::connection::create_client;
-use crate::utils::display::{print_unicode_box, BorderColor};
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::display::{json_output, print_unicode_box, BorderColor};
 use crate::utils::server::{check_and_start_server, stop_local_server};
 
 /// Configures the `test` command for the CLI application.
@@ -29,43 +34,105 @@ pub fn command() -> Command {
         .about("Run test queries for the stack")
         .arg(stack_dir())
         .arg(stack_env())
+        .arg(manifest_env_var())
         .arg(log_level())
+        .arg(fail_if_binary_older_than())
+        .arg(registry())
         .arg(env_file())
         .arg(env_var())
+        .arg(var_file())
+        .arg(var_from_command())
+        .arg(env_all())
+        .arg(env_all_unsafe())
         .arg(dry_run())
         .arg(show_queries())
         .arg(on_failure())
+        .arg(resources())
+        .arg(target())
+        .arg(strict_manifest())
+        .arg(dump_sql_to_dir())
+        .arg(strip_comments())
+        .arg(cache_queries())
+        .arg(mask_protected_exports())
+        .arg(include_secrets())
+        .arg(export_format())
         .arg(
             Arg::new("output-file")
                 .long("output-file")
-                .help("File path to write deployment outputs as JSON")
+                .help("File path to write deployment outputs as JSON (or YAML for .yml/.yaml)")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("assert")
+                .long("assert")
+                .value_name("NAME=VALUE")
+                .help("Assert that an exported variable has the expected value (can be used multiple times)")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("assert-file")
+                .long("assert-file")
+                .value_name("FILE")
+                .help("Assert exported values against a JSON object, such as a previous --output-file")
                 .num_args(1),
         )
 }
 
 /// Executes the `test` command.
-pub fn execute(matches: &ArgMatches) {
+pub fn execute(matches: &ArgMatches, server: &ServerConfig, options: &RunOptions) {
     let stack_dir_val = matches.get_one::<String>("stack_dir").unwrap();
-    let stack_env_val = matches.get_one::<String>("stack_env").unwrap();
+    let stack_env_val = &stack_env_value(matches);
     let env_file_val = matches.get_one::<String>("env-file").unwrap();
-    let env_vars: Vec<String> = matches
-        .get_many::<String>("env")
-        .map(|v| v.cloned().collect())
-        .unwrap_or_default();
+    let env_vars = env_overrides(matches);
     let is_dry_run = matches.get_flag("dry-run");
     let is_show_queries = matches.get_flag("show-queries");
-    let on_failure_val = matches.get_one::<FailureAction>("on-failure").unwrap();
+    let (on_failure_cli, on_failure_explicit) = on_failure_value(matches);
     let output_file = matches.get_one::<String>("output-file");
+    let assertions = collect_assertions(matches);
 
-    check_and_start_server();
-    let client = create_client();
+    let strict_manifest = matches.get_flag("strict-manifest");
+    if strict_manifest {
+        check_manifest_defines_resources(stack_dir_val);
+    }
+
+    check_binary_version(matches);
+    let options = &registry_options(matches, options);
+    check_and_start_server(server, options);
+    let client = create_client(server, options);
     let mut runner = CommandRunner::new(
         client,
         stack_dir_val,
         stack_env_val,
         env_file_val,
+        &var_files_value(matches),
         &env_vars,
+        process_env_value(matches),
     );
+    runner.track_report("test", is_dry_run);
+    let on_failure_val = runner.resolve_on_failure(on_failure_cli, on_failure_explicit);
+    let defined = runner.manifest.resources.len();
+    let resource_patterns = resources_value(matches);
+    runner.select_resources(&resource_patterns);
+    let targets = targets_value(matches);
+    runner.select_targets(&targets, false);
+    if strict_manifest {
+        let filter = if !targets.is_empty() {
+            Some("--target")
+        } else {
+            (!resource_patterns.is_empty()).then_some("--resources")
+        };
+        runner.check_resources_to_process(defined, filter);
+    }
+    if let Some(dir) = matches.get_one::<String>("dump-sql-to-dir") {
+        runner.enable_sql_dump(dir);
+    }
+    runner.strip_comments = matches.get_flag("strip-comments");
+    if matches.get_flag("cache-queries") {
+        runner.enable_query_cache();
+    }
+    runner.mask_protected_exports = matches.get_flag("mask-protected-exports");
+    runner.include_secrets = matches.get_flag("include-secrets");
+    runner.export_format = matches.get_one::<ExportFormat>("export-format").copied();
 
     let stack_name_display = if runner.stack_name.is_empty() {
         runner.stack_dir.clone()
@@ -87,24 +154,37 @@ pub fn execute(matches: &ArgMatches) {
         is_show_queries,
         &format!("{:?}", on_failure_val),
         output_file.map(|s| s.as_str()),
+        &assertions,
     );
 
+    // Printed ahead of the summary, which exits on failures
+    if json_output() {
+        runner.print_json_report("test", is_dry_run);
+    }
+    if !is_dry_run {
+        report_test_summary(&runner);
+    }
+
     if is_dry_run {
         print_unicode_box("dry-run tests complete", BorderColor::Green);
     } else {
         print_unicode_box("tests complete", BorderColor::Green);
     }
 
-    stop_local_server();
+    stop_local_server(server);
 }
 
 /// Main test workflow matching Python's StackQLTestRunner.run().
-fn run_test(
+///
+/// Every resource is checked; those not in the desired state are recorded as
+/// failed in `runner.report` rather than stopping the run.
+pub fn run_test(
     runner: &mut CommandRunner,
     dry_run: bool,
     show_queries: bool,
     _on_failure: &str,
     output_file: Option<&str>,
+    assertions: &[(String, String)],
 ) {
     let start_time = Instant::now();
 
@@ -119,9 +199,10 @@ fn run_test(
 
     for resource in &resources {
         print_unicode_box(
-            &format!("Processing resource: [{}]", resource.name),
+            &format!("Processing resource: {}", resource.display_name()),
             BorderColor::Blue,
         );
+        runner.report.start_resource();
 
         let res_type = get_resource_type(resource).to_string();
 
@@ -129,7 +210,10 @@ fn run_test(
             info!("exporting variables for [{}]", resource.name);
         } else if res_type == "resource" || res_type == "multi" {
             info!("testing resource [{}], type: {}", resource.name, res_type);
-        } else if res_type == "command" {
+        } else if res_type == "command" || res_type == "script" {
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
             continue;
         } else {
             catch_error_and_exit(&format!("unknown resource type: {}", res_type));
@@ -137,6 +221,42 @@ fn run_test(
 
         let mut full_context = runner.get_full_context(resource);
 
+        if !runner.evaluate_condition(resource, &full_context) {
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Skipped, None);
+            continue;
+        }
+
+        // A multi resource expanded over its for_each items is tested item
+        // by item, as build checks it
+        if let Some(for_each) = resource.for_each.as_deref() {
+            if !resource.skip_validation.unwrap_or(false) {
+                let failed = runner.test_multi_items(
+                    resource,
+                    for_each,
+                    &full_context,
+                    dry_run,
+                    show_queries,
+                );
+                if !failed.is_empty() && !dry_run {
+                    info!("test failed for {}", resource.name);
+                    let items: Vec<String> = failed.iter().map(|i| i.to_string()).collect();
+                    runner.record_failure(
+                        &resource.name,
+                        &res_type,
+                        &format!("item(s) {} not in the desired state", items.join(", ")),
+                    );
+                    continue;
+                }
+            }
+            runner.process_multi_exports(resource, &full_context, dry_run, show_queries);
+            runner
+                .report
+                .record(&resource.name, &res_type, ResourceOutcome::Unchanged, None);
+            continue;
+        }
+
         // Get test queries (templates only, not yet rendered)
         let (test_queries, inline_query) =
             if let Some(sql_val) = resource.sql.as_ref().filter(|_| res_type == "query") {
@@ -145,6 +265,9 @@ fn run_test(
             } else {
                 (runner.get_queries(resource, &full_context), None)
             };
+        if dry_run {
+            runner.report_query_options(resource, &test_queries);
+        }
 
         // Run the exists query first if present to capture this.* fields
         // (e.g. identifier) before rendering statecheck/exports.
@@ -258,7 +381,9 @@ fn run_test(
             }
 
             if !is_correct_state && !dry_run {
-                catch_error_and_exit(&format!("test failed for {}.", resource.name));
+                info!("test failed for {}", resource.name);
+                runner.record_failure(&resource.name, &res_type, "not in the desired state");
+                continue;
             }
         }
 
@@ -291,11 +416,326 @@ fn run_test(
         if res_type == "resource" && !dry_run {
             info!("test passed for {}", resource.name);
         }
+        runner
+            .report
+            .record(&resource.name, &res_type, ResourceOutcome::Unchanged, None);
     }
 
     let elapsed = start_time.elapsed();
     let elapsed_str = format!("{:.2?}", elapsed);
+    runner.report.finish(elapsed);
     info!("test completed in {}", elapsed_str);
+    info!("{}", runner.report);
+
+    check_assertions(runner, assertions, dry_run);
 
     runner.process_stack_exports(dry_run, output_file, &elapsed_str);
 }
+
+/// Print the pass/fail count of the tested resources (`resource` and `multi`
+/// types) and exit non-zero if any failed.
+fn report_test_summary(runner: &CommandRunner) {
+    let tested = runner
+        .report
+        .resources
+        .iter()
+        .filter(|r| r.resource_type == "resource" || r.resource_type == "multi");
+    let (passed, failed): (Vec<_>, Vec<_>) = tested
+        .filter(|r| r.outcome != ResourceOutcome::Skipped)
+        .partition(|r| r.outcome != ResourceOutcome::Failed);
+
+    info!("{} passed, {} failed", passed.len(), failed.len());
+    if !failed.is_empty() {
+        let names: Vec<&str> = failed.iter().map(|r| r.name.as_str()).collect();
+        catch_error_and_exit(&format!(
+            "{} resource(s) not in the desired state: {}",
+            failed.len(),
+            names.join(", ")
+        ));
+    }
+}
+
+/// Collects the expected values from `--assert NAME=VALUE` and
+/// `--assert-file FILE`, with `--assert` taking precedence.  Keys may be
+/// unscoped (`vpc_id`) or resource-scoped (`vpc.vpc_id`).
+fn collect_assertions(matches: &ArgMatches) -> Vec<(String, String)> {
+    let mut assertions: Vec<(String, String)> = Vec::new();
+
+    if let Some(path) = matches.get_one::<String>("assert-file") {
+        let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            catch_error_and_exit(&format!("failed to read assert file {}: {}", path, e))
+        });
+        let expected: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&content)
+            .unwrap_or_else(|e| {
+                catch_error_and_exit(&format!(
+                    "assert file {} must contain a JSON object: {}",
+                    path, e
+                ))
+            });
+        for (key, value) in expected {
+            // Timing is recorded in output files but never reproducible
+            if key == "elapsed_time" {
+                continue;
+            }
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            assertions.push((key, value));
+        }
+    }
+
+    for assertion in matches.get_many::<String>("assert").into_iter().flatten() {
+        let Some((key, value)) = assertion.split_once('=') else {
+            catch_error_and_exit(&format!(
+                "invalid --assert '{}', expected NAME=VALUE",
+                assertion
+            ));
+        };
+        let key = key.trim().to_string();
+        assertions.retain(|(k, _)| *k != key);
+        assertions.push((key, value.to_string()));
+    }
+
+    assertions
+}
+
+/// Whether a context value matches an expected value, comparing JSON
+/// values structurally so formatting differences do not count.
+fn values_match(actual: &str, expected: &str) -> bool {
+    if actual == expected {
+        return true;
+    }
+    match (
+        serde_json::from_str::<serde_json::Value>(actual),
+        serde_json::from_str::<serde_json::Value>(expected),
+    ) {
+        (Ok(a), Ok(e)) => a == e,
+        _ => false,
+    }
+}
+
+/// Evaluates the assertions against the context computed by the run and
+/// exits with the list of mismatches if any fail.
+fn check_assertions(runner: &CommandRunner, assertions: &[(String, String)], dry_run: bool) {
+    if assertions.is_empty() {
+        return;
+    }
+    if dry_run {
+        info!(
+            "dry run: would check {} assertion(s) against exported values",
+            assertions.len()
+        );
+        return;
+    }
+
+    let failures = assertion_failures(runner, assertions);
+    if !failures.is_empty() {
+        catch_error_and_exit(&format!(
+            "{} of {} assertion(s) failed:\n{}",
+            failures.len(),
+            assertions.len(),
+            failures.join("\n")
+        ));
+    }
+    info!("{} assertion(s) passed", assertions.len());
+}
+
+/// The assertions which do not hold in the context computed by the run, one
+/// line each.  Values of protected exports, whether declared in the manifest
+/// or exported under another name, are not shown.
+fn assertion_failures(runner: &CommandRunner, assertions: &[(String, String)]) -> Vec<String> {
+    let declared: Vec<String> = runner
+        .manifest
+        .resources
+        .iter()
+        .flat_map(|r| {
+            r.protected
+                .iter()
+                .flat_map(move |p| [p.clone(), format!("{}.{}", r.name, p)])
+        })
+        .collect();
+    let show = |key: &str, value: &str| {
+        if declared.iter().any(|p| p == key) || runner.protected_names.contains(key) {
+            "<protected>".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+
+    let mut failures = Vec::new();
+    for (key, expected) in assertions {
+        match runner.global_context.get(key) {
+            Some(actual) if values_match(actual, expected) => {
+                info!("assertion passed for [{}]", key);
+            }
+            Some(actual) => failures.push(format!(
+                "  {}: expected [{}], got [{}]",
+                key,
+                show(key, expected),
+                show(key, actual)
+            )),
+            None => failures.push(format!(
+                "  {}: expected [{}], not found in context",
+                key,
+                show(key, expected)
+            )),
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing::TestStack;
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    const BUCKETS_MANIFEST: &str = r#"
+version: 1
+name: store
+providers:
+  - aws
+resources:
+  - name: buckets
+    type: multi
+    for_each: names
+    props:
+      - name: names
+        value: [logs, assets]
+"#;
+
+    /// Test the buckets stack against a server holding the `existing`
+    /// buckets, returning the report entry of the resource.
+    fn test_buckets(existing: &'static [&'static str]) -> (ResourceOutcome, Option<String>) {
+        let stack = TestStack::new(
+            BUCKETS_MANIFEST,
+            &[(
+                "buckets.iql",
+                "/*+ exists */\n\
+                 SELECT COUNT(*) as count FROM aws.s3.buckets WHERE name = '{{ item }}';\n",
+            )],
+        );
+        let server = MockServer::start(move |query| {
+            let found = existing
+                .iter()
+                .any(|name| query.contains(&format!("'{}'", name)));
+            Reply::count(usize::from(found))
+        });
+        let mut runner = stack.runner(&server);
+
+        run_test(&mut runner, false, false, "Error", None, &[]);
+
+        let entry = &runner.report.resources[0];
+        (entry.outcome, entry.message.clone())
+    }
+
+    #[test]
+    fn test_for_each_items_tested_one_by_one() {
+        assert_eq!(
+            test_buckets(&["logs", "assets"]),
+            (ResourceOutcome::Unchanged, None)
+        );
+        assert_eq!(
+            test_buckets(&["logs"]),
+            (
+                ResourceOutcome::Failed,
+                Some("item(s) 1 not in the desired state".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_collect_assertions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let assert_file = dir.path().join("outputs.json");
+        std::fs::write(
+            &assert_file,
+            r#"{"vpc_id": "vpc-1", "subnet_count": 2, "tags": {"env": "dev"}, "elapsed_time": "3.2s"}"#,
+        )
+        .unwrap();
+        let matches = command()
+            .try_get_matches_from([
+                "test",
+                "stack",
+                "dev",
+                "--assert-file",
+                assert_file.to_str().unwrap(),
+                "--assert",
+                "vpc_id=vpc-2",
+                "--assert",
+                " filter =name=web",
+            ])
+            .unwrap();
+
+        let assertions: HashMap<String, String> =
+            collect_assertions(&matches).into_iter().collect();
+        assert_eq!(
+            assertions,
+            HashMap::from([
+                // --assert takes precedence over the file
+                ("vpc_id".to_string(), "vpc-2".to_string()),
+                ("subnet_count".to_string(), "2".to_string()),
+                ("tags".to_string(), r#"{"env":"dev"}"#.to_string()),
+                ("filter".to_string(), "name=web".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_values_match() {
+        assert!(values_match("vpc-1", "vpc-1"));
+        assert!(values_match(
+            r#"{"b": 2, "a": [1, 2]}"#,
+            r#"{"a":[1,2],"b":2}"#
+        ));
+        assert!(values_match("true", "true"));
+        assert!(!values_match("vpc-1", "vpc-2"));
+        assert!(!values_match(r#"{"a": [1, 2]}"#, r#"{"a": [2, 1]}"#));
+        assert!(!values_match("2", "two"));
+    }
+
+    #[test]
+    fn test_assertion_failures() {
+        let stack = TestStack::new(
+            r#"
+version: 1
+name: app
+providers:
+  - aws
+resources:
+  - name: db
+    exports:
+      - host: db_host
+      - password: db_password
+    protected:
+      - password
+"#,
+            &[],
+        );
+        let server = MockServer::start(|_| Reply::ok());
+        let mut runner = stack.runner(&server);
+        let db = crate::commands::testing::resource(&runner, "db");
+        let row = HashMap::from([
+            ("host".to_string(), "db.internal".to_string()),
+            ("password".to_string(), "s3cr3t".to_string()),
+        ]);
+        runner.process_exports_from_result(&db, &[row]);
+
+        let assertions = [
+            ("db_host".to_string(), "db.internal".to_string()),
+            ("db.db_host".to_string(), "db.example".to_string()),
+            // Exported under another name than the protected column
+            ("db_password".to_string(), "hunter2".to_string()),
+            ("vpc_id".to_string(), "vpc-1".to_string()),
+        ];
+        assert_eq!(
+            assertion_failures(&runner, &assertions),
+            [
+                "  db.db_host: expected [db.example], got [db.internal]",
+                "  db_password: expected [<protected>], got [<protected>]",
+                "  vpc_id: expected [vpc-1], not found in context",
+            ]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// commands/testing.rs
+
+//! # Command Test Fixtures
+//!
+//! Stacks written to a temporary directory, and `CommandRunner`s for them
+//! connected to a [`MockServer`], so tests can drive the command flows
+//! end to end without a StackQL server.
+
+use std::fs;
+
+use tempfile::TempDir;
+
+use crate::commands::base::{CommandRunner, EXPORTS_FILE_NAME};
+use crate::core::env::ProcessEnv;
+use crate::resource::manifest::{Resource, MANIFEST_FILE_NAME};
+use crate::utils::mock_server::MockServer;
+
+/// A stack directory holding a manifest and its resource query files.
+pub struct TestStack {
+    dir: TempDir,
+}
+
+impl TestStack {
+    /// Write `manifest` and each `(file name, contents)` query file of
+    /// `queries` to a new stack directory.
+    pub fn new(manifest: &str, queries: &[(&str, &str)]) -> Self {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(MANIFEST_FILE_NAME), manifest).unwrap();
+        let resources = dir.path().join("resources");
+        fs::create_dir(&resources).unwrap();
+        for (file, contents) in queries {
+            fs::write(resources.join(file), contents).unwrap();
+        }
+        Self { dir }
+    }
+
+    /// The stack directory.
+    pub fn path(&self) -> &str {
+        self.dir.path().to_str().unwrap()
+    }
+
+    /// A runner for the `dev` environment of the stack, connected to
+    /// `server`, writing its exports file into the stack directory.
+    pub fn runner(&self, server: &MockServer) -> CommandRunner {
+        let env_file = self.dir.path().join(".env");
+        let mut runner = CommandRunner::new(
+            server.client(),
+            self.path(),
+            "dev",
+            env_file.to_str().unwrap(),
+            &[],
+            &[],
+            ProcessEnv::Ignore,
+        );
+        runner.exports_file = format!("{}/{}", self.path(), EXPORTS_FILE_NAME);
+        runner
+    }
+}
+
+/// The resource named `name` in the runner's manifest.
+pub fn resource(runner: &CommandRunner, name: &str) -> Resource {
+    runner
+        .manifest
+        .resources
+        .iter()
+        .find(|r| r.name == name)
+        .unwrap()
+        .clone()
+}
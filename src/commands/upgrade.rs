@@ -18,7 +18,7 @@
 
 use std::process;
 
-use clap::Command;
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
 use log::{error, info};
 
@@ -28,18 +28,34 @@ use crate::utils::stackql::get_version;
 
 /// Configures the `upgrade` command for the CLI application.
 pub fn command() -> Command {
-    Command::new("upgrade").about("Upgrade stackql to the latest version")
+    Command::new("upgrade")
+        .about("Upgrade stackql to the latest version")
+        .arg(
+            Arg::new("keep-temp")
+                .long("keep-temp")
+                .help("Keep the downloaded archive (reused by later upgrades while it matches the published checksum)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip-checksum")
+                .long("skip-checksum")
+                .help("Install the archive without verifying it, for a release with no published checksum")
+                .action(ArgAction::SetTrue),
+        )
 }
 
 /// Executes the `upgrade` command.
-pub fn execute() {
+pub fn execute(matches: &ArgMatches) {
     print_unicode_box(
         "Installing or upgrading stackql...",
         crate::utils::display::BorderColor::Yellow,
     );
 
     // Download the latest version of stackql binary
-    match download_binary() {
+    match download_binary(
+        matches.get_flag("keep-temp"),
+        matches.get_flag("skip-checksum"),
+    ) {
         Ok(path) => {
             // Get the version of the newly installed binary
             match get_version() {
@@ -0,0 +1,183 @@
+// lib/checkpoint.rs
+
+//! # Build Checkpoints
+//!
+//! Records which resources a `build` has completed so that an interrupted
+//! deployment can be continued with `build --resume`.  The checkpoint is a
+//! small JSON file in the stack directory, one per environment, which is
+//! removed once a build finishes without failures.  It also keeps the
+//! variables exported by completed `script` and `command` resources, which
+//! a resumed build skips and so cannot re-export.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// On-disk checkpoint contents.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CheckpointData {
+    stack_name: String,
+    stack_env: String,
+    completed: Vec<String>,
+    /// Exports of completed resources which are not re-run on resume.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    exports: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Tracks completed resources for a stack and environment.
+pub struct Checkpoint {
+    path: PathBuf,
+    data: CheckpointData,
+}
+
+impl Checkpoint {
+    /// Path of the checkpoint file for `stack_env` in `stack_dir`.
+    pub fn path_for(stack_dir: &str, stack_env: &str) -> PathBuf {
+        Path::new(stack_dir).join(format!(".stackql-deploy-checkpoint.{}.json", stack_env))
+    }
+
+    /// Start a fresh, empty checkpoint (nothing is written until `record`).
+    pub fn new(stack_dir: &str, stack_name: &str, stack_env: &str) -> Self {
+        Self {
+            path: Self::path_for(stack_dir, stack_env),
+            data: CheckpointData {
+                stack_name: stack_name.to_string(),
+                stack_env: stack_env.to_string(),
+                completed: Vec::new(),
+                exports: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// Load the checkpoint left by a previous run. Falls back to a fresh
+    /// checkpoint if there is none, or if it is unreadable or belongs to a
+    /// different stack.
+    pub fn load(stack_dir: &str, stack_name: &str, stack_env: &str) -> Self {
+        let mut checkpoint = Self::new(stack_dir, stack_name, stack_env);
+        let content = match fs::read_to_string(&checkpoint.path) {
+            Ok(content) => content,
+            Err(_) => {
+                debug!("No checkpoint found at {:?}", checkpoint.path);
+                return checkpoint;
+            }
+        };
+        match serde_json::from_str::<CheckpointData>(&content) {
+            Ok(data) if data.stack_name == stack_name && data.stack_env == stack_env => {
+                checkpoint.data = data;
+            }
+            Ok(_) => warn!(
+                "ignoring checkpoint {:?}: it belongs to a different stack or environment",
+                checkpoint.path
+            ),
+            Err(e) => warn!(
+                "ignoring unreadable checkpoint {:?}: {}",
+                checkpoint.path, e
+            ),
+        }
+        checkpoint
+    }
+
+    /// Names of the resources completed so far, in completion order.
+    pub fn completed(&self) -> &[String] {
+        &self.data.completed
+    }
+
+    /// Whether `resource_name` was completed by a previous run.
+    pub fn is_completed(&self, resource_name: &str) -> bool {
+        self.data.completed.iter().any(|r| r == resource_name)
+    }
+
+    /// Variables exported by `resource_name`, as recorded with it.
+    pub fn exports(&self, resource_name: &str) -> Option<&BTreeMap<String, String>> {
+        self.data.exports.get(resource_name)
+    }
+
+    /// Mark `resource_name` as completed, with the variables it exported
+    /// (empty unless they are needed on resume), and persist the checkpoint.
+    /// A checkpoint that cannot be written only costs the ability to resume,
+    /// so failures are logged rather than fatal.
+    pub fn record(&mut self, resource_name: &str, exports: BTreeMap<String, String>) {
+        if !self.is_completed(resource_name) {
+            self.data.completed.push(resource_name.to_string());
+        }
+        if exports.is_empty() {
+            self.data.exports.remove(resource_name);
+        } else {
+            self.data.exports.insert(resource_name.to_string(), exports);
+        }
+        let json = match serde_json::to_string_pretty(&self.data) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!("failed to serialize checkpoint: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = fs::write(&self.path, json) {
+            warn!("failed to write checkpoint {:?}: {}", self.path, e);
+        }
+    }
+
+    /// Remove the checkpoint file once the build has fully succeeded.
+    pub fn clear(&self) {
+        if self.path.exists() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("failed to remove checkpoint {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let stack_dir = dir.path().to_str().unwrap();
+
+        let mut checkpoint = Checkpoint::new(stack_dir, "my-stack", "dev");
+        checkpoint.record("vpc", BTreeMap::new());
+        checkpoint.record(
+            "subnet",
+            BTreeMap::from([("subnet_id".to_string(), "subnet-1".to_string())]),
+        );
+        checkpoint.record("vpc", BTreeMap::new());
+
+        let loaded = Checkpoint::load(stack_dir, "my-stack", "dev");
+        assert_eq!(loaded.completed(), ["vpc", "subnet"]);
+        assert!(loaded.is_completed("subnet"));
+        assert!(!loaded.is_completed("route_table"));
+        assert_eq!(loaded.exports("subnet").unwrap()["subnet_id"], "subnet-1");
+        assert!(loaded.exports("vpc").is_none());
+
+        // A checkpoint for another environment or stack is not picked up
+        assert!(Checkpoint::load(stack_dir, "my-stack", "prd")
+            .completed()
+            .is_empty());
+        assert!(Checkpoint::load(stack_dir, "other-stack", "dev")
+            .completed()
+            .is_empty());
+
+        loaded.clear();
+        assert!(!Checkpoint::path_for(stack_dir, "dev").exists());
+    }
+
+    #[test]
+    fn test_checkpoint_without_exports_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let stack_dir = dir.path().to_str().unwrap();
+        fs::write(
+            Checkpoint::path_for(stack_dir, "dev"),
+            r#"{"stack_name": "my-stack", "stack_env": "dev", "completed": ["vpc"]}"#,
+        )
+        .unwrap();
+
+        let loaded = Checkpoint::load(stack_dir, "my-stack", "dev");
+        assert_eq!(loaded.completed(), ["vpc"]);
+        assert!(loaded.exports("vpc").is_none());
+    }
+}
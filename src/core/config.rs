@@ -13,9 +13,9 @@ use log::{debug, error};
 use serde_json::Value as JsonValue;
 use serde_yaml::Value as YamlValue;
 
-use crate::core::utils::catch_error_and_exit;
+use crate::core::utils::{catch_error_and_exit, glob_match};
 
-use crate::resource::manifest::{Manifest, Property};
+use crate::resource::manifest::{GlobalVar, Manifest, Property, PropertyValue};
 use crate::template::engine::TemplateEngine;
 
 /// Convert a serde_yaml::Value to a SQL-compatible string representation.
@@ -174,7 +174,12 @@ pub fn render_globals(
             combined_context.insert(k.clone(), v.clone());
         }
 
-        let rendered = render_value(engine, &global_var.value, &combined_context);
+        let value =
+            global_value(global_var, stack_env, &manifest.env_aliases).unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+        let rendered = render_value(engine, value, &combined_context);
 
         if rendered.is_empty() {
             error!("Global variable '{}' cannot be empty", global_var.name);
@@ -192,13 +197,128 @@ pub fn render_globals(
     global_context
 }
 
+/// The value of a global for `stack_env`: its `value`, or else the entry of
+/// its per-env `values` chosen by [`select_env_value`].
+pub fn global_value<'a>(
+    global_var: &'a GlobalVar,
+    stack_env: &str,
+    env_aliases: &HashMap<String, String>,
+) -> Result<&'a YamlValue, String> {
+    if !global_var.value.is_null() {
+        return Ok(&global_var.value);
+    }
+    let selected = global_var
+        .values
+        .as_ref()
+        .and_then(|values| select_env_value(values, stack_env, env_aliases));
+    match selected {
+        Some((key, env_val)) => {
+            if key != stack_env {
+                debug!(
+                    "Global variable [{}] has no value for [{}], using [{}]",
+                    global_var.name, stack_env, key
+                );
+            }
+            Ok(&env_val.value)
+        }
+        None => Err(format!(
+            "No value specified for global variable '{}' in stack_env '{}'",
+            global_var.name, stack_env
+        )),
+    }
+}
+
+/// Render the manifest `providers` list against the global context, so
+/// entries such as `{{ registry_host }}/aws` resolve before providers are
+/// pulled.  Entries without template syntax are returned unchanged.
+pub fn render_providers(
+    engine: &TemplateEngine,
+    providers: &[String],
+    global_context: &HashMap<String, String>,
+) -> Vec<String> {
+    providers
+        .iter()
+        .map(|provider| {
+            if !provider.contains("{{") {
+                return provider.clone();
+            }
+            match engine.render(provider, global_context) {
+                Ok(rendered) => {
+                    debug!("Rendered provider [{}] to [{}]", provider, rendered);
+                    rendered.trim().to_string()
+                }
+                Err(e) => catch_error_and_exit(&format!(
+                    "Failed to render provider entry '{}': {}",
+                    provider, e
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Environments consulted, in order, when resolving per-env `values` for
+/// `stack_env`: the environment itself followed by its chain of aliases.
+/// The chain stops at the first environment that repeats.
+pub fn env_alias_chain<'a>(
+    stack_env: &'a str,
+    env_aliases: &'a HashMap<String, String>,
+) -> Vec<&'a str> {
+    let mut chain = vec![stack_env];
+    let mut current = stack_env;
+    while let Some(alias) = env_aliases.get(current) {
+        if chain.contains(&alias.as_str()) {
+            break;
+        }
+        chain.push(alias);
+        current = alias;
+    }
+    chain
+}
+
+/// Select the entry of a property's per-env `values` for `stack_env`,
+/// returning the matching key with its value.
+///
+/// For each environment in the alias chain, an exact key wins over a glob
+/// pattern (`*`, `?`); of several matching patterns the most specific (most
+/// literal characters) wins, ties going to the first in sort order.  The
+/// `default` key is used when nothing else matches.
+pub fn select_env_value<'a>(
+    values: &'a HashMap<String, PropertyValue>,
+    stack_env: &str,
+    env_aliases: &HashMap<String, String>,
+) -> Option<(&'a str, &'a PropertyValue)> {
+    let mut patterns: Vec<&String> = values.keys().filter(|k| k.contains(['*', '?'])).collect();
+    patterns.sort_by_key(|k| {
+        (
+            std::cmp::Reverse(k.matches(|c| c != '*' && c != '?').count()),
+            *k,
+        )
+    });
+
+    env_alias_chain(stack_env, env_aliases)
+        .into_iter()
+        .find_map(|env| {
+            values.get_key_value(env).or_else(|| {
+                patterns
+                    .iter()
+                    .find(|pattern| glob_match(pattern, env))
+                    .and_then(|pattern| values.get_key_value(*pattern))
+            })
+        })
+        .or_else(|| values.get_key_value("default"))
+        .map(|(k, v)| (k.as_str(), v))
+}
+
 /// Render resource properties and return the property context.
 /// Matches Python's `render_properties`.
+///
+/// Per-env `values` are selected with [`select_env_value`].
 pub fn render_properties(
     engine: &TemplateEngine,
     resource_props: &[Property],
     global_context: &HashMap<String, String>,
     stack_env: &str,
+    env_aliases: &HashMap<String, String>,
 ) -> HashMap<String, String> {
     let mut prop_context: HashMap<String, String> = HashMap::new();
     let mut resource_context = global_context.clone();
@@ -216,9 +336,15 @@ pub fn render_properties(
         }
         // Handle 'values' (environment-specific)
         else if let Some(ref values) = prop.values {
-            if let Some(env_val) = values.get(stack_env) {
+            if let Some((key, env_val)) = select_env_value(values, stack_env, env_aliases) {
                 let rendered = render_value(engine, &env_val.value, &resource_context);
                 let sql_compat = to_sql_compatible_json(&rendered);
+                if key != stack_env {
+                    debug!(
+                        "Property [{}] has no value for [{}], using [{}]",
+                        prop.name, stack_env, key
+                    );
+                }
                 debug!(
                     "Setting property [{}] using env-specific value to {}",
                     prop.name, sql_compat
@@ -319,6 +445,7 @@ pub fn get_full_context(
     global_context: &HashMap<String, String>,
     resource: &crate::resource::manifest::Resource,
     stack_env: &str,
+    env_aliases: &HashMap<String, String>,
     idempotency_token: Option<&str>,
 ) -> HashMap<String, String> {
     debug!("Getting full context for {}...", resource.name);
@@ -341,10 +468,22 @@ pub fn get_full_context(
     // Re-render any global values that contain deferred template expressions.
     // This allows globals (e.g., global_tags) to use {{ resource_name }} which couldn't
     // be resolved at global rendering time since the resource wasn't known yet.
-    let resolved_context =
+    let mut resolved_context =
         re_render_context_with_deferred_vars(engine, &context_with_resource_name);
 
-    let prop_context = render_properties(engine, &resource.props, &resolved_context, stack_env);
+    // Resource-scoped variables sit above globals (and below props); they
+    // only exist in this resource's context and never reach global_context
+    for (k, v) in render_resource_env(engine, resource, &resolved_context) {
+        resolved_context.insert(k, v);
+    }
+
+    let prop_context = render_properties(
+        engine,
+        &resource.props,
+        &resolved_context,
+        stack_env,
+        env_aliases,
+    );
 
     let mut full_context = resolved_context;
     for (k, v) in prop_context {
@@ -355,6 +494,19 @@ pub fn get_full_context(
     full_context
 }
 
+/// Render a resource's `env` values against `context`.
+pub fn render_resource_env(
+    engine: &TemplateEngine,
+    resource: &crate::resource::manifest::Resource,
+    context: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    resource
+        .env
+        .iter()
+        .map(|(k, v)| (k.clone(), render_string_value(engine, v, context)))
+        .collect()
+}
+
 /// Re-render context values that contain deferred template expressions (`{{ ... }}`).
 /// This is used to resolve variables like `resource_name` that weren't available
 /// when globals were initially rendered.
@@ -413,17 +565,60 @@ pub fn prepare_query_context(context: &HashMap<String, String>) -> HashMap<Strin
     prepared
 }
 
-/// Get the resource type, validating it against allowed types.
-/// Matches Python's `get_type`.
+/// Get the resource type.  Matches Python's `get_type`; the type is checked
+/// against the allowed types when the manifest is loaded.
 pub fn get_resource_type(resource: &crate::resource::manifest::Resource) -> &str {
-    let res_type = resource.r#type.as_str();
-    match res_type {
-        "resource" | "query" | "script" | "multi" | "command" => res_type,
-        _ => catch_error_and_exit(&format!(
-            "Resource type must be 'resource', 'script', 'multi', 'query', or 'command', got '{}'",
-            res_type
-        )),
-    }
+    resource.r#type.as_str()
+}
+
+/// Contexts for the iterations of a `multi` resource whose `for_each`
+/// property resolves to a JSON array: one per item, each binding the item
+/// as `item` (an object item as `item.<key>`) and its zero-based position as
+/// `item_index`, over `full_context`.
+pub fn multi_item_contexts(
+    resource_name: &str,
+    for_each: &str,
+    full_context: &HashMap<String, String>,
+) -> Result<Vec<HashMap<String, String>>, String> {
+    let raw = full_context.get(for_each).ok_or_else(|| {
+        format!(
+            "for_each property '{}' of [{}] is not set",
+            for_each, resource_name
+        )
+    })?;
+    let items = match serde_json::from_str::<JsonValue>(raw) {
+        Ok(JsonValue::Array(items)) => items,
+        _ => {
+            return Err(format!(
+                "for_each property '{}' of [{}] must resolve to a JSON array, got: {}",
+                for_each, resource_name, raw
+            ))
+        }
+    };
+
+    let as_string = |value: &JsonValue| match value {
+        JsonValue::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    Ok(items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let mut context = full_context.clone();
+            context.insert("item_index".to_string(), index.to_string());
+            match item {
+                JsonValue::Object(fields) => {
+                    for (key, value) in fields {
+                        context.insert(format!("item.{}", key), as_string(value));
+                    }
+                }
+                other => {
+                    context.insert("item".to_string(), as_string(other));
+                }
+            }
+            context
+        })
+        .collect())
 }
 
 /// Check if a string is valid JSON (object or array).
@@ -437,7 +632,7 @@ pub fn is_json(s: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::resource::manifest::{Property, Resource};
+    use crate::resource::manifest::{Property, PropertyValue, Resource};
 
     /// Helper to create a minimal Resource for testing.
     fn make_resource(name: &str, props: Vec<Property>) -> Resource {
@@ -447,6 +642,8 @@ mod tests {
             file: None,
             sql: None,
             run: None,
+            shell: None,
+            script_timeout: None,
             props,
             exports: vec![],
             protected: vec![],
@@ -455,6 +652,11 @@ mod tests {
             skip_validation: None,
             auth: None,
             return_vals: None,
+            retry_budget: None,
+            env: HashMap::new(),
+            depends_on: vec![],
+            replace_on_change: None,
+            for_each: None,
         }
     }
 
@@ -469,6 +671,100 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_global_values_select_stack_env() {
+        let manifest: Manifest = serde_yaml::from_str(
+            r#"
+name: my-stack
+providers: [aws]
+globals:
+  - name: region
+    values:
+      dev:
+        value: us-east-1
+      prd:
+        value: eu-west-1
+  - name: account
+    value: "{{ stack_env }}-account"
+"#,
+        )
+        .unwrap();
+        let engine = TemplateEngine::new();
+
+        let ctx = render_globals(&engine, &HashMap::new(), &manifest, "prd", "my-stack");
+        assert_eq!(ctx.get("region").unwrap(), "eu-west-1");
+        assert_eq!(ctx.get("account").unwrap(), "prd-account");
+
+        let ctx = render_globals(&engine, &HashMap::new(), &manifest, "dev", "my-stack");
+        assert_eq!(ctx.get("region").unwrap(), "us-east-1");
+    }
+
+    #[test]
+    fn test_list_global_renders_as_json_array() {
+        let manifest: Manifest = serde_yaml::from_str(
+            r#"
+name: my-stack
+providers: [aws]
+globals:
+  - name: zones
+    value:
+      - "{{ region }}a"
+      - "{{ region }}b"
+"#,
+        )
+        .unwrap();
+        let vars = HashMap::from([("region".to_string(), "us-east-1".to_string())]);
+
+        let ctx = render_globals(&TemplateEngine::new(), &vars, &manifest, "dev", "my-stack");
+        let zones: JsonValue = serde_json::from_str(ctx.get("zones").unwrap()).unwrap();
+        assert_eq!(zones, serde_json::json!(["us-east-1a", "us-east-1b"]));
+    }
+
+    #[test]
+    fn test_global_without_env_value_is_an_error() {
+        let global: GlobalVar = serde_yaml::from_str(
+            r#"
+name: region
+values:
+  prd:
+    value: eu-west-1
+"#,
+        )
+        .unwrap();
+        let no_aliases = HashMap::new();
+
+        assert_eq!(
+            global_value(&global, "prd", &no_aliases).unwrap(),
+            &YamlValue::String("eu-west-1".to_string())
+        );
+        let err = global_value(&global, "dev", &no_aliases).unwrap_err();
+        assert_eq!(
+            err,
+            "No value specified for global variable 'region' in stack_env 'dev'"
+        );
+
+        // An alias resolves to an environment which has a value
+        let aliases: HashMap<String, String> = [("dev".to_string(), "prd".to_string())].into();
+        assert!(global_value(&global, "dev", &aliases).is_ok());
+    }
+
+    #[test]
+    fn test_render_providers_resolves_globals() {
+        let engine = TemplateEngine::new();
+        let mut ctx = HashMap::new();
+        ctx.insert("registry_host".to_string(), "registry.internal".to_string());
+
+        let providers = vec![
+            "{{ registry_host }}/aws".to_string(),
+            "google::v24.11.00274".to_string(),
+        ];
+        let rendered = render_providers(&engine, &providers, &ctx);
+        assert_eq!(
+            rendered,
+            vec!["registry.internal/aws", "google::v24.11.00274"]
+        );
+    }
+
     #[test]
     fn test_resource_name_available_in_full_context() {
         let engine = TemplateEngine::new();
@@ -478,7 +774,14 @@ mod tests {
 
         let resource = make_resource("cross_account_role", vec![]);
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", None);
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
 
         assert_eq!(ctx.get("resource_name").unwrap(), "cross_account_role");
         // Existing variables still present
@@ -498,7 +801,14 @@ mod tests {
             vec![make_prop("tag_value", "{{ resource_name }}")],
         );
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", None);
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
 
         assert_eq!(ctx.get("tag_value").unwrap(), "cross_account_role");
     }
@@ -518,7 +828,14 @@ mod tests {
 
         let resource = make_resource("cross_account_role", vec![]);
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", None);
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
 
         let global_tags = ctx.get("global_tags").unwrap();
         assert!(
@@ -546,8 +863,22 @@ mod tests {
         let res1 = make_resource("vpc_network", vec![]);
         let res2 = make_resource("storage_bucket", vec![]);
 
-        let ctx1 = get_full_context(&engine, &global_context, &res1, "dev", None);
-        let ctx2 = get_full_context(&engine, &global_context, &res2, "dev", None);
+        let ctx1 = get_full_context(
+            &engine,
+            &global_context,
+            &res1,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
+        let ctx2 = get_full_context(
+            &engine,
+            &global_context,
+            &res2,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
 
         assert_eq!(ctx1.get("resource_name").unwrap(), "vpc_network");
         assert_eq!(ctx2.get("resource_name").unwrap(), "storage_bucket");
@@ -555,6 +886,52 @@ mod tests {
         assert!(ctx2.get("global_tags").unwrap().contains("storage_bucket"));
     }
 
+    #[test]
+    fn test_resource_env_is_scoped_to_resource() {
+        let engine = TemplateEngine::new();
+        let mut global_context = HashMap::new();
+        global_context.insert("region".to_string(), "us-east-1".to_string());
+        global_context.insert("log_level".to_string(), "info".to_string());
+
+        let mut res1 = make_resource(
+            "app",
+            vec![make_prop(
+                "endpoint",
+                "https://{{ api_host }}/{{ log_level }}",
+            )],
+        );
+        res1.env.insert(
+            "api_host".to_string(),
+            "api.{{ region }}.example.com".to_string(),
+        );
+        res1.env
+            .insert("log_level".to_string(), "debug".to_string());
+        let res2 = make_resource("db", vec![]);
+
+        let ctx1 = get_full_context(
+            &engine,
+            &global_context,
+            &res1,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
+        let ctx2 = get_full_context(
+            &engine,
+            &global_context,
+            &res2,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
+
+        assert_eq!(ctx1["api_host"], "api.us-east-1.example.com");
+        assert_eq!(ctx1["endpoint"], "https://api.us-east-1.example.com/debug");
+        assert_eq!(ctx2["log_level"], "info");
+        assert!(!ctx2.contains_key("api_host"));
+        assert!(!global_context.contains_key("api_host"));
+    }
+
     #[test]
     fn test_re_render_context_no_templates_is_noop() {
         let engine = TemplateEngine::new();
@@ -597,7 +974,14 @@ mod tests {
         let resource = make_resource("my_resource", vec![]);
         let token = "550e8400-e29b-41d4-a716-446655440000";
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", Some(token));
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            Some(token),
+        );
 
         // Unscoped form is available
         assert_eq!(ctx.get("idempotency_token").unwrap(), token);
@@ -606,6 +990,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unnecessary_get_then_check)]
     fn test_idempotency_token_none_not_injected() {
         let engine = TemplateEngine::new();
         let mut global_context = HashMap::new();
@@ -614,7 +999,14 @@ mod tests {
 
         let resource = make_resource("my_resource", vec![]);
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", None);
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
 
         assert!(ctx.get("idempotency_token").is_none());
         assert!(ctx.get("my_resource.idempotency_token").is_none());
@@ -629,8 +1021,22 @@ mod tests {
         let res1 = make_resource("vpc_network", vec![]);
         let res2 = make_resource("storage_bucket", vec![]);
 
-        let ctx1 = get_full_context(&engine, &global_context, &res1, "dev", Some(token));
-        let ctx2 = get_full_context(&engine, &global_context, &res2, "dev", Some(token));
+        let ctx1 = get_full_context(
+            &engine,
+            &global_context,
+            &res1,
+            "dev",
+            &HashMap::new(),
+            Some(token),
+        );
+        let ctx2 = get_full_context(
+            &engine,
+            &global_context,
+            &res2,
+            "dev",
+            &HashMap::new(),
+            Some(token),
+        );
 
         assert_eq!(ctx1.get("vpc_network.idempotency_token").unwrap(), token);
         assert_eq!(ctx2.get("storage_bucket.idempotency_token").unwrap(), token);
@@ -650,8 +1056,146 @@ mod tests {
             vec![make_prop("client_token", "{{ idempotency_token }}")],
         );
 
-        let ctx = get_full_context(&engine, &global_context, &resource, "dev", Some(token));
+        let ctx = get_full_context(
+            &engine,
+            &global_context,
+            &resource,
+            "dev",
+            &HashMap::new(),
+            Some(token),
+        );
 
         assert_eq!(ctx.get("client_token").unwrap(), token);
     }
+
+    #[test]
+    fn test_env_values_fall_back_to_alias() {
+        let engine = TemplateEngine::new();
+        let env_values = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(env, v)| {
+                    (
+                        env.to_string(),
+                        PropertyValue {
+                            value: serde_yaml::Value::String(v.to_string()),
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let mut instance_type = make_prop("instance_type", "");
+        instance_type.value = None;
+        instance_type.values = Some(env_values(&[("prd", "m5.large"), ("dev", "t3.micro")]));
+        let mut replicas = make_prop("replicas", "");
+        replicas.value = None;
+        replicas.values = Some(env_values(&[("prd", "3"), ("staging", "1")]));
+        let props = vec![instance_type, replicas];
+
+        let mut aliases = HashMap::new();
+        aliases.insert("staging".to_string(), "prd".to_string());
+        aliases.insert("uat".to_string(), "staging".to_string());
+
+        let ctx = render_properties(&engine, &props, &HashMap::new(), "staging", &aliases);
+        assert_eq!(ctx.get("instance_type").unwrap(), "m5.large");
+        assert_eq!(ctx.get("replicas").unwrap(), "1");
+
+        // Aliases chain: uat -> staging -> prd
+        let ctx = render_properties(&engine, &props, &HashMap::new(), "uat", &aliases);
+        assert_eq!(ctx.get("instance_type").unwrap(), "m5.large");
+        assert_eq!(ctx.get("replicas").unwrap(), "1");
+
+        // A cyclic chain stops at the first repeat
+        aliases.insert("prd".to_string(), "uat".to_string());
+        assert_eq!(env_alias_chain("uat", &aliases), ["uat", "staging", "prd"]);
+    }
+
+    #[test]
+    fn test_env_values_match_patterns_then_default() {
+        let values: HashMap<String, PropertyValue> = [
+            ("prod-eu", "exact"),
+            ("prod-*", "prod"),
+            ("prod-us-*", "prod-us"),
+            ("default", "fallback"),
+        ]
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                PropertyValue {
+                    value: serde_yaml::Value::String(v.to_string()),
+                },
+            )
+        })
+        .collect();
+        let select = |env: &str, aliases: &HashMap<String, String>| {
+            select_env_value(&values, env, aliases).map(|(k, _)| k.to_string())
+        };
+        let no_aliases = HashMap::new();
+
+        assert_eq!(select("prod-eu", &no_aliases).unwrap(), "prod-eu");
+        assert_eq!(select("prod-apac", &no_aliases).unwrap(), "prod-*");
+        // The most specific pattern wins
+        assert_eq!(select("prod-us-east", &no_aliases).unwrap(), "prod-us-*");
+        assert_eq!(select("dev", &no_aliases).unwrap(), "default");
+
+        // An alias is tried (exact, then patterns) before the default
+        let aliases: HashMap<String, String> =
+            [("staging".to_string(), "prod-staging".to_string())].into();
+        assert_eq!(select("staging", &aliases).unwrap(), "prod-*");
+    }
+
+    #[test]
+    fn test_multi_item_contexts_binds_each_of_three_items() {
+        let engine = TemplateEngine::new();
+        let mut subnets = make_prop("subnets", "");
+        subnets.value = Some(
+            serde_yaml::from_str(
+                "[{cidr: 10.0.1.0/24, zone: a}, {cidr: 10.0.2.0/24, zone: b}, {cidr: 10.0.3.0/24, zone: c}]",
+            )
+            .unwrap(),
+        );
+        let mut resource = make_resource("subnets", vec![subnets, make_prop("vpc", "vpc-1")]);
+        resource.r#type = "multi".to_string();
+        let ctx = get_full_context(
+            &engine,
+            &HashMap::new(),
+            &resource,
+            "dev",
+            &HashMap::new(),
+            None,
+        );
+
+        let contexts = multi_item_contexts("subnets", "subnets", &ctx).unwrap();
+        assert_eq!(contexts.len(), 3);
+        let rendered: Vec<String> = contexts
+            .iter()
+            .map(|c| {
+                engine
+                    .render(
+                        "{{ item_index }}:{{ item.cidr }}@{{ item.zone }} in {{ vpc }}",
+                        c,
+                    )
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            rendered,
+            [
+                "0:10.0.1.0/24@a in vpc-1",
+                "1:10.0.2.0/24@b in vpc-1",
+                "2:10.0.3.0/24@c in vpc-1"
+            ]
+        );
+
+        let mut names = HashMap::new();
+        names.insert("names".to_string(), r#"["a", "b", "c"]"#.to_string());
+        let contexts = multi_item_contexts("vms", "names", &names).unwrap();
+        let items: Vec<&str> = contexts.iter().map(|c| c["item"].as_str()).collect();
+        assert_eq!(items, ["a", "b", "c"]);
+
+        names.insert("names".to_string(), "a,b,c".to_string());
+        let err = multi_item_contexts("vms", "names", &names).unwrap_err();
+        assert!(err.contains("must resolve to a JSON array"), "{}", err);
+    }
 }
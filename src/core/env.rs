@@ -2,22 +2,93 @@
 
 //! # Environment Variable Handling
 //!
-//! Loads environment variables from .env files and CLI overrides,
-//! matching the Python `load_env_vars` and `parse_env_var` functions.
+//! Loads environment variables from .env files, YAML/JSON `--var-file`s and
+//! CLI overrides, matching the Python `load_env_vars` and `parse_env_var`
+//! functions.
 
 use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 
 use log::debug;
+use serde_yaml::Value as YamlValue;
 
-/// Load environment variables from a .env file and apply CLI overrides.
+use crate::core::config::to_sql_compatible_value;
+use crate::core::utils::{catch_error_and_exit, try_run_shell_command};
+use crate::template::engine::TemplateEngine;
+
+/// Substrings that mark a process environment variable name as sensitive.
+/// Matching is case-insensitive.
+const SENSITIVE_NAME_MARKERS: [&str; 9] = [
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "KEY",
+    "CREDENTIAL",
+    "PRIVATE",
+    "AUTH",
+    "COOKIE",
+];
+
+/// Which process environment variables to load (`--env-all` and
+/// `--env-all-unsafe`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessEnv {
+    /// Do not load the process environment (default)
+    Ignore,
+    /// Load the process environment, skipping sensitive-looking names
+    Safe,
+    /// Load the entire process environment
+    Unsafe,
+}
+
+/// Load environment variables from a .env file and `--var-file`s, and apply
+/// CLI overrides.
 ///
 /// # Arguments
 /// * `env_file` - Path to the .env file (relative to cwd)
+/// * `var_files` - YAML or JSON variable files, later files overriding
+///   earlier ones and the .env file.  Exits if a file cannot be loaded.
 /// * `overrides` - Additional KEY=VALUE pairs from `-e` CLI flags
-pub fn load_env_vars(env_file: &str, overrides: &[String]) -> HashMap<String, String> {
+/// * `process_env` - Whether to seed the result with the process
+///   environment, at the lowest precedence
+pub fn load_env_vars(
+    env_file: &str,
+    var_files: &[String],
+    overrides: &[String],
+    process_env: ProcessEnv,
+) -> HashMap<String, String> {
+    load_env_vars_from(env_file, var_files, overrides, process_env, std::env::vars)
+}
+
+/// [`load_env_vars`], with `process_vars` supplying the process environment.
+fn load_env_vars_from<I: IntoIterator<Item = (String, String)>>(
+    env_file: &str,
+    var_files: &[String],
+    overrides: &[String],
+    process_env: ProcessEnv,
+    process_vars: impl FnOnce() -> I,
+) -> HashMap<String, String> {
     let mut env_vars = HashMap::new();
 
+    // Seed from the process environment
+    if process_env != ProcessEnv::Ignore {
+        let mut skipped = 0;
+        for (key, value) in process_vars() {
+            if process_env == ProcessEnv::Safe && is_sensitive_name(&key) {
+                skipped += 1;
+                continue;
+            }
+            env_vars.insert(key, value);
+        }
+        debug!(
+            "Loaded {} process environment variables ({} sensitive skipped)",
+            env_vars.len(),
+            skipped
+        );
+    }
+
     // Load from .env file
     let dotenv_path = Path::new(env_file);
     if dotenv_path.exists() {
@@ -37,6 +108,16 @@ pub fn load_env_vars(env_file: &str, overrides: &[String]) -> HashMap<String, St
         debug!("No .env file found at: {}", env_file);
     }
 
+    // Load from --var-file files
+    for var_file in var_files {
+        debug!("Loading variables from: {}", var_file);
+        let vars = load_var_file(var_file).unwrap_or_else(|e| catch_error_and_exit(&e));
+        for (key, value) in vars {
+            debug!("  Loaded var: {}", key);
+            env_vars.insert(key, value);
+        }
+    }
+
     // Apply overrides from -e flags
     for override_str in overrides {
         if let Some((key, value)) = parse_env_var(override_str) {
@@ -48,6 +129,96 @@ pub fn load_env_vars(env_file: &str, overrides: &[String]) -> HashMap<String, St
     env_vars
 }
 
+/// Load a YAML or JSON `--var-file` holding a mapping of variable names to
+/// values.  Strings are kept as is, numbers and booleans as their text, null
+/// as empty, and lists and objects as serialized JSON.
+pub fn load_var_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("failed to read var file {}: {}", path, e))?;
+    // JSON is valid YAML, so one parser handles both
+    let parsed: YamlValue = serde_yaml::from_str(&content)
+        .map_err(|e| format!("failed to parse var file {}: {}", path, e))?;
+    let map = match parsed {
+        YamlValue::Mapping(map) => map,
+        YamlValue::Null => return Ok(Vec::new()),
+        _ => {
+            return Err(format!(
+                "var file {} must contain a mapping of variable names to values",
+                path
+            ))
+        }
+    };
+    map.into_iter()
+        .map(|(key, value)| match key {
+            YamlValue::String(name) => Ok((name, to_sql_compatible_value(&value))),
+            other => Err(format!(
+                "var file {} has a non-string variable name: {:?}",
+                path, other
+            )),
+        })
+        .collect()
+}
+
+/// Render a `--env-file` path which references the stack, such as
+/// `.env.{{ stack_env }}`, against `stack_env` and `stack_name` so one command
+/// line picks the file for each environment.  Exits if the path does not
+/// render.
+pub fn render_env_file_path(
+    engine: &TemplateEngine,
+    env_file: &str,
+    stack_env: &str,
+    stack_name: &str,
+) -> String {
+    if !env_file.contains("{{") {
+        return env_file.to_string();
+    }
+    let context = HashMap::from([
+        ("stack_env".to_string(), stack_env.to_string()),
+        ("stack_name".to_string(), stack_name.to_string()),
+    ]);
+    let rendered = engine.render(env_file, &context).unwrap_or_else(|e| {
+        catch_error_and_exit(&format!(
+            "failed to render env file path '{}' (only stack_env and stack_name are available): {}",
+            env_file, e
+        ))
+    });
+    debug!("Env file path '{}' rendered to '{}'", env_file, rendered);
+    rendered
+}
+
+/// Run each `NAME=COMMAND` spec from `--var-from-command` and return the
+/// results as `NAME=value` overrides, with the command's stdout trimmed.
+/// Exits if a spec is malformed or a command fails.
+pub fn vars_from_commands(specs: &[String]) -> Vec<String> {
+    try_vars_from_commands(specs, &HashMap::new()).unwrap_or_else(|e| catch_error_and_exit(&e))
+}
+
+/// `vars_from_commands`, running the commands with `envs` added to their
+/// environment and returning the first error instead of exiting.
+fn try_vars_from_commands(
+    specs: &[String],
+    envs: &HashMap<String, String>,
+) -> Result<Vec<String>, String> {
+    let mut vars = Vec::new();
+    for spec in specs {
+        let (name, cmd) = parse_env_var(spec)
+            .ok_or_else(|| format!("--var-from-command expects NAME=COMMAND, got '{}'", spec))?;
+        debug!("  Running command for var {}: {}", name, cmd);
+        let value = try_run_shell_command(&cmd, None, envs)
+            .map_err(|e| format!("--var-from-command for {} failed: {}", name, e))?;
+        vars.push(format!("{}={}", name, value.trim()));
+    }
+    Ok(vars)
+}
+
+/// Returns `true` if a variable name looks like it holds a secret.
+pub(crate) fn is_sensitive_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    SENSITIVE_NAME_MARKERS
+        .iter()
+        .any(|marker| upper.contains(marker))
+}
+
 /// Parse a single KEY=VALUE environment variable string.
 fn parse_env_var(s: &str) -> Option<(String, String)> {
     let parts: Vec<&str> = s.splitn(2, '=').collect();
@@ -57,3 +228,170 @@ fn parse_env_var(s: &str) -> Option<(String, String)> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_sensitive_name() {
+        assert!(is_sensitive_name("AWS_SECRET_ACCESS_KEY"));
+        assert!(is_sensitive_name("github_token"));
+        assert!(is_sensitive_name("DB_PASSWORD"));
+        assert!(is_sensitive_name("DATABRICKS_CLIENT_SECRET"));
+        assert!(!is_sensitive_name("AWS_REGION"));
+        assert!(!is_sensitive_name("CI_PIPELINE_ID"));
+    }
+
+    #[test]
+    fn test_process_env_has_lowest_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let env_file = dir.path().join(".env");
+        std::fs::write(&env_file, "STACKQL_DEPLOY_ENV_TEST=from_file\n").unwrap();
+        let process_vars = || {
+            [
+                ("STACKQL_DEPLOY_ENV_TEST", "from_process"),
+                ("STACKQL_DEPLOY_ENV_TEST_TOKEN", "hidden"),
+                ("PATH", "/usr/bin"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        };
+        let load = |env_file: &str, process_env| {
+            load_env_vars_from(env_file, &[], &[], process_env, process_vars)
+        };
+
+        let vars = load(env_file.to_str().unwrap(), ProcessEnv::Safe);
+        assert_eq!(vars["STACKQL_DEPLOY_ENV_TEST"], "from_file");
+        assert_eq!(vars["PATH"], "/usr/bin");
+        assert!(!vars.contains_key("STACKQL_DEPLOY_ENV_TEST_TOKEN"));
+
+        let vars = load("missing.env", ProcessEnv::Unsafe);
+        assert_eq!(vars["STACKQL_DEPLOY_ENV_TEST_TOKEN"], "hidden");
+
+        let vars = load("missing.env", ProcessEnv::Ignore);
+        assert!(vars.is_empty());
+    }
+
+    #[test]
+    fn test_render_env_file_path() {
+        let engine = TemplateEngine::new();
+        assert_eq!(
+            render_env_file_path(&engine, ".env.{{ stack_env }}", "prd", "my-stack"),
+            ".env.prd"
+        );
+        assert_eq!(
+            render_env_file_path(
+                &engine,
+                "envs/{{ stack_name }}/{{ stack_env }}.env",
+                "dev",
+                "web"
+            ),
+            "envs/web/dev.env"
+        );
+        assert_eq!(render_env_file_path(&engine, ".env", "dev", "web"), ".env");
+    }
+
+    #[test]
+    fn test_load_var_file_yaml_and_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("vars.yml");
+        std::fs::write(
+            &yaml,
+            "region: us-east-1\nreplicas: 3\nenabled: true\nnothing: null\nzones:\n  - a\n  - b\ntags:\n  Name: vpc\n",
+        )
+        .unwrap();
+        let vars: HashMap<String, String> = load_var_file(yaml.to_str().unwrap())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(vars["region"], "us-east-1");
+        assert_eq!(vars["replicas"], "3");
+        assert_eq!(vars["enabled"], "true");
+        assert_eq!(vars["nothing"], "");
+        assert_eq!(vars["zones"], r#"["a","b"]"#);
+        assert_eq!(vars["tags"], r#"{"Name":"vpc"}"#);
+
+        let json = dir.path().join("vars.json");
+        std::fs::write(
+            &json,
+            r#"{"region": "eu-west-1", "cidrs": ["10.0.0.0/16"]}"#,
+        )
+        .unwrap();
+        let vars: HashMap<String, String> = load_var_file(json.to_str().unwrap())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(vars["region"], "eu-west-1");
+        assert_eq!(vars["cidrs"], r#"["10.0.0.0/16"]"#);
+    }
+
+    #[test]
+    fn test_load_var_file_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("list.yml");
+        std::fs::write(&list, "- a\n- b\n").unwrap();
+        assert!(load_var_file(list.to_str().unwrap())
+            .unwrap_err()
+            .contains("must contain a mapping"));
+        assert!(load_var_file("missing.yml")
+            .unwrap_err()
+            .contains("missing.yml"));
+    }
+
+    #[test]
+    fn test_var_file_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let env_file = dir.path().join(".env");
+        std::fs::write(&env_file, "A=env\nB=env\nC=env\nD=env\n").unwrap();
+        let first = dir.path().join("first.yml");
+        std::fs::write(&first, "B: first\nC: first\nD: first\n").unwrap();
+        let second = dir.path().join("second.json");
+        std::fs::write(&second, r#"{"C": "second", "D": "second"}"#).unwrap();
+
+        let vars = load_env_vars(
+            env_file.to_str().unwrap(),
+            &[
+                first.to_str().unwrap().to_string(),
+                second.to_str().unwrap().to_string(),
+            ],
+            &["D=cli".to_string()],
+            ProcessEnv::Ignore,
+        );
+        assert_eq!(vars["A"], "env");
+        assert_eq!(vars["B"], "first");
+        assert_eq!(vars["C"], "second");
+        assert_eq!(vars["D"], "cli");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_vars_from_commands() {
+        let envs = HashMap::from([("GREETING".to_string(), "hello".to_string())]);
+        let specs =
+            |specs: &[&str]| -> Vec<String> { specs.iter().map(|s| s.to_string()).collect() };
+
+        // stdout is trimmed; the command's own `=` stays in the command
+        let vars = try_vars_from_commands(
+            &specs(&[
+                r#"GREETING_VAR=printf '  %s\n\n' "$GREETING""#,
+                "EQ=echo a=b",
+            ]),
+            &envs,
+        )
+        .unwrap();
+        assert_eq!(vars, ["GREETING_VAR=hello", "EQ=a=b"]);
+
+        let err = try_vars_from_commands(&specs(&["NO_COMMAND"]), &envs).unwrap_err();
+        assert!(
+            err.contains("expects NAME=COMMAND, got 'NO_COMMAND'"),
+            "{}",
+            err
+        );
+
+        let err =
+            try_vars_from_commands(&specs(&["FAILS=echo oops >&2; exit 3"]), &envs).unwrap_err();
+        assert!(err.contains("FAILS"), "{}", err);
+        assert!(err.contains("Some(3)"), "{}", err);
+        assert!(err.contains("oops"), "{}", err);
+    }
+}
@@ -4,6 +4,8 @@
 //! (network issues, auth failures, etc.) vs normal operational errors
 //! (404 not found) that the retry/statecheck logic can handle.
 
+use once_cell::sync::OnceCell;
+
 /// Error patterns that indicate a fatal, non-retryable failure.
 ///
 /// These are checked against the error message string returned by the
@@ -49,6 +51,30 @@ const NON_FATAL_OVERRIDES: &[&str] = &[
     "was not found",
 ];
 
+/// User-supplied patterns (manifest `abort_on`) that abort a run as soon as
+/// they appear in an error or notice, without retrying.
+static ABORT_ON_PATTERNS: OnceCell<Vec<String>> = OnceCell::new();
+
+/// Set the manifest's `abort_on` patterns. Only the first call takes effect.
+pub fn set_abort_on_patterns(patterns: Vec<String>) {
+    let _ = ABORT_ON_PATTERNS.set(patterns);
+}
+
+/// Check a provider message against the manifest's `abort_on` patterns.
+///
+/// Returns the matching pattern, if any.  Unlike `check_fatal_error`, no
+/// overrides apply: an explicit `abort_on` entry always wins.
+pub fn check_abort_on(msg: &str) -> Option<&'static str> {
+    find_abort_on(ABORT_ON_PATTERNS.get()?, msg)
+}
+
+fn find_abort_on<'a>(patterns: &'a [String], msg: &str) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|p| !p.is_empty() && msg.contains(p.as_str()))
+        .map(|p| p.as_str())
+}
+
 /// Check if an error message indicates a fatal, non-retryable failure.
 ///
 /// Returns `Some(reason)` if the error is fatal, `None` if it's
@@ -114,6 +140,16 @@ mod tests {
         assert!(check_fatal_error(msg).is_none());
     }
 
+    #[test]
+    fn test_abort_on_matches_configured_substrings() {
+        let patterns = vec!["QuotaExceeded".to_string(), String::new()];
+        let msg =
+            r#"http response status code: 400, response body: {"__type":"QuotaExceededException"}"#;
+        assert_eq!(find_abort_on(&patterns, msg), Some("QuotaExceeded"));
+        // Empty patterns never match
+        assert_eq!(find_abort_on(&patterns, "throttled, try again"), None);
+    }
+
     #[test]
     fn test_normal_query_error_is_not_fatal() {
         let msg = r#"query returns error: no such column: foo"#;
@@ -5,8 +5,14 @@
 //! Core library functionality for stackql-deploy, mirroring the Python implementation.
 //! Contains configuration handling, templating, utility functions, and filters.
 
+pub mod checkpoint;
 pub mod config;
 pub mod env;
 pub mod errors;
+pub mod report;
+pub mod rollback;
+pub mod seed;
+pub mod sql_dump;
+pub mod state;
 pub mod templating;
 pub mod utils;
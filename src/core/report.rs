@@ -0,0 +1,338 @@
+// lib/report.rs
+
+//! # Run Reports
+//!
+//! Structured outcome of a `build`, `test`, `plan` or `teardown` run.  The
+//! command flows record what happened to each resource in a [`BuildReport`],
+//! which can be serialized for machine consumption (`--output json`) or
+//! printed as a summary.  A tracked report is also kept up to date in
+//! [`ACTIVE_RUN`], so a run which fails part way still prints its report,
+//! with the error, from the failure path.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Serializer};
+
+/// What happened to a single resource during a run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceOutcome {
+    /// The resource did not exist and was created
+    Created,
+    /// The resource existed but was not in the desired state and was updated
+    Updated,
+    /// The resource was deleted and recreated (`replace_on_change`)
+    Replaced,
+    /// A `createorupdate` query ran with no `exists` or `statecheck` query
+    /// to tell whether it created or updated the resource
+    Applied,
+    /// The resource was already in the desired state (or passed its test)
+    Unchanged,
+    /// The resource was deleted
+    Deleted,
+    /// A `script` or `command` resource was run
+    Executed,
+    /// The resource was not processed (condition false, nothing to do, ...)
+    Skipped,
+    /// The resource could not be brought to the desired state
+    Failed,
+}
+
+/// Outcome of a single resource.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceReport {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    pub outcome: ResourceOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Time spent on the resource, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+/// Aggregate outcome of a run, with per-resource detail.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildReport {
+    pub total: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub replaced: usize,
+    pub applied: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+    pub executed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub resources: Vec<ResourceReport>,
+    /// Wall-clock duration of the run, serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Set by [`BuildReport::start_resource`]; the next record is timed from it
+    #[serde(skip)]
+    resource_started: Option<Instant>,
+    /// Whether every change is copied to [`ACTIVE_RUN`]
+    #[serde(skip)]
+    tracked: bool,
+}
+
+impl BuildReport {
+    /// Mark the start of work on a resource, so its recorded outcome carries
+    /// how long it took.
+    pub fn start_resource(&mut self) {
+        self.resource_started = Some(Instant::now());
+    }
+
+    /// Record the outcome of a resource.
+    pub fn record(
+        &mut self,
+        name: &str,
+        resource_type: &str,
+        outcome: ResourceOutcome,
+        message: Option<String>,
+    ) {
+        self.total += 1;
+        match outcome {
+            ResourceOutcome::Created => self.created += 1,
+            ResourceOutcome::Updated => self.updated += 1,
+            ResourceOutcome::Replaced => self.replaced += 1,
+            ResourceOutcome::Applied => self.applied += 1,
+            ResourceOutcome::Unchanged => self.unchanged += 1,
+            ResourceOutcome::Deleted => self.deleted += 1,
+            ResourceOutcome::Executed => self.executed += 1,
+            ResourceOutcome::Skipped => self.skipped += 1,
+            ResourceOutcome::Failed => self.failed += 1,
+        }
+        self.resources.push(ResourceReport {
+            name: name.to_string(),
+            resource_type: resource_type.to_string(),
+            outcome,
+            message,
+            duration: self
+                .resource_started
+                .take()
+                .map(|started| started.elapsed().as_secs_f64()),
+        });
+        self.publish();
+    }
+
+    /// Append the outcomes recorded in `other`.
+    pub fn merge(&mut self, other: BuildReport) {
+        for r in other.resources {
+            self.record(&r.name, &r.resource_type, r.outcome, r.message);
+            if let Some(last) = self.resources.last_mut() {
+                last.duration = r.duration;
+            }
+        }
+        self.publish();
+    }
+
+    /// Set the run duration once the run has finished.
+    pub fn finish(&mut self, duration: Duration) {
+        self.duration = duration;
+        self.publish();
+    }
+
+    /// Copy this report to the run started with [`track_run`] on every
+    /// change, so the failure path can print it.
+    pub fn track(&mut self) {
+        self.tracked = true;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        if !self.tracked {
+            return;
+        }
+        if let Some(run) = ACTIVE_RUN
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            run.report = self.clone();
+        }
+    }
+}
+
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} resource(s): {} created, {} updated, {} replaced, {} applied, {} unchanged, {} deleted, {} executed, {} skipped, {} failed in {:.2?}",
+            self.total,
+            self.created,
+            self.updated,
+            self.replaced,
+            self.applied,
+            self.unchanged,
+            self.deleted,
+            self.executed,
+            self.skipped,
+            self.failed,
+            self.duration
+        )
+    }
+}
+
+/// The document printed by `--output json`: the run's report along with the
+/// command and stack it was run for.
+#[derive(Debug, Serialize)]
+pub struct RunOutput<'a> {
+    pub command: &'a str,
+    pub stack_name: &'a str,
+    pub stack_env: &'a str,
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub report: &'a BuildReport,
+    /// Why the run failed, if it did not complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+/// The run whose report `--output json` prints, while it is in progress.
+static ACTIVE_RUN: Mutex<Option<ActiveRun>> = Mutex::new(None);
+
+/// What [`RunOutput`] needs, owned, for the run in progress.
+struct ActiveRun {
+    command: String,
+    stack_name: String,
+    stack_env: String,
+    dry_run: bool,
+    report: BuildReport,
+}
+
+/// Start keeping the report of a run of `command`, updated by a report on
+/// which [`BuildReport::track`] is called.
+pub fn track_run(command: &str, stack_name: &str, stack_env: &str, dry_run: bool) {
+    *ACTIVE_RUN.lock().unwrap_or_else(|e| e.into_inner()) = Some(ActiveRun {
+        command: command.to_string(),
+        stack_name: stack_name.to_string(),
+        stack_env: stack_env.to_string(),
+        dry_run,
+        report: BuildReport::default(),
+    });
+}
+
+/// Stop keeping the run's report, once it has been printed.
+pub fn end_run() {
+    *ACTIVE_RUN.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// The JSON document of the run in progress, failed with `error`; `None`
+/// unless a run is tracked.
+pub fn failure_report(error: &str) -> Option<String> {
+    let active = ACTIVE_RUN.lock().unwrap_or_else(|e| e.into_inner());
+    let run = active.as_ref()?;
+    let output = RunOutput {
+        command: &run.command,
+        stack_name: &run.stack_name,
+        stack_env: &run.stack_env,
+        dry_run: run.dry_run,
+        report: &run.report,
+        error: Some(error),
+    };
+    serde_json::to_string_pretty(&output).ok()
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_report_counts_and_serializes() {
+        let mut report = BuildReport::default();
+        report.record("vpc", "resource", ResourceOutcome::Created, None);
+        report.record("subnet", "resource", ResourceOutcome::Unchanged, None);
+        report.record("policy", "resource", ResourceOutcome::Applied, None);
+        report.record(
+            "route",
+            "resource",
+            ResourceOutcome::Failed,
+            Some("post-deploy checks did not pass".to_string()),
+        );
+        report.finish(Duration::from_millis(1500));
+
+        assert_eq!(report.total, 4);
+        assert_eq!(report.created, 1);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.applied, 1);
+        assert_eq!(report.failed, 1);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["duration"], 1.5);
+        assert_eq!(json["resources"][0]["type"], "resource");
+        assert_eq!(json["resources"][0]["outcome"], "created");
+        assert!(json["resources"][0].get("message").is_none());
+        assert_eq!(json["resources"][2]["outcome"], "applied");
+        assert_eq!(
+            json["resources"][3]["message"],
+            "post-deploy checks did not pass"
+        );
+
+        let mut other = BuildReport::default();
+        other.record("gateway", "resource", ResourceOutcome::Created, None);
+        report.merge(other);
+        assert_eq!((report.total, report.created), (5, 2));
+        assert_eq!(report.resources[4].name, "gateway");
+    }
+
+    #[test]
+    fn test_run_output_times_resources_and_flattens_report() {
+        let mut report = BuildReport::default();
+        report.start_resource();
+        report.record("vpc", "resource", ResourceOutcome::Created, None);
+        report.record("subnet", "resource", ResourceOutcome::Skipped, None);
+
+        let output = RunOutput {
+            command: "build",
+            stack_name: "my-stack",
+            stack_env: "dev",
+            dry_run: false,
+            report: &report,
+            error: None,
+        };
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["command"], "build");
+        assert_eq!(json["stack_env"], "dev");
+        assert_eq!(json["total"], 2);
+        assert!(json["resources"][0]["duration"].as_f64().is_some());
+        // Only a started resource is timed
+        assert!(json["resources"][1].get("duration").is_none());
+        assert!(json.get("resource_started").is_none());
+        assert!(json.get("error").is_none());
+    }
+
+    #[test]
+    fn test_failure_report_of_tracked_run() {
+        track_run("build", "my-stack", "dev", false);
+        let mut report = BuildReport::default();
+        report.track();
+        report.record("vpc", "resource", ResourceOutcome::Created, None);
+        // A fork's report is only published once merged
+        let mut fork = BuildReport::default();
+        fork.record("subnet", "resource", ResourceOutcome::Created, None);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&failure_report("x").unwrap()).unwrap()
+                ["total"],
+            1
+        );
+        report.merge(fork);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&failure_report("exports query failed").unwrap()).unwrap();
+        end_run();
+        assert_eq!(json["command"], "build");
+        assert_eq!(json["error"], "exports query failed");
+        assert_eq!(json["total"], 2);
+        assert_eq!(json["resources"][1]["name"], "subnet");
+        assert!(json.get("tracked").is_none());
+        assert!(failure_report("exports query failed").is_none());
+    }
+}
@@ -0,0 +1,280 @@
+// lib/rollback.rs
+
+//! # Build Rollback
+//!
+//! Implements `build --on-failure rollback`.  While a build runs, each
+//! resource it creates is pushed onto a rollback stack together with its
+//! rendered `delete` query.  When the build then fails, the failure path
+//! ([`catch_error_and_exit`](crate::core::utils::catch_error_and_exit))
+//! deletes those resources in reverse order over a fresh connection before
+//! the process exits non-zero.  Resources which already existed, or were
+//! updated or replaced, are never deleted.
+
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+use crate::core::utils::{apply_session_settings, error_detected_in_notice};
+use crate::utils::connection::{create_client, RunOptions, ServerConfig};
+use crate::utils::query::{execute_query, QueryResult};
+
+/// Rollback stack of the running build, `None` unless rollback is armed.
+static ROLLBACK: Mutex<Option<Rollback>> = Mutex::new(None);
+
+/// A resource created by this run and the query which deletes it, `None`
+/// while the query cannot be rendered yet (e.g. it needs `this.*` fields
+/// which the post-deploy checks capture).
+#[derive(Debug, Clone, PartialEq)]
+struct CreatedResource {
+    name: String,
+    delete_query: Option<String>,
+}
+
+/// Resources created by the running build, in creation order.
+#[derive(Debug)]
+struct Rollback {
+    server: ServerConfig,
+    options: RunOptions,
+    session_settings: Vec<(String, String)>,
+    created: Vec<CreatedResource>,
+}
+
+impl Rollback {
+    fn new(
+        server: &ServerConfig,
+        options: &RunOptions,
+        session_settings: Vec<(String, String)>,
+    ) -> Self {
+        Self {
+            server: server.clone(),
+            options: options.clone(),
+            session_settings,
+            created: Vec::new(),
+        }
+    }
+
+    /// Record `name`, or refine the delete query of an earlier record of it
+    /// (keeping its place in the creation order).
+    fn push(&mut self, name: &str, delete_query: Option<&str>) {
+        let delete_query = delete_query.map(str::to_string);
+        match self.created.iter_mut().find(|r| r.name == name) {
+            Some(existing) => {
+                if delete_query.is_some() {
+                    existing.delete_query = delete_query;
+                }
+            }
+            None => self.created.push(CreatedResource {
+                name: name.to_string(),
+                delete_query,
+            }),
+        }
+    }
+
+    /// Delete the created resources, most recent first, with `delete`.
+    /// A failed delete is logged and does not stop the rest; the names of
+    /// the resources left in place are returned.
+    fn run(&self, mut delete: impl FnMut(&str) -> Result<(), String>) -> Vec<String> {
+        let mut failed = Vec::new();
+        for resource in self.created.iter().rev() {
+            let Some(ref delete_query) = resource.delete_query else {
+                warn!(
+                    "cannot roll back [{}]: its delete query could not be rendered",
+                    resource.name
+                );
+                failed.push(resource.name.clone());
+                continue;
+            };
+            info!("rolling back [{}]...", resource.name);
+            match delete(delete_query) {
+                Ok(()) => info!("rolled back [{}]", resource.name),
+                Err(e) => {
+                    warn!("failed to roll back [{}]: {}", resource.name, e);
+                    failed.push(resource.name.clone());
+                }
+            }
+        }
+        failed
+    }
+}
+
+/// Start tracking created resources for rollback, connecting to `server`
+/// as set up by `options`, with the manifest `session_settings`, if the
+/// build fails.
+pub fn arm(server: &ServerConfig, options: &RunOptions, session_settings: Vec<(String, String)>) {
+    *ROLLBACK.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(Rollback::new(server, options, session_settings));
+}
+
+/// Stop tracking, once the build is over; nothing is rolled back.
+pub fn disarm() {
+    ROLLBACK.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Whether a failing build will be rolled back.
+pub fn is_armed() -> bool {
+    ROLLBACK.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Record a resource created by the build and the rendered query which
+/// deletes it, as soon as it is created so that a failure in its own
+/// post-deploy checks still rolls it back.  Recording it again once more of
+/// its context is known refines the delete query.  Does nothing unless
+/// rollback is armed.
+pub fn record_created(name: &str, delete_query: Option<&str>) {
+    if let Some(rollback) = ROLLBACK.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        rollback.push(name, delete_query);
+    }
+}
+
+/// Delete every resource created by the failed build, in reverse order.
+/// Called on the failure path; the stack is taken first, so an error raised
+/// while rolling back exits without starting another rollback.
+pub fn roll_back() {
+    let rollback = match ROLLBACK.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        Some(rollback) if !rollback.created.is_empty() => rollback,
+        _ => return,
+    };
+
+    warn!(
+        "build failed, rolling back {} resource(s) created by this run",
+        rollback.created.len()
+    );
+    let mut client = create_client(&rollback.server, &rollback.options);
+    apply_session_settings(&rollback.session_settings, &mut client);
+
+    let failed = rollback.run(|query| match execute_query(query, &mut client)? {
+        QueryResult::Data { notices, .. } => {
+            match notices.iter().find(|n| error_detected_in_notice(n)) {
+                Some(notice) => Err(notice.clone()),
+                None => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    });
+    if !failed.is_empty() {
+        warn!(
+            "{} resource(s) could not be rolled back and must be removed manually: [{}]",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+}
+
+/// Serializes tests which arm the process-wide rollback stack.
+#[cfg(test)]
+pub(crate) static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Names and delete queries recorded so far, for tests.
+#[cfg(test)]
+pub(crate) fn recorded() -> Vec<(String, Option<String>)> {
+    ROLLBACK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|rollback| {
+            rollback
+                .created
+                .iter()
+                .map(|r| (r.name.clone(), r.delete_query.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rollback_of(names: &[&str]) -> Rollback {
+        let mut rollback =
+            Rollback::new(&ServerConfig::default(), &RunOptions::default(), Vec::new());
+        for name in names {
+            rollback.push(name, Some(&format!("DELETE FROM {}", name)));
+        }
+        rollback
+    }
+
+    #[test]
+    fn test_rollback_deletes_in_reverse_order() {
+        let rollback = rollback_of(&["vpc", "subnet", "instance"]);
+        let mut deleted = Vec::new();
+        let failed = rollback.run(|query| {
+            deleted.push(query.to_string());
+            Ok(())
+        });
+        assert_eq!(
+            deleted,
+            [
+                "DELETE FROM instance",
+                "DELETE FROM subnet",
+                "DELETE FROM vpc"
+            ]
+        );
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn test_rollback_continues_past_failed_delete() {
+        let rollback = rollback_of(&["vpc", "subnet", "instance"]);
+        let mut deleted = Vec::new();
+        let failed = rollback.run(|query| {
+            if query.ends_with("subnet") {
+                return Err("dependency violation".to_string());
+            }
+            deleted.push(query.to_string());
+            Ok(())
+        });
+        assert_eq!(deleted, ["DELETE FROM instance", "DELETE FROM vpc"]);
+        assert_eq!(failed, ["subnet"]);
+    }
+
+    #[test]
+    fn test_rerecording_refines_delete_query() {
+        let mut rollback = rollback_of(&["vpc"]);
+        rollback.push("subnet", None);
+        rollback.push("instance", Some("DELETE FROM instance"));
+        rollback.push("subnet", Some("DELETE FROM subnet WHERE id = 'subnet-1'"));
+        // A later record without a query keeps the one already known
+        rollback.push("vpc", None);
+
+        let mut deleted = Vec::new();
+        let failed = rollback.run(|query| {
+            deleted.push(query.to_string());
+            Ok(())
+        });
+        assert_eq!(
+            deleted,
+            [
+                "DELETE FROM instance",
+                "DELETE FROM subnet WHERE id = 'subnet-1'",
+                "DELETE FROM vpc"
+            ]
+        );
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn test_unrenderable_delete_is_left_in_place() {
+        let mut rollback = rollback_of(&["vpc"]);
+        rollback.push("subnet", None);
+        let failed = rollback.run(|_| Ok(()));
+        assert_eq!(failed, ["subnet"]);
+    }
+
+    #[test]
+    fn test_record_created_only_when_armed() {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        disarm();
+        record_created("vpc", Some("DELETE FROM vpc"));
+        assert!(!is_armed());
+
+        arm(&ServerConfig::default(), &RunOptions::default(), Vec::new());
+        record_created("vpc", Some("DELETE FROM vpc"));
+        assert!(is_armed());
+        let created = ROLLBACK.lock().unwrap().as_ref().unwrap().created.clone();
+        assert_eq!(created[0].name, "vpc");
+
+        disarm();
+        assert!(!is_armed());
+    }
+}
@@ -0,0 +1,170 @@
+// lib/sql_dump.rs
+
+//! # Rendered SQL Dumps
+//!
+//! Writes every rendered query of a run to `<dir>/<resource>/<anchor>.sql`
+//! (`--dump-sql-to-dir`), so the generated SQL of a stack can be committed
+//! and reviewed as a diff when templates change.  An `index.txt` lists the
+//! files in manifest order.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+/// Name of the index file written to the dump directory.
+const INDEX_FILE: &str = "index.txt";
+
+/// Rendered SQL written so far, per resource.
+pub struct SqlDump {
+    dir: PathBuf,
+    header: String,
+    /// Resource names in manifest order, used to order the index.
+    manifest_order: Vec<String>,
+    /// `(resource, anchors)` in the order they were first rendered.
+    written: Vec<(String, Vec<String>)>,
+}
+
+impl SqlDump {
+    /// Prepare `dir` for a new dump.  Files listed in the index of a previous
+    /// dump are removed so that queries which are no longer rendered do not
+    /// linger; nothing else in the directory is touched.
+    pub fn new(
+        dir: &str,
+        stack_name: &str,
+        stack_env: &str,
+        manifest_order: Vec<String>,
+    ) -> io::Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        if let Ok(index) = fs::read_to_string(dir.join(INDEX_FILE)) {
+            for line in index.lines().filter(|l| !l.starts_with('#')) {
+                let path = dir.join(line.trim());
+                if line.trim().ends_with(".sql") && fs::remove_file(&path).is_ok() {
+                    debug!("removed stale dump file {:?}", path);
+                }
+            }
+        }
+        Ok(Self {
+            dir,
+            header: format!(
+                "# rendered SQL for stack [{}] in environment [{}]",
+                stack_name, stack_env
+            ),
+            manifest_order,
+            written: Vec::new(),
+        })
+    }
+
+    /// Relative path of the dump file for `resource` and `anchor`.  Resource
+    /// names containing `/` become nested directories.
+    fn relative_path(resource: &str, anchor: &str) -> PathBuf {
+        let mut path: PathBuf = resource
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+            .collect();
+        path.push(format!("{}.sql", anchor.replace(['/', '\\', ':'], "_")));
+        path
+    }
+
+    /// Write the rendered `sql` for `anchor` of `resource`, replacing an
+    /// earlier render of the same anchor.  Failures only cost the dump, so
+    /// they are logged rather than fatal.
+    pub fn write(&mut self, resource: &str, anchor: &str, sql: &str) {
+        let path = self.dir.join(Self::relative_path(resource, anchor));
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, format!("{}\n", sql.trim_end())));
+        if let Err(e) = result {
+            warn!("failed to write rendered SQL to {:?}: {}", path, e);
+            return;
+        }
+
+        let entry = match self.written.iter().position(|(r, _)| r == resource) {
+            Some(i) => &mut self.written[i].1,
+            None => {
+                self.written.push((resource.to_string(), Vec::new()));
+                &mut self.written.last_mut().unwrap().1
+            }
+        };
+        if !entry.iter().any(|a| a == anchor) {
+            entry.push(anchor.to_string());
+            self.write_index();
+        }
+    }
+
+    /// Rewrite the index, listing resources in manifest order and each
+    /// resource's anchors in the order they were rendered.
+    fn write_index(&self) {
+        let position = |name: &str| {
+            self.manifest_order
+                .iter()
+                .position(|r| r == name)
+                .unwrap_or(usize::MAX)
+        };
+        let mut resources: Vec<&(String, Vec<String>)> = self.written.iter().collect();
+        resources.sort_by_key(|(name, _)| position(name));
+
+        let mut lines = vec![self.header.clone()];
+        for (resource, anchors) in resources {
+            for anchor in anchors {
+                let path = Self::relative_path(resource, anchor);
+                lines.push(path.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        let index = self.dir.join(INDEX_FILE);
+        if let Err(e) = fs::write(&index, lines.join("\n") + "\n") {
+            warn!("failed to write {:?}: {}", index, e);
+        }
+    }
+
+    /// Directory the dump is written to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sql_dump_layout_and_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("sql");
+        let dir_str = dir.to_str().unwrap();
+        let order = vec!["vpc".to_string(), "aws/iam/role".to_string()];
+
+        let mut dump = SqlDump::new(dir_str, "my-stack", "dev", order.clone()).unwrap();
+        // Rendered out of manifest order, as teardown does
+        dump.write("aws/iam/role", "exists", "SELECT 1");
+        dump.write("vpc", "exists", "SELECT count(*) as count FROM vpcs");
+        dump.write("vpc", "create", "INSERT INTO vpcs SELECT 'a'");
+        dump.write("vpc", "exists", "SELECT count(*) as count FROM vpcs");
+
+        assert_eq!(
+            fs::read_to_string(dir.join("vpc/create.sql")).unwrap(),
+            "INSERT INTO vpcs SELECT 'a'\n"
+        );
+        assert!(dir.join("aws/iam/role/exists.sql").exists());
+        let index = fs::read_to_string(dir.join(INDEX_FILE)).unwrap();
+        let files: Vec<&str> = index.lines().skip(1).collect();
+        assert_eq!(
+            files,
+            [
+                "vpc/exists.sql",
+                "vpc/create.sql",
+                "aws/iam/role/exists.sql"
+            ]
+        );
+
+        // A new dump removes files from the previous one before writing
+        fs::write(dir.join("README.md"), "keep").unwrap();
+        let mut dump = SqlDump::new(dir_str, "my-stack", "dev", order).unwrap();
+        dump.write("vpc", "exists", "SELECT 2");
+        assert!(!dir.join("vpc/create.sql").exists());
+        assert!(dir.join("README.md").exists());
+    }
+}
@@ -0,0 +1,159 @@
+// lib/state.rs
+
+//! # Stack State Files
+//!
+//! A state file records the resources deployed by a successful `build`, in
+//! deployment order, together with the identifiers each one exported.  It is
+//! written with `build --state-file` and read with `teardown --state-file`,
+//! which then only tears down the recorded resources and takes their exports
+//! from the file instead of re-discovering them with exists and exports
+//! queries.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+/// A deployed resource and the variables it exported.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResourceState {
+    pub name: String,
+    #[serde(default)]
+    pub exports: BTreeMap<String, String>,
+}
+
+/// On-disk state of a deployed stack.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StateFile {
+    pub stack_name: String,
+    pub stack_env: String,
+    /// Deployed resources, in deployment order.
+    #[serde(default)]
+    pub resources: Vec<ResourceState>,
+}
+
+impl StateFile {
+    /// Start an empty state for a stack and environment.
+    pub fn new(stack_name: &str, stack_env: &str) -> Self {
+        Self {
+            stack_name: stack_name.to_string(),
+            stack_env: stack_env.to_string(),
+            resources: Vec::new(),
+        }
+    }
+
+    /// Read a state file written by `save`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read state file {}: {}", path, e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse state file {}: {}", path, e))
+    }
+
+    /// Write the state as pretty-printed JSON, replacing any existing file.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize state: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write state file {}: {}", path, e))
+    }
+
+    /// Append a deployed resource, after those already recorded.
+    pub fn record(&mut self, name: &str, exports: BTreeMap<String, String>) {
+        self.resources.push(ResourceState {
+            name: name.to_string(),
+            exports,
+        });
+    }
+
+    /// Merge in `deployed`, the state of a build of only some of the
+    /// stack's resources.  A resource already recorded keeps its place in
+    /// deployment order with its exports replaced; the others are appended.
+    pub fn merge(&mut self, deployed: StateFile) {
+        for resource in deployed.resources {
+            match self.resources.iter_mut().find(|r| r.name == resource.name) {
+                Some(existing) => existing.exports = resource.exports,
+                None => self.resources.push(resource),
+            }
+        }
+    }
+
+    /// The recorded state of `name`, if it was deployed.
+    pub fn resource(&self, name: &str) -> Option<&ResourceState> {
+        self.resources.iter().find(|r| r.name == name)
+    }
+
+    /// Position of `name` in deployment order, if it was deployed.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.resources.iter().position(|r| r.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let path = path.to_str().unwrap();
+
+        let mut state = StateFile::new("my-stack", "dev");
+        state.record(
+            "vpc",
+            BTreeMap::from([("vpc_id".to_string(), "vpc-123".to_string())]),
+        );
+        state.record("subnet", BTreeMap::new());
+        state.save(path).unwrap();
+
+        let loaded = StateFile::load(path).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.position("subnet"), Some(1));
+        assert_eq!(loaded.resource("vpc").unwrap().exports["vpc_id"], "vpc-123");
+        assert!(loaded.resource("route_table").is_none());
+    }
+
+    #[test]
+    fn test_state_file_merge_keeps_deployment_order() {
+        let exports = |id: &str| BTreeMap::from([("id".to_string(), id.to_string())]);
+        let mut state = StateFile::new("my-stack", "dev");
+        state.record("vpc", exports("vpc-1"));
+        state.record("subnet", exports("subnet-1"));
+
+        let mut deployed = StateFile::new("my-stack", "dev");
+        deployed.record("subnet", exports("subnet-2"));
+        deployed.record("route_table", exports("rtb-1"));
+        state.merge(deployed);
+
+        let names: Vec<&str> = state.resources.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["vpc", "subnet", "route_table"]);
+        assert_eq!(state.resource("vpc").unwrap().exports, exports("vpc-1"));
+        assert_eq!(
+            state.resource("subnet").unwrap().exports,
+            exports("subnet-2")
+        );
+    }
+
+    #[test]
+    fn test_state_file_defaults_missing_fields() {
+        let state: StateFile = serde_json::from_str(
+            r#"{"stack_name": "s", "stack_env": "dev", "resources": [{"name": "vpc"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(state.resources[0].name, "vpc");
+        assert!(state.resources[0].exports.is_empty());
+    }
+
+    #[test]
+    fn test_state_file_load_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.json");
+        let err = StateFile::load(missing.to_str().unwrap()).unwrap_err();
+        assert!(err.contains("missing.json"));
+
+        let invalid = dir.path().join("invalid.json");
+        fs::write(&invalid, "not json").unwrap();
+        let err = StateFile::load(invalid.to_str().unwrap()).unwrap_err();
+        assert!(err.starts_with("Failed to parse state file"));
+    }
+}
@@ -12,13 +12,14 @@
 
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 
-use log::{debug, error};
+use log::{debug, error, warn};
 use regex::Regex;
 
 use crate::core::config::prepare_query_context;
+use crate::core::utils::{sorted_keys, Backoff};
 use crate::resource::manifest::Resource;
 use crate::template::engine::TemplateEngine;
 
@@ -44,6 +45,49 @@ pub struct QueryOptions {
     /// Value of `short_circuit_field` that means polling can be skipped.
     /// Only used on `callback` anchors.
     pub short_circuit_value: Option<String>,
+    /// Only the first row of the result is needed (e.g. `exists`,
+    /// `statecheck` or `exports` queries over large result sets), so any
+    /// further rows are discarded as they arrive instead of being buffered.
+    pub first_row_only: bool,
+    /// Overrides the `--max-rows` cap for this anchor's query.
+    pub max_rows: Option<u32>,
+    /// Seconds to wait for each attempt of this anchor's query before it
+    /// fails as a retryable error.  `None` waits indefinitely.
+    pub timeout: Option<u32>,
+    /// How the wait between attempts grows from `retry_delay`.
+    pub backoff: Backoff,
+}
+
+impl std::fmt::Display for QueryOptions {
+    /// Effective options as `key=value` pairs, as reported in dry-run output.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "retries={}, retry_delay={}, postdelete_retries={}, postdelete_retry_delay={}",
+            self.retries, self.retry_delay, self.postdelete_retries, self.postdelete_retry_delay
+        )?;
+        if let Some(ref field) = self.short_circuit_field {
+            write!(f, ", short_circuit_field={}", field)?;
+        }
+        if let Some(ref value) = self.short_circuit_value {
+            write!(f, ", short_circuit_value={}", value)?;
+        }
+        if self.first_row_only {
+            write!(f, ", first_row_only=true")?;
+        }
+        if let Some(max_rows) = self.max_rows {
+            write!(f, ", max_rows={}", max_rows)?;
+        }
+        if let Some(timeout) = self.timeout {
+            write!(f, ", timeout={}", timeout)?;
+        }
+        match self.backoff {
+            Backoff::Constant => {}
+            Backoff::Linear => write!(f, ", backoff=linear")?,
+            Backoff::Exponential => write!(f, ", backoff=exponential")?,
+        }
+        Ok(())
+    }
 }
 
 /// Parse an anchor line to extract key, numeric options, and string options.
@@ -73,6 +117,88 @@ fn parse_anchor(anchor: &str) -> (String, HashMap<String, u32>, HashMap<String,
     (key, uint_options, str_options)
 }
 
+/// Build the options of an anchor from its parsed values, applying the
+/// defaults for options it does not set.
+fn query_options(
+    uint_opts: &HashMap<String, u32>,
+    str_opts: &HashMap<String, String>,
+) -> QueryOptions {
+    QueryOptions {
+        retries: *uint_opts.get("retries").unwrap_or(&1),
+        retry_delay: *uint_opts.get("retry_delay").unwrap_or(&0),
+        postdelete_retries: *uint_opts.get("postdelete_retries").unwrap_or(&10),
+        postdelete_retry_delay: *uint_opts.get("postdelete_retry_delay").unwrap_or(&5),
+        short_circuit_field: str_opts.get("short_circuit_field").cloned(),
+        short_circuit_value: str_opts.get("short_circuit_value").cloned(),
+        first_row_only: str_opts.get("first_row_only").is_some_and(|v| v == "true"),
+        max_rows: uint_opts.get("max_rows").copied(),
+        // timeout=0 means no timeout, as when it is not set
+        timeout: uint_opts.get("timeout").copied().filter(|t| *t > 0),
+        backoff: str_opts
+            .get("backoff")
+            .and_then(|b| b.parse().ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Anchor options parsed as unsigned integers.
+const UINT_ANCHOR_OPTIONS: &[&str] = &[
+    "retries",
+    "retry_delay",
+    "postdelete_retries",
+    "postdelete_retry_delay",
+    "max_rows",
+    "timeout",
+];
+
+/// Anchor options kept as strings.
+const STR_ANCHOR_OPTIONS: &[&str] = &["short_circuit_field", "short_circuit_value", "backoff"];
+
+/// Anchor options parsed as booleans (`true` or `false`).
+const BOOL_ANCHOR_OPTIONS: &[&str] = &["first_row_only"];
+
+/// Return a description of every option token in an anchor that
+/// `parse_anchor` would ignore or misread: unknown keys (e.g. `retrys=3`),
+/// non-numeric values for numeric keys (e.g. `retry_delay=5s`), and tokens
+/// without `=`.
+fn invalid_anchor_options(anchor: &str) -> Vec<String> {
+    let mut invalid = Vec::new();
+    for part in anchor.split(',').skip(1) {
+        let token = part.trim();
+        if token.is_empty() {
+            continue;
+        }
+        match token.split_once('=') {
+            Some((k, v)) => {
+                let k = k.trim();
+                if UINT_ANCHOR_OPTIONS.contains(&k) {
+                    if v.trim().parse::<u32>().is_err() {
+                        invalid.push(format!(
+                            "'{}' ({} expects a non-negative integer)",
+                            token, k
+                        ));
+                    }
+                } else if BOOL_ANCHOR_OPTIONS.contains(&k) {
+                    if v.trim().parse::<bool>().is_err() {
+                        invalid.push(format!("'{}' ({} expects true or false)", token, k));
+                    }
+                } else if k == "backoff" {
+                    if v.trim().parse::<Backoff>().is_err() {
+                        invalid.push(format!(
+                            "'{}' (backoff expects constant, linear or exponential)",
+                            token
+                        ));
+                    }
+                } else if !STR_ANCHOR_OPTIONS.contains(&k) {
+                    invalid.push(format!("'{}' (unknown option '{}')", token, k));
+                }
+            }
+            None => invalid.push(format!("'{}' (expected key=value)", token)),
+        }
+    }
+    invalid
+}
+
 /// Return type of `load_sql_queries`: (templates, uint_options, str_options).
 type SqlQueriesResult = (
     HashMap<String, String>,
@@ -90,15 +216,50 @@ fn load_sql_queries(file_path: &Path) -> SqlQueriesResult {
             process::exit(1);
         }
     };
+    match parse_sql_queries(&content, file_path) {
+        Ok(queries) => queries,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
 
+/// Split the content of a .iql file by anchors.  An anchor comment may span
+/// several lines, from the line starting with `/*+` to the first `*/`, with
+/// each line break separating options like a comma; one which is never
+/// closed is an error rather than swallowing the queries after it.
+fn parse_sql_queries(content: &str, file_path: &Path) -> Result<SqlQueriesResult, String> {
     let mut queries: HashMap<String, String> = HashMap::new();
     let mut uint_options: HashMap<String, HashMap<String, u32>> = HashMap::new();
     let mut str_options: HashMap<String, HashMap<String, String>> = HashMap::new();
     let mut current_anchor: Option<String> = None;
     let mut query_buffer: Vec<String> = Vec::new();
+    // An anchor comment still open at the end of a line: its first line
+    // number and the lines read so far.
+    let mut open_anchor: Option<(usize, Vec<&str>)> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let anchor_line = match open_anchor.take() {
+            Some((start, mut lines)) => {
+                lines.push(line);
+                if !line.contains("*/") {
+                    open_anchor = Some((start, lines));
+                    continue;
+                }
+                Some(lines.join(","))
+            }
+            None if line.trim_start().starts_with("/*+") => {
+                if !line.contains("*/") {
+                    open_anchor = Some((index + 1, vec![line]));
+                    continue;
+                }
+                Some(line.to_string())
+            }
+            None => None,
+        };
 
-    for line in content.lines() {
-        if line.trim_start().starts_with("/*+") && line.contains("*/") {
+        if let Some(line) = anchor_line {
             // Store the current query under the last anchor
             if let Some(ref anchor) = current_anchor {
                 if !query_buffer.is_empty() {
@@ -115,12 +276,33 @@ fn load_sql_queries(file_path: &Path) -> SqlQueriesResult {
             // Extract new anchor
             let start = line.find("/*+").unwrap() + 3;
             let end = line.find("*/").unwrap();
-            current_anchor = Some(line[start..end].trim().to_string());
+            let anchor = line[start..end]
+                .split(',')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ");
+            for bad in invalid_anchor_options(&anchor) {
+                warn!(
+                    "ignoring anchor option {} in [{}] anchor of {:?}",
+                    bad,
+                    anchor.split(',').next().unwrap_or("").trim(),
+                    file_path
+                );
+            }
+            current_anchor = Some(anchor);
         } else {
             query_buffer.push(line.to_string());
         }
     }
 
+    if let Some((start, _)) = open_anchor {
+        return Err(format!(
+            "Unterminated query anchor at line {} of {:?}: '/*+' is never closed with '*/'",
+            start, file_path
+        ));
+    }
+
     // Store the last query
     if let Some(ref anchor) = current_anchor {
         if !query_buffer.is_empty() {
@@ -134,7 +316,61 @@ fn load_sql_queries(file_path: &Path) -> SqlQueriesResult {
         }
     }
 
-    (queries, uint_options, str_options)
+    Ok((queries, uint_options, str_options))
+}
+
+/// Remove `--` line comments and `/* */` block comments from a query body
+/// (`--strip-comments`).  Comment markers inside single- or double-quoted
+/// literals are kept, as are `/*+ */` hints; the newline ending a line
+/// comment is kept, so line structure is unchanged.
+pub fn strip_sql_comments(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            out.push(c);
+            // A doubled quote is an escaped quote and stays in the literal
+            if c == q {
+                if chars.peek() == Some(&q) {
+                    out.push(chars.next().unwrap_or(q));
+                } else {
+                    quote = None;
+                }
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('\'', _) | ('"', _) => {
+                quote = Some(c);
+                out.push(c);
+            }
+            ('-', Some('-')) => {
+                while chars.peek().is_some_and(|&n| n != '\n') {
+                    chars.next();
+                }
+                let kept = out.trim_end_matches([' ', '\t']).len();
+                out.truncate(kept);
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                if chars.peek() == Some(&'+') {
+                    out.push_str("/*");
+                    continue;
+                }
+                let mut prev = '\0';
+                for n in chars.by_ref() {
+                    if prev == '*' && n == '/' {
+                        break;
+                    }
+                    prev = n;
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
 }
 
 /// Pre-process Jinja2 inline dict expressions that Tera doesn't support.
@@ -275,6 +511,47 @@ fn preprocess_jinja2_compat(template: &str) -> String {
     result
 }
 
+/// Top-level variables referenced by a template, for diagnostics: the head
+/// of every `{{ ... }}` expression and the operands of `{% if %}`,
+/// `{% elif %}` and `{% for ... in %}` tags.  Loop variables are excluded,
+/// as are names that are not variables (`not`, `and`, `loop`, ...).
+pub(crate) fn referenced_variables(template: &str) -> Vec<String> {
+    const NOT_VARIABLES: &[&str] = &[
+        "and", "or", "not", "in", "is", "true", "false", "True", "False", "loop",
+    ];
+    let expr_re = Regex::new(r"\{\{-?\s*(\w+)").unwrap();
+    let tag_re = Regex::new(r"(?s)\{%-?\s*(if|elif|for)\b(.*?)-?%\}").unwrap();
+    let loop_vars_re = Regex::new(r"^\s*(\w+)(?:\s*,\s*(\w+))?\s+in\b").unwrap();
+    // Quoted literals, filter names and attribute accesses are not variables
+    let non_var_re = Regex::new(r#""[^"]*"|'[^']*'|\|\s*\w+|\.\w+"#).unwrap();
+    let ident_re = Regex::new(r"[A-Za-z_]\w*").unwrap();
+
+    let mut loop_vars = Vec::new();
+    let mut vars: Vec<String> = expr_re
+        .captures_iter(template)
+        .map(|c| c[1].to_string())
+        .collect();
+    for caps in tag_re.captures_iter(template) {
+        let mut expr = &caps[2];
+        if &caps[1] == "for" {
+            if let Some(lv) = loop_vars_re.captures(expr) {
+                loop_vars.extend(lv.iter().skip(1).flatten().map(|m| m.as_str().to_string()));
+                expr = &expr[lv.get(0).unwrap().end()..];
+            }
+        }
+        let stripped = non_var_re.replace_all(expr, " ");
+        vars.extend(
+            ident_re
+                .find_iter(&stripped)
+                .map(|m| m.as_str().to_string()),
+        );
+    }
+    vars.retain(|v| !NOT_VARIABLES.contains(&v.as_str()) && !loop_vars.contains(v));
+    let mut seen = std::collections::HashSet::new();
+    vars.retain(|v| seen.insert(v.clone()));
+    vars
+}
+
 /// Render a single query template with the given context.
 /// This is the JIT rendering function called when a query is actually needed.
 pub fn render_query(
@@ -324,14 +601,9 @@ pub fn render_query(
             );
 
             // Extract template variable references for diagnostics
-            let re = Regex::new(r"\{\{\s*(\w+)").unwrap();
-            let referenced_vars: Vec<&str> = re
-                .captures_iter(&processed_query)
-                .filter_map(|c| c.get(1).map(|m| m.as_str()))
-                .collect();
-            let missing: Vec<&&str> = referenced_vars
-                .iter()
-                .filter(|v| !ctx.contains_key(**v))
+            let missing: Vec<String> = referenced_variables(&processed_query)
+                .into_iter()
+                .filter(|v| !ctx.contains_key(v))
                 .collect();
 
             if !missing.is_empty() {
@@ -350,12 +622,21 @@ pub fn render_query(
                 "[{}] [{}] available context keys: {:?}",
                 res_name,
                 anchor,
-                ctx.keys().collect::<Vec<_>>()
+                sorted_keys(&ctx)
             );
 
+            let at = e
+                .position()
+                .map(|p| {
+                    format!(
+                        " at line {}, column {}:\n\n    {}\n",
+                        p.line, p.column, p.snippet
+                    )
+                })
+                .unwrap_or_default();
             crate::core::utils::catch_error_and_exit(&format!(
-                "Failed to render query for [{}] [{}]",
-                res_name, anchor
+                "Failed to render query for [{}] [{}]{}",
+                res_name, anchor, at
             ));
         }
     }
@@ -419,13 +700,7 @@ pub fn get_queries(
 ) -> HashMap<String, ParsedQuery> {
     let mut result = HashMap::new();
 
-    let template_path = if let Some(ref file) = resource.file {
-        Path::new(stack_dir).join("resources").join(file)
-    } else {
-        Path::new(stack_dir)
-            .join("resources")
-            .join(format!("{}.iql", resource.name))
-    };
+    let template_path = query_file_path(stack_dir, resource);
 
     if !template_path.exists() {
         error!("Query file not found: {:?}", template_path);
@@ -451,14 +726,7 @@ pub fn get_queries(
             normalized_anchor.clone(),
             ParsedQuery {
                 template: template.clone(),
-                options: QueryOptions {
-                    retries: *uint_opts.get("retries").unwrap_or(&1),
-                    retry_delay: *uint_opts.get("retry_delay").unwrap_or(&0),
-                    postdelete_retries: *uint_opts.get("postdelete_retries").unwrap_or(&10),
-                    postdelete_retry_delay: *uint_opts.get("postdelete_retry_delay").unwrap_or(&5),
-                    short_circuit_field: str_opts.get("short_circuit_field").cloned(),
-                    short_circuit_value: str_opts.get("short_circuit_value").cloned(),
-                },
+                options: query_options(&uint_opts, &str_opts),
             },
         );
     }
@@ -466,11 +734,21 @@ pub fn get_queries(
     debug!(
         "Queries for [{}]: {:?}",
         resource.name,
-        result.keys().collect::<Vec<_>>()
+        sorted_keys(&result)
     );
     result
 }
 
+/// Path of the .iql file holding a resource's queries: its `file`, or
+/// `<name>.iql`, in the stack's `resources` directory.
+pub fn query_file_path(stack_dir: &str, resource: &Resource) -> PathBuf {
+    let file = match resource.file {
+        Some(ref file) => file.clone(),
+        None => format!("{}.iql", resource.name),
+    };
+    Path::new(stack_dir).join("resources").join(file)
+}
+
 /// Pre-process `this.` prefix inside Tera template blocks.
 ///
 /// Within every `{{ ... }}` and `{% ... %}` block, replaces `this.` with
@@ -552,14 +830,9 @@ pub fn render_inline_template(
                 resource_name, e
             );
 
-            let re = Regex::new(r"\{\{\s*(\w+)").unwrap();
-            let referenced_vars: Vec<&str> = re
-                .captures_iter(&processed)
-                .filter_map(|c| c.get(1).map(|m| m.as_str()))
-                .collect();
-            let missing: Vec<&&str> = referenced_vars
-                .iter()
-                .filter(|v| !temp_context.contains_key(**v))
+            let missing: Vec<String> = referenced_variables(&processed)
+                .into_iter()
+                .filter(|v| !temp_context.contains_key(v))
                 .collect();
 
             if !missing.is_empty() {
@@ -577,7 +850,7 @@ pub fn render_inline_template(
             debug!(
                 "[{}] available context keys: {:?}",
                 resource_name,
-                temp_context.keys().collect::<Vec<_>>()
+                sorted_keys(&temp_context)
             );
 
             process::exit(1);
@@ -590,6 +863,230 @@ mod tests {
     use super::*;
     use crate::template::engine::TemplateEngine;
 
+    // ── QueryOptions ──────────────────────────────────────────────────────
+
+    #[test]
+    fn test_query_options_display() {
+        let opts = QueryOptions {
+            retries: 3,
+            retry_delay: 5,
+            postdelete_retries: 10,
+            postdelete_retry_delay: 5,
+            short_circuit_field: Some("ProgressEvent.OperationStatus".to_string()),
+            short_circuit_value: None,
+            first_row_only: true,
+            max_rows: Some(500),
+            timeout: Some(120),
+            backoff: Backoff::Exponential,
+        };
+        assert_eq!(
+            opts.to_string(),
+            "retries=3, retry_delay=5, postdelete_retries=10, postdelete_retry_delay=5, \
+             short_circuit_field=ProgressEvent.OperationStatus, first_row_only=true, max_rows=500, \
+             timeout=120, backoff=exponential"
+        );
+    }
+
+    #[test]
+    fn test_backoff_anchor_option() {
+        let (_, uint_opts, str_opts) = parse_anchor("create, retries=5, backoff=exponential");
+        let opts = query_options(&uint_opts, &str_opts);
+        assert_eq!(opts.backoff, Backoff::Exponential);
+        assert_eq!(opts.retries, 5);
+
+        // Constant delay unless set
+        let (_, uint_opts, str_opts) = parse_anchor("create, retries=5");
+        assert_eq!(
+            query_options(&uint_opts, &str_opts).backoff,
+            Backoff::Constant
+        );
+
+        assert!(invalid_anchor_options("create, backoff=linear").is_empty());
+        let invalid = invalid_anchor_options("create, backoff=fibonacci");
+        assert_eq!(invalid.len(), 1);
+        assert!(invalid[0].contains("backoff expects constant, linear or exponential"));
+    }
+
+    #[test]
+    fn test_timeout_anchor_option() {
+        let (key, uint_opts, str_opts) = parse_anchor("create, timeout=120, retries=2");
+        assert_eq!(key, "create");
+        let opts = query_options(&uint_opts, &str_opts);
+        assert_eq!(opts.timeout, Some(120));
+        assert_eq!(opts.retries, 2);
+
+        // Unset or zero means no timeout
+        let (_, uint_opts, str_opts) = parse_anchor("create");
+        assert_eq!(query_options(&uint_opts, &str_opts).timeout, None);
+        let (_, uint_opts, str_opts) = parse_anchor("create, timeout=0");
+        assert_eq!(query_options(&uint_opts, &str_opts).timeout, None);
+
+        assert!(invalid_anchor_options("create, timeout=120").is_empty());
+        let invalid = invalid_anchor_options("create, timeout=2m");
+        assert_eq!(invalid.len(), 1);
+        assert!(invalid[0].contains("timeout expects a non-negative integer"));
+    }
+
+    #[test]
+    fn test_invalid_anchor_options() {
+        assert!(invalid_anchor_options("create, retries=3, retry_delay=5").is_empty());
+        assert!(invalid_anchor_options(
+            "callback, short_circuit_field=ProgressEvent.OperationStatus, short_circuit_value=SUCCESS"
+        )
+        .is_empty());
+        assert!(invalid_anchor_options("exports, first_row_only=true").is_empty());
+        assert!(invalid_anchor_options("exports, max_rows=500").is_empty());
+
+        let invalid =
+            invalid_anchor_options("statecheck, retrys=3, retry_delay=5s, fast, first_row_only=1");
+        assert_eq!(invalid.len(), 4, "got: {:?}", invalid);
+        assert!(invalid[0].contains("unknown option 'retrys'"));
+        assert!(invalid[1].contains("retry_delay=5s"));
+        assert!(invalid[2].contains("'fast'"));
+        assert!(invalid[3].contains("expects true or false"));
+    }
+
+    // ── get_queries ───────────────────────────────────────────────────────
+
+    fn queries_from(content: &str) -> HashMap<String, ParsedQuery> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("resources")).unwrap();
+        fs::write(dir.path().join("resources").join("vpc.iql"), content).unwrap();
+        let resource: Resource = serde_yaml::from_str("name: vpc").unwrap();
+        get_queries(
+            &TemplateEngine::new(),
+            dir.path().to_str().unwrap(),
+            &resource,
+            &HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_get_queries_splits_anchors_and_options() {
+        let queries = queries_from(
+            "-- vpc queries\n\
+             /*+ exists */\n\
+             SELECT COUNT(*) as count FROM vpcs\n\
+             \n\
+             /*+ create, retries=3, retry_delay=5, timeout=60 */\n\
+             INSERT INTO vpcs\n\
+             SELECT 1\n\
+             /*+ callback:create, short_circuit_field=Status, short_circuit_value=SUCCESS */\n\
+             SELECT status FROM requests",
+        );
+        assert_eq!(
+            sorted_keys(&queries),
+            ["callback:create", "create", "exists"]
+        );
+        // Text before the first anchor leads the first query, as in the
+        // Python parser; bodies are trimmed
+        assert_eq!(
+            queries["exists"].template,
+            "-- vpc queries\nSELECT COUNT(*) as count FROM vpcs"
+        );
+        assert_eq!(queries["create"].template, "INSERT INTO vpcs\nSELECT 1");
+        assert_eq!(queries["create"].options.retries, 3);
+        assert_eq!(queries["create"].options.retry_delay, 5);
+        assert_eq!(queries["create"].options.timeout, Some(60));
+        // Unset options take their defaults
+        assert_eq!(queries["exists"].options.retries, 1);
+        assert_eq!(queries["exists"].options.postdelete_retries, 10);
+        let callback = &queries["callback:create"].options;
+        assert_eq!(callback.short_circuit_field.as_deref(), Some("Status"));
+        assert_eq!(callback.short_circuit_value.as_deref(), Some("SUCCESS"));
+    }
+
+    #[test]
+    fn test_get_queries_normalizes_legacy_anchors() {
+        let queries = queries_from(
+            "/*+ preflight, postdelete_retries=2 */\n\
+             SELECT COUNT(*) as count FROM vpcs\n\
+             /*+ postdeploy, retries=4 */\n\
+             SELECT COUNT(*) as count FROM vpcs WHERE state = 'available'\n",
+        );
+        assert_eq!(sorted_keys(&queries), ["exists", "statecheck"]);
+        assert_eq!(queries["exists"].options.postdelete_retries, 2);
+        assert_eq!(queries["statecheck"].options.retries, 4);
+    }
+
+    #[test]
+    fn test_get_queries_skips_empty_anchor_and_invalid_options() {
+        let queries = queries_from(
+            "/*+ exports */\n\
+             /*+ delete, retries=two */\n\
+             DELETE FROM vpcs\n",
+        );
+        // An anchor with no query before the next one is not stored
+        assert_eq!(sorted_keys(&queries), ["delete"]);
+        // An invalid option value falls back to the default
+        assert_eq!(queries["delete"].options.retries, 1);
+    }
+
+    #[test]
+    fn test_multi_line_anchor() {
+        let queries = queries_from(
+            "/*+ exists */\n\
+             SELECT COUNT(*) as count FROM vpcs\n\
+             /*+ create,\n\
+             \x20   retries=3, retry_delay=5\n\
+             \x20   timeout=60\n\
+             */\n\
+             INSERT INTO vpcs SELECT 1\n\
+             /*+\n\
+             \x20 statecheck, retries=2 */\n\
+             SELECT COUNT(*) as count FROM vpcs WHERE state = 'available'\n",
+        );
+        assert_eq!(sorted_keys(&queries), ["create", "exists", "statecheck"]);
+        // The anchor comment is not part of the previous query
+        assert_eq!(
+            queries["exists"].template,
+            "SELECT COUNT(*) as count FROM vpcs"
+        );
+        assert_eq!(queries["create"].template, "INSERT INTO vpcs SELECT 1");
+        assert_eq!(queries["create"].options.retries, 3);
+        assert_eq!(queries["create"].options.retry_delay, 5);
+        assert_eq!(queries["create"].options.timeout, Some(60));
+        assert_eq!(queries["statecheck"].options.retries, 2);
+    }
+
+    #[test]
+    fn test_unterminated_anchor_is_an_error() {
+        let content = "/*+ exists */\n\
+                       SELECT COUNT(*) as count FROM vpcs\n\
+                       /*+ create, retries=3\n\
+                       INSERT INTO vpcs SELECT 1\n";
+        let err = parse_sql_queries(content, Path::new("vpc.iql")).unwrap_err();
+        assert!(err.contains("line 3"), "got: {}", err);
+        assert!(err.contains("vpc.iql"), "got: {}", err);
+    }
+
+    // ── strip_sql_comments ───────────────────────────────────────────────
+
+    #[test]
+    fn test_strip_sql_comments_outside_strings() {
+        let sql = "-- create the vpc\n\
+                   INSERT INTO vpcs (region, cidr) -- columns\n\
+                   SELECT /* region */ '{{ region }}', '10.0.0.0/16'\n\
+                   /* multi\n\
+                   line */";
+        assert_eq!(
+            strip_sql_comments(sql),
+            "\nINSERT INTO vpcs (region, cidr)\nSELECT  '{{ region }}', '10.0.0.0/16'\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_sql_comments_keeps_strings_and_hints() {
+        let sql = "SELECT '-- not a comment', 'it''s /* kept */' AS \"a--b\"\n\
+                   /*+ hint */ FROM t -- gone";
+        assert_eq!(
+            strip_sql_comments(sql),
+            "SELECT '-- not a comment', 'it''s /* kept */' AS \"a--b\"\n/*+ hint */ FROM t"
+        );
+        // Arithmetic and single dashes are untouched
+        assert_eq!(strip_sql_comments("SELECT 5 - -3 / 2"), "SELECT 5 - -3 / 2");
+    }
+
     // ── preprocess_this_prefix unit tests ─────────────────────────────────
 
     #[test]
@@ -653,6 +1150,44 @@ mod tests {
 
     // ── End-to-end rendering tests via TemplateEngine ─────────────────────
 
+    #[test]
+    fn test_render_query_conditionals_and_nested_loops_over_exports() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("stack_env".to_string(), "prod".to_string());
+        context.insert("my_vpc.vpc_id".to_string(), "vpc-123".to_string());
+        context.insert(
+            "my_vpc.subnets".to_string(),
+            r#"[{"az": "a", "cidrs": ["10.0.0.0/24", "10.0.1.0/24"]}, {"az": "b", "cidrs": []}]"#
+                .to_string(),
+        );
+
+        let template = "{% if stack_env == \"prod\" %}HA{% else %}SINGLE{% endif %}\n\
+            {% for subnet in this.subnets | from_json %}\
+            {% for cidr in subnet.cidrs %}{{ this.vpc_id }}/{{ subnet.az }}={{ cidr }};{% endfor %}\
+            {% if subnet.cidrs | length == 0 %}{{ subnet.az }}:none;{% endif %}\
+            {% endfor %}";
+        let rendered = render_query(&engine, "my_vpc", "create", template, &context);
+        assert_eq!(
+            rendered,
+            "HA\nvpc-123/a=10.0.0.0/24;vpc-123/a=10.0.1.0/24;b:none;"
+        );
+
+        context.insert("stack_env".to_string(), "dev".to_string());
+        let rendered = render_inline_template(&engine, "my_vpc", template, &context);
+        assert!(rendered.starts_with("SINGLE\n"));
+    }
+
+    #[test]
+    fn test_referenced_variables_includes_block_tags() {
+        let template = "{% for s in subnets | from_json %}{{ s.id }}{% endfor %}\
+            {% if enabled and not region == \"us-east-1\" %}{{ my_vpc.vpc_id }}{% elif loop_count > 1 %}{% endif %}";
+        assert_eq!(
+            referenced_variables(template),
+            vec!["my_vpc", "subnets", "enabled", "region", "loop_count"]
+        );
+    }
+
     #[test]
     fn test_this_resolves_resource_scoped_over_global() {
         // When both a global 'fred' and a resource-scoped 'resource_name_x.fred'
@@ -6,22 +6,39 @@
 //! provider management, and script execution.
 //! Matches the Python `lib/utils.py` implementation.
 
-use std::collections::HashMap;
-use std::process;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::process::{self, Child, Output, Stdio};
+use std::str::FromStr;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use log::{debug, error, info, warn};
 
-use crate::core::errors::check_fatal_error;
+use crate::app::APP_NAME;
+use crate::core::errors::{check_abort_on, check_fatal_error};
 use crate::utils::pgwire::PgwireLite;
+use crate::utils::platform::shell_invocation;
 use crate::utils::query::{execute_query, QueryResult};
+use crate::utils::stackql::get_version;
 
 /// Exit with error message. Matches Python's `catch_error_and_exit`.
+///
+/// Only local servers this process started are stopped on the way out; one
+/// started separately (e.g. with `start-server`) is left running when a
+/// command which never starts a server, such as `lint`, fails.
 pub fn catch_error_and_exit(msg: &str) -> ! {
     error!("{}", msg);
+    // Delete what a failed `build --on-failure rollback` created
+    crate::core::rollback::roll_back();
     // Stop the local server before exiting to avoid stale sessions
-    crate::utils::server::stop_local_server();
+    crate::utils::server::stop_started_servers();
+    // `--output json` still gets the report of a run which failed part way
+    if let Some(report) = crate::core::report::failure_report(msg) {
+        println!("{}", report);
+    }
     crate::utils::display::print_unicode_box(
         "stackql-deploy operation failed",
         crate::utils::display::BorderColor::Red,
@@ -29,6 +46,34 @@ pub fn catch_error_and_exit(msg: &str) -> ! {
     process::exit(1);
 }
 
+/// Re-establish the server connection if the last query failed because the
+/// connection dropped.  Returns `Ok(true)` once the client is usable again,
+/// in which case the failed attempt should be retried rather than treated
+/// as fatal.  With `--no-reconnect` a dropped connection is returned as an
+/// error, as every remaining attempt would fail on the dead socket.
+fn reconnect_if_dropped(client: &mut PgwireLite, error: &str) -> Result<bool, String> {
+    if !client.connection_lost() {
+        return Ok(false);
+    }
+    if !client.reconnect_on_error() {
+        return Err(format!(
+            "Connection to the stackql server was lost:\n\n{}\n",
+            error
+        ));
+    }
+    warn!("connection to the stackql server was lost, reconnecting...");
+    match client.reconnect() {
+        Ok(()) => {
+            info!("reconnected to the stackql server");
+            Ok(true)
+        }
+        Err(e) => {
+            error!("failed to reconnect to the stackql server: {}", e);
+            Ok(false)
+        }
+    }
+}
+
 /// Execute a StackQL SELECT query with retry logic.
 /// Returns rows as Vec<HashMap<String, String>>.
 /// Matches Python's `run_stackql_query`.
@@ -38,9 +83,11 @@ pub fn run_stackql_query(
     suppress_errors: bool,
     retries: u32,
     delay: u32,
+    backoff: Backoff,
 ) -> Vec<HashMap<String, String>> {
     let mut attempt = 0;
     let mut last_error: Option<String> = None;
+    let mut retried_after_reconnect = false;
 
     while attempt <= retries {
         match execute_query(query, client) {
@@ -66,7 +113,7 @@ pub fn run_stackql_query(
                     if rows.is_empty() {
                         debug!("Query returned no results");
                         if attempt < retries {
-                            thread::sleep(Duration::from_secs(delay as u64));
+                            wait_before_retry(backoff, delay, attempt);
                             attempt += 1;
                             continue;
                         }
@@ -81,12 +128,7 @@ pub fn run_stackql_query(
                         .map(|row| {
                             let mut map = HashMap::new();
                             for (i, col_name) in col_names.iter().enumerate() {
-                                let value = row
-                                    .values
-                                    .get(i)
-                                    .cloned()
-                                    .unwrap_or_else(|| "NULL".to_string());
-                                map.insert(col_name.clone(), value);
+                                map.insert(col_name.clone(), row.text(i));
                             }
                             map
                         })
@@ -113,7 +155,7 @@ pub fn run_stackql_query(
                                     error!("Attempt {} failed:\n\n{}\n", attempt + 1, err);
                                 }
                             }
-                            thread::sleep(Duration::from_secs(delay as u64));
+                            wait_before_retry(backoff, delay, attempt);
                             attempt += 1;
                             continue;
                         }
@@ -153,7 +195,7 @@ pub fn run_stackql_query(
                 QueryResult::Empty => {
                     debug!("Query returned no results");
                     if attempt < retries {
-                        thread::sleep(Duration::from_secs(delay as u64));
+                        wait_before_retry(backoff, delay, attempt);
                         attempt += 1;
                         continue;
                     }
@@ -163,8 +205,14 @@ pub fn run_stackql_query(
             Err(e) => {
                 last_error = Some(e.clone());
                 debug!("Query error on attempt {}: {}", attempt + 1, e);
+                let reconnected = reconnect_if_dropped(client, &e)
+                    .unwrap_or_else(|lost| catch_error_and_exit(&lost));
+                if reconnected && !retried_after_reconnect {
+                    retried_after_reconnect = true;
+                    continue;
+                }
                 // Check for fatal errors (network, auth) that should not be retried
-                if let Some(pattern) = check_fatal_error(&e) {
+                if let Some(pattern) = check_fatal_error(&e).filter(|_| !reconnected) {
                     catch_error_and_exit(&format!(
                         "Fatal error (matched '{}'):\n\n{}\n",
                         pattern, e
@@ -179,7 +227,7 @@ pub fn run_stackql_query(
             }
         }
 
-        thread::sleep(Duration::from_secs(delay as u64));
+        wait_before_retry(backoff, delay, attempt);
         attempt += 1;
     }
 
@@ -203,8 +251,10 @@ pub fn run_stackql_command(
     ignore_errors: bool,
     retries: u32,
     retry_delay: u32,
+    backoff: Backoff,
 ) -> String {
     let mut attempt = 0;
+    let mut retried_after_reconnect = false;
 
     // Handle REGISTRY PULL command format
     let processed_command = if command.starts_with("REGISTRY PULL") {
@@ -234,13 +284,14 @@ pub fn run_stackql_command(
                     } => {
                         // Check for errors in notices
                         for notice in &notices {
+                            abort_if_abort_on(notice, &processed_command);
                             if error_detected_in_notice(notice) && !ignore_errors {
                                 if attempt < retries {
                                     debug!(
                                         "Command notice on attempt {}/{}, retrying in {} seconds: {}",
-                                        attempt + 1, retries + 1, retry_delay, notice
+                                        attempt + 1, retries + 1, backoff.delay_secs(retry_delay, attempt), notice
                                     );
-                                    thread::sleep(Duration::from_secs(retry_delay as u64));
+                                    wait_before_retry(backoff, retry_delay, attempt);
                                     attempt += 1;
                                     continue;
                                 } else {
@@ -261,11 +312,7 @@ pub fn run_stackql_command(
                                     col_names
                                         .iter()
                                         .enumerate()
-                                        .map(|(i, &name)| {
-                                            let val =
-                                                row.values.get(i).cloned().unwrap_or_default();
-                                            (name.to_string(), val)
-                                        })
+                                        .map(|(i, &name)| (name.to_string(), row.text(i)))
                                         .collect()
                                 })
                                 .collect();
@@ -290,8 +337,15 @@ pub fn run_stackql_command(
                 }
             }
             Err(e) => {
+                abort_if_abort_on(&e, &processed_command);
+                let reconnected = reconnect_if_dropped(client, &e)
+                    .unwrap_or_else(|lost| catch_error_and_exit(&lost));
+                if reconnected && !retried_after_reconnect {
+                    retried_after_reconnect = true;
+                    continue;
+                }
                 // Check for fatal errors (network, auth) before retrying
-                if let Some(pattern) = check_fatal_error(&e) {
+                if let Some(pattern) = check_fatal_error(&e).filter(|_| !reconnected) {
                     catch_error_and_exit(&format!(
                         "Fatal error (matched '{}'):\n\n{}\n",
                         pattern, e
@@ -303,10 +357,10 @@ pub fn run_stackql_command(
                             "Command returned error on attempt {}/{}, retrying in {} seconds: {}",
                             attempt + 1,
                             retries + 1,
-                            retry_delay,
+                            backoff.delay_secs(retry_delay, attempt),
                             e
                         );
-                        thread::sleep(Duration::from_secs(retry_delay as u64));
+                        wait_before_retry(backoff, retry_delay, attempt);
                         attempt += 1;
                         continue;
                     }
@@ -325,13 +379,24 @@ pub fn run_stackql_command(
     String::new()
 }
 
+/// Exit immediately if a provider message matches a manifest `abort_on`
+/// pattern, before any retry decision is made.
+fn abort_if_abort_on(msg: &str, query: &str) {
+    if let Some(pattern) = check_abort_on(msg) {
+        catch_error_and_exit(&format!(
+            "Aborting, provider message matched abort_on pattern '{}':\n\n{}\n\nlast rendered query:\n\n{}\n",
+            pattern, msg, query
+        ));
+    }
+}
+
 /// Check if a notice/message indicates an error.
 ///
 /// Patterns can appear either at the start of the notice message or inside
 /// the `DETAIL:` payload (stackql wraps provider errors as a generic "a
 /// notice level event has occurred" message with the real HTTP status in
 /// the detail), so match against the whole notice string.
-fn error_detected_in_notice(msg: &str) -> bool {
+pub(crate) fn error_detected_in_notice(msg: &str) -> bool {
     msg.contains("http response status code: 4")
         || msg.contains("http response status code: 5")
         || msg.starts_with("error:")
@@ -364,7 +429,7 @@ pub fn run_test_with_fields(
     client: &mut PgwireLite,
     delete_test: bool,
 ) -> (bool, Option<HashMap<String, String>>) {
-    let result = run_stackql_query(query, client, true, 0, 5);
+    let result = run_stackql_query(query, client, true, 0, 5, Backoff::Constant);
 
     if result.is_empty() {
         if delete_test {
@@ -467,10 +532,20 @@ pub fn perform_retries(
     query: &str,
     retries: u32,
     delay: u32,
+    backoff: Backoff,
     client: &mut PgwireLite,
     delete_test: bool,
 ) -> bool {
-    perform_retries_with_fields(resource_name, query, retries, delay, client, delete_test).0
+    perform_retries_with_fields(
+        resource_name,
+        query,
+        retries,
+        delay,
+        backoff,
+        client,
+        delete_test,
+    )
+    .0
 }
 
 /// Perform retries on a test query, capturing any non-count fields from the result.
@@ -479,6 +554,7 @@ pub fn perform_retries_with_fields(
     query: &str,
     retries: u32,
     delay: u32,
+    backoff: Backoff,
     client: &mut PgwireLite,
     delete_test: bool,
 ) -> (bool, Option<HashMap<String, String>>) {
@@ -495,10 +571,10 @@ pub fn perform_retries_with_fields(
             "attempt {}/{}: retrying in {} seconds ({} seconds elapsed).",
             attempt + 1,
             retries,
-            delay,
+            backoff.delay_secs(delay, attempt),
             elapsed
         );
-        thread::sleep(Duration::from_secs(delay as u64));
+        wait_before_retry(backoff, delay, attempt);
         attempt += 1;
     }
 
@@ -516,6 +592,133 @@ pub fn perform_retries_with_fields(
     (false, None)
 }
 
+/// Wall-clock retry budget shared across all phases of a single resource.
+///
+/// Each phase (exists, create, update, statecheck, exports, callbacks)
+/// carries its own `retries`/`retry_delay` anchor options.  Without a budget
+/// a slow resource can wait for the sum of all of them; with one, each
+/// phase's retry count is clamped to what still fits in the remaining time.
+pub struct RetryBudget {
+    resource_name: String,
+    deadline: Option<Instant>,
+}
+
+impl RetryBudget {
+    /// Start a budget of `budget_secs` seconds for a resource; `None` means unlimited.
+    pub fn new(resource_name: &str, budget_secs: Option<u64>) -> Self {
+        Self {
+            resource_name: resource_name.to_string(),
+            deadline: budget_secs.map(|secs| Instant::now() + Duration::from_secs(secs)),
+        }
+    }
+
+    /// Seconds left in the budget, or `None` when no budget is configured.
+    pub fn remaining_secs(&self) -> Option<u64> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()).as_secs())
+    }
+
+    /// Clamp a phase's configured retries, waiting `delay` seconds between
+    /// attempts with `backoff`, to the remaining budget.
+    pub fn cap(&self, retries: u32, delay: u32, backoff: Backoff) -> u32 {
+        let Some(remaining) = self.remaining_secs() else {
+            return retries;
+        };
+        let capped = cap_retries_to_budget(retries, delay, backoff, remaining);
+        if capped < retries {
+            info!(
+                "retry budget for [{}] limits this phase to {} of {} attempt(s) ({} seconds remaining).",
+                self.resource_name, capped, retries, remaining
+            );
+        }
+        capped
+    }
+}
+
+/// Number of attempts (at most `retries`) whose inter-attempt waits, growing
+/// with `backoff`, fit in `remaining_secs`.  At least one attempt is always
+/// allowed so an exhausted budget still performs a final check instead of
+/// skipping the phase.
+fn cap_retries_to_budget(retries: u32, delay: u32, backoff: Backoff, remaining_secs: u64) -> u32 {
+    if delay == 0 || retries <= 1 {
+        return retries;
+    }
+    let mut attempts = 1;
+    let mut waited = 0u64;
+    while attempts < retries {
+        waited = waited.saturating_add(backoff.delay_secs(delay, attempts - 1));
+        if waited > remaining_secs {
+            break;
+        }
+        attempts += 1;
+    }
+    attempts
+}
+
+/// Longest wait between retries with a linear or exponential backoff.
+pub const MAX_BACKOFF_DELAY_SECS: u64 = 300;
+
+/// Growth of the wait between attempts of a query (`backoff` anchor option).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backoff {
+    /// `retry_delay` before every retry.
+    #[default]
+    Constant,
+    /// `retry_delay * n` before the n-th retry.
+    Linear,
+    /// `retry_delay * 2^(n-1)` before the n-th retry.
+    Exponential,
+}
+
+impl FromStr for Backoff {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(Backoff::Constant),
+            "linear" => Ok(Backoff::Linear),
+            "exponential" => Ok(Backoff::Exponential),
+            other => Err(format!(
+                "invalid backoff '{}' (expected constant, linear or exponential)",
+                other
+            )),
+        }
+    }
+}
+
+impl Backoff {
+    /// Seconds to wait after failed attempt `attempt` (zero-based) with a
+    /// base `delay`, before jitter.  Growing delays are capped at
+    /// `MAX_BACKOFF_DELAY_SECS`.
+    pub fn delay_secs(self, delay: u32, attempt: u32) -> u64 {
+        let delay = delay as u64;
+        let factor = match self {
+            Backoff::Constant => return delay,
+            Backoff::Linear => attempt as u64 + 1,
+            Backoff::Exponential => 1u64.checked_shl(attempt).unwrap_or(u64::MAX),
+        };
+        delay.saturating_mul(factor).min(MAX_BACKOFF_DELAY_SECS)
+    }
+}
+
+/// The wait after failed attempt `attempt`.  A growing backoff adds up to
+/// 10% random jitter, so retries of rate-limited calls spread out rather
+/// than arriving together; a constant delay is waited exactly.
+fn retry_wait(backoff: Backoff, delay: u32, attempt: u32) -> Duration {
+    let secs = backoff.delay_secs(delay, attempt);
+    let jitter_ms = match backoff {
+        Backoff::Constant => 0,
+        // A fresh RandomState is keyed randomly, so its empty hash is too
+        _ => RandomState::new().build_hasher().finish() % (secs * 100 + 1),
+    };
+    Duration::from_secs(secs) + Duration::from_millis(jitter_ms)
+}
+
+/// Sleep before retrying after failed attempt `attempt` (zero-based).
+fn wait_before_retry(backoff: Backoff, delay: u32, attempt: u32) {
+    thread::sleep(retry_wait(backoff, delay, attempt));
+}
+
 /// Show a query in logs if show_queries is enabled.
 pub fn show_query(show_queries: bool, query: &str) {
     if show_queries {
@@ -523,10 +726,22 @@ pub fn show_query(show_queries: bool, query: &str) {
     }
 }
 
+/// Apply manifest `session` settings with `SET name = 'value'` statements.
+/// Values are logged at debug level only, as they may carry credentials.
+pub fn apply_session_settings(settings: &[(String, String)], client: &mut PgwireLite) {
+    for (name, value) in settings {
+        let statement = format!("SET {} = '{}'", name, value.replace('\'', "''"));
+        run_stackql_command(&statement, client, false, 0, 0, Backoff::Constant);
+        client.add_session_statement(&statement);
+        debug!("session setting [{}] set to [{}]", name, value);
+        info!("applied session setting [{}]", name);
+    }
+}
+
 /// Pull providers using the StackQL server.
 /// Matches Python's `pull_providers`.
 pub fn pull_providers(providers: &[String], client: &mut PgwireLite) {
-    let installed = run_stackql_query("SHOW PROVIDERS", client, false, 0, 5);
+    let installed = run_stackql_query("SHOW PROVIDERS", client, false, 0, 5, Backoff::Constant);
 
     for provider in providers {
         if provider.contains("::") {
@@ -558,7 +773,7 @@ pub fn pull_providers(providers: &[String], client: &mut PgwireLite) {
                 } else {
                     info!("Pulling provider '{}'...", provider);
                     let cmd = format!("REGISTRY PULL {}", provider);
-                    let msg = run_stackql_command(&cmd, client, false, 0, 5);
+                    let msg = run_stackql_command(&cmd, client, false, 0, 5, Backoff::Constant);
                     if !msg.is_empty() {
                         info!("{}", msg);
                     }
@@ -572,7 +787,7 @@ pub fn pull_providers(providers: &[String], client: &mut PgwireLite) {
             } else {
                 info!("Pulling provider '{}'...", provider);
                 let cmd = format!("REGISTRY PULL {}", provider);
-                let msg = run_stackql_command(&cmd, client, false, 0, 5);
+                let msg = run_stackql_command(&cmd, client, false, 0, 5, Backoff::Constant);
                 if !msg.is_empty() {
                     info!("{}", msg);
                 }
@@ -582,9 +797,60 @@ pub fn pull_providers(providers: &[String], client: &mut PgwireLite) {
 }
 
 /// Compare version strings. Returns true if installed > requested.
+///
+/// Versions are compared component by component (`v0.10.0` > `v0.9.0`);
+/// a version with extra components is higher (`1.2.1` > `1.2`).  A leading
+/// `v` is ignored, as is anything after the digits of a component.
 fn is_version_higher(installed: &str, requested: &str) -> bool {
-    let parse = |v: &str| -> u64 { v.replace(['v', '.'], "").parse::<u64>().unwrap_or(0) };
-    parse(installed) > parse(requested)
+    parse_version(installed) > parse_version(requested)
+}
+
+/// Whether `version` is a dotted numeric version such as `v0.5.612` or
+/// `0.6`, as accepted for a minimum stackql version.
+pub fn is_valid_version(version: &str) -> bool {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    !version.is_empty()
+        && version
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Exit with an error, pointing at `upgrade`, when the installed stackql
+/// binary is older than `minimum`.  `source` names where the minimum was set
+/// (the CLI flag or the manifest) for the message.
+pub fn require_min_stackql_version(minimum: &str, source: &str) {
+    let installed = match get_version() {
+        Ok(info) => info.version,
+        Err(e) => catch_error_and_exit(&format!(
+            "unable to check the stackql version against the minimum {} ({}): {}",
+            minimum, source, e
+        )),
+    };
+    if is_version_higher(minimum, &installed) {
+        catch_error_and_exit(&format!(
+            "stackql {} is older than the minimum version {} required by {}; run `{} upgrade` to install the latest version",
+            installed, minimum, source, APP_NAME
+        ));
+    }
+    debug!(
+        "stackql {} meets the minimum version {} ({})",
+        installed, minimum, source
+    );
+}
+
+/// Split a version string into its numeric dotted components.
+fn parse_version(version: &str) -> Vec<u64> {
+    let version = version.trim();
+    version
+        .strip_prefix(['v', 'V'])
+        .unwrap_or(version)
+        .split('.')
+        .map(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().unwrap_or(0)
+        })
+        .collect()
 }
 
 /// Update global context with exported values.
@@ -599,14 +865,20 @@ fn is_version_higher(installed: &str, requested: &str) -> bool {
 ///   This is written **once** and never overwritten, so it is immutable once
 ///   set.  Consumers that need an unambiguous reference should use this form.
 ///
+/// `protected_names` tracks which context keys hold protected values: the
+/// keys written for a `protected_exports` entry are added, and a global key
+/// overridden by an unprotected value is removed.
+///
 /// Matches Python's `export_vars`.
 pub fn export_vars(
     global_context: &mut HashMap<String, String>,
+    protected_names: &mut HashSet<String>,
     resource_name: &str,
     export_data: &HashMap<String, String>,
     protected_exports: &[String],
 ) {
-    for (key, value) in export_data {
+    for key in sorted_keys(export_data) {
+        let value = &export_data[key];
         let is_protected = protected_exports.contains(key);
         let display_value = if is_protected {
             "*".repeat(value.len())
@@ -616,6 +888,9 @@ pub fn export_vars(
 
         // --- resource-scoped key (immutable: only written if not already set) ---
         let scoped_key = format!("{}.{}", resource_name, key);
+        if is_protected && !global_context.contains_key(&scoped_key) {
+            protected_names.insert(scoped_key.clone());
+        }
         global_context.entry(scoped_key.clone()).or_insert_with(|| {
             debug!(
                 "set {} [{}] to [{}] in exports",
@@ -642,9 +917,22 @@ pub fn export_vars(
             display_value,
         );
         global_context.insert(key.clone(), value.clone());
+        if is_protected {
+            protected_names.insert(key.clone());
+        } else {
+            protected_names.remove(key);
+        }
     }
 }
 
+/// Keys of `map` in sorted order, so that anything logged or applied key by
+/// key from a `HashMap` comes out the same on every run.
+pub fn sorted_keys<V>(map: &HashMap<String, V>) -> Vec<&String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
 /// Check if exports result can serve as a statecheck proxy.
 /// Returns true if result is non-empty and has no errors.
 /// Matches Python's `check_exports_as_statecheck_proxy`.
@@ -683,59 +971,269 @@ pub fn check_all_dicts(items: &[serde_yaml::Value]) -> bool {
     items.iter().all(|item| item.is_mapping())
 }
 
-/// Run an external script and capture output.
-/// Matches Python's `run_ext_script`.
-pub fn run_ext_script(
+/// Match `text` against a glob `pattern`, where `*` matches any run of
+/// characters (including none) and `?` matches exactly one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` seen and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = backtrack {
+            // Let the last `*` absorb one more character and retry
+            pi = star_pi + 1;
+            ti = star_ti + 1;
+            backtrack = Some((star_pi, star_ti + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Run a command through `shell` (see `shell_invocation`; `sh -c` or
+/// `cmd /C` by default) with `envs` added to its environment and return its
+/// stdout, exiting with the command's stderr if it cannot be started or
+/// returns a non-zero status.
+pub fn run_shell_command(cmd: &str, shell: Option<&str>, envs: &HashMap<String, String>) -> String {
+    run_shell_output(cmd, shell, None, envs).0
+}
+
+/// `run_shell_command`, returning the error instead of exiting.
+pub fn try_run_shell_command(
     cmd: &str,
-    expected_exports: Option<&[String]>,
-) -> Option<HashMap<String, String>> {
-    debug!("Running external script: {}", cmd);
+    shell: Option<&str>,
+    envs: &HashMap<String, String>,
+) -> Result<String, String> {
+    try_shell_output(cmd, shell, None, envs).map(|(stdout, _)| stdout)
+}
 
-    let output = match std::process::Command::new("sh").arg("-c").arg(cmd).output() {
-        Ok(output) => output,
-        Err(e) => {
-            catch_error_and_exit(&format!("Script failed: {}", e));
-        }
-    };
+/// `run_shell_command`, also returning the command's stderr when it
+/// succeeds: `(stdout, stderr)`.  A command still running after `timeout`
+/// is killed and the run exits with a timeout error.
+fn run_shell_output(
+    cmd: &str,
+    shell: Option<&str>,
+    timeout: Option<Duration>,
+    envs: &HashMap<String, String>,
+) -> (String, String) {
+    try_shell_output(cmd, shell, timeout, envs).unwrap_or_else(|e| catch_error_and_exit(&e))
+}
 
+/// `run_shell_output`, returning the error instead of exiting.
+fn try_shell_output(
+    cmd: &str,
+    shell: Option<&str>,
+    timeout: Option<Duration>,
+    envs: &HashMap<String, String>,
+) -> Result<(String, String), String> {
+    let output = run_with_timeout(cmd, shell, timeout, envs)?;
+
+    // Script output can hold secrets, so it is only logged at debug level
     let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
     debug!("Script output: {}", stdout);
+    if !stderr.trim().is_empty() {
+        debug!("Script stderr: {}", stderr);
+    }
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        catch_error_and_exit(&format!(
+        return Err(format!(
             "Script failed with status {:?}: {}",
             output.status.code(),
             stderr
         ));
     }
 
-    match expected_exports {
-        Some(exports) if !exports.is_empty() => {
-            match serde_json::from_str::<HashMap<String, String>>(&stdout) {
-                Ok(exported_vars) => {
-                    for export in exports {
-                        if !exported_vars.contains_key(export) {
-                            catch_error_and_exit(&format!(
-                                "Exported variable '{}' not found in script output",
-                                export
-                            ));
-                        }
+    Ok((stdout, stderr))
+}
+
+/// Grace period between asking a timed out script to stop and killing it.
+const SCRIPT_KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// Run a command through `shell` and collect its output, as
+/// `Command::output` does, but stop it once it has run for `timeout`:
+/// it is asked to terminate (SIGTERM on Unix), killed if it is still
+/// running after `SCRIPT_KILL_GRACE`, and an error is returned.  With a
+/// timeout, the script runs in a process group of its own on Unix, so the
+/// processes it starts are stopped with it.
+fn run_with_timeout(
+    cmd: &str,
+    shell: Option<&str>,
+    timeout: Option<Duration>,
+    envs: &HashMap<String, String>,
+) -> Result<Output, String> {
+    let (program, args) = shell_invocation(shell);
+    let mut command = process::Command::new(&program);
+    command
+        .args(&args)
+        .arg(cmd)
+        .envs(envs)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    if timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Script failed to start {}: {}", program, e))?;
+
+    // Drain both pipes while waiting, so a chatty script cannot block on a
+    // full pipe
+    let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read_pipe(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = read_pipe(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let status = match timeout {
+        None => child.wait(),
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => break Ok(status),
+                    Ok(None) if Instant::now() >= deadline => {
+                        stop_child(&mut child);
+                        // The pipe readers are left behind: processes the
+                        // script started may still hold the pipes open
+                        return Err(format!(
+                            "Script timed out after {}s and was killed: {}",
+                            timeout.as_secs_f64(),
+                            cmd
+                        ));
                     }
-                    Some(exported_vars)
-                }
-                Err(_) => {
-                    catch_error_and_exit(&format!(
-                        "External scripts must return valid JSON: {}",
-                        stdout
-                    ));
+                    Ok(None) => thread::sleep(Duration::from_millis(50)),
+                    Err(e) => break Err(e),
                 }
             }
         }
+    }
+    .map_err(|e| format!("Script failed: {}", e))?;
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Stop a timed out script and the processes it started: ask its process
+/// group to terminate, then kill whatever is left of the group after
+/// `SCRIPT_KILL_GRACE`.
+#[cfg(unix)]
+fn stop_child(child: &mut Child) {
+    // The script leads its own group, whose id is its pid
+    let group = -(child.id() as libc::pid_t);
+    // SAFETY: kill only sends a signal, to the script's own process group
+    let signal_group = |signal| unsafe { libc::kill(group, signal) } == 0;
+    if signal_group(libc::SIGTERM) {
+        let deadline = Instant::now() + SCRIPT_KILL_GRACE;
+        while Instant::now() < deadline {
+            // Reap the script, so only live processes keep the group alive
+            let _ = child.try_wait();
+            if !signal_group(0) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        signal_group(libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Stop a timed out script and the processes it started, with
+/// `taskkill /T`, which kills the whole process tree.
+#[cfg(windows)]
+fn stop_child(child: &mut Child) {
+    let killed_tree = process::Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if !killed_tree {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+/// `message`, followed by the script's stderr when it wrote any.
+fn with_script_stderr(message: String, stderr: &str) -> String {
+    if stderr.trim().is_empty() {
+        message
+    } else {
+        format!("{}\nScript stderr:\n{}", message, stderr.trim_end())
+    }
+}
+
+/// Run an external script through `shell` and capture output.
+/// Matches Python's `run_ext_script`.
+pub fn run_ext_script(
+    cmd: &str,
+    shell: Option<&str>,
+    timeout: Option<Duration>,
+    expected_exports: Option<&[String]>,
+    envs: &HashMap<String, String>,
+) -> Option<HashMap<String, String>> {
+    debug!("Running external script: {}", cmd);
+
+    let (stdout, stderr) = run_shell_output(cmd, shell, timeout, envs);
+
+    match expected_exports {
+        Some(exports) if !exports.is_empty() => {
+            match parse_script_exports(&stdout, &stderr, exports) {
+                Ok(exported_vars) => Some(exported_vars),
+                Err(e) => catch_error_and_exit(&e),
+            }
+        }
         _ => None,
     }
 }
 
+/// Parse the JSON object a script wrote to stdout, checking it has every
+/// one of `exports`.  Errors include the script's stderr, which usually
+/// says why its output is not what was expected.
+fn parse_script_exports(
+    stdout: &str,
+    stderr: &str,
+    exports: &[String],
+) -> Result<HashMap<String, String>, String> {
+    let exported_vars: HashMap<String, String> = serde_json::from_str(stdout).map_err(|_| {
+        with_script_stderr(
+            format!("External scripts must return valid JSON: {}", stdout),
+            stderr,
+        )
+    })?;
+    for export in exports {
+        if !exported_vars.contains_key(export) {
+            return Err(with_script_stderr(
+                format!("Exported variable '{}' not found in script output", export),
+                stderr,
+            ));
+        }
+    }
+    Ok(exported_vars)
+}
+
 // ---------------------------------------------------------------------------
 // RETURNING * capture helpers
 // ---------------------------------------------------------------------------
@@ -777,8 +1275,10 @@ pub fn run_stackql_dml_returning(
     ignore_errors: bool,
     retries: u32,
     retry_delay: u32,
+    backoff: Backoff,
 ) -> (String, Option<HashMap<String, String>>) {
     let mut attempt = 0u32;
+    let mut retried_after_reconnect = false;
 
     while attempt <= retries {
         match execute_query(command, client) {
@@ -791,16 +1291,17 @@ pub fn run_stackql_dml_returning(
                     // Check for errors in notices before accepting the result.
                     let mut error_noticed = false;
                     for notice in &notices {
+                        abort_if_abort_on(notice, command);
                         if error_detected_in_notice(notice) && !ignore_errors {
                             if attempt < retries {
                                 debug!(
                                     "DML notice on attempt {}/{}, retrying in {} seconds: {}",
                                     attempt + 1,
                                     retries + 1,
-                                    retry_delay,
+                                    backoff.delay_secs(retry_delay, attempt),
                                     notice
                                 );
-                                thread::sleep(Duration::from_secs(retry_delay as u64));
+                                wait_before_retry(backoff, retry_delay, attempt);
                                 attempt += 1;
                                 error_noticed = true;
                                 break;
@@ -823,8 +1324,7 @@ pub fn run_stackql_dml_returning(
                         let row = &rows[0];
                         let mut map = HashMap::new();
                         for (i, col_name) in col_names.iter().enumerate() {
-                            let value = row.values.get(i).cloned().unwrap_or_default();
-                            map.insert(col_name.clone(), value);
+                            map.insert(col_name.clone(), row.text(i));
                         }
                         Some(map)
                     } else {
@@ -842,8 +1342,15 @@ pub fn run_stackql_dml_returning(
                 }
             },
             Err(e) => {
+                abort_if_abort_on(&e, command);
+                let reconnected = reconnect_if_dropped(client, &e)
+                    .unwrap_or_else(|lost| catch_error_and_exit(&lost));
+                if reconnected && !retried_after_reconnect {
+                    retried_after_reconnect = true;
+                    continue;
+                }
                 // Check for fatal errors (network, auth) before retrying
-                if let Some(pattern) = check_fatal_error(&e) {
+                if let Some(pattern) = check_fatal_error(&e).filter(|_| !reconnected) {
                     catch_error_and_exit(&format!(
                         "Fatal error (matched '{}'):\n\n{}\n",
                         pattern, e
@@ -855,10 +1362,10 @@ pub fn run_stackql_dml_returning(
                             "DML error on attempt {}/{}, retrying in {} seconds: {}",
                             attempt + 1,
                             retries + 1,
-                            retry_delay,
+                            backoff.delay_secs(retry_delay, attempt),
                             e
                         );
-                        thread::sleep(Duration::from_secs(retry_delay as u64));
+                        wait_before_retry(backoff, retry_delay, attempt);
                         attempt += 1;
                         continue;
                     }
@@ -992,6 +1499,7 @@ pub fn run_callback_poll(
     query: &str,
     retries: u32,
     retry_delay: u32,
+    backoff: Backoff,
     client: &mut PgwireLite,
 ) -> bool {
     let mut attempt = 0u32;
@@ -1004,7 +1512,7 @@ pub fn run_callback_poll(
             query
         );
 
-        let result = run_stackql_query(query, client, true, 0, 0);
+        let result = run_stackql_query(query, client, true, 0, 0, Backoff::Constant);
 
         if !result.is_empty() {
             let row = &result[0];
@@ -1040,9 +1548,9 @@ pub fn run_callback_poll(
                 resource_name,
                 attempt + 1,
                 retries + 1,
-                retry_delay
+                backoff.delay_secs(retry_delay, attempt)
             );
-            thread::sleep(Duration::from_secs(retry_delay as u64));
+            wait_before_retry(backoff, retry_delay, attempt);
         }
         attempt += 1;
     }
@@ -1053,6 +1561,150 @@ pub fn run_callback_poll(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    // ------------------------------------------------------------------
+    // is_version_higher / is_valid_version
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn test_is_valid_version() {
+        assert!(is_valid_version("v0.5.612"));
+        assert!(is_valid_version("0.6"));
+        assert!(!is_valid_version("latest"));
+        assert!(!is_valid_version("v0..1"));
+        assert!(!is_valid_version(""));
+    }
+
+    #[test]
+    fn test_is_version_higher() {
+        assert!(is_version_higher("v0.10.0", "v0.9.0"));
+        assert!(!is_version_higher("v0.9.0", "v0.10.0"));
+        assert!(!is_version_higher("v24.11.00274", "v24.11.00274"));
+        assert!(is_version_higher("1.2.1", "1.2"));
+        assert!(!is_version_higher("1.2", "1.2.1"));
+        // A minimum is met by the same or any later version
+        assert!(is_version_higher("v0.6.0", "v0.5.612"));
+        assert!(!is_version_higher("v0.5.612", "v0.5.612"));
+        // Multi-digit components no longer collide when concatenated
+        assert!(!is_version_higher("v1.10.1", "v11.0.1"));
+        assert!(is_version_higher("v24.11.00274", "24.9.00100"));
+    }
+
+    // ------------------------------------------------------------------
+    // glob_match
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("network-*", "network-vpc"));
+        assert!(glob_match("network-*", "network-"));
+        assert!(glob_match("*-subnet-?", "aws-subnet-1"));
+        assert!(glob_match("vpc", "vpc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(!glob_match("network-*", "subnet-network-vpc"));
+        assert!(!glob_match("subnet-?", "subnet-10"));
+        assert!(!glob_match("vpc", "vpc2"));
+    }
+
+    // ------------------------------------------------------------------
+    // RetryBudget
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn test_cap_retries_to_budget() {
+        let constant = Backoff::Constant;
+        // Plenty of budget: configured retries are kept
+        assert_eq!(cap_retries_to_budget(5, 10, constant, 100), 5);
+        // 25s left with a 10s delay affords 3 attempts (waits of 10s + 10s)
+        assert_eq!(cap_retries_to_budget(10, 10, constant, 25), 3);
+        // Exhausted budget still allows a single final attempt
+        assert_eq!(cap_retries_to_budget(10, 10, constant, 0), 1);
+        // No delay means no waiting to budget for
+        assert_eq!(cap_retries_to_budget(10, 0, constant, 0), 10);
+    }
+
+    #[test]
+    fn test_cap_retries_to_budget_with_growing_backoff() {
+        // Waits of 10s, 20s, 30s: 60s affords 4 attempts, 59s only 3
+        assert_eq!(cap_retries_to_budget(10, 10, Backoff::Linear, 60), 4);
+        assert_eq!(cap_retries_to_budget(10, 10, Backoff::Linear, 59), 3);
+        // Waits of 10s, 20s, 40s, 80s: 150s affords 5 attempts, 149s only 4
+        assert_eq!(cap_retries_to_budget(10, 10, Backoff::Exponential, 150), 5);
+        assert_eq!(cap_retries_to_budget(10, 10, Backoff::Exponential, 149), 4);
+        // The same budget with a constant delay affords all 10
+        assert_eq!(cap_retries_to_budget(10, 10, Backoff::Constant, 150), 10);
+        // Capped waits still fit the budget
+        assert_eq!(
+            cap_retries_to_budget(100, 200, Backoff::Exponential, 1000),
+            4
+        );
+
+        let budget = RetryBudget::new("res", Some(60));
+        assert_eq!(budget.cap(10, 10, Backoff::Exponential), 3);
+    }
+
+    // ------------------------------------------------------------------
+    // Backoff
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn test_backoff_delays() {
+        let delays = |backoff: Backoff| -> Vec<u64> {
+            (0..5)
+                .map(|attempt| backoff.delay_secs(10, attempt))
+                .collect()
+        };
+        assert_eq!(delays(Backoff::Constant), [10, 10, 10, 10, 10]);
+        assert_eq!(delays(Backoff::Linear), [10, 20, 30, 40, 50]);
+        assert_eq!(delays(Backoff::Exponential), [10, 20, 40, 80, 160]);
+    }
+
+    #[test]
+    fn test_backoff_delays_are_capped() {
+        assert_eq!(
+            Backoff::Exponential.delay_secs(10, 5),
+            MAX_BACKOFF_DELAY_SECS
+        );
+        assert_eq!(
+            Backoff::Exponential.delay_secs(10, 200),
+            MAX_BACKOFF_DELAY_SECS
+        );
+        assert_eq!(Backoff::Linear.delay_secs(100, 9), MAX_BACKOFF_DELAY_SECS);
+        // A constant delay is never capped
+        assert_eq!(Backoff::Constant.delay_secs(600, 9), 600);
+        // No delay stays no delay
+        assert_eq!(Backoff::Exponential.delay_secs(0, 3), 0);
+    }
+
+    #[test]
+    fn test_retry_wait_jitter() {
+        assert_eq!(retry_wait(Backoff::Constant, 5, 3), Duration::from_secs(5));
+        for _ in 0..20 {
+            let wait = retry_wait(Backoff::Exponential, 5, 2);
+            assert!(wait >= Duration::from_secs(20), "{:?}", wait);
+            assert!(wait <= Duration::from_secs(22), "{:?}", wait);
+        }
+        assert_eq!(retry_wait(Backoff::Linear, 0, 2), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_backoff_from_str() {
+        assert_eq!("exponential".parse::<Backoff>(), Ok(Backoff::Exponential));
+        assert_eq!("linear".parse::<Backoff>(), Ok(Backoff::Linear));
+        assert_eq!("constant".parse::<Backoff>(), Ok(Backoff::Constant));
+        assert!("fibonacci".parse::<Backoff>().is_err());
+    }
+
+    #[test]
+    fn test_retry_budget_unlimited_when_unset() {
+        let budget = RetryBudget::new("res", None);
+        assert_eq!(budget.remaining_secs(), None);
+        assert_eq!(budget.cap(20, 30, Backoff::Exponential), 20);
+    }
 
     // ------------------------------------------------------------------
     // export_vars
@@ -1064,7 +1716,13 @@ mod tests {
         let mut data: HashMap<String, String> = HashMap::new();
         data.insert("role_name".to_string(), "my-role".to_string());
 
-        export_vars(&mut ctx, "aws_cross_account_role", &data, &[]);
+        export_vars(
+            &mut ctx,
+            &mut HashSet::new(),
+            "aws_cross_account_role",
+            &data,
+            &[],
+        );
 
         // Global key
         assert_eq!(ctx.get("role_name").map(|s| s.as_str()), Some("my-role"));
@@ -1083,12 +1741,12 @@ mod tests {
         // First resource exports role_name
         let mut data1 = HashMap::new();
         data1.insert("role_name".to_string(), "first-role".to_string());
-        export_vars(&mut ctx, "resource_a", &data1, &[]);
+        export_vars(&mut ctx, &mut HashSet::new(), "resource_a", &data1, &[]);
 
         // Second resource exports role_name with a different value
         let mut data2 = HashMap::new();
         data2.insert("role_name".to_string(), "second-role".to_string());
-        export_vars(&mut ctx, "resource_b", &data2, &[]);
+        export_vars(&mut ctx, &mut HashSet::new(), "resource_b", &data2, &[]);
 
         // Global key reflects the most recent export
         assert_eq!(
@@ -1104,13 +1762,13 @@ mod tests {
         // First resource exports role_name
         let mut data1 = HashMap::new();
         data1.insert("role_name".to_string(), "original-role".to_string());
-        export_vars(&mut ctx, "resource_a", &data1, &[]);
+        export_vars(&mut ctx, &mut HashSet::new(), "resource_a", &data1, &[]);
 
         // Simulate an accidental re-export of the same resource (e.g. called
         // twice): the scoped key must not be overwritten.
         let mut data2 = HashMap::new();
         data2.insert("role_name".to_string(), "should-not-overwrite".to_string());
-        export_vars(&mut ctx, "resource_a", &data2, &[]);
+        export_vars(&mut ctx, &mut HashSet::new(), "resource_a", &data2, &[]);
 
         // Scoped key is unchanged
         assert_eq!(
@@ -1131,7 +1789,13 @@ mod tests {
         let mut data = HashMap::new();
         data.insert("secret_key".to_string(), "super-secret".to_string());
 
-        export_vars(&mut ctx, "vault", &data, &["secret_key".to_string()]);
+        export_vars(
+            &mut ctx,
+            &mut HashSet::new(),
+            "vault",
+            &data,
+            &["secret_key".to_string()],
+        );
 
         assert_eq!(
             ctx.get("secret_key").map(|s| s.as_str()),
@@ -1143,6 +1807,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_query_retried_after_dropped_connection() {
+        let dropped = AtomicBool::new(false);
+        let server = MockServer::start(move |_| {
+            // The first query drops the connection, the retry is answered
+            if dropped.swap(true, Ordering::SeqCst) {
+                Reply::rows(&["name"], &[&["vpc"]])
+            } else {
+                Reply::Close
+            }
+        });
+        let mut client = server.client();
+
+        let rows = run_stackql_query(
+            "SELECT name FROM vpcs",
+            &mut client,
+            false,
+            0,
+            0,
+            Backoff::Constant,
+        );
+
+        assert!(!client.connection_lost());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["name"], "vpc");
+        assert_eq!(server.queries().len(), 2);
+    }
+
+    #[test]
+    fn test_export_vars_tracks_protected_names() {
+        let mut ctx: HashMap<String, String> = HashMap::new();
+        let mut protected = HashSet::new();
+        let secret = HashMap::from([("token".to_string(), "s3cr3t".to_string())]);
+        export_vars(
+            &mut ctx,
+            &mut protected,
+            "vault",
+            &secret,
+            &["token".to_string()],
+        );
+        assert_eq!(
+            protected,
+            HashSet::from(["token".to_string(), "vault.token".to_string()])
+        );
+
+        // A later unprotected export of the same name replaces the global
+        // value, which is no longer protected; the scoped one still is
+        let public = HashMap::from([("token".to_string(), "public".to_string())]);
+        export_vars(&mut ctx, &mut protected, "app", &public, &[]);
+        assert_eq!(protected, HashSet::from(["vault.token".to_string()]));
+    }
+
     // ------------------------------------------------------------------
     // has_returning_clause
     // ------------------------------------------------------------------
@@ -1164,6 +1880,126 @@ mod tests {
         assert!(!has_returning_clause("INSERT INTO t(col) SELECT 'val'"));
     }
 
+    // ------------------------------------------------------------------
+    // run_ext_script / shell_invocation
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn test_run_ext_script_captures_json_exports() {
+        let script = if cfg!(windows) {
+            r#"echo {"vpc_id": "%VPC_ID%"}"#
+        } else {
+            r#"echo "{\"vpc_id\": \"$VPC_ID\"}""#
+        };
+        let envs = HashMap::from([("VPC_ID".to_string(), "vpc-123".to_string())]);
+        let exports = ["vpc_id".to_string()];
+        let vars = run_ext_script(script, None, None, Some(&exports), &envs).unwrap();
+        assert_eq!(vars["vpc_id"], "vpc-123");
+    }
+
+    #[test]
+    fn test_script_timeout_kills_script() {
+        let script = if cfg!(windows) {
+            "ping -n 6 127.0.0.1 > NUL"
+        } else {
+            "sleep 5"
+        };
+        let started = Instant::now();
+        let err = run_with_timeout(script, None, Some(Duration::from_secs(1)), &HashMap::new())
+            .unwrap_err();
+        assert!(err.starts_with("Script timed out after 1s"), "{}", err);
+        assert!(
+            started.elapsed() < Duration::from_secs(4),
+            "{:?}",
+            started.elapsed()
+        );
+
+        // A script which finishes in time is unaffected
+        let output = run_with_timeout(
+            "echo done",
+            None,
+            Some(Duration::from_secs(10)),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "done");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_timeout_kills_processes_started_by_script() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pid_file = dir.path().join("pid");
+        // The background sleep ignores SIGTERM, so only the kill stops it
+        let script = format!(
+            "trap '' TERM; sleep 30 & echo $! > {}; wait",
+            pid_file.display()
+        );
+        let err = run_with_timeout(&script, None, Some(Duration::from_secs(1)), &HashMap::new())
+            .unwrap_err();
+        assert!(err.starts_with("Script timed out after 1s"), "{}", err);
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // Gone, or a zombie left for init to reap
+        let state = process::Command::new("ps")
+            .args(["-o", "stat=", "-p", pid.trim()])
+            .output()
+            .unwrap();
+        let state = String::from_utf8_lossy(&state.stdout);
+        assert!(
+            state.trim().is_empty() || state.starts_with('Z'),
+            "sleep {} still running: {}",
+            pid.trim(),
+            state
+        );
+    }
+
+    #[test]
+    fn test_parse_script_exports_errors_include_stderr() {
+        let exports = ["vpc_id".to_string()];
+        let err =
+            parse_script_exports("not json", "aws: command not found\n", &exports).unwrap_err();
+        assert_eq!(
+            err,
+            "External scripts must return valid JSON: not json\n\
+             Script stderr:\naws: command not found"
+        );
+
+        let err = parse_script_exports(r#"{"subnet_id": "subnet-1"}"#, "warning: no vpc", &exports)
+            .unwrap_err();
+        assert!(err.starts_with("Exported variable 'vpc_id' not found in script output"));
+        assert!(err.ends_with("Script stderr:\nwarning: no vpc"));
+
+        // Without stderr the message is unchanged
+        let err = parse_script_exports("", "", &exports).unwrap_err();
+        assert_eq!(err, "External scripts must return valid JSON: ");
+    }
+
+    #[test]
+    fn test_shell_invocation() {
+        let default = shell_invocation(None);
+        if cfg!(windows) {
+            assert_eq!(default, ("cmd".to_string(), vec!["/C".to_string()]));
+        } else {
+            assert_eq!(default, ("sh".to_string(), vec!["-c".to_string()]));
+        }
+        let args = |shell: &str| shell_invocation(Some(shell)).1;
+        assert_eq!(args("bash"), ["-c"]);
+        assert_eq!(args("/usr/bin/python3"), ["-c"]);
+        assert_eq!(args("pwsh"), ["-Command"]);
+        assert_eq!(args("C:\\Windows\\System32\\cmd.exe"), ["/C"]);
+        assert_eq!(args("node"), ["-e"]);
+        // Explicit arguments are used as given
+        assert_eq!(
+            shell_invocation(Some("pwsh -NoProfile -Command")),
+            (
+                "pwsh".to_string(),
+                vec!["-NoProfile".to_string(), "-Command".to_string()]
+            )
+        );
+    }
+
     // ------------------------------------------------------------------
     // flatten_returning_row
     // ------------------------------------------------------------------
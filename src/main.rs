@@ -3,7 +3,7 @@
 //! # StackQL Deploy - Main Entry Point
 //!
 //! This is the main entry point for the StackQL Deploy application.
-//! It initializes the CLI, configures global settings, and handles user commands (e.g., `build`, `teardown`, `test`, `info`, `shell`, etc.).
+//! It initializes the CLI, configures global settings, and handles user commands (e.g., `build`, `teardown`, `test`, `list`, `info`, `shell`, etc.).
 //!
 //! ## Global Arguments
 //!
@@ -12,6 +12,15 @@
 //! - `--server`, `-h` - The server host to connect to (default: `localhost`).
 //! - `--port`, `-p` - The server port to connect to (default: `5444`).
 //! - `--log-level` - The logging level (default: `info`). Possible values: `error`, `warn`, `info`, `debug`, `trace`.
+//! - `--no-download` (alias `--offline`) - Fail instead of downloading the stackql binary when it is missing.
+//! - `--yes`, `-y` - Download a missing stackql binary without prompting.
+//! - `--fail-on-warning` - Exit non-zero at the end of the run if any warnings were logged.
+//! - `--seed` - Seed uuid generation so rendered queries are reproducible.
+//! - `--trace-http` - Log the provider HTTP requests and responses of a local server at debug level.
+//! - `--max-rows` - Fail any query returning more rows than this cap (default: `100000`).
+//! - `--connect-retries`, `--connect-retry-delay` - Attempts and initial backoff (ms) for connecting to the server.
+//! - `--no-reconnect` - Fail when the server connection drops (by default the run reconnects and retries the query).
+//! - `--tls-ca-cert`, `--tls-client-cert`, `--tls-client-key` - Connect to the server over TLS / mTLS.
 //!
 //! ## Example Usage
 //! ```bash
@@ -26,11 +35,11 @@ mod app;
 mod commands;
 pub mod core;
 mod error;
-mod globals;
 mod resource;
 mod template;
 mod utils;
 
+use std::io::{self, IsTerminal, Write};
 use std::process;
 
 use clap::{Arg, ArgAction, Command};
@@ -39,10 +48,16 @@ use error::{get_binary_path_with_error, AppError};
 use log::{debug, error, info};
 
 use crate::app::{
-    APP_AUTHOR, APP_DESCRIPTION, APP_NAME, APP_VERSION, DEFAULT_LOG_LEVEL, DEFAULT_SERVER_HOST,
-    DEFAULT_SERVER_PORT, DEFAULT_SERVER_PORT_STR, EXEMPT_COMMANDS, LOG_LEVELS,
+    APP_AUTHOR, APP_DESCRIPTION, APP_NAME, APP_VERSION, CHANGES_PENDING_EXIT_CODE,
+    DEFAULT_CONNECT_RETRIES_STR, DEFAULT_CONNECT_RETRY_DELAY_MS_STR, DEFAULT_LOG_LEVEL,
+    DEFAULT_MAX_ROWS_STR, DEFAULT_OUTPUT_FORMAT, DEFAULT_SERVER_HOST, DEFAULT_SERVER_PORT_STR,
+    EXEMPT_COMMANDS, LOG_LEVELS, OUTPUT_FORMATS,
 };
-use crate::utils::logging::initialize_logger;
+use crate::core::seed::set_seed;
+use crate::utils::connection::{RunOptions, ServerConfig};
+use crate::utils::display::{no_color_env, set_color_output, set_json_output};
+use crate::utils::download::download_binary;
+use crate::utils::logging::{initialize_logger, warning_count};
 
 /// Main function that initializes the CLI and handles command execution.
 fn main() {
@@ -83,6 +98,127 @@ fn main() {
                 .default_value(DEFAULT_LOG_LEVEL)
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("FORMAT")
+                .help("Output format; json prints a single result document to stdout")
+                .global(true)
+                .value_parser(clap::builder::PossibleValuesParser::new(OUTPUT_FORMATS))
+                .default_value(DEFAULT_OUTPUT_FORMAT)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("no-download")
+                .long("no-download")
+                .alias("offline")
+                .help("Never download the stackql binary; fail if it is missing")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .help("Disable colored output (also disabled by a non-empty NO_COLOR variable)")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fail-on-warning")
+                .long("fail-on-warning")
+                .help("Exit with an error at the end of the run if any warnings were logged")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed uuid generation so runs with the same seed render identical queries")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("trace-http")
+                .long("trace-http")
+                .help(
+                    "Log provider HTTP requests and responses (at debug level) for a local server",
+                )
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-rows")
+                .long("max-rows")
+                .value_name("ROWS")
+                .help("Fail any query that returns more than this many rows")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+                .default_value(DEFAULT_MAX_ROWS_STR)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("connect-retries")
+                .long("connect-retries")
+                .value_name("COUNT")
+                .help("Attempts to connect to the server before giving up")
+                .global(true)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value(DEFAULT_CONNECT_RETRIES_STR)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("connect-retry-delay")
+                .long("connect-retry-delay")
+                .value_name("MS")
+                .help("Delay before retrying a failed connect, doubled after each attempt")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .default_value(DEFAULT_CONNECT_RETRY_DELAY_MS_STR)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("no-reconnect")
+                .long("no-reconnect")
+                .help("Fail the run if the server connection drops, instead of reconnecting and retrying the query")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tls-ca-cert")
+                .long("tls-ca-cert")
+                .value_name("FILE")
+                .help("CA certificate (PEM) to verify the server with; enables TLS")
+                .global(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("tls-client-cert")
+                .long("tls-client-cert")
+                .value_name("FILE")
+                .help("Client certificate (PEM) for mTLS; requires --tls-client-key")
+                .requires("tls-client-key")
+                .global(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("tls-client-key")
+                .long("tls-client-key")
+                .value_name("FILE")
+                .help("Private key (PEM) of the client certificate")
+                .requires("tls-client-cert")
+                .global(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .help("Download the stackql binary without asking if it is missing")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
         .subcommand_required(true)
         .arg_required_else_help(true)
         // ====================
@@ -98,8 +234,14 @@ fn main() {
         .subcommand(commands::start_server::command())
         .subcommand(commands::stop_server::command())
         .subcommand(commands::plan::command())
+        .subcommand(commands::list::command())
+        .subcommand(commands::lint::command())
         .get_matches();
 
+    if matches.get_flag("no-color") || no_color_env() {
+        set_color_output(false);
+    }
+
     // ====================
     // Initialize Logger
     // ====================
@@ -108,31 +250,51 @@ fn main() {
 
     debug!("Logger initialized with level: {}", log_level);
 
-    // Get the server and port values from command-line arguments
-    let server_host = matches
-        .get_one::<String>("server")
-        .unwrap_or(&DEFAULT_SERVER_HOST.to_string())
-        .clone();
-
-    let server_port = *matches
-        .get_one::<u16>("port")
-        .unwrap_or(&DEFAULT_SERVER_PORT);
+    set_json_output(matches.get_one::<String>("output").map(String::as_str) == Some("json"));
 
-    debug!("Server Host: {}", server_host);
-    debug!("Server Port: {}", server_port);
+    if let Some(seed) = matches.get_one::<u64>("seed") {
+        debug!("Seeding uuid generation with {}", seed);
+        set_seed(*seed);
+    }
 
-    // Initialize the global values
-    globals::init_globals(server_host, server_port);
+    // Server selected with --server / --port, and how the run uses it,
+    // passed to the commands
+    let server = ServerConfig::from_matches(&matches);
+    let options = RunOptions::from_matches(&matches);
+    debug!("Server Host: {}", server.host);
+    debug!("Server Port: {}", server.port);
 
     // Check for binary existence except for exempt commands
     if !EXEMPT_COMMANDS.contains(&matches.subcommand_name().unwrap_or("")) {
         match get_binary_path_with_error() {
             Ok(path) => debug!("StackQL binary found at: {:?}", path),
+            Err(_e) if matches.get_flag("no-download") => {
+                error!(
+                    "StackQL binary not found and downloads are disabled (--no-download). \
+                     Run `{} upgrade` to install it, or place the stackql binary in the \
+                     current directory or on your PATH.",
+                    APP_NAME
+                );
+                process::exit(1);
+            }
             Err(_e) => {
+                if !matches.get_flag("yes") && !confirm_download() {
+                    error!(
+                        "StackQL binary not found. Run `{} upgrade` to install it, or pass --yes to download it automatically.",
+                        APP_NAME
+                    );
+                    process::exit(1);
+                }
                 info!("StackQL binary not found. Downloading the latest version...");
-                commands::upgrade::execute();
+                match download_binary(false, false) {
+                    Ok(path) => info!("StackQL binary installed at: {}", path.display()),
+                    Err(e) => {
+                        error!("Failed to download StackQL binary: {}. Please try again or check your network connection.", e);
+                        process::exit(1);
+                    }
+                }
 
-                // Re-check for binary existence after upgrade attempt
+                // Re-check for binary existence after the download
                 if let Err(AppError::BinaryNotFound) = get_binary_path_with_error() {
                     error!("Failed to download StackQL binary. Please try again or check your network connection.");
                     process::exit(1);
@@ -144,20 +306,63 @@ fn main() {
     // ====================
     // Command Execution
     // ====================
+    let mut changes_pending = false;
     match matches.subcommand() {
-        Some(("build", sub_matches)) => commands::build::execute(sub_matches),
-        Some(("test", sub_matches)) => commands::test::execute(sub_matches),
-        Some(("plan", sub_matches)) => commands::plan::execute(sub_matches),
-        Some(("teardown", sub_matches)) => commands::teardown::execute(sub_matches),
-        Some(("info", _)) => commands::info::execute(),
-        Some(("shell", sub_matches)) => commands::shell::execute(sub_matches),
-        Some(("upgrade", _)) => commands::upgrade::execute(),
+        Some(("build", sub_matches)) => {
+            changes_pending = commands::build::execute(sub_matches, &server, &options)
+        }
+        Some(("test", sub_matches)) => commands::test::execute(sub_matches, &server, &options),
+        Some(("plan", sub_matches)) => {
+            changes_pending = commands::plan::execute(sub_matches, &server, &options)
+        }
+        Some(("teardown", sub_matches)) => {
+            commands::teardown::execute(sub_matches, &server, &options)
+        }
+        Some(("info", _)) => commands::info::execute(&server),
+        Some(("shell", sub_matches)) => commands::shell::execute(sub_matches, &server, &options),
+        Some(("upgrade", sub_matches)) => commands::upgrade::execute(sub_matches),
         Some(("init", sub_matches)) => commands::init::execute(sub_matches),
-        Some(("start-server", sub_matches)) => commands::start_server::execute(sub_matches),
-        Some(("stop-server", sub_matches)) => commands::stop_server::execute(sub_matches),
+        Some(("start-server", sub_matches)) => {
+            commands::start_server::execute(sub_matches, &server)
+        }
+        Some(("stop-server", sub_matches)) => commands::stop_server::execute(sub_matches, &server),
+        Some(("list", sub_matches)) => commands::list::execute(sub_matches),
+        Some(("lint", sub_matches)) => commands::lint::execute(sub_matches),
         _ => {
             print_error!("Unknown command. Use --help for usage.");
             process::exit(1);
         }
     }
+
+    // Promote warnings to a failure in strict mode
+    let warnings = warning_count();
+    if matches.get_flag("fail-on-warning") && warnings > 0 {
+        error!(
+            "{} warning(s) were logged and --fail-on-warning is set",
+            warnings
+        );
+        process::exit(1);
+    }
+
+    // Signal pending changes for --detailed-exitcode
+    if changes_pending {
+        process::exit(CHANGES_PENDING_EXIT_CODE);
+    }
+}
+
+/// Asks whether to download the missing stackql binary.
+///
+/// Only prompts when stdin is a terminal; non-interactive runs (CI) proceed
+/// with the download as before.
+fn confirm_download() -> bool {
+    if !io::stdin().is_terminal() {
+        return true;
+    }
+    print!("StackQL binary not found. Download the latest version now? [Y/n] ");
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "" | "y" | "yes")
 }
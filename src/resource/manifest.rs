@@ -8,7 +8,7 @@
 //! The primary type is `Manifest`, which represents a parsed stackql_manifest.yml file.
 //! This module also provides types for resources, properties, and other manifest components.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::{fs, process};
 
@@ -41,6 +41,9 @@ pub enum ManifestError {
 /// Type alias for ManifestResult
 pub type ManifestResult<T> = Result<T, ManifestError>;
 
+/// File name of the manifest expected at the root of every stack directory.
+pub const MANIFEST_FILE_NAME: &str = "stackql_manifest.yml";
+
 /// Represents a stack manifest file.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Manifest {
@@ -69,6 +72,41 @@ pub struct Manifest {
     /// Stack-level exports (written to JSON output file)
     #[serde(default)]
     pub exports: Vec<String>,
+
+    /// Prepended to the name of each stack-level export in the outputs
+    /// (templatable, e.g. `"{{ branch }}_"`).
+    #[serde(default)]
+    pub export_prefix: Option<String>,
+
+    /// Appended to the name of each stack-level export in the outputs
+    /// (templatable).
+    #[serde(default)]
+    pub export_suffix: Option<String>,
+
+    /// Default `--on-failure` action per stack environment (e.g.
+    /// `dev: rollback`, `prd: error`), used when the flag is not passed.
+    #[serde(default)]
+    pub on_failure_by_env: HashMap<String, String>,
+
+    /// Provider message substrings that abort the run immediately instead
+    /// of being retried (e.g. quota exceeded).
+    #[serde(default)]
+    pub abort_on: Vec<String>,
+
+    /// Environment aliases (e.g. `staging: prd`): an environment missing from
+    /// a property's `values` falls back to the value of its alias.
+    #[serde(default)]
+    pub env_aliases: HashMap<String, String>,
+
+    /// StackQL session settings applied with `SET` after connecting
+    /// (e.g. provider caching behaviour).
+    #[serde(default)]
+    pub session: HashMap<String, serde_yaml::Value>,
+
+    /// Minimum stackql binary version the stack needs (e.g. for recent
+    /// provider features); older binaries abort the run.
+    #[serde(default)]
+    pub min_stackql_version: Option<String>,
 }
 
 /// Default version for manifest when not specified
@@ -86,6 +124,10 @@ pub struct GlobalVar {
     #[serde(default)]
     pub value: serde_yaml::Value,
 
+    /// Environment-specific values, used when `value` is not set
+    #[serde(default)]
+    pub values: Option<HashMap<String, PropertyValue>>,
+
     /// Optional description
     #[serde(default)]
     pub description: String,
@@ -113,6 +155,17 @@ pub struct Resource {
     #[serde(default)]
     pub run: Option<String>,
 
+    /// Interpreter for the `run` script of a script resource (e.g. `bash`,
+    /// `pwsh` or `python3`, optionally with its arguments); defaults to
+    /// `sh -c`, or `cmd /C` on Windows
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    /// Seconds the `run` script of a script resource may run before it is
+    /// killed and the deploy fails (no limit when unset)
+    #[serde(default)]
+    pub script_timeout: Option<u64>,
+
     /// Properties for the resource
     #[serde(default)]
     pub props: Vec<Property>,
@@ -147,9 +200,58 @@ pub struct Resource {
     ///   - `ErrorCode` (direct: capture as `this.ErrorCode`)
     #[serde(default)]
     pub return_vals: Option<HashMap<String, Vec<serde_yaml::Value>>>,
+
+    /// Total seconds this resource may spend waiting on retries across all
+    /// of its phases (exists, create/update, statecheck, exports, callbacks).
+    #[serde(default)]
+    pub retry_budget: Option<u64>,
+
+    /// Variables visible only to this resource: layered over the global
+    /// context in its templates and set in the environment of its script.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Names of resources which must be processed before this one.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Delete and recreate the resource instead of updating it when it is
+    /// not in the desired state.
+    #[serde(default)]
+    pub replace_on_change: Option<ReplaceOnChange>,
+
+    /// Name of a property resolving to a JSON array: a `multi` resource runs
+    /// its queries once per item, with the item bound as `item`.
+    #[serde(default)]
+    pub for_each: Option<String>,
+}
+
+/// `replace_on_change` setting of a resource.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ReplaceOnChange {
+    /// `true` replaces the resource whenever it is not in the desired state
+    Always(bool),
+    /// Replace only when one of these properties differs from the value the
+    /// `exists` query returns for it; other changes are updated in place
+    Properties(Vec<String>),
 }
 
 impl Resource {
+    /// `[name]`, followed by the first line of the description when there
+    /// is one, for progress logs.
+    pub fn display_name(&self) -> String {
+        match self
+            .description
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty())
+        {
+            Some(description) => format!("[{}] - {}", self.name, description),
+            None => format!("[{}]", self.name),
+        }
+    }
+
     /// Parse `return_vals` for a given operation (create, update, delete).
     /// Returns a list of (source_field, target_field) pairs.
     /// - `Identifier: identifier` -> ("Identifier", "identifier")
@@ -181,8 +283,55 @@ impl Resource {
         }
         mappings
     }
+
+    /// Resolve declared exports to (source column, exported name) pairs.
+    /// When every entry is a `{ source: target }` map, the pairs come from
+    /// the maps; otherwise only plain string entries are exported (under
+    /// their own name) and any map entries are ignored.
+    pub fn export_mappings(&self) -> Vec<(String, String)> {
+        let all_dicts = self.exports.iter().all(|e| e.is_mapping());
+        let mut mappings = Vec::new();
+        for item in &self.exports {
+            if all_dicts {
+                if let Some(map) = item.as_mapping() {
+                    for (k, v) in map {
+                        if let (Some(src), Some(tgt)) = (k.as_str(), v.as_str()) {
+                            mappings.push((src.to_string(), tgt.to_string()));
+                        }
+                    }
+                }
+            } else if let Some(name) = item.as_str().filter(|s| !s.is_empty()) {
+                mappings.push((name.to_string(), name.to_string()));
+            }
+        }
+        mappings
+    }
+
+    /// Names of the variables this resource exports to the global context.
+    pub fn export_names(&self) -> Vec<String> {
+        self.export_mappings()
+            .into_iter()
+            .map(|(_, target)| target)
+            .collect()
+    }
+
+    /// Names of the exported variables which are protected: those listed in
+    /// `protected`, and those exported from a protected source column under
+    /// another name.
+    pub fn protected_export_names(&self) -> Vec<String> {
+        let mut names = self.protected.clone();
+        for (source, target) in self.export_mappings() {
+            if self.protected.contains(&source) && !names.contains(&target) {
+                names.push(target);
+            }
+        }
+        names
+    }
 }
 
+/// Allowed values of a resource's `type`
+pub const RESOURCE_TYPES: [&str; 5] = ["resource", "query", "script", "multi", "command"];
+
 /// Default resource type value
 fn default_resource_type() -> String {
     "resource".to_string()
@@ -353,6 +502,11 @@ fn resolve_manifest_file_directives(
     // Resolve in globals
     for global in &mut manifest.globals {
         resolve_file_directives(&mut global.value, base_dir)?;
+        if let Some(ref mut values) = global.values {
+            for env_val in values.values_mut() {
+                resolve_file_directives(&mut env_val.value, base_dir)?;
+            }
+        }
     }
 
     // Resolve in resource properties
@@ -392,9 +546,39 @@ impl Manifest {
         Ok(manifest)
     }
 
+    /// Resolves a user-supplied stack path to the stack directory.
+    ///
+    /// Accepts either the stack directory itself or a direct path to its
+    /// `stackql_manifest.yml`, in which case the parent directory is used.
+    pub fn resolve_stack_dir(path: &Path) -> ManifestResult<PathBuf> {
+        if path.is_dir() {
+            return Ok(path.to_path_buf());
+        }
+
+        if path.is_file() {
+            if path.file_name().and_then(|n| n.to_str()) != Some(MANIFEST_FILE_NAME) {
+                return Err(ManifestError::InvalidField(format!(
+                    "'{}' is a file; expected a stack directory or a path to {}",
+                    path.display(),
+                    MANIFEST_FILE_NAME
+                )));
+            }
+            let parent = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            return Ok(parent);
+        }
+
+        Err(ManifestError::InvalidField(format!(
+            "stack directory '{}' does not exist",
+            path.display()
+        )))
+    }
+
     /// Loads a manifest file from the specified stack directory.
     pub fn load_from_stack_dir(stack_dir: &Path) -> ManifestResult<Self> {
-        let manifest_path = stack_dir.join("stackql_manifest.yml");
+        let manifest_path = stack_dir.join(MANIFEST_FILE_NAME);
         Self::load_from_file(&manifest_path)
     }
 
@@ -415,6 +599,30 @@ impl Manifest {
                 return Err(ManifestError::MissingField("resource.name".to_string()));
             }
 
+            if let Some(for_each) = &resource.for_each {
+                if resource.r#type != "multi" {
+                    return Err(ManifestError::InvalidField(format!(
+                        "resource '{}' sets for_each, which is only supported for multi resources",
+                        resource.name
+                    )));
+                }
+                if !resource.props.iter().any(|p| &p.name == for_each) {
+                    return Err(ManifestError::InvalidField(format!(
+                        "resource '{}' for_each names unknown property '{}'",
+                        resource.name, for_each
+                    )));
+                }
+            }
+
+            if !RESOURCE_TYPES.contains(&resource.r#type.as_str()) {
+                return Err(ManifestError::InvalidField(format!(
+                    "resource '{}' has type '{}'; expected one of: {}",
+                    resource.name,
+                    resource.r#type,
+                    RESOURCE_TYPES.join(", ")
+                )));
+            }
+
             // Validate properties
             for prop in &resource.props {
                 if prop.name.is_empty() {
@@ -431,6 +639,8 @@ impl Manifest {
             }
         }
 
+        self.check_dependencies()?;
+
         // Run the extensible validation rule-set
         if let Err(errors) = crate::resource::validation::validate_manifest(self) {
             let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
@@ -440,6 +650,79 @@ impl Manifest {
         Ok(())
     }
 
+    /// Check that every `depends_on` entry names a resource and that the
+    /// dependencies form no cycle.
+    fn check_dependencies(&self) -> ManifestResult<()> {
+        for resource in &self.resources {
+            for dep in &resource.depends_on {
+                if self.find_resource(dep).is_none() {
+                    return Err(ManifestError::InvalidField(format!(
+                        "resource '{}' depends on unknown resource '{}'",
+                        resource.name, dep
+                    )));
+                }
+            }
+        }
+
+        let ordered = self.dependency_order();
+        if ordered.len() < self.resources.len() {
+            let cyclic: Vec<&str> = self
+                .resources
+                .iter()
+                .filter(|r| !ordered.iter().any(|o| o.name == r.name))
+                .map(|r| r.name.as_str())
+                .collect();
+            return Err(ManifestError::InvalidField(format!(
+                "depends_on cycle between resources: {}",
+                cyclic.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Resources ordered so each comes after everything it `depends_on`,
+    /// otherwise keeping manifest order.  Dependencies on resources not in
+    /// the manifest (e.g. removed by a `--resources` filter) are ignored;
+    /// resources in a cycle are left out.
+    pub fn dependency_order(&self) -> Vec<Resource> {
+        let mut remaining: Vec<&Resource> = self.resources.iter().collect();
+        let mut ordered: Vec<Resource> = Vec::with_capacity(remaining.len());
+        loop {
+            let ready = remaining.iter().position(|r| {
+                r.depends_on
+                    .iter()
+                    .all(|dep| !remaining.iter().any(|other| &other.name == dep))
+            });
+            match ready {
+                Some(i) => ordered.push(remaining.remove(i).clone()),
+                None => break,
+            }
+        }
+        ordered
+    }
+
+    /// `dependency_order` grouped into waves: each resource is in the wave
+    /// after the last of its dependencies, so no resource depends on another
+    /// in its own wave.
+    pub fn dependency_waves(&self) -> Vec<Vec<Resource>> {
+        let mut wave_of: HashMap<String, usize> = HashMap::new();
+        let mut waves: Vec<Vec<Resource>> = Vec::new();
+        for resource in self.dependency_order() {
+            let wave = resource
+                .depends_on
+                .iter()
+                .filter_map(|dep| wave_of.get(dep).map(|w| w + 1))
+                .max()
+                .unwrap_or(0);
+            wave_of.insert(resource.name.clone(), wave);
+            if waves.len() <= wave {
+                waves.push(Vec::new());
+            }
+            waves[wave].push(resource);
+        }
+        waves
+    }
+
     /// Gets the resource query file path for a resource.
     pub fn get_resource_query_path(&self, stack_dir: &Path, resource: &Resource) -> PathBuf {
         let file_name = match &resource.file {
@@ -470,11 +753,48 @@ impl Manifest {
         None
     }
 
+    /// Names of `targets` and, transitively, of every resource they
+    /// `depends_on`.
+    pub fn with_dependencies(&self, targets: &[String]) -> HashSet<String> {
+        let mut selected: HashSet<String> = HashSet::new();
+        let mut pending: Vec<&str> = targets.iter().map(String::as_str).collect();
+        while let Some(name) = pending.pop() {
+            if !selected.insert(name.to_string()) {
+                continue;
+            }
+            if let Some(resource) = self.find_resource(name) {
+                pending.extend(resource.depends_on.iter().map(String::as_str));
+            }
+        }
+        selected
+    }
+
     /// Finds a resource by name.
     pub fn find_resource(&self, name: &str) -> Option<&Resource> {
         self.resources.iter().find(|r| r.name == name)
     }
 
+    /// Session settings as `(name, value)` pairs sorted by name.  Scalar
+    /// values (strings, numbers, booleans) are converted to strings; other
+    /// values are rejected by manifest validation and skipped here.
+    pub fn session_settings(&self) -> Vec<(String, String)> {
+        let mut settings: Vec<(String, String)> = self
+            .session
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = match value {
+                    serde_yaml::Value::String(s) => s.clone(),
+                    serde_yaml::Value::Number(n) => n.to_string(),
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    _ => return None,
+                };
+                Some((name.clone(), value))
+            })
+            .collect();
+        settings.sort();
+        settings
+    }
+
     /// Gets global variables as a map of name to YAML value.
     pub fn globals_as_map(&self) -> HashMap<String, serde_yaml::Value> {
         self.globals
@@ -483,6 +803,27 @@ impl Manifest {
             .collect()
     }
 
+    /// Resolves a user-supplied stack path (directory or manifest file) or exits
+    /// with an error message.
+    pub fn resolve_stack_dir_or_exit(stack_dir: &str) -> String {
+        match Self::resolve_stack_dir(Path::new(stack_dir)) {
+            Ok(dir) => {
+                let resolved = dir.to_string_lossy().to_string();
+                if resolved != stack_dir {
+                    debug!(
+                        "Stack path {} points at the manifest; using stack directory {}",
+                        stack_dir, resolved
+                    );
+                }
+                resolved
+            }
+            Err(err) => {
+                error!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
     /// Loads a manifest file from the specified stack directory or exits with an error message.
     pub fn load_from_dir_or_exit(stack_dir: &str) -> Self {
         debug!("Loading manifest file from stack directory: {}", stack_dir);
@@ -761,6 +1102,280 @@ resources:
         );
     }
 
+    #[test]
+    fn test_export_mappings_strings_and_renames() {
+        let yaml = r#"
+name: vpc
+exports:
+  - VpcId: vpc_id
+  - cidr_block: vpc_cidr_block
+"#;
+        let resource: Resource = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            resource.export_mappings(),
+            vec![
+                ("VpcId".to_string(), "vpc_id".to_string()),
+                ("cidr_block".to_string(), "vpc_cidr_block".to_string()),
+            ]
+        );
+        assert_eq!(resource.export_names(), vec!["vpc_id", "vpc_cidr_block"]);
+
+        // Mixing strings and maps exports only the strings
+        let yaml = r#"
+name: vpc
+exports:
+  - vpc_id
+  - cidr_block: vpc_cidr_block
+"#;
+        let resource: Resource = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(resource.export_names(), vec!["vpc_id"]);
+    }
+
+    #[test]
+    fn test_replace_on_change_accepts_bool_or_properties() {
+        let resource: Resource =
+            serde_yaml::from_str("name: bucket\nreplace_on_change: true\n").unwrap();
+        assert_eq!(
+            resource.replace_on_change,
+            Some(ReplaceOnChange::Always(true))
+        );
+
+        let resource: Resource =
+            serde_yaml::from_str("name: bucket\nreplace_on_change: [region, name]\n").unwrap();
+        assert_eq!(
+            resource.replace_on_change,
+            Some(ReplaceOnChange::Properties(vec![
+                "region".to_string(),
+                "name".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_protected_export_names() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: db
+    exports: [db_host, db_password]
+    protected: [db_password]
+  - name: mapped
+    exports:
+      - password: admin_password
+      - host: admin_host
+    protected: [password]
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            manifest.resources[0].protected_export_names(),
+            ["db_password"]
+        );
+        assert_eq!(
+            manifest.resources[1].protected_export_names(),
+            ["password", "admin_password"]
+        );
+    }
+
+    #[test]
+    fn test_resource_display_name() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: vpc
+  - name: subnet
+    description: |
+
+      Private subnet for the app tier
+      (one per AZ)
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(manifest.resources[0].display_name(), "[vpc]");
+        assert_eq!(
+            manifest.resources[1].display_name(),
+            "[subnet] - Private subnet for the app tier"
+        );
+    }
+
+    #[test]
+    fn test_dependency_order_keeps_manifest_order() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: zeta
+  - name: alpha
+  - name: mid
+    depends_on: [alpha]
+  - name: beta
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        for _ in 0..3 {
+            let order: Vec<String> = manifest
+                .dependency_order()
+                .into_iter()
+                .map(|r| r.name)
+                .collect();
+            assert_eq!(order, ["zeta", "alpha", "mid", "beta"]);
+        }
+    }
+
+    #[test]
+    fn test_dependency_order_and_cycles() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: subnet
+    depends_on: [vpc]
+  - name: route
+    depends_on: [subnet, gateway]
+  - name: vpc
+  - name: gateway
+    depends_on: [vpc]
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        assert!(manifest.validate().is_ok());
+        let order: Vec<String> = manifest
+            .dependency_order()
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(order, ["vpc", "subnet", "gateway", "route"]);
+        let waves: Vec<Vec<String>> = manifest
+            .dependency_waves()
+            .into_iter()
+            .map(|wave| wave.into_iter().map(|r| r.name).collect())
+            .collect();
+        assert_eq!(
+            waves,
+            [vec!["vpc"], vec!["subnet", "gateway"], vec!["route"]]
+        );
+
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: vpc
+  - name: subnet
+    depends_on: [route]
+  - name: route
+    depends_on: [subnet]
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        let err = manifest.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("cycle between resources: subnet, route"),
+            "{}",
+            err
+        );
+
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: subnet
+    depends_on: [vpc]
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        let err = manifest.validate().unwrap_err().to_string();
+        assert!(err.contains("unknown resource 'vpc'"), "{}", err);
+    }
+
+    #[test]
+    fn test_with_dependencies_is_transitive() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: vpc
+  - name: subnet
+    depends_on: [vpc]
+  - name: route
+    depends_on: [subnet]
+  - name: bucket
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        let mut selected: Vec<String> = manifest
+            .with_dependencies(&["route".to_string()])
+            .into_iter()
+            .collect();
+        selected.sort();
+        assert_eq!(selected, ["route", "subnet", "vpc"]);
+        assert_eq!(manifest.with_dependencies(&["bucket".to_string()]).len(), 1);
+    }
+
+    #[test]
+    fn test_resource_type_validated_at_load() {
+        let yaml = r#"
+name: s
+providers: [aws]
+resources:
+  - name: vpc
+  - name: lookup
+    type: query
+  - name: bucket
+    type: resources
+"#;
+        let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        let err = manifest.validate().unwrap_err();
+        assert!(matches!(err, ManifestError::InvalidField(_)));
+        assert!(
+            err.to_string()
+                .contains("resource 'bucket' has type 'resources'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_resolve_stack_dir_accepts_dir_and_manifest_file() {
+        let dir = setup_test_dir();
+        let manifest_path = dir.path().join(MANIFEST_FILE_NAME);
+        fs::write(&manifest_path, "name: s\nproviders: [aws]\n").unwrap();
+
+        assert_eq!(Manifest::resolve_stack_dir(dir.path()).unwrap(), dir.path());
+        assert_eq!(
+            Manifest::resolve_stack_dir(&manifest_path).unwrap(),
+            dir.path()
+        );
+    }
+
+    #[test]
+    fn test_resolve_stack_dir_of_nested_and_relative_manifest_paths() {
+        let dir = setup_test_dir();
+        let stack = dir.path().join("stacks").join("vpc");
+        fs::create_dir_all(&stack).unwrap();
+        fs::write(
+            stack.join(MANIFEST_FILE_NAME),
+            "name: s\nproviders: [aws]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Manifest::resolve_stack_dir(&stack.join(MANIFEST_FILE_NAME)).unwrap(),
+            stack
+        );
+        // A path with trailing separator still names the directory
+        let with_slash = PathBuf::from(format!("{}/", stack.display()));
+        assert_eq!(
+            Manifest::resolve_stack_dir(&with_slash).unwrap(),
+            with_slash
+        );
+    }
+
+    #[test]
+    fn test_resolve_stack_dir_rejects_other_files_and_missing_paths() {
+        let dir = setup_test_dir();
+        let other = dir.path().join("other.yml");
+        fs::write(&other, "name: s\n").unwrap();
+
+        let err = Manifest::resolve_stack_dir(&other).unwrap_err().to_string();
+        assert!(err.contains(MANIFEST_FILE_NAME), "got: {}", err);
+        assert!(Manifest::resolve_stack_dir(&dir.path().join("missing")).is_err());
+    }
+
     #[test]
     fn test_nested_file_directives() {
         let dir = setup_test_dir();
@@ -10,7 +10,10 @@
 //! the `RULES` array in [`validate_manifest`].
 
 use std::collections::HashMap;
+use std::str::FromStr;
 
+use crate::commands::common_args::FailureAction;
+use crate::core::utils::is_valid_version;
 use crate::resource::manifest::Manifest;
 
 /// A single validation error with a rule name and human-readable message.
@@ -36,7 +39,13 @@ pub fn validate_manifest(manifest: &Manifest) -> Result<(), Vec<ValidationError>
     // Register rules here.  Each entry is a function that accepts a &Manifest
     // and returns a Vec<ValidationError>.  Adding a new rule is as simple as
     // appending another entry to this list.
-    let rules: Vec<fn(&Manifest) -> Vec<ValidationError>> = vec![rule_unique_resource_names];
+    let rules: Vec<fn(&Manifest) -> Vec<ValidationError>> = vec![
+        rule_unique_resource_names,
+        rule_valid_on_failure_by_env,
+        rule_acyclic_env_aliases,
+        rule_valid_session_settings,
+        rule_valid_min_stackql_version,
+    ];
 
     let errors: Vec<ValidationError> = rules.iter().flat_map(|rule| rule(manifest)).collect();
 
@@ -77,6 +86,102 @@ fn rule_unique_resource_names(manifest: &Manifest) -> Vec<ValidationError> {
     errors
 }
 
+/// Values in `on_failure_by_env` must be valid `--on-failure` actions.
+fn rule_valid_on_failure_by_env(manifest: &Manifest) -> Vec<ValidationError> {
+    let mut envs: Vec<&String> = manifest.on_failure_by_env.keys().collect();
+    envs.sort();
+    envs.into_iter()
+        .filter_map(|env| {
+            let action = &manifest.on_failure_by_env[env];
+            FailureAction::from_str(action).err().map(|_| ValidationError {
+                rule: "valid_on_failure_by_env".to_string(),
+                message: format!(
+                    "on_failure_by_env value '{}' for environment '{}' must be one of: rollback, ignore, error",
+                    action, env
+                ),
+            })
+        })
+        .collect()
+}
+
+/// `env_aliases` must not alias an environment back to itself, directly or
+/// through a chain of aliases.
+fn rule_acyclic_env_aliases(manifest: &Manifest) -> Vec<ValidationError> {
+    let mut envs: Vec<&String> = manifest.env_aliases.keys().collect();
+    envs.sort();
+    envs.into_iter()
+        .filter(|env| {
+            let mut current = env.as_str();
+            for _ in 0..manifest.env_aliases.len() {
+                match manifest.env_aliases.get(current) {
+                    Some(alias) if alias == *env => return true,
+                    Some(alias) => current = alias,
+                    None => return false,
+                }
+            }
+            false
+        })
+        .map(|env| ValidationError {
+            rule: "acyclic_env_aliases".to_string(),
+            message: format!(
+                "env_aliases entry for environment '{}' resolves back to itself",
+                env
+            ),
+        })
+        .collect()
+}
+
+/// `session` settings must have plain names and scalar values, since they
+/// are applied verbatim as `SET name = 'value'` statements.
+fn rule_valid_session_settings(manifest: &Manifest) -> Vec<ValidationError> {
+    let mut names: Vec<&String> = manifest.session.keys().collect();
+    names.sort();
+    let mut errors = Vec::new();
+    for name in names {
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if !valid_name {
+            errors.push(ValidationError {
+                rule: "valid_session_settings".to_string(),
+                message: format!(
+                    "session setting name '{}' may only contain letters, digits, '_' and '.'",
+                    name
+                ),
+            });
+        }
+        if !matches!(
+            manifest.session[name],
+            serde_yaml::Value::String(_)
+                | serde_yaml::Value::Number(_)
+                | serde_yaml::Value::Bool(_)
+        ) {
+            errors.push(ValidationError {
+                rule: "valid_session_settings".to_string(),
+                message: format!("session setting '{}' must have a string value", name),
+            });
+        }
+    }
+    errors
+}
+
+/// `min_stackql_version` must be a dotted numeric version.
+fn rule_valid_min_stackql_version(manifest: &Manifest) -> Vec<ValidationError> {
+    manifest
+        .min_stackql_version
+        .iter()
+        .filter(|version| !is_valid_version(version))
+        .map(|version| ValidationError {
+            rule: "valid_min_stackql_version".to_string(),
+            message: format!(
+                "min_stackql_version '{}' must be a version such as v0.5.612",
+                version
+            ),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,6 +204,8 @@ mod tests {
                     file: None,
                     sql: None,
                     run: None,
+                    shell: None,
+                    script_timeout: None,
                     props: vec![],
                     exports: vec![],
                     protected: vec![],
@@ -107,9 +214,21 @@ mod tests {
                     skip_validation: None,
                     auth: None,
                     return_vals: None,
+                    retry_budget: None,
+                    env: HashMap::new(),
+                    depends_on: vec![],
+                    replace_on_change: None,
+                    for_each: None,
                 })
                 .collect(),
             exports: vec![],
+            on_failure_by_env: HashMap::new(),
+            abort_on: vec![],
+            env_aliases: HashMap::new(),
+            export_prefix: None,
+            export_suffix: None,
+            session: HashMap::new(),
+            min_stackql_version: None,
         }
     }
 
@@ -175,6 +294,109 @@ mod tests {
         );
     }
 
+    // --------------------------------------------------
+    // rule_valid_on_failure_by_env
+    // --------------------------------------------------
+
+    #[test]
+    fn test_on_failure_by_env_values_validated() {
+        let mut manifest = manifest_with_resources(&["vpc"]);
+        manifest
+            .on_failure_by_env
+            .insert("dev".to_string(), "rollback".to_string());
+        manifest
+            .on_failure_by_env
+            .insert("prd".to_string(), "Error".to_string());
+        assert!(validate_manifest(&manifest).is_ok());
+
+        manifest
+            .on_failure_by_env
+            .insert("sit".to_string(), "retry".to_string());
+        let errors = validate_manifest(&manifest).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, "valid_on_failure_by_env");
+        assert!(errors[0].message.contains("'retry'"));
+    }
+
+    // --------------------------------------------------
+    // rule_acyclic_env_aliases
+    // --------------------------------------------------
+
+    #[test]
+    fn test_env_aliases_must_not_cycle() {
+        let mut manifest = manifest_with_resources(&["vpc"]);
+        manifest
+            .env_aliases
+            .insert("staging".to_string(), "prd".to_string());
+        manifest
+            .env_aliases
+            .insert("uat".to_string(), "staging".to_string());
+        assert!(validate_manifest(&manifest).is_ok());
+
+        manifest
+            .env_aliases
+            .insert("prd".to_string(), "uat".to_string());
+        let errors = validate_manifest(&manifest).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors.iter().all(|e| e.rule == "acyclic_env_aliases"));
+        assert!(errors[0].message.contains("'prd'"));
+    }
+
+    // --------------------------------------------------
+    // rule_valid_min_stackql_version
+    // --------------------------------------------------
+
+    #[test]
+    fn test_min_stackql_version_validated() {
+        let mut manifest = manifest_with_resources(&["vpc"]);
+        manifest.min_stackql_version = Some("v0.5.612".to_string());
+        assert!(validate_manifest(&manifest).is_ok());
+
+        manifest.min_stackql_version = Some("latest".to_string());
+        let errors = validate_manifest(&manifest).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, "valid_min_stackql_version");
+    }
+
+    // --------------------------------------------------
+    // rule_valid_session_settings
+    // --------------------------------------------------
+
+    #[test]
+    fn test_session_settings_validated() {
+        let mut manifest = manifest_with_resources(&["vpc"]);
+        manifest.session.insert(
+            "cache_ttl".to_string(),
+            serde_yaml::Value::Number(3600.into()),
+        );
+        manifest.session.insert(
+            "auth.mode".to_string(),
+            serde_yaml::Value::String("interactive".to_string()),
+        );
+        assert!(validate_manifest(&manifest).is_ok());
+        assert_eq!(
+            manifest.session_settings(),
+            [
+                ("auth.mode".to_string(), "interactive".to_string()),
+                ("cache_ttl".to_string(), "3600".to_string()),
+            ]
+        );
+
+        manifest
+            .session
+            .insert("bad name;".to_string(), serde_yaml::Value::Bool(true));
+        manifest
+            .session
+            .insert("limits".to_string(), serde_yaml::Value::Sequence(vec![]));
+        let errors = validate_manifest(&manifest).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.rule == "valid_session_settings"));
+        assert!(errors[0].message.contains("'bad name;'"));
+        assert!(errors[1]
+            .message
+            .contains("'limits' must have a string value"));
+    }
+
     // --------------------------------------------------
     // validate_manifest integration
     // --------------------------------------------------
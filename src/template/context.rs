@@ -141,7 +141,7 @@ impl Context {
         );
 
         // Add a unique ID
-        let uuid = uuid::Uuid::new_v4().to_string();
+        let uuid = crate::core::seed::uuid_v4().to_string();
         self.add_variable("uuid".to_string(), uuid);
     }
 }
@@ -5,23 +5,90 @@
 //! Provides Jinja2-compatible template rendering using the Tera engine.
 //! Includes custom filters matching the Python stackql-deploy implementation:
 //! `from_json`, `base64_encode`, `merge_lists`, `merge_objects`,
-//! `generate_patch_document`, `sql_list`, `sql_escape`.
-
+//! `generate_patch_document`, `sql_list`, `sql_escape`, `tojson`, plus the uuid helpers
+//! `uuid_short`, `uuid_nodash` and the `uuid(version=5, ...)` function, and
+//! `slugify` for deriving resource names, and the arithmetic filters `add`,
+//! `sub`, `mul`, `div`, and `cidr_subnet` for carving subnets from a CIDR.
+//! `default` also accepts Jinja2's positional form and replaces empty values,
+//! and the `env("NAME", "fallback")` function reads the process environment.
+//! Dotted access (`{{ outputs.subnet_id }}`) indexes into variables holding
+//! JSON objects or arrays.
+
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::error::Error as StdError;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
 
 use base64::Engine as Base64Engine;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
 use serde_json::Value as JsonValue;
 use tera::{Context as TeraContext, Tera};
 
+use crate::core::seed::uuid_v4;
+
+/// Where in a template an error occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplatePosition {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number
+    pub column: usize,
+    /// The offending line of the template, trimmed
+    pub snippet: String,
+}
+
+impl TemplatePosition {
+    /// Position of `line` and `column` in `template`.
+    fn new(template: &str, line: usize, column: usize) -> Self {
+        let snippet = template
+            .lines()
+            .nth(line.saturating_sub(1))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        Self {
+            line,
+            column,
+            snippet,
+        }
+    }
+
+    /// Position of the byte `offset` in `template`.
+    fn at_offset(template: &str, offset: usize) -> Self {
+        let before = &template[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        Self::new(template, line, column)
+    }
+}
+
+impl std::fmt::Display for TemplatePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.snippet
+        )
+    }
+}
+
 /// Error types that can occur during template rendering.
 #[derive(Debug)]
 pub enum TemplateError {
-    /// Variable not found in context
-    VariableNotFound(String),
-
-    /// Syntax error in template
-    SyntaxError(String),
+    /// Variable not found in context, with where it is first referenced
+    VariableNotFound {
+        message: String,
+        position: Option<TemplatePosition>,
+    },
+
+    /// Syntax error in template, with where parsing failed
+    SyntaxError {
+        message: String,
+        position: Option<TemplatePosition>,
+    },
 
     /// Invalid template structure
     InvalidTemplate(String),
@@ -33,24 +100,84 @@ pub enum TemplateError {
 impl std::fmt::Display for TemplateError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TemplateError::VariableNotFound(var) => write!(f, "Variable not found: {}", var),
-            TemplateError::SyntaxError(msg) => write!(f, "Template syntax error: {}", msg),
-            TemplateError::InvalidTemplate(msg) => write!(f, "Invalid template: {}", msg),
-            TemplateError::RenderError(msg) => write!(f, "Render error: {}", msg),
+            TemplateError::VariableNotFound { message, .. } => {
+                write!(f, "Variable not found: {}", message)?
+            }
+            TemplateError::SyntaxError { message, .. } => {
+                write!(f, "Template syntax error: {}", message)?
+            }
+            TemplateError::InvalidTemplate(msg) => write!(f, "Invalid template: {}", msg)?,
+            TemplateError::RenderError(msg) => write!(f, "Render error: {}", msg)?,
+        }
+        match self.position() {
+            Some(position) => write!(f, " (at {})", position),
+            None => Ok(()),
         }
     }
 }
 
 impl std::error::Error for TemplateError {}
 
+impl TemplateError {
+    /// Where in the template the error occurred, when known.
+    pub fn position(&self) -> Option<&TemplatePosition> {
+        match self {
+            TemplateError::VariableNotFound { position, .. }
+            | TemplateError::SyntaxError { position, .. } => position.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Classify a Tera parse error, taking the position from its
+    /// ` --> line:column` marker.
+    fn syntax(message: String, template: &str) -> Self {
+        let marker_re = Regex::new(r"-->\s*(\d+):(\d+)").unwrap();
+        let position = marker_re.captures(&message).and_then(|caps| {
+            Some(TemplatePosition::new(
+                template,
+                caps[1].parse().ok()?,
+                caps[2].parse().ok()?,
+            ))
+        });
+        TemplateError::SyntaxError { message, position }
+    }
+
+    /// Classify a Tera render error.  Tera does not report where a missing
+    /// variable is used, so the position is that of its first reference.
+    fn render(message: String, template: &str) -> Self {
+        if !message.contains("not found in context") {
+            return TemplateError::RenderError(message);
+        }
+        let name_re = Regex::new(r"Variable `([^`]+)` not found").unwrap();
+        let position = name_re
+            .captures(&message)
+            .and_then(|caps| variable_position(template, &caps[1]));
+        TemplateError::VariableNotFound { message, position }
+    }
+}
+
+/// Position of the first reference to `name` inside a `{{ }}` or `{% %}`
+/// block of `template`.
+fn variable_position(template: &str, name: &str) -> Option<TemplatePosition> {
+    let block_re = Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap();
+    let name_re = Regex::new(&format!(r"(^|[^\w.]){}\b", regex::escape(name))).unwrap();
+    let offset = block_re.find_iter(template).find_map(|block| {
+        let caps = name_re.captures(block.as_str())?;
+        Some(block.start() + caps.get(0)?.start() + caps[1].len())
+    })?;
+    Some(TemplatePosition::at_offset(template, offset))
+}
+
 /// Type alias for template rendering results
 pub type TemplateResult<T> = Result<T, TemplateError>;
 
 /// A structure that renders templates using Tera (Jinja2-compatible).
 #[derive(Debug)]
 pub struct TemplateEngine {
-    #[allow(dead_code)]
-    tera: Tera,
+    /// Compiled templates rendered through `render_with_filters`, keyed by
+    /// template name and a hash of the template source, so re-rendering the
+    /// same template (retries, repeated resources) does not recompile it.
+    tera: Mutex<Tera>,
 }
 
 impl Default for TemplateEngine {
@@ -62,9 +189,23 @@ impl Default for TemplateEngine {
 impl TemplateEngine {
     /// Creates a new template engine with custom filters registered.
     pub fn new() -> Self {
-        let mut tera = Tera::default();
-        register_custom_filters(&mut tera);
-        Self { tera }
+        Self {
+            tera: Mutex::new(new_tera()),
+        }
+    }
+
+    /// Drops every cached compiled template.
+    pub fn clear_cache(&self) {
+        *self.tera.lock().unwrap_or_else(|e| e.into_inner()) = new_tera();
+    }
+
+    /// Number of compiled templates currently cached.
+    pub fn cached_templates(&self) -> usize {
+        self.tera
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_template_names()
+            .count()
     }
 
     /// Renders a template string using the provided context (HashMap<String, String>).
@@ -83,8 +224,8 @@ impl TemplateEngine {
         template: &str,
         context: &HashMap<String, String>,
     ) -> TemplateResult<String> {
-        let tera_context = build_tera_context(context);
-        self.render_with_tera_context(template, &tera_context)
+        let (tera_context, source) = build_tera_context(context, template);
+        render_one_off(template, &source, &tera_context)
     }
 
     /// Renders a template string using a full Tera context (supports non-string values).
@@ -93,24 +234,12 @@ impl TemplateEngine {
         template: &str,
         context: &TeraContext,
     ) -> TemplateResult<String> {
-        // Use Tera's one-off rendering
-        match Tera::one_off(template, context, false) {
-            Ok(rendered) => Ok(rendered),
-            Err(e) => {
-                let err_str = e.to_string();
-                if err_str.contains("not found in context") {
-                    // Extract the variable name from the error
-                    Err(TemplateError::VariableNotFound(err_str))
-                } else {
-                    Err(TemplateError::RenderError(err_str))
-                }
-            }
-        }
+        render_one_off(template, template, context)
     }
 
     /// Renders a template string with context and custom filters.
-    /// This method creates a fresh Tera instance with the template registered,
-    /// which allows custom filters to work.
+    /// The template is compiled once per `template_name` and source and
+    /// reused from the engine's cache on later calls.
     ///
     /// Dotted keys in `context` (e.g. `"resource.var"`) are automatically
     /// converted to nested objects so that Tera's native property-access syntax
@@ -121,29 +250,110 @@ impl TemplateEngine {
         template: &str,
         context: &HashMap<String, String>,
     ) -> TemplateResult<String> {
-        let mut tera = Tera::default();
-        register_custom_filters(&mut tera);
-
-        tera.add_raw_template(template_name, template)
-            .map_err(|e| TemplateError::SyntaxError(full_error_chain(&e)))?;
-
-        let mut tera_context = build_tera_context(context);
+        let (mut tera_context, normalized) =
+            build_tera_context(context, &normalize_positional_args(template));
+        let mut hasher = DefaultHasher::new();
+        normalized.hash(&mut hasher);
+        let cache_key = format!("{}#{:016x}", template_name, hasher.finish());
+
+        let mut tera = self.tera.lock().unwrap_or_else(|e| e.into_inner());
+        if !tera.get_template_names().any(|name| name == cache_key) {
+            tera.add_raw_template(&cache_key, &normalized)
+                .map_err(|e| {
+                    TemplateError::syntax(
+                        full_error_chain(&e)
+                            .replace(&cache_key, template_name)
+                            .replace(DECODED_JSON_PREFIX, ""),
+                        template,
+                    )
+                })?;
+        }
 
         // Add uuid global function via context
-        let uuid_val = uuid::Uuid::new_v4().to_string();
+        let uuid_val = uuid_v4().to_string();
         tera_context.insert("uuid", &uuid_val);
 
-        tera.render(template_name, &tera_context).map_err(|e| {
-            let full_msg = full_error_chain(&e);
-            if full_msg.contains("not found in context") {
-                TemplateError::VariableNotFound(full_msg)
-            } else {
-                TemplateError::RenderError(full_msg)
-            }
+        tera.render(&cache_key, &tera_context).map_err(|e| {
+            TemplateError::render(
+                full_error_chain(&e)
+                    .replace(&cache_key, template_name)
+                    .replace(DECODED_JSON_PREFIX, ""),
+                template,
+            )
         })
     }
 }
 
+/// Name under which `render_with_tera_context` compiles its template.
+const ONE_OFF_TEMPLATE: &str = "__one_off";
+
+/// Prefix of the context variables holding decoded copies of JSON string
+/// variables, see [`decode_indexed_json`].
+const DECODED_JSON_PREFIX: &str = "__json_";
+
+/// A `{{ }}` or `{% %}` tag.
+static TAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)\{[{%](.*?)[}%]\}").unwrap());
+
+/// The start of a dotted path (`outputs.subnet_id`), not itself following a
+/// dot: the character before the variable, its name and the first character
+/// after the dot.
+static PATH_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(^|[^\w.])([A-Za-z_]\w*)\.(\w)").unwrap());
+
+/// One-off rendering of `source` with the custom filters available; errors
+/// are reported against `template`, which `source` was derived from.
+fn render_one_off(template: &str, source: &str, context: &TeraContext) -> TemplateResult<String> {
+    let normalized = normalize_positional_args(source);
+    let mut tera = new_tera();
+    tera.add_raw_template(ONE_OFF_TEMPLATE, &normalized)
+        .map_err(|e| {
+            TemplateError::syntax(
+                full_error_chain(&e).replace(DECODED_JSON_PREFIX, ""),
+                template,
+            )
+        })?;
+    tera.render(ONE_OFF_TEMPLATE, context).map_err(|e| {
+        TemplateError::render(
+            full_error_chain(&e).replace(DECODED_JSON_PREFIX, ""),
+            template,
+        )
+    })
+}
+
+/// Rewrite Jinja2-style positional arguments, which Tera does not support:
+///
+/// - `default("x")` becomes Tera's named `default(value="x")`, followed by
+///   `default_if_empty(value="x")`.  Tera substitutes the default only for a
+///   variable missing from the context (and never calls a registered
+///   `default` filter), so empty values are replaced by the second filter.
+/// - `env("NAME")` and `env("NAME", "fallback")` become
+///   `env(name="NAME")` and `env(name="NAME", default="fallback")`.
+fn normalize_positional_args(template: &str) -> String {
+    let default_re =
+        Regex::new(r#"\|(\s*)default\(\s*(?:value\s*=\s*)?("[^"]*"|'[^']*'|[\w.-]+)\s*\)"#)
+            .unwrap();
+    let env_re =
+        Regex::new(r#"\benv\(\s*("[^"]*"|'[^']*')\s*(?:,\s*("[^"]*"|'[^']*'|[\w.-]+)\s*)?\)"#)
+            .unwrap();
+
+    let result = default_re.replace_all(
+        template,
+        "|${1}default(value=$2) | default_if_empty(value=$2)",
+    );
+    env_re
+        .replace_all(&result, |caps: &regex::Captures| match caps.get(2) {
+            Some(default) => format!("env(name={}, default={})", &caps[1], default.as_str()),
+            None => format!("env(name={})", &caps[1]),
+        })
+        .into_owned()
+}
+
+/// A Tera instance with the custom filters registered.
+fn new_tera() -> Tera {
+    let mut tera = Tera::default();
+    register_custom_filters(&mut tera);
+    tera
+}
+
 /// Walk the full error source chain and concatenate all messages.
 /// Tera's top-level `Display` often only shows "Failed to render 'name'" while
 /// the root cause (e.g., missing variable) is buried in `source()`.
@@ -176,20 +386,70 @@ fn full_error_chain(err: &dyn StdError) -> String {
 ///   → {{ res.callback.ProgressEvent.RequestToken }} renders as "abc"
 /// ```
 ///
-/// Non-dotted keys are inserted as top-level strings.
-fn build_tera_context(context: &HashMap<String, String>) -> TeraContext {
+/// Non-dotted keys are inserted as top-level strings.  A variable holding a
+/// JSON object or array which `template` indexes into
+/// (`{{ outputs.subnet_id }}`) is also inserted decoded, with the template
+/// returned alongside the context rewritten to index the decoded copy, see
+/// [`decode_indexed_json`].
+fn build_tera_context(context: &HashMap<String, String>, template: &str) -> (TeraContext, String) {
     let mut tera_context = TeraContext::new();
     let mut root: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
 
     for (key, value) in context {
         insert_nested_key(&mut root, key, value);
     }
+    let template = decode_indexed_json(&mut root, template);
 
     for (key, val) in &root {
         tera_context.insert(key, val);
     }
 
-    tera_context
+    (tera_context, template)
+}
+
+/// Decode the string variables `template` accesses with a dotted path inside
+/// `{{ }}` or `{% %}` tags, when they hold a JSON object or array, so the
+/// path indexes into the value.  The decoded value is added under
+/// [`DECODED_JSON_PREFIX`] and the dotted paths rewritten to use it, so the
+/// variable itself keeps its string value for `{{ outputs }}` or
+/// `outputs | from_json` in the same template.  Variables which are already
+/// objects (from dotted context keys) and strings which are not JSON are
+/// left unchanged, so the flat-key behaviour is kept.
+fn decode_indexed_json(
+    root: &mut serde_json::Map<String, serde_json::Value>,
+    template: &str,
+) -> String {
+    let mut decoded: Vec<(String, JsonValue)> = Vec::new();
+    let rewritten = TAG_RE.replace_all(template, |tag: &Captures| {
+        PATH_RE
+            .replace_all(&tag[0], |path: &Captures| {
+                let name = &path[2];
+                let is_json = decoded.iter().any(|(n, _)| n == name)
+                    || match root.get(name) {
+                        Some(JsonValue::String(value)) => {
+                            match serde_json::from_str::<JsonValue>(value) {
+                                Ok(value @ (JsonValue::Object(_) | JsonValue::Array(_))) => {
+                                    decoded.push((name.to_string(), value));
+                                    true
+                                }
+                                _ => false,
+                            }
+                        }
+                        _ => false,
+                    };
+                if is_json {
+                    format!("{}{}{}.{}", &path[1], DECODED_JSON_PREFIX, name, &path[3])
+                } else {
+                    path[0].to_string()
+                }
+            })
+            .into_owned()
+    });
+    let rewritten = rewritten.into_owned();
+    for (name, value) in decoded {
+        root.insert(format!("{}{}", DECODED_JSON_PREFIX, name), value);
+    }
+    rewritten
 }
 
 /// Recursively insert a dotted key into a JSON object tree.
@@ -221,7 +481,10 @@ fn insert_nested_key(map: &mut serde_json::Map<String, serde_json::Value>, key:
 
 /// Register all custom Jinja2 filters matching the Python implementation.
 fn register_custom_filters(tera: &mut Tera) {
+    tera.register_filter("default_if_empty", filter_default_if_empty);
     tera.register_filter("from_json", filter_from_json);
+    tera.register_filter("tojson", filter_tojson);
+    tera.register_filter("to_json", filter_tojson);
     tera.register_filter("base64_encode", filter_base64_encode);
     tera.register_filter("merge_lists", filter_merge_lists);
     tera.register_filter("merge_objects", filter_merge_objects);
@@ -229,6 +492,16 @@ fn register_custom_filters(tera: &mut Tera) {
     tera.register_filter("sql_list", filter_sql_list);
     tera.register_filter("sql_escape", filter_sql_escape);
     tera.register_filter("to_aws_tag_filters", filter_to_aws_tag_filters);
+    tera.register_filter("uuid_short", filter_uuid_short);
+    tera.register_filter("uuid_nodash", filter_uuid_nodash);
+    tera.register_filter("slugify", filter_slugify);
+    tera.register_filter("add", filter_add);
+    tera.register_filter("sub", filter_sub);
+    tera.register_filter("mul", filter_mul);
+    tera.register_filter("div", filter_div);
+    tera.register_filter("cidr_subnet", filter_cidr_subnet);
+    tera.register_function("uuid", function_uuid);
+    tera.register_function("env", function_env);
 }
 
 /// from_json filter: parse a JSON string into a Tera value
@@ -244,6 +517,65 @@ fn filter_from_json(
     Ok(tera::to_value(parsed)?)
 }
 
+/// tojson filter: serialize a value as JSON for embedding in a query.
+/// Strings holding a JSON object or array pass through unchanged (once any
+/// bare Python-style `True`/`False` inside them are lowercased),
+/// `true`/`True` and `false`/`False` become JSON booleans (as
+/// `to_sql_compatible_json`), and other strings are quoted and escaped.
+fn filter_tojson(
+    value: &tera::Value,
+    _args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let json = match value {
+        JsonValue::String(s) => match s.as_str() {
+            "true" | "True" => JsonValue::Bool(true),
+            "false" | "False" => JsonValue::Bool(false),
+            _ => {
+                let normalized = normalize_json_booleans(s);
+                return match serde_json::from_str::<JsonValue>(&normalized) {
+                    Ok(JsonValue::Object(_) | JsonValue::Array(_)) => {
+                        Ok(JsonValue::String(normalized))
+                    }
+                    _ => Ok(JsonValue::String(JsonValue::String(s.clone()).to_string())),
+                };
+            }
+        },
+        other => other.clone(),
+    };
+    serde_json::to_string(&json)
+        .map(JsonValue::String)
+        .map_err(|e| tera::Error::msg(format!("tojson: {}", e)))
+}
+
+/// `s` with bare `True`/`False` tokens outside string literals lowercased,
+/// so JSON rendered from Python-style values parses.  Everything else,
+/// including key order and whitespace, is left as it is.
+fn normalize_json_booleans(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if let Some(word) = ["True", "False"].iter().find(|w| {
+            rest.starts_with(**w)
+                && !out.ends_with(|p: char| p.is_alphanumeric() || p == '_')
+                && !rest[w.len()..].starts_with(|n: char| n.is_alphanumeric() || n == '_')
+        }) {
+            out.push_str(&word.to_lowercase());
+            rest = &rest[word.len()..];
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
 /// base64_encode filter: encode a string to base64
 fn filter_base64_encode(
     value: &tera::Value,
@@ -288,6 +620,23 @@ fn filter_merge_lists(
     Ok(tera::to_value(merged)?)
 }
 
+/// default_if_empty filter: `value` when the input is an empty string or
+/// null, as with Jinja2's `default(x, true)`.  Appended to every `default`
+/// filter by `normalize_positional_args`.
+fn filter_default_if_empty(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let default = args
+        .get("value")
+        .ok_or_else(|| tera::Error::msg("default_if_empty: missing 'value' argument"))?;
+    match value {
+        tera::Value::Null => Ok(default.clone()),
+        tera::Value::String(s) if s.is_empty() => Ok(default.clone()),
+        _ => Ok(value.clone()),
+    }
+}
+
 /// merge_objects filter: merge two objects (dicts)
 fn filter_merge_objects(
     value: &tera::Value,
@@ -433,6 +782,371 @@ fn filter_to_aws_tag_filters(
     Ok(tera::to_value(result)?)
 }
 
+/// slugify filter: lowercase a string and collapse every run of characters
+/// other than ASCII letters and digits into a single hyphen, trimming
+/// leading/trailing hyphens.  `max_len` truncates the result (without
+/// leaving a trailing hyphen).
+fn filter_slugify(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("slugify: expected a string"))?;
+    let max_len = match args.get("max_len") {
+        Some(v) => Some(
+            v.as_u64()
+                .ok_or_else(|| tera::Error::msg("slugify: 'max_len' must be a positive integer"))?
+                as usize,
+        ),
+        None => None,
+    };
+    Ok(tera::to_value(slugify(s, max_len))?)
+}
+
+fn slugify(s: &str, max_len: Option<usize>) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if let Some(max) = max_len {
+        slug.truncate(max);
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// A numeric operand: integers are kept exact, anything else is a float.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+}
+
+/// Parse a filter operand (a number or a numeric string) as a [`Number`].
+fn parse_number(filter: &str, value: &tera::Value) -> tera::Result<Number> {
+    if let Some(i) = value.as_i64() {
+        return Ok(Number::Int(i));
+    }
+    if let Some(f) = value.as_f64() {
+        return Ok(Number::Float(f));
+    }
+    let s = value.as_str().map(str::trim).unwrap_or_default();
+    if let Ok(i) = s.parse::<i64>() {
+        return Ok(Number::Int(i));
+    }
+    match s.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(Number::Float(f)),
+        _ => Err(tera::Error::msg(format!(
+            "{}: expected a number, got {}",
+            filter, value
+        ))),
+    }
+}
+
+/// Apply an arithmetic filter (`value | op(other)`) and return the result as a
+/// numeric string.  Integer operands stay integral; mixed operands use floats.
+fn arithmetic(
+    filter: &str,
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> tera::Result<tera::Value> {
+    let other = args
+        .get("other")
+        .or_else(|| args.values().next())
+        .ok_or_else(|| tera::Error::msg(format!("{}: missing 'other' argument", filter)))?;
+    let lhs = parse_number(filter, value)?;
+    let rhs = parse_number(filter, other)?;
+
+    let result = match (lhs, rhs) {
+        (Number::Int(a), Number::Int(b)) => match int_op(a, b) {
+            Some(r) => r.to_string(),
+            None => {
+                return Err(tera::Error::msg(format!(
+                    "{}: invalid operation on {} and {}",
+                    filter, a, b
+                )))
+            }
+        },
+        (a, b) => {
+            let r = float_op(a.as_f64(), b.as_f64());
+            if !r.is_finite() {
+                return Err(tera::Error::msg(format!(
+                    "{}: invalid operation on {} and {}",
+                    filter,
+                    a.as_f64(),
+                    b.as_f64()
+                )));
+            }
+            r.to_string()
+        }
+    };
+    Ok(tera::to_value(result)?)
+}
+
+/// add filter: `{{ base_port | add(other=10) }}`
+fn filter_add(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    arithmetic("add", value, args, i64::checked_add, |a, b| a + b)
+}
+
+/// sub filter: `{{ count | sub(other=1) }}`
+fn filter_sub(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    arithmetic("sub", value, args, i64::checked_sub, |a, b| a - b)
+}
+
+/// mul filter: `{{ index | mul(other=256) }}`
+fn filter_mul(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    arithmetic("mul", value, args, i64::checked_mul, |a, b| a * b)
+}
+
+/// div filter: `{{ total | div(other=2) }}`; integer division when both
+/// operands are integers and divide exactly, otherwise a float result.
+fn filter_div(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let other = args
+        .get("other")
+        .or_else(|| args.values().next())
+        .ok_or_else(|| tera::Error::msg("div: missing 'other' argument"))?;
+    let lhs = parse_number("div", value)?;
+    let rhs = parse_number("div", other)?;
+    if rhs.as_f64() == 0.0 {
+        return Err(tera::Error::msg("div: division by zero"));
+    }
+    match (lhs, rhs) {
+        (Number::Int(a), Number::Int(b)) => match a.checked_rem(b) {
+            Some(0) => arithmetic("div", value, args, i64::checked_div, |a, b| a / b),
+            Some(_) => Ok(tera::to_value((a as f64 / b as f64).to_string())?),
+            None => Err(tera::Error::msg(format!(
+                "div: invalid operation on {} and {}",
+                a, b
+            ))),
+        },
+        _ => Ok(tera::to_value((lhs.as_f64() / rhs.as_f64()).to_string())?),
+    }
+}
+
+/// cidr_subnet filter: the `index`-th subnet with prefix length `prefix_len`
+/// inside a base CIDR, modeled on terraform's `cidrsubnet` (which takes the
+/// number of additional bits rather than the final prefix length).
+///
+/// `{{ "10.0.0.0/16" | cidr_subnet(prefix_len=24, index=2) }}` -> `10.0.2.0/24`
+fn filter_cidr_subnet(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let base = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("cidr_subnet: expected a CIDR string"))?;
+    let prefix_len = args
+        .get("prefix_len")
+        .map(|v| parse_number("cidr_subnet", v))
+        .transpose()?
+        .ok_or_else(|| tera::Error::msg("cidr_subnet: missing 'prefix_len' argument"))?;
+    let index = args
+        .get("index")
+        .map(|v| parse_number("cidr_subnet", v))
+        .transpose()?
+        .ok_or_else(|| tera::Error::msg("cidr_subnet: missing 'index' argument"))?;
+    let (Number::Int(prefix_len), Number::Int(index)) = (prefix_len, index) else {
+        return Err(tera::Error::msg(
+            "cidr_subnet: 'prefix_len' and 'index' must be integers",
+        ));
+    };
+    if prefix_len < 0 || index < 0 {
+        return Err(tera::Error::msg(
+            "cidr_subnet: 'prefix_len' and 'index' must not be negative",
+        ));
+    }
+    let prefix_len = u32::try_from(prefix_len).map_err(|_| {
+        tera::Error::msg(format!(
+            "cidr_subnet: 'prefix_len' {} is too large",
+            prefix_len
+        ))
+    })?;
+    let index = u128::try_from(index)
+        .map_err(|_| tera::Error::msg(format!("cidr_subnet: 'index' {} is too large", index)))?;
+    let subnet = cidr_subnet(base, prefix_len, index).map_err(tera::Error::msg)?;
+    Ok(tera::to_value(subnet)?)
+}
+
+fn cidr_subnet(base: &str, prefix_len: u32, index: u128) -> Result<String, String> {
+    let (addr, base_len) = base
+        .trim()
+        .split_once('/')
+        .ok_or_else(|| format!("cidr_subnet: '{}' is not in CIDR notation", base))?;
+    let addr: std::net::IpAddr = addr
+        .parse()
+        .map_err(|e| format!("cidr_subnet: invalid address in '{}': {}", base, e))?;
+    let base_len: u32 = base_len
+        .parse()
+        .map_err(|_| format!("cidr_subnet: invalid prefix length in '{}'", base))?;
+
+    let (bits, addr_num) = match addr {
+        std::net::IpAddr::V4(a) => (32u32, u32::from(a) as u128),
+        std::net::IpAddr::V6(a) => (128u32, u128::from(a)),
+    };
+    if base_len > bits {
+        return Err(format!(
+            "cidr_subnet: prefix length {} in '{}' exceeds {} bits",
+            base_len, base, bits
+        ));
+    }
+    if prefix_len < base_len || prefix_len > bits {
+        return Err(format!(
+            "cidr_subnet: prefix_len {} must be between {} and {} for '{}'",
+            prefix_len, base_len, bits, base
+        ));
+    }
+
+    let new_bits = prefix_len - base_len;
+    if new_bits < 128 && index >= (1u128 << new_bits) {
+        return Err(format!(
+            "cidr_subnet: index {} out of range, '{}' has {} /{} subnets",
+            index,
+            base,
+            1u128 << new_bits,
+            prefix_len
+        ));
+    }
+
+    let host_mask = |len: u32| -> u128 {
+        if len >= bits {
+            0
+        } else {
+            (u128::MAX >> (128 - bits)) >> len
+        }
+    };
+    let network = addr_num & !host_mask(base_len) & (u128::MAX >> (128 - bits));
+    let shift = bits - prefix_len;
+    let subnet = network | if shift >= 128 { 0 } else { index << shift };
+
+    let subnet_addr = match addr {
+        std::net::IpAddr::V4(_) => std::net::Ipv4Addr::from(subnet as u32).to_string(),
+        std::net::IpAddr::V6(_) => std::net::Ipv6Addr::from(subnet).to_string(),
+    };
+    Ok(format!("{}/{}", subnet_addr, prefix_len))
+}
+
+/// uuid_nodash filter: strip hyphens from a uuid string
+/// (`{{ uuid | uuid_nodash }}` -> 32 hex characters).
+fn filter_uuid_nodash(
+    value: &tera::Value,
+    _args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("uuid_nodash: expected a string"))?;
+    Ok(tera::to_value(s.replace('-', ""))?)
+}
+
+/// uuid_short filter: first `len` (default 8) hyphenless characters of a
+/// uuid string, for names with tight length constraints.
+fn filter_uuid_short(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("uuid_short: expected a string"))?;
+    let len = match args.get("len") {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| tera::Error::msg("uuid_short: 'len' must be a positive integer"))?
+            as usize,
+        None => 8,
+    };
+    let short: String = s.chars().filter(|c| *c != '-').take(len).collect();
+    Ok(tera::to_value(short)?)
+}
+
+/// uuid function: `uuid()` returns a random (or `--seed`ed) v4 uuid; `uuid(version=5,
+/// namespace="dns", name="my-bucket")` returns a deterministic v5 uuid.
+/// `namespace` is one of `dns`, `url`, `oid`, `x500` or a uuid string.
+fn function_uuid(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let version = args.get("version").and_then(|v| v.as_u64()).unwrap_or(4);
+    match version {
+        4 => Ok(tera::to_value(uuid_v4().to_string())?),
+        5 => {
+            let namespace = args
+                .get("namespace")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| tera::Error::msg("uuid: version=5 requires a 'namespace'"))?;
+            let name = args
+                .get("name")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| tera::Error::msg("uuid: version=5 requires a 'name'"))?;
+            let ns = match namespace.to_lowercase().as_str() {
+                "dns" => uuid::Uuid::NAMESPACE_DNS,
+                "url" => uuid::Uuid::NAMESPACE_URL,
+                "oid" => uuid::Uuid::NAMESPACE_OID,
+                "x500" => uuid::Uuid::NAMESPACE_X500,
+                other => uuid::Uuid::parse_str(other).map_err(|e| {
+                    tera::Error::msg(format!("uuid: invalid namespace '{}': {}", namespace, e))
+                })?,
+            };
+            Ok(tera::to_value(
+                uuid::Uuid::new_v5(&ns, name.as_bytes()).to_string(),
+            )?)
+        }
+        other => Err(tera::Error::msg(format!(
+            "uuid: unsupported version {} (expected 4 or 5)",
+            other
+        ))),
+    }
+}
+
+/// env function: `env(name="HOME")` returns the process environment variable,
+/// or `default` when it is unset.  An unset variable without a default fails
+/// the render.
+fn function_env(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    env_value(args, |name| std::env::var(name).ok())
+}
+
+/// The value of the `env` function, reading variables with `env_var`.
+fn env_value(
+    args: &HashMap<String, tera::Value>,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> tera::Result<tera::Value> {
+    let name = args
+        .get("name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| tera::Error::msg("env: missing 'name' argument"))?;
+    match env_var(name) {
+        Some(value) => Ok(tera::to_value(value)?),
+        None => args.get("default").cloned().ok_or_else(|| {
+            tera::Error::msg(format!(
+                "env: environment variable '{}' is not set and no default was given",
+                name
+            ))
+        }),
+    }
+}
+
 /// Unit tests for template engine functionality.
 #[cfg(test)]
 mod tests {
@@ -480,6 +1194,35 @@ mod tests {
         assert_eq!(result, r#"JSON: {"key": "value"}"#);
     }
 
+    #[test]
+    fn test_render_with_filters_caches_compiled_templates() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), "vpc".to_string());
+
+        for _ in 0..3 {
+            let result = engine
+                .render_with_filters("res__create", "CREATE {{ name }}", &context)
+                .unwrap();
+            assert_eq!(result, "CREATE vpc");
+        }
+        assert_eq!(engine.cached_templates(), 1);
+
+        // Same name with different source is compiled separately
+        let result = engine
+            .render_with_filters("res__create", "CREATE {{ name | upper }}", &context)
+            .unwrap();
+        assert_eq!(result, "CREATE VPC");
+        assert_eq!(engine.cached_templates(), 2);
+
+        engine.clear_cache();
+        assert_eq!(engine.cached_templates(), 0);
+        let result = engine
+            .render_with_filters("res__create", "CREATE {{ name }}", &context)
+            .unwrap();
+        assert_eq!(result, "CREATE vpc");
+    }
+
     #[test]
     fn test_render_with_filters_missing_var_shows_name() {
         let engine = TemplateEngine::new();
@@ -502,7 +1245,7 @@ mod tests {
 
         let result = engine.render_with_filters("test_tpl", "{{ no_such_var }}", &context);
         match result {
-            Err(TemplateError::VariableNotFound(msg)) => {
+            Err(TemplateError::VariableNotFound { message: msg, .. }) => {
                 assert!(
                     msg.contains("no_such_var"),
                     "VariableNotFound error should contain variable name, got: {}",
@@ -612,6 +1355,248 @@ mod tests {
         assert_eq!(result, "req-abc");
     }
 
+    #[test]
+    fn test_dotted_access_into_json_object_value() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert(
+            "outputs".to_string(),
+            r#"{"subnet_id": "subnet-123", "network": {"vpc_id": "vpc-456"}, "azs": ["a", "b"]}"#
+                .to_string(),
+        );
+
+        let result = engine
+            .render_with_filters("t1", "{{ outputs.subnet_id }}", &context)
+            .unwrap();
+        assert_eq!(result, "subnet-123");
+
+        let result = engine
+            .render_with_filters("t2", "{{ outputs.network.vpc_id }}", &context)
+            .unwrap();
+        assert_eq!(result, "vpc-456");
+
+        let result = engine.render("{{ outputs.azs.1 }}", &context).unwrap();
+        assert_eq!(result, "b");
+
+        // Without dotted access the variable is still the JSON string
+        let result = engine
+            .render_with_filters("t3", "{{ outputs | from_json | json_encode }}", &context)
+            .unwrap();
+        assert!(result.contains("subnet-123"));
+    }
+
+    #[test]
+    fn test_dotted_access_keeps_json_string_value() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        let outputs = r#"{"subnet_id": "subnet-123", "tags": {"env": "dev"}}"#;
+        context.insert("outputs".to_string(), outputs.to_string());
+
+        // The same template both indexes into the value and uses it whole
+        let template = "{{ outputs.subnet_id }}|{{ outputs }}|\
+                        {{ outputs | from_json | length }}|{{ outputs.tags.env }}";
+        let expected = format!("subnet-123|{}|2|dev", outputs);
+        assert_eq!(
+            engine.render_with_filters("t", template, &context).unwrap(),
+            expected
+        );
+        assert_eq!(engine.render(template, &context).unwrap(), expected);
+
+        // A missing path is reported under the variable's own name
+        let err = engine
+            .render_with_filters("t", "{{ outputs.missing }}", &context)
+            .unwrap_err();
+        assert!(err.to_string().contains("outputs.missing"), "{}", err);
+        assert!(!err.to_string().contains(DECODED_JSON_PREFIX), "{}", err);
+    }
+
+    #[test]
+    fn test_dotted_access_prefers_flat_keys() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("vpc.id".to_string(), "vpc-flat".to_string());
+        context.insert("name".to_string(), "not json".to_string());
+
+        let result = engine
+            .render_with_filters("t", "{{ vpc.id }}-{{ name }}", &context)
+            .unwrap();
+        assert_eq!(result, "vpc-flat-not json");
+    }
+
+    #[test]
+    fn test_tojson_filter() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), r#"my "quoted" vpc"#.to_string());
+        context.insert("zones".to_string(), r#"["a", "b"]"#.to_string());
+        context.insert(
+            "tags".to_string(),
+            r#"{"Name": "vpc", "Env": "dev"}"#.to_string(),
+        );
+        context.insert("enabled".to_string(), "True".to_string());
+
+        let render = |template: &str| engine.render_with_filters("t", template, &context).unwrap();
+
+        assert_eq!(render("{{ name | tojson }}"), r#""my \"quoted\" vpc""#);
+        // JSON input is emitted unchanged, keeping its key order
+        assert_eq!(render("{{ zones | tojson }}"), r#"["a", "b"]"#);
+        assert_eq!(
+            render("{{ tags | to_json }}"),
+            r#"{"Name": "vpc", "Env": "dev"}"#
+        );
+        assert_eq!(render("{{ enabled | tojson }}"), "true");
+        assert_eq!(render("{{ 'plain' | tojson }}"), r#""plain""#);
+    }
+
+    #[test]
+    fn test_tojson_normalizes_nested_booleans() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert(
+            "settings".to_string(),
+            r#"{"Enabled": True, "Note": "True story", "Flags": [False, true]}"#.to_string(),
+        );
+        let rendered = engine
+            .render_with_filters("t", "{{ settings | tojson }}", &context)
+            .unwrap();
+        assert_eq!(
+            rendered,
+            r#"{"Enabled": true, "Note": "True story", "Flags": [false, true]}"#
+        );
+    }
+
+    #[test]
+    fn test_uuid_nodash_and_short_filters() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert(
+            "id".to_string(),
+            "6ba7b810-9dad-11d1-80b4-00c04fd430c8".to_string(),
+        );
+
+        let nodash = engine
+            .render_with_filters("t", "{{ id | uuid_nodash }}", &context)
+            .unwrap();
+        assert_eq!(nodash, "6ba7b8109dad11d180b400c04fd430c8");
+
+        let short = engine
+            .render_with_filters("t2", "{{ id | uuid_short }}", &context)
+            .unwrap();
+        assert_eq!(short, "6ba7b810");
+
+        let shorter = engine
+            .render_with_filters("t3", "{{ id | uuid_short(len=4) }}", &context)
+            .unwrap();
+        assert_eq!(shorter, "6ba7");
+    }
+
+    #[test]
+    fn test_slugify_filter() {
+        assert_eq!(
+            slugify("Feature/My Branch_Name!", None),
+            "feature-my-branch-name"
+        );
+        assert_eq!(slugify("--Already-Slugged--", None), "already-slugged");
+        assert_eq!(slugify("abc def ghi", Some(8)), "abc-def");
+
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("branch".to_string(), "Dev Team's Stack".to_string());
+        let result = engine
+            .render_with_filters("t", "{{ branch | slugify(max_len=8) }}", &context)
+            .unwrap();
+        assert_eq!(result, "dev-team");
+    }
+
+    #[test]
+    fn test_arithmetic_filters() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("base_port".to_string(), "8080".to_string());
+        context.insert("ratio".to_string(), "1.5".to_string());
+
+        let render = |tpl: &str| engine.render_with_filters("t", tpl, &context);
+        assert_eq!(render("{{ base_port | add(other=10) }}").unwrap(), "8090");
+        assert_eq!(render("{{ base_port | sub(other=80) }}").unwrap(), "8000");
+        assert_eq!(render("{{ 3 | mul(other=256) }}").unwrap(), "768");
+        assert_eq!(render("{{ ratio | mul(other=2) }}").unwrap(), "3");
+        assert_eq!(render("{{ 10 | div(other=2) }}").unwrap(), "5");
+        assert_eq!(render("{{ 7 | div(other=2) }}").unwrap(), "3.5");
+
+        let err = render("{{ 1 | div(other=0) }}").unwrap_err().to_string();
+        assert!(err.contains("division by zero"), "got: {}", err);
+        let mut context = context.clone();
+        context.insert("min".to_string(), i64::MIN.to_string());
+        let err = engine
+            .render_with_filters("t", "{{ min | div(other=-1) }}", &context)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("div: invalid operation"), "got: {}", err);
+        let err = render(r#"{{ "abc" | add(other=1) }}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("add: expected a number"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_cidr_subnet() {
+        assert_eq!(cidr_subnet("10.0.0.0/16", 24, 2).unwrap(), "10.0.2.0/24");
+        assert_eq!(cidr_subnet("10.0.0.0/16", 20, 15).unwrap(), "10.0.240.0/20");
+        // Host bits in the base are ignored
+        assert_eq!(cidr_subnet("10.1.2.3/16", 16, 0).unwrap(), "10.1.0.0/16");
+        assert_eq!(
+            cidr_subnet("fd00:1::/48", 64, 5).unwrap(),
+            "fd00:1:0:5::/64"
+        );
+
+        assert!(cidr_subnet("10.0.0.0/16", 24, 256).is_err());
+        assert!(cidr_subnet("10.0.0.0/16", 8, 0).is_err());
+        assert!(cidr_subnet("10.0.0.0/33", 34, 0).is_err());
+        assert!(cidr_subnet("not-a-cidr", 24, 0).is_err());
+
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("vpc_cidr".to_string(), "10.0.0.0/16".to_string());
+        let result = engine
+            .render_with_filters(
+                "t",
+                "{{ vpc_cidr | cidr_subnet(prefix_len=24, index=3) }}",
+                &context,
+            )
+            .unwrap();
+        assert_eq!(result, "10.0.3.0/24");
+
+        // Too large for a prefix length, rather than wrapping to /24
+        let err = engine
+            .render_with_filters(
+                "t",
+                "{{ vpc_cidr | cidr_subnet(prefix_len=4294967320, index=3) }}",
+                &context,
+            )
+            .unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("'prefix_len' 4294967320 is too large"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_uuid_v5_is_deterministic() {
+        let engine = TemplateEngine::new();
+        let context = HashMap::new();
+        let tpl = r#"{{ uuid(version=5, namespace="dns", name="example.com") }}"#;
+
+        let first = engine.render_with_filters("t", tpl, &context).unwrap();
+        let second = engine.render_with_filters("t2", tpl, &context).unwrap();
+        assert_eq!(first, "cfbff0d1-9375-5685-968c-48ce8b15ae17");
+        assert_eq!(first, second);
+
+        let missing_name =
+            engine.render_with_filters("t3", r#"{{ uuid(version=5, namespace="dns") }}"#, &context);
+        assert!(missing_name.is_err());
+    }
+
     #[test]
     fn test_mixed_depth_keys_same_prefix() {
         // A prefix can have both shallow and deep dotted children.
@@ -630,4 +1615,138 @@ mod tests {
             .unwrap();
         assert_eq!(result2, "deep_val");
     }
+
+    #[test]
+    fn test_merge_objects_key_order_is_stable() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("a".to_string(), r#"{"zone": "1", "Name": "x"}"#.to_string());
+        context.insert(
+            "b".to_string(),
+            r#"{"env": "dev", "Name": "y"}"#.to_string(),
+        );
+
+        let template = "{{ a | from_json | merge_objects(other=b | from_json) | json_encode() }}";
+        for _ in 0..3 {
+            let rendered = engine.render_with_filters("m", template, &context).unwrap();
+            assert_eq!(rendered, r#"{"Name":"y","env":"dev","zone":"1"}"#);
+        }
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("present".to_string(), "value".to_string());
+        context.insert("empty".to_string(), String::new());
+
+        for template in [
+            "{{ missing | default(\"fallback\") }}",
+            "{{ missing | default(value='fallback') }}",
+            "{{ empty | default('fallback') }}",
+            "{{ this_res.missing | default(\"fallback\") }}",
+        ] {
+            assert_eq!(engine.render(template, &context).unwrap(), "fallback");
+            assert_eq!(
+                engine.render_with_filters("d", template, &context).unwrap(),
+                "fallback"
+            );
+        }
+        assert_eq!(
+            engine
+                .render("{{ present | default(\"fallback\") }}", &context)
+                .unwrap(),
+            "value"
+        );
+        assert_eq!(
+            engine
+                .render_with_filters("d", "{{ present | default(\"fallback\") }}", &context)
+                .unwrap(),
+            "value"
+        );
+    }
+
+    #[test]
+    fn test_env_value() {
+        let env_var = |name: &str| (name == "VPC_ID").then(|| "vpc-123".to_string());
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, tera::Value> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), tera::Value::from(*v)))
+                .collect()
+        };
+
+        let value = |pairs: &[(&str, &str)]| env_value(&args(pairs), env_var);
+        assert_eq!(value(&[("name", "VPC_ID")]).unwrap(), "vpc-123");
+        assert_eq!(
+            value(&[("name", "VPC_ID"), ("default", "fallback")]).unwrap(),
+            "vpc-123"
+        );
+        assert_eq!(
+            value(&[("name", "SUBNET_ID"), ("default", "false")]).unwrap(),
+            "false"
+        );
+        let err = value(&[("name", "SUBNET_ID")]).unwrap_err().to_string();
+        assert!(err.contains("'SUBNET_ID' is not set"), "{}", err);
+        assert!(value(&[]).is_err());
+    }
+
+    #[test]
+    fn test_env_function() {
+        // Reads the real process environment, which tests never modify
+        let path = std::env::var("PATH").unwrap();
+        let engine = TemplateEngine::new();
+        let context = HashMap::new();
+
+        let render = |template: &str| engine.render_with_filters("e", template, &context);
+        assert_eq!(render("{{ env(\"PATH\") }}").unwrap(), path);
+        assert_eq!(render("{{ env('PATH', 'fallback') }}").unwrap(), path);
+        assert_eq!(
+            render("{{ env(\"STACKQL_DEPLOY_TEMPLATE_ENV_UNSET\", \"false\") }}").unwrap(),
+            "false"
+        );
+        assert_eq!(
+            render("{{ env(name=\"STACKQL_DEPLOY_TEMPLATE_ENV_UNSET\", default=\"x\") }}").unwrap(),
+            "x"
+        );
+        assert!(render("{{ env(\"STACKQL_DEPLOY_TEMPLATE_ENV_UNSET\") }}").is_err());
+        assert_eq!(
+            engine.render("{{ env(\"PATH\") }}", &context).unwrap(),
+            path
+        );
+    }
+
+    #[test]
+    fn test_errors_report_line_and_column() {
+        let engine = TemplateEngine::new();
+        let mut context = HashMap::new();
+        context.insert("region".to_string(), "us-east-1".to_string());
+
+        let template = "SELECT region\nFROM aws.ec2.vpcs\nWHERE region = '{{ region }}'\n  AND vpc_id = '{{ my_vpc.vpc_id }}'";
+        let err = engine
+            .render_with_filters("p1", template, &context)
+            .unwrap_err();
+        let position = err.position().expect("variable position");
+        assert!(matches!(err, TemplateError::VariableNotFound { .. }));
+        assert_eq!((position.line, position.column), (4, 20));
+        assert_eq!(position.snippet, "AND vpc_id = '{{ my_vpc.vpc_id }}'");
+        assert!(err
+            .to_string()
+            .contains("(at line 4, column 20: AND vpc_id"));
+
+        let err = engine
+            .render("SELECT 1\n{% if region %}\n{{ region }\n", &context)
+            .unwrap_err();
+        assert!(
+            matches!(err, TemplateError::SyntaxError { .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.position().expect("syntax position").line, 3);
+
+        let err = engine
+            .render_with_filters("p2", "{{ region | add(other=1) }}", &context)
+            .unwrap_err();
+        assert!(err.position().is_none());
+    }
 }
@@ -2,43 +2,188 @@
 
 //! # Connection Utility Module
 //!
-//! This module provides functions for creating a PgwireLite client connection
-//! to the StackQL server. It utilizes global configuration for host and port
-//! and supports error handling during connection attempts.
+//! This module defines the StackQL server a command talks to
+//! ([`ServerConfig`]), how the run uses it ([`RunOptions`]), and creates
+//! PgwireLite client connections to it.
 //!
 //! ## Features
+//! - Resolves the server host and port from the `--server` / `--port` arguments.
+//! - Applies the `--max-rows` result cap to every client it creates.
+//! - Lets clients re-establish dropped connections (unless `--no-reconnect`).
+//! - Connects over TLS / mTLS when `--tls-*` certificates are given, unencrypted otherwise.
 //! - Establishes a connection to the StackQL server using `pgwire_lite::PgwireLite`.
-//! - Uses global host and port settings for consistency across the application.
+//! - Retries the connection with backoff (`--connect-retries`, `--connect-retry-delay`),
+//!   for a server which was just started and is not accepting connections yet.
 //! - Handles connection errors and exits the program if unsuccessful.
 //!
 //! ## Example Usage
 //! ```rust
-//! use crate::utils::connection::create_client;
+//! use crate::utils::connection::{create_client, RunOptions, ServerConfig};
 //!
-//! let client = create_client();
+//! let server = ServerConfig::new("localhost", 5444);
+//! let client = create_client(&server, &RunOptions::default());
 //! ```
 
+use std::fmt;
 use std::process;
+use std::thread;
+use std::time::Duration;
 
+use clap::ArgMatches;
 use colored::*;
+use log::debug;
 
-use crate::globals::{server_host, server_port};
+use crate::app::{
+    DEFAULT_CONNECT_RETRIES, DEFAULT_CONNECT_RETRY_DELAY_MS, DEFAULT_MAX_ROWS, DEFAULT_SERVER_HOST,
+    DEFAULT_SERVER_PORT, LOCAL_SERVER_ADDRESSES,
+};
+use crate::utils::display::json_output;
 use crate::utils::pgwire::PgwireLite;
+use crate::utils::tls::TlsConfig;
 
-/// Creates a new PgwireLite client connection
-pub fn create_client() -> PgwireLite {
-    let host = server_host();
-    let port = server_port();
+/// Host and port of a StackQL server.  Passed to everything that starts,
+/// stops or connects to a server, so a single invocation can work with more
+/// than one server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_SERVER_HOST, DEFAULT_SERVER_PORT)
+    }
+}
+
+impl fmt::Display for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl ServerConfig {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// Server selected by the `--server` and `--port` arguments, falling
+    /// back to the defaults.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let host = matches
+            .get_one::<String>("server")
+            .map_or(DEFAULT_SERVER_HOST, |s| s.as_str());
+        let port = matches
+            .get_one::<u16>("port")
+            .copied()
+            .unwrap_or(DEFAULT_SERVER_PORT);
+        Self::new(host, port)
+    }
+
+    /// Whether the server runs on this machine (and is managed by this tool).
+    pub fn is_local(&self) -> bool {
+        LOCAL_SERVER_ADDRESSES.contains(&self.host.as_str())
+    }
+}
+
+/// How a run uses its server: the settings of the clients it connects, and
+/// of a local server it starts.  Passed alongside the [`ServerConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOptions {
+    /// Maximum rows a single query may return before it fails
+    pub max_rows: usize,
+    /// Reconnect clients whose connection drops mid-run (unless `--no-reconnect`)
+    pub reconnect_on_error: bool,
+    /// Certificates for TLS / mTLS connections (`--tls-*`)
+    pub tls: TlsConfig,
+    /// Attempts to connect before giving up (`--connect-retries`)
+    pub connect_retries: u32,
+    /// Milliseconds before the first connect retry, doubled after each
+    /// failed attempt (`--connect-retry-delay`)
+    pub connect_retry_delay: u64,
+    /// Log provider HTTP traffic of a locally started server (`--trace-http`)
+    pub trace_http: bool,
+    /// Provider registry of a local server started by a deploy command
+    /// (`--registry`)
+    pub registry: Option<String>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            max_rows: DEFAULT_MAX_ROWS,
+            reconnect_on_error: true,
+            tls: TlsConfig::default(),
+            connect_retries: DEFAULT_CONNECT_RETRIES,
+            connect_retry_delay: DEFAULT_CONNECT_RETRY_DELAY_MS,
+            trace_http: false,
+            registry: None,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Options selected by the `--max-rows`, `--no-reconnect`, `--tls-*`,
+    /// `--connect-*` and `--trace-http` arguments, falling back to the
+    /// defaults.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let mut options = Self::default();
+        if let Some(max_rows) = matches.get_one::<usize>("max-rows") {
+            options.max_rows = *max_rows;
+        }
+        options.reconnect_on_error = !matches.get_flag("no-reconnect");
+        options.tls = TlsConfig::from_matches(matches);
+        if let Some(retries) = matches.get_one::<u32>("connect-retries") {
+            options.connect_retries = *retries;
+        }
+        if let Some(delay) = matches.get_one::<u64>("connect-retry-delay") {
+            options.connect_retry_delay = *delay;
+        }
+        options.trace_http = matches.get_flag("trace-http");
+        options
+    }
+}
 
-    // Create a new PgwireLite client with the server's host and port
-    // Default to no TLS and default verbosity
-    let client = PgwireLite::new(host, port, false, "default").unwrap_or_else(|e| {
-        eprintln!("{}", format!("Failed to connect to server: {}", e).red());
-        process::exit(1); // Exit the program if connection fails
+/// Creates a new PgwireLite client connection to `server`, set up with the
+/// client settings of `options`
+pub fn create_client(server: &ServerConfig, options: &RunOptions) -> PgwireLite {
+    let tls = options.tls.client_config().unwrap_or_else(|e| {
+        eprintln!("{}", format!("Invalid TLS configuration: {}", e).red());
+        process::exit(1);
     });
+    // Create a new PgwireLite client with the server's host and port,
+    // unencrypted unless TLS certificates were given.  A server started
+    // moments ago may not accept connections yet, so retry with backoff.
+    let mut delay = options.connect_retry_delay;
+    let mut attempt = 1;
+    let mut client = loop {
+        match PgwireLite::connect(&server.host, server.port, tls.clone()) {
+            Ok(client) => break client,
+            Err(e) if attempt < options.connect_retries => {
+                debug!(
+                    "connect attempt {}/{} to {} failed, retrying in {}ms: {}",
+                    attempt, options.connect_retries, server, delay, e
+                );
+                thread::sleep(Duration::from_millis(delay));
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(e) => {
+                eprintln!("{}", format!("Failed to connect to server: {}", e).red());
+                process::exit(1); // Exit the program if connection fails
+            }
+        }
+    };
+    client.set_max_rows(Some(options.max_rows));
+    client.set_reconnect_on_error(options.reconnect_on_error);
 
-    println!("Connected to stackql server at {}:{}", host, port);
-    println!("Using pgwire client: {}", client.libpq_version());
+    if !json_output() {
+        println!("Connected to stackql server at {}", server);
+        println!("Using pgwire client: {}", client.libpq_version());
+    }
 
     client
 }
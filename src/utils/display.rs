@@ -3,11 +3,51 @@
 //! # Display Utility Module
 //!
 //! This module provides utility functions for rendering messages with various styles
-//! including Unicode-styled message boxes and color-coded output for errors, success messages, and informational messages.
+//! including Unicode-styled message boxes, query result tables and color-coded output for errors, success messages, and informational messages.
 //! It leverages the `colored` crate for styling and `unicode_width` crate for handling Unicode text width.
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use unicode_width::UnicodeWidthStr;
 
+use crate::utils::query::{QueryResultColumn, QueryResultRow};
+
+/// Set by `--output json`; the decorative console output is suppressed so
+/// stdout carries only the JSON document.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Cleared by `--no-color` or a non-empty `NO_COLOR`; output is then written
+/// without ANSI color codes.
+static COLOR_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+/// Enable or disable colored output, for the `colored` crate as well as the
+/// raw ANSI codes of message boxes and log levels.
+pub fn set_color_output(enabled: bool) {
+    COLOR_OUTPUT.store(enabled, Ordering::Relaxed);
+    colored::control::set_override(enabled);
+}
+
+/// Whether output may be colored.
+pub fn color_output() -> bool {
+    COLOR_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Whether the `NO_COLOR` convention (<https://no-color.org>) asks for
+/// uncolored output: the variable is set to a non-empty value.
+pub fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// Switch between text (the default) and JSON output on stdout.
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether `--output json` was requested.
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
 /// Border color options for Unicode boxes, matching Python's BorderColor enum.
 #[derive(Debug, Clone, Copy)]
 pub enum BorderColor {
@@ -33,8 +73,14 @@ impl BorderColor {
 /// Utility function to print a Unicode-styled message box
 /// that correctly handles the width of emojis and other wide characters.
 pub fn print_unicode_box(message: &str, color: BorderColor) {
-    let border_color = color.ansi_code();
-    let reset_color = "\x1b[0m";
+    if json_output() {
+        return;
+    }
+    let (border_color, reset_color) = if color_output() {
+        (color.ansi_code(), "\x1b[0m")
+    } else {
+        ("", "")
+    };
     let lines: Vec<&str> = message.split('\n').collect();
 
     // Calculate width using unicode_width to properly account for emojis
@@ -67,6 +113,54 @@ pub fn print_unicode_box(message: &str, color: BorderColor) {
     println!("{}", bottom_border);
 }
 
+/// Render query results as an aligned ASCII table, with a border after the
+/// header and after every row.  Column widths are measured with
+/// `unicode_width`, as in [`print_unicode_box`], so wide characters and
+/// emojis stay aligned.  NULL values are shown as `NULL`.
+pub fn render_table(columns: &[QueryResultColumn], rows: &[QueryResultRow]) -> String {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            (0..columns.len())
+                .map(|i| row.values.get(i).map_or(String::new(), |v| v.to_string()))
+                .collect()
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = columns
+        .iter()
+        .map(|col| UnicodeWidthStr::width(col.name.as_str()))
+        .collect();
+    for row in &cells {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(UnicodeWidthStr::width(value.as_str()));
+        }
+    }
+
+    let border: String = widths.iter().fold("+".to_string(), |line, width| {
+        line + &"-".repeat(width + 2) + "+"
+    });
+    let line = |values: &mut dyn Iterator<Item = &str>| {
+        values
+            .zip(&widths)
+            .fold("|".to_string(), |line, (value, width)| {
+                let padding = width - UnicodeWidthStr::width(value);
+                format!("{} {}{} |", line, value, " ".repeat(padding))
+            })
+    };
+
+    let mut table = vec![
+        border.clone(),
+        line(&mut columns.iter().map(|col| col.name.as_str())),
+        border.clone(),
+    ];
+    for row in &cells {
+        table.push(line(&mut row.iter().map(String::as_str)));
+        table.push(border.clone());
+    }
+    table.join("\n")
+}
+
 #[macro_export]
 macro_rules! print_info {
     ($($arg:tt)*) => {{
@@ -90,3 +184,75 @@ macro_rules! print_success {
         println!("{}", format!($($arg)*).green())
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::query::CellValue;
+
+    fn columns(names: &[&str]) -> Vec<QueryResultColumn> {
+        names
+            .iter()
+            .map(|name| QueryResultColumn {
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    fn row(values: &[Option<&str>]) -> QueryResultRow {
+        QueryResultRow {
+            values: values
+                .iter()
+                .map(|v| v.map_or(CellValue::Null, |s| CellValue::Text(s.to_string())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_table_sizes_columns_to_widest_cell() {
+        let table = render_table(
+            &columns(&["name", "id"]),
+            &[
+                row(&[Some("vpc"), Some("vpc-0123")]),
+                row(&[None, Some("")]),
+            ],
+        );
+        assert_eq!(
+            table,
+            [
+                "+------+----------+",
+                "| name | id       |",
+                "+------+----------+",
+                "| vpc  | vpc-0123 |",
+                "+------+----------+",
+                "| NULL |          |",
+                "+------+----------+",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_table_aligns_wide_and_emoji_cells() {
+        let table = render_table(
+            &columns(&["region", "status"]),
+            &[
+                row(&[Some("東京"), Some("✅ ok")]),
+                row(&[Some("eu"), Some("🚀")]),
+            ],
+        );
+        let lines: Vec<&str> = table.lines().collect();
+        // "東京" and "🚀" are two columns wide each
+        assert_eq!(lines[3], "| 東京   | ✅ ok  |");
+        assert_eq!(lines[5], "| eu     | 🚀     |");
+        // Every line has the same display width
+        let widths: Vec<usize> = lines.iter().map(|l| UnicodeWidthStr::width(*l)).collect();
+        assert!(widths.iter().all(|w| *w == widths[0]));
+    }
+
+    #[test]
+    fn test_render_table_without_rows() {
+        let table = render_table(&columns(&["name"]), &[]);
+        assert_eq!(table, "+------+\n| name |\n+------+");
+    }
+}
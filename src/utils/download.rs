@@ -9,6 +9,9 @@
 //! ## Features
 //! - Downloads the StackQL binary from a predefined URL.
 //! - Supports progress tracking during download.
+//! - Resumes interrupted downloads with HTTP range requests when the server supports them.
+//! - Verifies the archive against the SHA256 checksum published alongside it.
+//! - Optionally keeps the archive, reusing a kept archive which still verifies.
 //! - Extracts the binary on various platforms (Windows, Linux, macOS).
 //! - Sets executable permissions on Unix-like systems.
 //!
@@ -16,26 +19,32 @@
 //! ```rust
 //! use crate::utils::download::download_binary;
 //!
-//! match download_binary() {
+//! match download_binary(false, false) {
 //!     Ok(path) => println!("Binary downloaded to: {}", path.display()),
 //!     Err(e) => eprintln!("Failed to download binary: {}", e),
 //! }
 //! ```
 
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use log::debug;
+use log::{debug, info, warn};
 use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
 use zip::ZipArchive;
 
 use crate::app::STACKQL_RELEASE_BASE_URL;
 use crate::error::AppError;
 use crate::utils::platform::{get_platform, Platform};
 
+/// Number of times an interrupted download is resumed before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
 /// Retrieves the URL for downloading the StackQL binary based on OS and architecture.
 pub fn get_download_url() -> Result<String, AppError> {
     let platform = get_platform();
@@ -69,7 +78,14 @@ pub fn get_download_url() -> Result<String, AppError> {
 ///
 /// This function downloads the StackQL binary from a URL and unzips it if necessary.
 /// It also sets executable permissions on Unix-like systems.
-pub fn download_binary() -> Result<PathBuf, AppError> {
+///
+/// With `keep_archive` the archive is left in place (also when extraction
+/// fails) and its path logged.  An archive already present that matches the
+/// published checksum is reused instead of being downloaded again.
+///
+/// The download fails if no checksum is published for it, unless
+/// `skip_checksum` is set, in which case the archive is not verified.
+pub fn download_binary(keep_archive: bool, skip_checksum: bool) -> Result<PathBuf, AppError> {
     let download_url = get_download_url()?;
     let current_dir = std::env::current_dir().map_err(AppError::IoError)?;
     let binary_name = crate::utils::platform::get_binary_name();
@@ -79,29 +95,184 @@ pub fn download_binary() -> Result<PathBuf, AppError> {
         .to_string_lossy()
         .to_string();
     let archive_path = current_dir.join(&archive_name);
+    let partial_path = current_dir.join(format!("{}.part", archive_name));
 
-    // Download the file with progress bar
+    // Download the file with progress bar, resuming from any partial archive
+    // left behind by an interrupted download
     debug!("Downloading from {}", download_url);
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(300))
         .build()
         .map_err(|e| AppError::CommandFailed(format!("Failed to create HTTP client: {}", e)))?;
-    let mut response = client
-        .get(&download_url)
+
+    let expected_checksum = if skip_checksum {
+        warn!("Checksum verification skipped (--skip-checksum), the archive is not verified");
+        None
+    } else {
+        Some(fetch_checksum(&client, &download_url)?)
+    };
+    let reusable = match (&expected_checksum, File::open(&archive_path)) {
+        (Some(expected), Ok(archive)) => verify_checksum(archive, expected).unwrap_or(false),
+        _ => false,
+    };
+    if reusable {
+        info!(
+            "Reusing verified archive {}, skipping download",
+            archive_path.display()
+        );
+    } else {
+        download_archive(&client, &download_url, &partial_path)?;
+        fs::rename(&partial_path, &archive_path).map_err(AppError::IoError)?;
+    }
+
+    // Verify integrity before extracting anything
+    if let Some(expected) = expected_checksum.filter(|_| !reusable) {
+        let archive = File::open(&archive_path).map_err(AppError::IoError)?;
+        if !verify_checksum(archive, &expected).map_err(AppError::IoError)? {
+            fs::remove_file(&archive_path).ok();
+            return Err(AppError::CommandFailed(format!(
+                "Checksum mismatch for {}: expected SHA256 {}, the download may be corrupt or tampered with",
+                archive_name,
+                expected.trim()
+            )));
+        }
+        debug!("SHA256 checksum of {} verified", archive_name);
+    }
+
+    // Extract the file based on platform
+    debug!("Extracting the binary...");
+    let extracted = extract_binary(&archive_path, &current_dir, &binary_name);
+
+    if keep_archive {
+        info!("Archive kept at {}", archive_path.display());
+    } else {
+        // Don't leave a possibly corrupt archive around
+        fs::remove_file(&archive_path).ok();
+    }
+    let binary_path = extracted?;
+
+    // Set executable permissions on Unix-like systems
+    if get_platform() != Platform::Windows {
+        Command::new("chmod")
+            .arg("+x")
+            .arg(&binary_path)
+            .output()
+            .map_err(|e| {
+                AppError::CommandFailed(format!("Failed to set executable permission: {}", e))
+            })?;
+    }
+
+    debug!(
+        "StackQL executable successfully installed at: {}",
+        binary_path.display()
+    );
+    Ok(binary_path)
+}
+
+/// Downloads `url` into `partial_path`, resuming an interrupted download up
+/// to `DOWNLOAD_ATTEMPTS` times.
+fn download_archive(client: &Client, url: &str, partial_path: &Path) -> Result<(), AppError> {
+    let mut attempt = 1;
+    loop {
+        match download_to_partial(client, url, partial_path) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                warn!(
+                    "Download interrupted ({}), resuming (attempt {}/{})...",
+                    e,
+                    attempt + 1,
+                    DOWNLOAD_ATTEMPTS
+                );
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(AppError::CommandFailed(format!(
+                    "{} (the partial download is kept and will be resumed on the next attempt)",
+                    e
+                )))
+            }
+        }
+    }
+}
+
+/// Downloads `url` into `partial_path`, resuming from the bytes already
+/// present when the server supports range requests.
+///
+/// On success the partial file holds the complete archive, checked against
+/// the size reported by the server. On failure the bytes received so far are
+/// kept so that a later call can resume.
+fn download_to_partial(client: &Client, url: &str, partial_path: &Path) -> Result<(), AppError> {
+    let offset = fs::metadata(partial_path).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = client.get(url);
+    if offset > 0 {
+        debug!("Resuming download from byte {}", offset);
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request
         .send()
         .map_err(|e| AppError::CommandFailed(format!("Failed to download: {}", e)))?;
 
-    let total_size = response.content_length().unwrap_or(0);
-    let progress_bar = ProgressBar::new(total_size);
+    let status = response.status();
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        // Nothing is left to fetch when the partial file is already the
+        // size of the archive (`Content-Range: bytes */<total>`)
+        let total = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_total);
+        if total == Some(offset) {
+            debug!("Partial download of {} bytes is already complete", offset);
+            return Ok(());
+        }
+        // The partial file does not fit the current archive; start over
+        debug!("Server rejected resume from byte {}, restarting", offset);
+        fs::remove_file(partial_path).map_err(AppError::IoError)?;
+        return download_to_partial(client, url, partial_path);
+    }
+    if !status.is_success() {
+        return Err(AppError::CommandFailed(format!(
+            "Failed to download: server returned {}",
+            status
+        )));
+    }
+
+    let resuming = offset > 0 && status == StatusCode::PARTIAL_CONTENT;
+    let (start, total_size) = if resuming {
+        let total = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_total);
+        (offset, total)
+    } else {
+        if offset > 0 {
+            debug!("Server does not support range requests, restarting download");
+        }
+        (0, response.content_length())
+    };
+
+    let progress_bar = ProgressBar::new(total_size.unwrap_or(0));
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
+    progress_bar.set_position(start);
 
-    let mut file = File::create(&archive_path).map_err(AppError::IoError)?;
+    let mut file = if resuming {
+        OpenOptions::new()
+            .append(true)
+            .open(partial_path)
+            .map_err(AppError::IoError)?
+    } else {
+        File::create(partial_path).map_err(AppError::IoError)?
+    };
+    // Stream the body to disk in chunks so memory stays flat regardless of
+    // the archive size and the progress bar advances as bytes arrive
     let mut buffer = [0u8; 8192];
-    let mut downloaded: u64 = 0;
+    let mut downloaded = start;
     loop {
         let bytes_read = response
             .read(&mut buffer)
@@ -112,33 +283,69 @@ pub fn download_binary() -> Result<PathBuf, AppError> {
         file.write_all(&buffer[..bytes_read])
             .map_err(AppError::IoError)?;
         downloaded += bytes_read as u64;
-        progress_bar.set_position(downloaded);
+        progress_bar.inc(bytes_read as u64);
     }
     progress_bar.finish_with_message("Download complete");
 
-    // Extract the file based on platform
-    debug!("Extracting the binary...");
-    let binary_path = extract_binary(&archive_path, &current_dir, &binary_name)?;
-
-    // Clean up the archive
-    fs::remove_file(&archive_path).ok();
+    if let Some(total) = total_size {
+        if downloaded != total {
+            return Err(AppError::CommandFailed(format!(
+                "Download incomplete: received {} of {} bytes",
+                downloaded, total
+            )));
+        }
+    }
+    Ok(())
+}
 
-    // Set executable permissions on Unix-like systems
-    if get_platform() != Platform::Windows {
-        Command::new("chmod")
-            .arg("+x")
-            .arg(&binary_path)
-            .output()
-            .map_err(|e| {
-                AppError::CommandFailed(format!("Failed to set executable permission: {}", e))
-            })?;
+/// Fetches the checksum published as `<url>.sha256`, failing when the
+/// release has no checksum file.
+fn fetch_checksum(client: &Client, url: &str) -> Result<String, AppError> {
+    let checksum_url = format!("{}.sha256", url);
+    debug!("Fetching checksum from {}", checksum_url);
+    let response = client
+        .get(&checksum_url)
+        .send()
+        .map_err(|e| AppError::CommandFailed(format!("Failed to download checksum: {}", e)))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(AppError::CommandFailed(format!(
+            "No checksum published at {}, cannot verify the download; use --skip-checksum to install it unverified",
+            checksum_url
+        )));
     }
+    if !response.status().is_success() {
+        return Err(AppError::CommandFailed(format!(
+            "Failed to download checksum: server returned {}",
+            response.status()
+        )));
+    }
+    response
+        .text()
+        .map_err(|e| AppError::CommandFailed(format!("Failed to read checksum: {}", e)))
+}
 
-    debug!(
-        "StackQL executable successfully installed at: {}",
-        binary_path.display()
-    );
-    Ok(binary_path)
+/// Whether the SHA256 of the bytes read from `reader` matches `expected`, a
+/// hex digest optionally followed by a file name (`sha256sum` output
+/// format).  The input is hashed in chunks, never held in memory; bytes
+/// already in memory are checked by passing them as a `&[u8]` reader.
+pub fn verify_checksum(mut reader: impl Read, expected: &str) -> io::Result<bool> {
+    let Some(expected) = expected.split_whitespace().next() else {
+        return Ok(false);
+    };
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    let actual: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(actual.eq_ignore_ascii_case(expected))
+}
+
+/// Extracts the total size from a `Content-Range: bytes start-end/total` (or
+/// `bytes */total`) header.
+fn content_range_total(header: &str) -> Option<u64> {
+    header.rsplit_once('/')?.1.trim().parse().ok()
 }
 
 /// Extracts the StackQL binary from an archive.
@@ -249,3 +456,178 @@ fn find_file_recursive(dir: &Path, target_name: &str) -> Option<PathBuf> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// SHA256 of `hello world`.
+    const HELLO_SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    /// Serve one HTTP request with `status` (e.g. `404 Not Found`) and
+    /// `body`, returning the server's base URL.
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        serve_request(status, "", body).0
+    }
+
+    /// Serve one HTTP request with `status`, extra `headers` (each ending in
+    /// `\r\n`) and `body`, returning the server's base URL and a receiver
+    /// for the request head.  The server accepts no further connections.
+    fn serve_request(
+        status: &'static str,
+        headers: &'static str,
+        body: &'static str,
+    ) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                request.push(byte[0]);
+            }
+            sender
+                .send(String::from_utf8_lossy(&request).to_string())
+                .ok();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                headers,
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        (format!("http://127.0.0.1:{}", port), receiver)
+    }
+
+    fn client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    #[test]
+    fn test_fetch_checksum() {
+        let base = serve_once("200 OK", "b94d27b9  stackql_linux_amd64.zip\n");
+        let checksum = fetch_checksum(&client(), &format!("{}/stackql.zip", base)).unwrap();
+        assert_eq!(checksum, "b94d27b9  stackql_linux_amd64.zip\n");
+    }
+
+    #[test]
+    fn test_fetch_checksum_fails_when_not_published() {
+        let base = serve_once("404 Not Found", "");
+        let err = fetch_checksum(&client(), &format!("{}/stackql.zip", base)).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("No checksum published"), "{}", message);
+        assert!(message.contains("--skip-checksum"), "{}", message);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        assert!(verify_checksum(&b"hello world"[..], HELLO_SHA256).unwrap());
+        // sha256sum output, in either case
+        let sha256sum = format!("{}  stackql.zip\n", HELLO_SHA256.to_uppercase());
+        assert!(verify_checksum(&b"hello world"[..], &sha256sum).unwrap());
+        assert!(!verify_checksum(&b"hello world!"[..], HELLO_SHA256).unwrap());
+        assert!(!verify_checksum(&b"hello world"[..], "").unwrap());
+    }
+
+    /// Reads its data a few bytes at a time, as a network stream would.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum_of_streamed_input() {
+        // Larger than the copy buffer, so hashed over many chunks
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let expected: String = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("stackql.zip");
+        fs::write(&path, &data).unwrap();
+        assert!(verify_checksum(File::open(&path).unwrap(), &expected).unwrap());
+        assert!(verify_checksum(Trickle(&data), &expected).unwrap());
+
+        // A change in the last chunk is caught
+        let mut corrupt = data.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(!verify_checksum(Trickle(&corrupt), &expected).unwrap());
+    }
+
+    #[test]
+    fn test_content_range_total() {
+        assert_eq!(content_range_total("bytes 100-199/200"), Some(200));
+        assert_eq!(content_range_total("bytes */200"), Some(200));
+        assert_eq!(content_range_total("bytes 100-199/*"), None);
+        assert_eq!(content_range_total("bytes 100-199"), None);
+        assert_eq!(content_range_total(""), None);
+    }
+
+    #[test]
+    fn test_download_restarts_when_range_is_ignored() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("stackql.zip.part");
+        fs::write(&partial, "stale").unwrap();
+
+        // A server without range support answers with the whole archive
+        let (base, request) = serve_request("200 OK", "", "hello world");
+        download_to_partial(&client(), &format!("{}/stackql.zip", base), &partial).unwrap();
+
+        let request = request.recv().unwrap().to_lowercase();
+        assert!(request.contains("range: bytes=5-"), "{}", request);
+        assert_eq!(fs::read_to_string(&partial).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_download_resumes_from_partial() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("stackql.zip.part");
+        fs::write(&partial, "hello").unwrap();
+
+        let (base, request) = serve_request(
+            "206 Partial Content",
+            "Content-Range: bytes 5-10/11\r\n",
+            " world",
+        );
+        download_to_partial(&client(), &format!("{}/stackql.zip", base), &partial).unwrap();
+
+        let request = request.recv().unwrap().to_lowercase();
+        assert!(request.contains("range: bytes=5-"), "{}", request);
+        assert!(verify_checksum(File::open(&partial).unwrap(), HELLO_SHA256).unwrap());
+    }
+
+    #[test]
+    fn test_download_of_complete_partial() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let partial = dir.path().join("stackql.zip.part");
+        fs::write(&partial, "hello world").unwrap();
+
+        // Nothing left past the end of the archive; the server only answers
+        // once, so a restarted download would fail
+        let (base, request) = serve_request(
+            "416 Range Not Satisfiable",
+            "Content-Range: bytes */11\r\n",
+            "",
+        );
+        download_to_partial(&client(), &format!("{}/stackql.zip", base), &partial).unwrap();
+
+        let request = request.recv().unwrap().to_lowercase();
+        assert!(request.contains("range: bytes=11-"), "{}", request);
+        assert_eq!(fs::read_to_string(&partial).unwrap(), "hello world");
+    }
+}
@@ -2,9 +2,45 @@
 
 use chrono::Local;
 use env_logger::Builder;
-use log::LevelFilter;
+use log::{LevelFilter, Log, Metadata, Record};
 use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils::display::color_output;
+
+/// Number of warnings logged during this run, counted even when the
+/// configured log level hides them (used by `--fail-on-warning`).
+static WARNING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of warnings logged so far.
+pub fn warning_count() -> usize {
+    WARNING_COUNT.load(Ordering::Relaxed)
+}
+
+/// Wraps the formatted `env_logger` output so every warning is counted.
+struct CountingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for CountingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() == log::Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == log::Level::Warn {
+            WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
 
 /// Colors for different log levels when printing to the terminal
 struct LevelColors;
@@ -52,8 +88,11 @@ pub fn initialize_logger(log_level: &str) {
     builder.format(|buf, record| {
         let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%SZ");
         let level_str = record.level();
-        let color = LevelColors::get_color(level_str);
-        let reset = LevelColors::RESET;
+        let (color, reset) = if color_output() {
+            (LevelColors::get_color(level_str), LevelColors::RESET)
+        } else {
+            ("", "")
+        };
 
         if record.level() <= log::Level::Info {
             // For info, warn, error: [timestamp LEVEL stackql_deploy] message
@@ -91,6 +130,10 @@ pub fn initialize_logger(log_level: &str) {
     // Set the default log level
     builder.filter_level(level);
 
-    // Initialize the logger
-    builder.init();
+    // Initialize the logger; warnings must always reach the counter
+    let logger = CountingLogger {
+        inner: builder.build(),
+    };
+    log::set_max_level(level.max(LevelFilter::Warn));
+    log::set_boxed_logger(Box::new(logger)).expect("logger already initialized");
 }
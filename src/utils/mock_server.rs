@@ -0,0 +1,206 @@
+// utils/mock_server.rs
+
+//! # Mock StackQL Server
+//!
+//! A minimal PostgreSQL wire protocol server for tests.  It accepts any
+//! number of connections, answers the startup handshake, and replies to
+//! each simple query with whatever [`Reply`] the test's responder returns
+//! for it, recording every query it receives.
+//!
+//! ## Example Usage
+//! ```rust
+//! let server = MockServer::start(|query| {
+//!     if query.contains("COUNT(*)") {
+//!         Reply::rows(&["count"], &[&["1"]])
+//!     } else {
+//!         Reply::ok()
+//!     }
+//! });
+//! let mut client = server.client();
+//! ```
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::utils::pgwire::PgwireLite;
+
+/// How the server answers a query.
+pub enum Reply {
+    /// A result set, `None` values sent as NULL.
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<Option<String>>>,
+    },
+    /// A command completing with `tag` (e.g. `INSERT 0 1`).
+    Command(String),
+    /// Close the connection without answering, as a dropped connection.
+    Close,
+    /// Never answer, holding the connection until the client closes it.
+    Hang,
+    /// Keep sending notices a few at a time without ever completing.
+    Trickle,
+}
+
+impl Reply {
+    /// A result set of text values.
+    pub fn rows(columns: &[&str], rows: &[&[&str]]) -> Self {
+        Reply::Rows {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|v| Some(v.to_string())).collect())
+                .collect(),
+        }
+    }
+
+    /// A single `count` column, as returned by `exists` queries.
+    pub fn count(count: usize) -> Self {
+        Reply::rows(&["count"], &[&[&count.to_string()]])
+    }
+
+    /// A successful command with no result set.
+    pub fn ok() -> Self {
+        Reply::Command("OK".to_string())
+    }
+}
+
+type Responder = dyn Fn(&str) -> Reply + Send + Sync;
+
+/// A running mock server; it stops with the test process.
+pub struct MockServer {
+    port: u16,
+    queries: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    /// Start a server on a free local port, answering each query with
+    /// `respond(query)`.
+    pub fn start(respond: impl Fn(&str) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Responder> = Arc::new(respond);
+
+        let recorded = Arc::clone(&queries);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let recorded = Arc::clone(&recorded);
+                let respond = Arc::clone(&respond);
+                thread::spawn(move || serve(stream, &recorded, &*respond));
+            }
+        });
+        Self { port, queries }
+    }
+
+    /// A new client connection to the server.
+    pub fn client(&self) -> PgwireLite {
+        PgwireLite::connect("127.0.0.1", self.port, None).unwrap()
+    }
+
+    /// The queries received so far, in order.
+    pub fn queries(&self) -> Vec<String> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+/// Answer the startup handshake, then every query on one connection.
+fn serve(mut stream: TcpStream, queries: &Mutex<Vec<String>>, respond: &Responder) {
+    if read_message(&mut stream, false).is_none() {
+        return;
+    }
+    let mut handshake = message(b'R', &0i32.to_be_bytes());
+    handshake.extend(message(b'Z', b"I"));
+    if stream.write_all(&handshake).is_err() {
+        return;
+    }
+
+    while let Some((tag, body)) = read_message(&mut stream, true) {
+        if tag != b'Q' {
+            // Terminate, or anything else the mock does not handle
+            return;
+        }
+        let query = String::from_utf8_lossy(body.strip_suffix(b"\0").unwrap_or(&body)).to_string();
+        queries.lock().unwrap().push(query.clone());
+
+        let response = match respond(&query) {
+            Reply::Rows { columns, rows } => {
+                let mut response = row_description(&columns);
+                for row in &rows {
+                    response.extend(data_row(row));
+                }
+                response.extend(message(b'C', format!("SELECT {}\0", rows.len()).as_bytes()));
+                response
+            }
+            Reply::Command(tag) => message(b'C', format!("{}\0", tag).as_bytes()),
+            Reply::Close => return,
+            Reply::Hang => {
+                let _ = stream.read(&mut [0u8; 1]);
+                return;
+            }
+            Reply::Trickle => {
+                let notice = message(b'N', b"SNOTICE\0Mstill working\0\0");
+                while stream.write_all(&notice).is_ok() {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                return;
+            }
+        };
+        let mut response = response;
+        response.extend(message(b'Z', b"I"));
+        if stream.write_all(&response).is_err() {
+            return;
+        }
+    }
+}
+
+/// A protocol message: `tag`, length and `body`.
+pub fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut msg = vec![tag];
+    msg.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+    msg.extend_from_slice(body);
+    msg
+}
+
+/// Read one message, with its tag unless it is the untagged startup
+/// message.  `None` once the connection is closed.
+fn read_message(stream: &mut impl Read, with_tag: bool) -> Option<(u8, Vec<u8>)> {
+    let mut tag = [0u8; 1];
+    if with_tag {
+        stream.read_exact(&mut tag).ok()?;
+    }
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).ok()?;
+    let mut body = vec![0u8; (i32::from_be_bytes(len) as usize).saturating_sub(4)];
+    stream.read_exact(&mut body).ok()?;
+    Some((tag[0], body))
+}
+
+/// RowDescription for text `columns`.
+pub fn row_description(columns: &[String]) -> Vec<u8> {
+    let mut body = (columns.len() as i16).to_be_bytes().to_vec();
+    for column in columns {
+        body.extend_from_slice(column.as_bytes());
+        body.push(0);
+        body.extend_from_slice(&[0u8; 18]);
+    }
+    message(b'T', &body)
+}
+
+/// DataRow with `None` sent as a NULL (length -1).
+pub fn data_row(values: &[Option<String>]) -> Vec<u8> {
+    let mut body = (values.len() as i16).to_be_bytes().to_vec();
+    for value in values {
+        match value {
+            Some(v) => {
+                body.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                body.extend_from_slice(v.as_bytes());
+            }
+            None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+    message(b'D', &body)
+}
@@ -3,8 +3,11 @@ pub mod connection;
 pub mod display;
 pub mod download;
 pub mod logging;
+#[cfg(test)]
+pub mod mock_server;
 pub mod pgwire;
 pub mod platform;
 pub mod query;
 pub mod server;
 pub mod stackql;
+pub mod tls;
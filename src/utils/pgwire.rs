@@ -2,13 +2,100 @@
 
 //! Pure-Rust PostgreSQL simple-query wire protocol client.
 //!
-//! Implements only what stackql-deploy needs: unencrypted TCP connections
-//! to a local StackQL server using the PostgreSQL simple query protocol (v3).
+//! Implements only what stackql-deploy needs: TCP connections, optionally
+//! TLS-encrypted (rustls), to a StackQL server using the PostgreSQL simple
+//! query protocol (v3).
 //! No native dependencies (replaces pgwire-lite → libpq-sys).
 
 use std::collections::{HashMap, HashSet};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::{ClientConfig, ClientConnection, ServerName, StreamOwned};
+
+/// Connection to the server, unencrypted or TLS.
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// Connect to `host:port`, negotiating TLS with an `SSLRequest` when a
+    /// TLS configuration is given.
+    fn connect(host: &str, addr: &str, tls: Option<&Arc<ClientConfig>>) -> Result<Self, String> {
+        // SSLRequest code 1234.5679
+        const SSL_REQUEST: i32 = 80877103;
+
+        let mut tcp = TcpStream::connect(addr)
+            .map_err(|e| format!("Connection to {} failed: {}", addr, e))?;
+        let Some(tls) = tls else {
+            return Ok(Stream::Plain(tcp));
+        };
+
+        let mut msg = Vec::with_capacity(8);
+        msg.extend_from_slice(&8i32.to_be_bytes());
+        msg.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+        tcp.write_all(&msg)
+            .map_err(|e| format!("SSLRequest write error: {}", e))?;
+        let mut reply = [0u8; 1];
+        tcp.read_exact(&mut reply)
+            .map_err(|e| format!("SSLRequest read error: {}", e))?;
+        if reply[0] != b'S' {
+            return Err(format!(
+                "Server at {} does not accept TLS connections",
+                addr
+            ));
+        }
+
+        let server_name = ServerName::try_from(host)
+            .map_err(|e| format!("Invalid TLS server name '{}': {}", host, e))?;
+        let conn = ClientConnection::new(Arc::clone(tls), server_name)
+            .map_err(|e| format!("TLS setup error: {}", e))?;
+        let mut stream = StreamOwned::new(conn, tcp);
+        // Complete the handshake now so certificate errors surface on connect
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(|e| format!("TLS handshake with {} failed: {}", addr, e))?;
+        }
+        Ok(Stream::Tls(Box::new(stream)))
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(tcp) => tcp,
+            Stream::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(tcp) => tcp.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(tcp) => tcp.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Plain(tcp) => tcp.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
 
 /// A single column value returned from a query.
 pub enum Value {
@@ -36,28 +123,74 @@ pub struct PgQueryResult {
 
 /// Minimal PostgreSQL wire-protocol client.
 pub struct PgwireLite {
-    stream: TcpStream,
+    stream: Stream,
+    /// Server host, also the name its TLS certificate is verified against
+    host: String,
+    /// `host:port` of the server, to reconnect to
+    addr: String,
+    /// TLS configuration, `None` for an unencrypted connection
+    tls: Option<Arc<ClientConfig>>,
+    /// Set when reading from or writing to the connection failed, so the
+    /// connection can no longer be used (as opposed to a query error).
+    connection_lost: bool,
+    /// Re-establish a lost connection before the next attempt of a query;
+    /// on unless `--no-reconnect` is given.
+    reconnect_on_error: bool,
+    /// `SET` statements applied to the session, replayed on reconnect.
+    session_statements: Vec<String>,
+    /// Longest a query may wait for its response (anchor `timeout`);
+    /// `None` waits indefinitely.
+    query_timeout: Option<Duration>,
+    /// When the response to the running query must have arrived by, from
+    /// `query_timeout`.
+    deadline: Option<Instant>,
+    /// Set when a read exceeded `query_timeout`.
+    timed_out: bool,
     /// Canonical signatures of every notice line surfaced earlier in this
     /// session. stackql emits each new query's NoticeResponse with a
     /// cumulative `detail` field containing every provider notice seen so
     /// far. Any detail line already present in this set is stale and
     /// dropped from subsequent query results.
     seen_notice_sigs: HashSet<String>,
+    /// Maximum number of rows kept per query; rows beyond the limit are
+    /// read off the wire (the protocol requires it) but not parsed or
+    /// buffered.  `None` keeps every row.
+    row_limit: Option<usize>,
+    /// Safety cap on the rows a query may return; a result with more rows
+    /// is an error rather than being buffered in full.  `None` is uncapped.
+    max_rows: Option<usize>,
 }
 
 impl PgwireLite {
     /// Connect to a PostgreSQL-protocol server (e.g. StackQL) at `host:port`.
     ///
     /// `_ssl` and `_verbosity` are accepted for API compatibility but ignored;
-    /// the connection is always unencrypted (StackQL default).
+    /// the connection is unencrypted (StackQL default), see
+    /// [`PgwireLite::connect`] for TLS.
     pub fn new(host: &str, port: u16, _ssl: bool, _verbosity: &str) -> Result<Self, String> {
+        Self::connect(host, port, None)
+    }
+
+    /// Connect to a PostgreSQL-protocol server at `host:port`, over TLS when
+    /// a TLS configuration is given.
+    pub fn connect(host: &str, port: u16, tls: Option<Arc<ClientConfig>>) -> Result<Self, String> {
         let addr = format!("{}:{}", host, port);
-        let stream = TcpStream::connect(&addr)
-            .map_err(|e| format!("Connection to {} failed: {}", addr, e))?;
+        let stream = Stream::connect(host, &addr, tls.as_ref())?;
 
         let mut client = PgwireLite {
             stream,
+            host: host.to_string(),
+            addr,
+            tls,
+            connection_lost: false,
+            reconnect_on_error: true,
+            session_statements: Vec::new(),
+            query_timeout: None,
+            deadline: None,
+            timed_out: false,
             seen_notice_sigs: HashSet::new(),
+            row_limit: None,
+            max_rows: None,
         };
         client.startup()?;
         Ok(client)
@@ -85,9 +218,10 @@ impl PgwireLite {
         msg.extend_from_slice(&PROTOCOL_V3.to_be_bytes());
         msg.extend_from_slice(params);
 
-        self.stream
-            .write_all(&msg)
-            .map_err(|e| format!("Startup write error: {}", e))?;
+        if let Err(e) = self.stream.write_all(&msg) {
+            self.connection_lost = true;
+            return Err(format!("Startup write error: {}", e));
+        }
 
         // Process auth / parameter-status messages until ReadyForQuery
         loop {
@@ -121,12 +255,98 @@ impl PgwireLite {
         Ok(())
     }
 
+    /// Whether the connection dropped (an I/O error rather than an error
+    /// returned by the server).
+    pub fn connection_lost(&self) -> bool {
+        self.connection_lost
+    }
+
+    /// Whether a lost connection should be re-established.
+    pub fn reconnect_on_error(&self) -> bool {
+        self.reconnect_on_error
+    }
+
+    /// Re-establish lost connections before the next attempt of a query.
+    pub fn set_reconnect_on_error(&mut self, enabled: bool) {
+        self.reconnect_on_error = enabled;
+    }
+
+    /// Remember a `SET` statement applied to the session, so it is applied
+    /// again after a reconnect.
+    pub fn add_session_statement(&mut self, statement: &str) {
+        self.session_statements.push(statement.to_string());
+    }
+
+    /// Open a new connection to the server in place of a lost one, and
+    /// re-apply the session's `SET` statements.  Row limits and the notice
+    /// history are kept.
+    pub fn reconnect(&mut self) -> Result<(), String> {
+        self.stream = Stream::connect(&self.host, &self.addr, self.tls.as_ref())?;
+        self.connection_lost = false;
+        self.startup()?;
+        for statement in self.session_statements.clone() {
+            self.query(&statement)?;
+        }
+        Ok(())
+    }
+
+    /// Set the per-query response timeout, returning the previous timeout so
+    /// callers can restore it.
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) -> Option<Duration> {
+        std::mem::replace(&mut self.query_timeout, timeout)
+    }
+
+    /// Set the per-query row limit, returning the previous limit so callers
+    /// can restore it.
+    pub fn set_row_limit(&mut self, limit: Option<usize>) -> Option<usize> {
+        std::mem::replace(&mut self.row_limit, limit)
+    }
+
+    /// The per-query row cap.
+    pub fn max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    /// Set the per-query row cap, returning the previous cap so callers
+    /// can restore it.
+    pub fn set_max_rows(&mut self, max_rows: Option<usize>) -> Option<usize> {
+        std::mem::replace(&mut self.max_rows, max_rows)
+    }
+
     // ------------------------------------------------------------------
     // Query
     // ------------------------------------------------------------------
 
     /// Execute a simple (non-prepared) SQL query and return structured results.
+    ///
+    /// With a query timeout set, a query whose whole response does not
+    /// arrive in time fails with a timeout error, however the server spaces
+    /// out its messages.  The response may still be in flight, so the
+    /// connection is replaced before returning, leaving the client usable for
+    /// a retry.
     pub fn query(&mut self, sql: &str) -> Result<PgQueryResult, String> {
+        self.deadline = self.query_timeout.map(|timeout| Instant::now() + timeout);
+        self.timed_out = false;
+        let result = self.run_query(sql);
+        self.deadline = None;
+        let _ = self.stream.tcp().set_read_timeout(None);
+        if !self.timed_out {
+            return result;
+        }
+
+        let timeout = self.query_timeout.unwrap_or_default();
+        let mut error = format!("Query timed out after {}s", timeout.as_secs());
+        self.connection_lost = false;
+        let session_timeout = self.query_timeout.take();
+        if let Err(e) = self.reconnect() {
+            self.connection_lost = true;
+            error = format!("{} (reconnect failed: {})", error, e);
+        }
+        self.query_timeout = session_timeout;
+        Err(error)
+    }
+
+    fn run_query(&mut self, sql: &str) -> Result<PgQueryResult, String> {
         // Drain any bytes the server may have emitted outside a prior query's
         // response window. stackql has been observed to re-emit stale
         // NoticeResponse frames from earlier statements, which would
@@ -143,15 +363,18 @@ impl PgwireLite {
         msg.extend_from_slice(sql_bytes);
         msg.push(0u8);
 
-        self.stream
-            .write_all(&msg)
-            .map_err(|e| format!("Query write error: {}", e))?;
+        if let Err(e) = self.stream.write_all(&msg) {
+            self.connection_lost = true;
+            return Err(format!("Query write error: {}", e));
+        }
 
         // Collect response messages
         let mut column_names: Vec<String> = Vec::new();
         let mut rows: Vec<HashMap<String, Value>> = Vec::new();
         let mut notices: Vec<Notice> = Vec::new();
         let mut row_count: usize = 0;
+        let mut discarded_rows: usize = 0;
+        let mut exceeded_max_rows = false;
 
         loop {
             let msg_type = self.read_byte()?;
@@ -165,6 +388,15 @@ impl PgwireLite {
                 }
                 b'D' => {
                     // DataRow
+                    if self.row_limit.is_some_and(|limit| rows.len() >= limit) {
+                        discarded_rows += 1;
+                        continue;
+                    }
+                    if self.max_rows.is_some_and(|max| rows.len() >= max) {
+                        // Keep reading to ReadyForQuery, but stop buffering
+                        exceeded_max_rows = true;
+                        continue;
+                    }
                     let row = parse_data_row(&data, &column_names);
                     rows.push(row);
                 }
@@ -207,6 +439,21 @@ impl PgwireLite {
         // query's NoticeResponse.
         let kept = filter_stale_notices(notices, &mut self.seen_notice_sigs);
 
+        if exceeded_max_rows {
+            return Err(format!(
+                "query returned more than {} rows (max_rows); add a filter to the query or raise the limit",
+                rows.len()
+            ));
+        }
+
+        if discarded_rows > 0 {
+            log::debug!(
+                "kept the first {} row(s), discarded {} more",
+                rows.len(),
+                discarded_rows
+            );
+        }
+
         Ok(PgQueryResult {
             column_names,
             rows,
@@ -225,7 +472,7 @@ impl PgwireLite {
     /// misattributed. Best-effort non-blocking read; any I/O error is
     /// swallowed since the normal path is zero bytes available.
     fn drain_pending(&mut self) {
-        if self.stream.set_nonblocking(true).is_err() {
+        if self.stream.tcp().set_nonblocking(true).is_err() {
             return;
         }
         let mut buf = [0u8; 4096];
@@ -236,7 +483,7 @@ impl PgwireLite {
                 Err(_) => break,   // WouldBlock or other — nothing more pending
             }
         }
-        let _ = self.stream.set_nonblocking(false);
+        let _ = self.stream.tcp().set_nonblocking(false);
     }
 
     // ------------------------------------------------------------------
@@ -245,27 +492,56 @@ impl PgwireLite {
 
     fn read_byte(&mut self) -> Result<u8, String> {
         let mut buf = [0u8; 1];
-        self.stream
-            .read_exact(&mut buf)
-            .map_err(|e| format!("Read error: {}", e))?;
+        self.read_into(&mut buf)?;
         Ok(buf[0])
     }
 
     fn read_i32(&mut self) -> Result<i32, String> {
         let mut buf = [0u8; 4];
-        self.stream
-            .read_exact(&mut buf)
-            .map_err(|e| format!("Read error: {}", e))?;
+        self.read_into(&mut buf)?;
         Ok(i32::from_be_bytes(buf))
     }
 
     fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, String> {
         let mut buf = vec![0u8; n];
-        self.stream
-            .read_exact(&mut buf)
-            .map_err(|e| format!("Read error: {}", e))?;
+        self.read_into(&mut buf)?;
         Ok(buf)
     }
+
+    /// Fill `buf` from the connection, marking it lost on an I/O error or
+    /// timed out once the query deadline passes.  Each read waits only for
+    /// the time left before the deadline.
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if let Some(deadline) = self.deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    self.timed_out = true;
+                    return Err(format!("Read error: {}", ErrorKind::TimedOut));
+                }
+                let _ = self.stream.tcp().set_read_timeout(Some(remaining));
+            }
+            match self.stream.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    self.connection_lost = true;
+                    return Err(format!("Read error: {}", ErrorKind::UnexpectedEof));
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    match e.kind() {
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut if self.deadline.is_some() => {
+                            self.timed_out = true
+                        }
+                        _ => self.connection_lost = true,
+                    }
+                    return Err(format!("Read error: {}", e));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 // ------------------------------------------------------------------
@@ -455,3 +731,48 @@ fn parse_error_fields(data: &[u8]) -> String {
     }
     "Unknown server error".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::{MockServer, Reply};
+
+    /// Run a query with a 300ms timeout against a server answering it with
+    /// `reply`, then a second query, returning the error of the first and
+    /// how long it took.
+    fn query_with_timeout(reply: fn() -> Reply) -> (Option<String>, Duration) {
+        let server = MockServer::start(move |query| {
+            if query == "SELECT 1" {
+                reply()
+            } else {
+                Reply::ok()
+            }
+        });
+        let mut client = server.client();
+        client.set_query_timeout(Some(Duration::from_millis(300)));
+
+        let start = Instant::now();
+        let error = client.query("SELECT 1").err();
+        let elapsed = start.elapsed();
+
+        // The connection was replaced, leaving the client usable
+        assert!(client.query("SELECT 2").is_ok());
+        (error, elapsed)
+    }
+
+    #[test]
+    fn test_query_times_out_on_silent_server() {
+        let (error, elapsed) = query_with_timeout(|| Reply::Hang);
+        assert!(error.unwrap().starts_with("Query timed out"));
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_query_deadline_spans_all_reads() {
+        // Each read gets a message well within the timeout, but the
+        // response never completes
+        let (error, elapsed) = query_with_timeout(|| Reply::Trickle);
+        assert!(error.unwrap().starts_with("Query timed out"));
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+}
@@ -8,6 +8,7 @@
 //! ## Features
 //! - Detects the current operating system (Windows, macOS, Linux).
 //! - Returns the platform-specific `stackql` binary name.
+//! - Resolves the shell which runs script commands.
 //!
 //! ## Example Usage
 //! ```rust
@@ -48,3 +49,34 @@ pub fn get_platform() -> Platform {
 pub fn get_binary_name() -> String {
     STACKQL_BINARY_NAME.to_string()
 }
+
+/// The program and leading arguments which run a script command: the
+/// command line in `shell` (e.g. `pwsh -NoProfile -Command`), or `sh -c`
+/// (`cmd /C` on Windows) when it is not set.  A bare interpreter name is
+/// given the flag it reads a command from (`-c`, `/C`, `-Command` or `-e`).
+pub fn shell_invocation(shell: Option<&str>) -> (String, Vec<String>) {
+    let mut words = shell.unwrap_or_default().split_whitespace();
+    let Some(program) = words.next() else {
+        return match get_platform() {
+            Platform::Windows => ("cmd".to_string(), vec!["/C".to_string()]),
+            _ => ("sh".to_string(), vec!["-c".to_string()]),
+        };
+    };
+    let mut args: Vec<String> = words.map(str::to_string).collect();
+    if args.is_empty() {
+        let name = program
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(program)
+            .to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        let flag = match name {
+            "cmd" => "/C",
+            "pwsh" | "powershell" => "-Command",
+            "node" => "-e",
+            _ => "-c",
+        };
+        args.push(flag.to_string());
+    }
+    (program.to_string(), args)
+}
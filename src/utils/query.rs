@@ -9,6 +9,7 @@
 //! ## Features
 //! - Executes SQL queries using `pgwire_lite::PgwireLite`.
 //! - Formats query results into structured data (columns, rows, notices).
+//! - Keeps SQL NULLs distinct from empty strings ([`CellValue`]).
 //! - Supports different query result types: Data, Command, and Empty.
 //!
 //! ## Example Usage
@@ -26,6 +27,8 @@
 //! }
 //! ```
 
+use std::fmt;
+
 use crate::utils::pgwire::{PgwireLite, Value};
 
 /// Represents a column in a query result.
@@ -33,9 +36,49 @@ pub struct QueryResultColumn {
     pub name: String,
 }
 
+/// A single value in a query result.  The simple query protocol returns
+/// every value as text, so the only distinction kept is SQL NULL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CellValue {
+    Null,
+    Text(String),
+}
+
+impl CellValue {
+    /// The text of the value, `None` for NULL.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            CellValue::Null => None,
+            CellValue::Text(s) => Some(s),
+        }
+    }
+}
+
+/// Displays NULL as `NULL`, for showing results to the user.
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellValue::Null => write!(f, "NULL"),
+            CellValue::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
 /// Represents a row in a query result.
 pub struct QueryResultRow {
-    pub values: Vec<String>,
+    pub values: Vec<CellValue>,
+}
+
+impl QueryResultRow {
+    /// Text of the value in column `index`, empty for NULL (or a missing
+    /// column), so NULLs are never mistaken for the string `"NULL"`.
+    pub fn text(&self, index: usize) -> String {
+        self.values
+            .get(index)
+            .and_then(CellValue::as_str)
+            .unwrap_or_default()
+            .to_string()
+    }
 }
 
 /// Enum representing the possible results of a query execution.
@@ -65,16 +108,15 @@ pub fn execute_query(query: &str, client: &mut PgwireLite) -> Result<QueryResult
                 .rows
                 .iter()
                 .map(|row_map| {
-                    let values: Vec<String> = columns
+                    let values: Vec<CellValue> = columns
                         .iter()
                         .map(|col| match row_map.get(&col.name) {
-                            Some(Value::String(s)) => s.clone(),
-                            Some(Value::Null) => "NULL".to_string(),
-                            Some(Value::Bool(b)) => b.to_string(),
-                            Some(Value::Integer(i)) => i.to_string(),
-                            Some(Value::Float(f)) => f.to_string(),
-                            Some(_) => "UNKNOWN_TYPE".to_string(),
-                            None => "NULL".to_string(),
+                            Some(Value::String(s)) => CellValue::Text(s.clone()),
+                            Some(Value::Null) | None => CellValue::Null,
+                            Some(Value::Bool(b)) => CellValue::Text(b.to_string()),
+                            Some(Value::Integer(i)) => CellValue::Text(i.to_string()),
+                            Some(Value::Float(f)) => CellValue::Text(f.to_string()),
+                            Some(_) => CellValue::Text("UNKNOWN_TYPE".to_string()),
                         })
                         .collect();
 
@@ -148,3 +190,125 @@ pub fn execute_query(query: &str, client: &mut PgwireLite) -> Result<QueryResult
         Err(e) => Err(format!("Query execution failed: {}", e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![tag];
+        msg.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    fn read_message(stream: &mut impl Read, with_tag: bool) {
+        if with_tag {
+            let mut tag = [0u8; 1];
+            stream.read_exact(&mut tag).unwrap();
+        }
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut body = vec![0u8; i32::from_be_bytes(len) as usize - 4];
+        stream.read_exact(&mut body).unwrap();
+    }
+
+    fn row_description(columns: &[&str]) -> Vec<u8> {
+        let mut body = (columns.len() as i16).to_be_bytes().to_vec();
+        for column in columns {
+            body.extend_from_slice(column.as_bytes());
+            body.push(0);
+            body.extend_from_slice(&[0u8; 18]);
+        }
+        message(b'T', &body)
+    }
+
+    /// DataRow with `None` sent as a NULL (length -1).
+    fn data_row(values: &[Option<&str>]) -> Vec<u8> {
+        let mut body = (values.len() as i16).to_be_bytes().to_vec();
+        for value in values {
+            match value {
+                Some(v) => {
+                    body.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                    body.extend_from_slice(v.as_bytes());
+                }
+                None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        message(b'D', &body)
+    }
+
+    /// Run `query` against a server answering the startup and a single
+    /// query with `response` (followed by CommandComplete and ReadyForQuery).
+    fn query_server(query: &str, response: Vec<u8>) -> QueryResult {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_message(&mut stream, false); // StartupMessage
+            stream
+                .write_all(&message(b'R', &0i32.to_be_bytes()))
+                .unwrap();
+            stream.write_all(&message(b'Z', b"I")).unwrap();
+
+            read_message(&mut stream, true); // Query
+            let mut response = response;
+            response.extend(message(b'C', b"SELECT 1\0"));
+            response.extend(message(b'Z', b"I"));
+            stream.write_all(&response).unwrap();
+        });
+
+        let mut client = PgwireLite::connect("127.0.0.1", port, None).unwrap();
+        let result = execute_query(query, &mut client).unwrap();
+        server.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn surfaces_notices_with_data() {
+        let mut response = row_description(&["name"]);
+        response.extend(message(
+            b'N',
+            b"SNOTICE\0Mprovider warning\0Dhttp response status code: 404\0\0",
+        ));
+        response.extend(data_row(&[Some("vpc")]));
+
+        match query_server("SELECT name FROM vpcs", response) {
+            QueryResult::Data { rows, notices, .. } => {
+                assert_eq!(rows[0].values, vec![CellValue::Text("vpc".to_string())]);
+                assert_eq!(
+                    notices,
+                    vec!["provider warning\nDETAIL: http response status code: 404".to_string()]
+                );
+            }
+            _ => panic!("expected a data result"),
+        }
+    }
+
+    #[test]
+    fn distinguishes_null_from_empty_string() {
+        let mut response = row_description(&["id", "name", "tag"]);
+        response.extend(data_row(&[Some("vpc-123"), None, Some("")]));
+
+        match query_server("SELECT id, name, tag FROM vpcs", response) {
+            QueryResult::Data { rows, .. } => {
+                let row = &rows[0];
+                assert_eq!(
+                    row.values,
+                    vec![
+                        CellValue::Text("vpc-123".to_string()),
+                        CellValue::Null,
+                        CellValue::Text(String::new()),
+                    ]
+                );
+                assert_eq!(row.text(1), "");
+                assert_eq!(row.text(2), "");
+                assert_eq!(row.values[1].to_string(), "NULL");
+            }
+            _ => panic!("expected a data result"),
+        }
+    }
+}
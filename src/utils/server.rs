@@ -30,17 +30,24 @@
 //! ```
 
 use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::path::Path;
 use std::process;
 use std::process::{Command as ProcessCommand, Stdio};
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
 use log::{debug, error, info, warn};
 
 use crate::app::{DEFAULT_LOG_FILE, LOCAL_SERVER_ADDRESSES};
-use crate::globals::{server_host, server_port};
 use crate::utils::binary::get_binary_path;
+use crate::utils::connection::{RunOptions, ServerConfig};
+
+/// Ports of the local servers started by `check_and_start_server` in this
+/// process, so they can be stopped when the run fails.
+static STARTED_LOCAL_PORTS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
 
 /// Options for starting a StackQL server
 pub struct StartServerOptions {
@@ -50,6 +57,10 @@ pub struct StartServerOptions {
     pub mtls_config: Option<String>,
     pub custom_auth_config: Option<String>,
     pub log_level: Option<String>,
+    /// Enable the server's provider HTTP request/response logging and copy
+    /// its output into this tool's log at debug level (as well as the server
+    /// log file).  Only for servers stopped before this process exits.
+    pub http_log: bool,
 }
 
 impl Default for StartServerOptions {
@@ -61,6 +72,7 @@ impl Default for StartServerOptions {
             mtls_config: None,
             custom_auth_config: None,
             log_level: None,
+            http_log: false,
         }
     }
 }
@@ -88,6 +100,29 @@ pub fn is_server_running(port: u16) -> bool {
     result
 }
 
+/// Check whether a server is reachable at `host:port`.
+///
+/// Local addresses are checked against running stackql processes; remote
+/// hosts are probed with a short TCP connection attempt.
+pub fn is_server_reachable(host: &str, port: u16) -> bool {
+    if LOCAL_SERVER_ADDRESSES.contains(&host) {
+        return is_server_running(port);
+    }
+    let addrs = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            debug!("is_server_reachable: cannot resolve {}: {}", host, e);
+            return false;
+        }
+    };
+    for addr in addrs {
+        if TcpStream::connect_timeout(&addr, Duration::from_secs(2)).is_ok() {
+            return true;
+        }
+    }
+    false
+}
+
 /// Find all stackql servers that are running and their ports
 pub fn find_all_running_servers() -> Vec<RunningServer> {
     let mut running_servers = Vec::new();
@@ -242,6 +277,16 @@ pub fn get_server_pid(port: u16) -> Option<u32> {
     servers.iter().find(|s| s.port == port).map(|s| s.pid)
 }
 
+/// The `--registry` value for the server: a registry JSON object is passed
+/// as is, a plain URL becomes `{"url": "<url>"}`.
+fn registry_config(registry: &str) -> String {
+    if registry.trim_start().starts_with('{') {
+        registry.to_string()
+    } else {
+        serde_json::json!({ "url": registry }).to_string()
+    }
+}
+
 /// Start the stackql server with the given options
 pub fn start_server(options: &StartServerOptions) -> Result<u32, String> {
     debug!(
@@ -279,7 +324,7 @@ pub fn start_server(options: &StartServerOptions) -> Result<u32, String> {
     cmd.arg("--pgsrv.loglevel=DEBUG");
 
     if let Some(registry) = &options.registry {
-        cmd.arg("--registry").arg(registry);
+        cmd.arg("--registry").arg(registry_config(registry));
     }
 
     if let Some(mtls_config) = &options.mtls_config {
@@ -294,6 +339,10 @@ pub fn start_server(options: &StartServerOptions) -> Result<u32, String> {
         cmd.arg("--log-level").arg(log_level);
     }
 
+    if options.http_log {
+        cmd.arg("--http.log.enabled=true");
+    }
+
     let log_path = Path::new(DEFAULT_LOG_FILE);
     let log_file = OpenOptions::new()
         .create(true)
@@ -304,12 +353,28 @@ pub fn start_server(options: &StartServerOptions) -> Result<u32, String> {
         .map_err(|e| format!("Failed to open log file: {}", e))?;
 
     debug!("Spawning stackql server process (log -> {:?})...", log_path);
-    let child = cmd
+    let stderr = if options.http_log {
+        Stdio::piped()
+    } else {
+        Stdio::from(log_file.try_clone().unwrap())
+    };
+    let mut child = cmd
         .stdout(Stdio::from(log_file.try_clone().unwrap()))
-        .stderr(Stdio::from(log_file))
+        .stderr(stderr)
         .spawn()
         .map_err(|e| format!("Failed to start server: {}", e))?;
 
+    // HTTP traces are written to stderr; tee them into our log
+    if let Some(server_stderr) = child.stderr.take() {
+        let mut log_file = log_file;
+        thread::spawn(move || {
+            for line in BufReader::new(server_stderr).lines().map_while(Result::ok) {
+                writeln!(log_file, "{}", line).ok();
+                debug!("[stackql] {}", line);
+            }
+        });
+    }
+
     let pid = child.id();
     info!("Starting stackql server with PID: {}", pid);
     debug!(
@@ -368,21 +433,20 @@ pub fn stop_server(port: u16) -> Result<(), String> {
 ///
 /// # Arguments
 ///
-/// * `host` - A reference to the server host address.
-/// * `port` - The port number to check.
+/// * `server` - The server host and port.
 ///
 /// # Behavior
 ///
 /// * If the server is already running locally, it will display a message indicating this.
 /// * If a remote server is specified, it will display a message indicating the remote connection.
 /// * If the server needs to be started, it will attempt to do so and indicate success or failure.
-pub fn check_and_start_server() {
-    let host = server_host();
-    let port = server_port();
+pub fn check_and_start_server(server: &ServerConfig, options: &RunOptions) {
+    let host = server.host.as_str();
+    let port = server.port;
 
     debug!("check_and_start_server: host={}, port={}", host, port);
 
-    if LOCAL_SERVER_ADDRESSES.contains(&host) {
+    if server.is_local() {
         debug!(
             "Host '{}' is local; checking if server is running on port {}...",
             host, port
@@ -405,6 +469,8 @@ pub fn check_and_start_server() {
         let options = StartServerOptions {
             host: host.to_string(),
             port,
+            registry: options.registry.clone(),
+            http_log: options.trace_http,
             ..Default::default()
         };
 
@@ -412,8 +478,20 @@ pub fn check_and_start_server() {
             error!("Failed to start server: {}", e);
             process::exit(1);
         }
+        let mut started = STARTED_LOCAL_PORTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !started.contains(&port) {
+            started.push(port);
+        }
     } else {
         debug!("Host '{}' is remote; skipping local server start.", host);
+        if options.trace_http {
+            warn!("--trace-http only applies to a local server started by this tool");
+        }
+        if options.registry.is_some() {
+            warn!("--registry only applies to a local server started by this tool, the registry of the remote server is used");
+        }
         info!("Using remote server {}:{}", host, port);
     }
 }
@@ -421,14 +499,34 @@ pub fn check_and_start_server() {
 /// Stops the local server after an operation completes.
 /// Called at the end of build, test, and teardown to ensure
 /// the server doesn't linger with stale auth context.
-pub fn stop_local_server() {
-    let host = server_host();
-    let port = server_port();
+pub fn stop_local_server(server: &ServerConfig) {
+    STARTED_LOCAL_PORTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|p| *p != server.port);
 
-    if LOCAL_SERVER_ADDRESSES.contains(&host) && is_server_running(port) {
+    if server.is_local() && is_server_running(server.port) {
         debug!("Stopping local server after operation.");
-        if let Err(e) = stop_server(port) {
+        if let Err(e) = stop_server(server.port) {
             warn!("Failed to stop server after operation: {}", e);
         }
     }
 }
+
+/// Stops every local server started by this process.  Used when a run
+/// fails, where the server configuration is not at hand.
+pub fn stop_started_servers() {
+    let ports: Vec<u16> = std::mem::take(
+        &mut *STARTED_LOCAL_PORTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()),
+    );
+    for port in ports {
+        if is_server_running(port) {
+            debug!("Stopping local server on port {} after failure.", port);
+            if let Err(e) = stop_server(port) {
+                warn!("Failed to stop server after failure: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// utils/tls.rs
+
+//! # TLS Utility Module
+//!
+//! This module builds the TLS configuration used to connect to a StackQL
+//! server started with TLS or mTLS (`start-server --mtls-config`).
+//!
+//! ## Features
+//! - Reads the CA certificate, client certificate and client key paths from
+//!   the `--tls-ca-cert`, `--tls-client-cert` and `--tls-client-key` arguments.
+//! - Trusts the given CA, or the bundled web PKI roots when only a client
+//!   certificate is given.
+//! - Presents the client certificate and key for mTLS.
+//! - Leaves connections unencrypted when no TLS arguments are given.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::ArgMatches;
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore};
+
+/// Certificate and key paths for connections to the StackQL server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// CA certificate (PEM) the server certificate must chain to
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate (PEM) presented for mTLS
+    pub client_cert: Option<PathBuf>,
+    /// Private key (PEM) of the client certificate
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// TLS settings from the `--tls-*` arguments.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let path = |name: &str| matches.get_one::<String>(name).map(PathBuf::from);
+        Self {
+            ca_cert: path("tls-ca-cert"),
+            client_cert: path("tls-client-cert"),
+            client_key: path("tls-client-key"),
+        }
+    }
+
+    /// Whether any TLS setting was given; connections are unencrypted if not.
+    pub fn is_enabled(&self) -> bool {
+        self.ca_cert.is_some() || self.client_cert.is_some() || self.client_key.is_some()
+    }
+
+    /// Build the rustls client configuration, or `None` when TLS is not
+    /// enabled.
+    pub fn client_config(&self) -> Result<Option<Arc<ClientConfig>>, String> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let mut roots = RootCertStore::empty();
+        match &self.ca_cert {
+            Some(path) => {
+                for cert in read_certs(path)? {
+                    roots.add(&cert).map_err(|e| {
+                        format!("Invalid CA certificate in {}: {}", path.display(), e)
+                    })?;
+                }
+            }
+            None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            })),
+        }
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+
+        let config = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+                .map_err(|e| format!("Invalid client certificate or key: {}", e))?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(
+                    "--tls-client-cert and --tls-client-key must be given together".to_string(),
+                )
+            }
+        };
+
+        Ok(Some(Arc::new(config)))
+    }
+}
+
+/// Read every certificate in a PEM file.
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, String> {
+    let mut reader = BufReader::new(open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Read the first private key (PKCS#8, PKCS#1 or SEC1) in a PEM file.
+fn read_key(path: &Path) -> Result<PrivateKey, String> {
+    let mut reader = BufReader::new(open(path)?);
+    let items = rustls_pemfile::read_all(&mut reader)
+        .map_err(|e| format!("Failed to read private key from {}: {}", path.display(), e))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", path.display()))
+}
+
+fn open(path: &Path) -> Result<File, String> {
+    File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> Option<PathBuf> {
+        Some(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("src/utils/testdata/tls")
+                .join(name),
+        )
+    }
+
+    #[test]
+    fn builds_mtls_config_from_paths() {
+        let tls = TlsConfig {
+            ca_cert: testdata("ca.pem"),
+            client_cert: testdata("client.pem"),
+            client_key: testdata("client.key"),
+        };
+        let config = tls.client_config().unwrap().unwrap();
+        assert!(config.client_auth_cert_resolver.has_certs());
+    }
+
+    #[test]
+    fn builds_server_only_tls_config() {
+        let tls = TlsConfig {
+            ca_cert: testdata("ca.pem"),
+            ..Default::default()
+        };
+        let config = tls.client_config().unwrap().unwrap();
+        assert!(!config.client_auth_cert_resolver.has_certs());
+    }
+
+    #[test]
+    fn no_tls_without_paths() {
+        assert!(TlsConfig::default().client_config().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_cert_without_key() {
+        let tls = TlsConfig {
+            client_cert: testdata("client.pem"),
+            ..Default::default()
+        };
+        assert!(tls.client_config().is_err());
+    }
+
+    #[test]
+    fn reports_missing_file() {
+        let tls = TlsConfig {
+            ca_cert: testdata("missing.pem"),
+            ..Default::default()
+        };
+        let err = tls.client_config().unwrap_err();
+        assert!(err.contains("missing.pem"));
+    }
+}
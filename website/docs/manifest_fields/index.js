@@ -5,6 +5,7 @@ export { default as Globals } from "./globals.mdx";
 export { default as GlobalName } from "./globals/name.mdx";
 export { default as GlobalDescription } from "./globals/description.mdx";
 export { default as GlobalValue } from "./globals/value.mdx";
+export { default as GlobalValues } from "./globals/values.mdx";
 export { default as Resources } from "./resources.mdx";
 export { default as ResourceName } from "./resources/name.mdx";
 export { default as ResourceType } from "./resources/type.mdx";
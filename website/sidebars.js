@@ -23,8 +23,11 @@ const sidebars = {
       items: [
         'cli-reference/build',
         'cli-reference/init',
+        'cli-reference/plan',
         'cli-reference/teardown',
         'cli-reference/test',
+        'cli-reference/list',
+        'cli-reference/lint',
         'cli-reference/info',
         'cli-reference/shell',
         'cli-reference/upgrade',